@@ -5,6 +5,9 @@ use formulac::core::{
 };
 use num_complex::Complex;
 use rayon::prelude::*;
+use serde::{
+    Serialize, Deserialize,
+};
 use std::ops::{
     Add, AddAssign,
     SubAssign,
@@ -13,14 +16,165 @@ use std::ops::{
     RemAssign,
 };
 use std::collections::VecDeque;
+use std::sync::atomic::{AtomicU64, Ordering};
 use crate::calculate::{
     ARITY,
     Func,
 };
 
-const UNCALCULATED: u16 = u16::MAX;
+/// 収束しなかった場合の根の番号
+pub const UNCONVERGED: u16 = u16::MAX;
+
+/// 分岐切断などの不連続点を跨いで |z| が急に跳んだため、収束とみなさなかった場合の根の番号
+pub const BRANCH_JUMP: u16 = u16::MAX - 1;
+
+/// calc_rect の1回の計算で使用してよいメモリの上限 [byte]
+pub const MEMORY_BUDGET: u64 = 2 << 30;
+
+/// 未計算のピクセルを埋める処理を繰り返す回数の既定値
+pub const DEFAULT_FILL_PASSES: u32 = 4;
+/// 未計算のピクセルを埋める処理を繰り返す回数の上限
+pub const MAX_FILL_PASSES: u32 = 64;
+
+/// 収束判定の許容誤差の既定値
+pub const DEFAULT_EPSILON: f64 = 1e-4;
+
+/// 反復が収束したかの判定方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Criterion {
+    /// 連続する反復点の相対誤差 `|z_{n+1} - z_n| / |z_n|` が許容誤差未満
+    #[default]
+    IterateDelta,
+    /// Newton法のステップ幅 `|f(z) / f'(z)|` が許容誤差未満 (緩和係数に依存しない)
+    StepSize,
+    /// 関数値 `|f(z)|` が許容誤差未満
+    Residual,
+}
+
+/// 反復の方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum IterationMethod {
+    /// Newton法 `z - a f / f'`
+    #[default]
+    Newton,
+    /// Halley法 `z - a 2 f f' / (2 f'^2 - f f'')`。単根の近傍では3次収束する
+    Halley,
+}
+
+/// 画像上の座標から、反復を始める複素平面上の点への写像
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Projection {
+    /// 画像上の座標をそのまま使用する
+    #[default]
+    Planar,
+    /// 半径1の円の内側に、リーマン球面全体を表示する
+    ///
+    /// 円の中心を原点、円周を無限遠点とし、中心からの距離 r を球面上の角度 πr に比例させる。
+    /// 円の外側は球面の裏側を重ねて表示する
+    Stereographic,
+    /// 横軸を log|z|、縦軸を arg z とする
+    Log,
+}
+
+impl Projection {
+    /// # 画像上の座標 w を、複素平面上の点に変換する
+    pub fn project<T>(&self, w: Complex<T>) -> Complex<T>
+    where
+        T: Real + Send + Sync + 'static
+            + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+    {
+        match self {
+            Self::Planar => w,
+            Self::Stereographic => {
+                let r = w.re.clone().hypot(w.im.clone());
+                if r == T::zero() {
+                    return w;
+                }
+                let scale = (T::frac_pi_2() * r.clone()).tan() / r;
+                Complex::new(w.re * scale.clone(), w.im * scale)
+            },
+            Self::Log => {
+                let (sin, cos) = w.im.sin_cos();
+                let abs = w.re.exp();
+                Complex::new(abs.clone() * cos, abs * sin)
+            },
+        }
+    }
+
+    /// # 複素平面上の点 z を、画像上の座標に変換する (project の逆変換)
+    ///
+    /// Stereographic の場合は半径1の円の内側、Log の場合は -π < arg z <= π の座標を返す
+    pub fn unproject<T>(&self, z: Complex<T>) -> Complex<T>
+    where
+        T: Real + Send + Sync + 'static
+            + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+    {
+        match self {
+            Self::Planar => z,
+            Self::Stereographic => {
+                let r = z.re.clone().hypot(z.im.clone());
+                if r == T::zero() {
+                    return z;
+                }
+                let scale = r.clone().atan() * T::frac_2_pi() / r;
+                Complex::new(z.re * scale.clone(), z.im * scale)
+            },
+            Self::Log => {
+                let abs = z.re.clone().hypot(z.im.clone());
+                Complex::new(abs.ln(), z.im.atan2(z.re))
+            },
+        }
+    }
+}
+
+/// 収束先が同じ根かどうかを判定する相対誤差
+const ROOT_RELATIVE_ERROR: f64 = 1e-3;
+/// attracting_period で検出する周期の既定の上限
+pub const DEFAULT_MAX_PERIOD: u16 = 16;
+/// 重根の収束先が同じ根かどうかを判定する相対誤差
+///
+/// 重根の近くではNewton法が1次収束になり、反復の打ち切り時点の誤差が単根よりも大きいので、許容誤差を広げる
+const MULTIPLE_ROOT_RELATIVE_ERROR: f64 = 1e-2;
+
+/// # 重複度mの根と同じ根かどうかを判定する相対誤差を返す
+#[inline]
+fn root_relative_error<T>(multiplicity: u32) -> T
+where
+    T: Real + Send + Sync + 'static
+{
+    T::from_f64(if multiplicity > 1 { MULTIPLE_ROOT_RELATIVE_ERROR } else { ROOT_RELATIVE_ERROR })
+}
+
+/// 1ピクセル分の計算結果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pixel {
+    /// 収束までの反復回数
+    pub iter: u16,
+    /// 収束先の根の番号 (未収束の場合は UNCONVERGED、不連続な跳躍を検出した場合は BRANCH_JUMP)
+    pub root: u16,
+}
+
+impl Pixel {
+    /// # 収束先の根のindexを返す
+    ///
+    /// ## Returns
+    ///  - 未収束の場合や、不連続な跳躍を検出した場合はNone
+    pub fn root_index(&self) -> Option<usize> {
+        match self.root {
+            UNCONVERGED | BRANCH_JUMP => None,
+            root => Some(root as usize),
+        }
+    }
+}
+
+/// 未計算のピクセルの初期値
+///
+/// 計算結果と区別できない値なので、計算済みかどうかは ComputedFlags で判定する
+const UNCALCULATED: Pixel = Pixel { iter: u16::MAX, root: UNCONVERGED };
 
 type PushedFlags = BitSlice<u8, Lsb0>;
+/// 各ピクセルが計算済み (または周囲の値で埋めた) か
+type ComputedFlags = BitSlice<u8, Lsb0>;
 
 #[derive(Debug, Clone, Copy, PartialEq)]
 pub struct Coordinates {
@@ -38,6 +192,15 @@ impl Coordinates {
         ]
     }
 
+    #[inline]
+    const fn four_directions() -> [Self; 4] {
+        [
+                                   Self { x: 0, y: -1 },
+            Self { x: -1, y:  0 },                       Self { x: 1, y:  0 },
+                                   Self { x:  0, y: 1 },
+        ]
+    }
+
     /// # 指定された矩形内の座標かどうかを判定する
     ///
     /// ## Returns
@@ -62,6 +225,105 @@ impl Add for Coordinates {
     }
 }
 
+/// 反復回数の上限を、注目点から離れるほど減らす設定
+#[derive(Debug, Clone)]
+pub struct IterationFalloff<T> {
+    /// 反復回数の上限が max_itr となる注目点
+    pub focus: Complex<T>,
+    /// 減衰率。上限は `max_itr / (1 + rate * d)` となる (d は注目点からの距離を、複素平面の範囲で割った値)
+    pub rate: f64,
+}
+
+/// calc_escape_time の反復の設定
+#[derive(Clone)]
+pub struct EscapeOptions<T> {
+    /// 1回の反復で |z| がこの倍率を超えて跳んだ場合に、不連続な跳躍とみなす (Noneの場合は検出しない)
+    pub branch_jump: Option<T>,
+    /// 反復が収束したかの判定方法
+    pub criterion: Criterion,
+    /// 収束判定の許容誤差 (正の有限値)
+    pub epsilon: f64,
+    /// 緩和係数を `a_n = a * (1 - exp(-n / tau))` (n = 1, 2, ...) で徐々に a に近づける時定数。0の場合は常に a とする
+    pub relaxation_tau: f64,
+    /// 点毎に反復回数の上限を変える設定 (Noneの場合は全ての点で max_itr とする)
+    pub falloff: Option<IterationFalloff<T>>,
+    /// Halley法で使用する2階導関数 (Noneの場合はNewton法で反復する)
+    pub deriv2: Option<Func<T, ARITY>>,
+}
+
+impl<T> Default for EscapeOptions<T> {
+    fn default() -> Self {
+        Self {
+            branch_jump: None,
+            criterion: Criterion::default(),
+            epsilon: DEFAULT_EPSILON,
+            relaxation_tau: 0.0,
+            falloff: None,
+            deriv2: None,
+        }
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for EscapeOptions<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("EscapeOptions")
+            .field("branch_jump", &self.branch_jump)
+            .field("criterion", &self.criterion)
+            .field("epsilon", &self.epsilon)
+            .field("relaxation_tau", &self.relaxation_tau)
+            .field("falloff", &self.falloff)
+            .field("method", &if self.deriv2.is_some() { IterationMethod::Halley } else { IterationMethod::Newton })
+            .finish()
+    }
+}
+
+impl<T> EscapeOptions<T>
+where
+    T: Real + Send + Sync + 'static
+{
+    /// # n回目 (0始まり) の反復で使用する緩和係数を返す
+    #[inline]
+    fn relaxation(&self, a: &Complex<T>, n: u16) -> Complex<T> {
+        if self.relaxation_tau > 0.0 {
+            // n = 0 で係数が0になって反復が進まないように、1始まりで数える
+            let ramp = 1.0 - (-(n as f64 + 1.0) / self.relaxation_tau).exp();
+            a.clone() * T::from_f64(ramp)
+        } else {
+            a.clone()
+        }
+    }
+}
+
+/// # 世代番号による計算の中断フラグ
+///
+/// 作成時の世代番号を保持し、世代番号のカウンタが進んだら中断されたとみなす
+#[derive(Debug, Clone, Copy)]
+pub struct CancelToken {
+    counter:    &'static AtomicU64,
+    generation: u64,
+}
+
+impl CancelToken {
+    /// # counter の現在の世代番号で中断フラグを作成する
+    pub fn new(counter: &'static AtomicU64) -> Self {
+        Self { counter, generation: counter.load(Ordering::SeqCst) }
+    }
+
+    /// # counter の世代番号を進め、進めた後の世代番号で中断フラグを作成する
+    ///
+    /// 以前に作成した中断フラグは全て中断されたとみなされる
+    pub fn advance(counter: &'static AtomicU64) -> Self {
+        Self { counter, generation: counter.fetch_add(1, Ordering::SeqCst) + 1 }
+    }
+
+    /// # 作成後に世代番号が進んだか
+    #[inline]
+    pub fn is_cancelled(&self) -> bool {
+        self.counter.load(Ordering::SeqCst) != self.generation
+    }
+}
+
+#[derive(Clone)]
 pub struct CalcInfo<T>
 where
     T: Real + Send + Sync + 'static
@@ -76,7 +338,25 @@ where
     pub range:  T,
     pub func:   Func<T, ARITY>,
     pub deriv:  Func<T, ARITY>,
-    pub coeff:  Complex<T>
+    pub coeff:  Complex<T>,
+    /// 反復の設定
+    pub escape: EscapeOptions<T>,
+    /// 境界追跡後に、未計算のピクセルを周囲の値で埋める処理を繰り返す最大回数
+    pub fill_passes: u32,
+    /// 矩形領域の外側1pixelの枠も含めて境界追跡を行うか
+    ///
+    /// 枠の分の 2 * (width + height) + 4 pixel を余分に計算する代わりに、隣の領域から入ってくる境界を枠の上で検出できる
+    pub halo: bool,
+    /// 計算前から分かっている根。Pixel::root の番号はこの順番で割り当て、見つからない根は後ろに追加する
+    pub known_roots: Vec<Complex<T>>,
+    /// known_rootsと同じ順番の根の重複度。足りない分は単根とみなす
+    pub known_multiplicities: Vec<u32>,
+    /// 計算を打ち切る時刻。過ぎた後のピクセルは計算せずに未計算のまま残す
+    pub deadline: Option<std::time::Instant>,
+    /// 計算の中断フラグ。中断された後のピクセルは deadline を過ぎた場合と同様に未計算のまま残す
+    pub cancel: Option<CancelToken>,
+    /// ピクセルの座標から複素平面上の点への写像
+    pub projection: Projection,
 }
 
 impl<T> CalcInfo<T>
@@ -102,11 +382,166 @@ where
             width: w, height: h,
             max_itr, size, center, range, func, deriv,
             coeff,
+            escape: EscapeOptions::default(),
+            fill_passes: DEFAULT_FILL_PASSES,
+            halo: false,
+            known_roots: Vec::new(),
+            known_multiplicities: Vec::new(),
+            deadline: None,
+            cancel: None,
+            projection: Projection::default(),
+        }
+    }
+
+    /// # 計算の設定はそのままに、矩形領域を (dx, dy) ずらした w * h の領域に変更した計算情報を作成する
+    fn sub_rect(&self, dx: u32, dy: u32, w: u32, h: u32) -> Self {
+        Self {
+            start: self.start + Coordinates { x: dx as i64, y: dy as i64 },
+            width: w, height: h,
+            max_itr: self.max_itr,
+            size: self.size.clone(),
+            center: self.center.clone(),
+            range: self.range.clone(),
+            func: self.func.clone(),
+            deriv: self.deriv.clone(),
+            coeff: self.coeff.clone(),
+            escape: self.escape.clone(),
+            fill_passes: self.fill_passes,
+            halo: self.halo,
+            known_roots: self.known_roots.clone(),
+            known_multiplicities: self.known_multiplicities.clone(),
+            deadline: self.deadline,
+            cancel: self.cancel,
+            projection: self.projection,
+        }
+    }
+
+    /// # 複素平面上の点zからNewton法を反復する
+    #[inline]
+    pub fn escape_time(&self, z: Complex<T>) -> Escape<T> {
+        let max_itr = self.max_itr_at(&z);
+        calc_escape_time(z, &self.coeff, &self.func, &self.deriv, max_itr, &self.escape)
+    }
+
+    /// # 点zから反復し、反復写像 g(z) = z - a δ(z) の微分の絶対値の対数の和 Σ log|g'(z_n)| を返す
+    ///
+    /// δ は1回のステップ (Newton法では f / f'、Halley法では 2 f f' / (2 f'^2 - f f''))。
+    /// 緩和なしの写像 N(z) = z - δ(z) を使うと g'(z) = 1 - a + a N'(z) で、N' は
+    ///  - Newton法: f f'' / f'^2 (f'' は導関数の中心差分で近似する)
+    ///  - Halley法: f^2 (3 f''^2 - 2 f' f''') / (2 f'^2 - f f'')^2 (f''' は2階導関数の中心差分で近似する)
+    ///
+    /// 根の近傍の超吸引的な点では g' = 0 となり -inf になるので、|g'| は MIN_DERIV 以上に切り上げる
+    pub fn lyapunov_sum(&self, z: Complex<T>) -> T {
+        /// 1回の反復の |g'| の下限
+        const MIN_DERIV: f64 = 1e-12;
+
+        let min_log = T::from_f64(MIN_DERIV.ln());
+        let min = T::from_f64(MIN_DERIV);
+        let mut sum = T::zero();
+        let max_itr = self.max_itr_at(&z);
+        calc_escape_time_observed(z, &self.coeff, &self.func, &self.deriv, max_itr, &self.escape, |z, a, newton| {
+            let dfz = (self.deriv)([z.clone()]);
+            let dn = match &self.escape.deriv2 {
+                None => newton.delta.clone() * second_derivative(&self.deriv, z) / dfz,
+                Some(deriv2) => {
+                    let ddfz = deriv2([z.clone()]);
+                    let dddfz = second_derivative(deriv2, z);
+                    let fz = newton.fz.clone();
+                    let two = Complex::from(T::from_f64(2.0));
+                    let three = Complex::from(T::from_f64(3.0));
+                    let denom = two.clone() * dfz.clone() * dfz.clone() - fz.clone() * ddfz.clone();
+                    fz.clone() * fz * (three * ddfz.clone() * ddfz - two * dfz * dddfz) / (denom.clone() * denom)
+                },
+            };
+            let g = Complex::from(T::one()) - a.clone() + a.clone() * dn;
+            let r = g.abs().re;
+            if !is_finite(&Complex::new(r.clone(), T::zero())) {
+                return;
+            }
+            sum += if r < min { min_log.clone() } else { r.ln() };
+        });
+        sum
+    }
+
+    /// # escape_time と同じ反復を行い、各反復の反復前の点を visit に渡す
+    pub fn escape_time_visiting(&self, z: Complex<T>, mut visit: impl FnMut(&Complex<T>)) -> Escape<T> {
+        let max_itr = self.max_itr_at(&z);
+        calc_escape_time_observed(z, &self.coeff, &self.func, &self.deriv, max_itr, &self.escape, |z, _, _| visit(z))
+    }
+
+    /// # 点zからの反復が引き込まれる周期軌道の周期を返す
+    ///
+    /// 根に収束した場合 (通常の収束) は周期1とする。最大反復回数まで収束しなかった場合は、
+    /// 最後の max_period + 1 点を保持し、最後の点と p 回前の点が同じとみなせる最小の p を周期とする
+    ///
+    /// ## Returns
+    ///  - 周期。max_period 以下の周期軌道が見つからない場合 (関数値が有限でない、不連続な跳躍を検出したなど) は0
+    pub fn attracting_period(&self, z: Complex<T>, max_period: u16) -> u16 {
+        let max_itr = self.max_itr_at(&z);
+        let capacity = max_period as usize + 1;
+        let mut recent: VecDeque<Complex<T>> = VecDeque::with_capacity(capacity);
+        let escape = self.escape_time_visiting(z, |z| {
+            if recent.len() == capacity {
+                recent.pop_front();
+            }
+            recent.push_back(z.clone());
+        });
+
+        if escape.z.is_some() {
+            return 1;
+        }
+        if escape.jumped || escape.iter < max_itr {
+            return 0;
+        }
+        let Some(last) = recent.back() else {
+            return 0;
+        };
+        let relative_error = T::from_f64(ROOT_RELATIVE_ERROR);
+        (1..=max_period)
+            .find(|&p| recent.len() > p as usize && is_same(&recent[recent.len() - 1 - p as usize], last, relative_error.clone()))
+            .unwrap_or(0)
+    }
+
+    /// # 点zから、収束判定をせずに steps 回だけNewton法を反復した軌道を返す
+    ///
+    /// 先頭は z 自身。関数値が有限でなくなった場合は、そこで打ち切る
+    pub fn newton_orbit(&self, z: Complex<T>, steps: usize) -> Vec<Complex<T>> {
+        let mut orbit = vec![z];
+        for n in 0..steps {
+            let z = orbit[n].clone();
+            let a = self.escape.relaxation(&self.coeff, n.min(u16::MAX as usize) as u16);
+            let Some(newton) = newton_method(z, a, &self.func, &self.deriv, self.escape.deriv2.as_ref()) else {
+                break;
+            };
+            orbit.push(newton.next);
+        }
+        orbit
+    }
+
+    /// # 計算を打ち切る時刻を過ぎたか、計算が中断されたか
+    #[inline]
+    fn is_expired(&self) -> bool {
+        self.deadline.is_some_and(|deadline| std::time::Instant::now() >= deadline)
+            || self.cancel.is_some_and(|cancel| cancel.is_cancelled())
+    }
+
+    /// # 点zでの反復回数の上限を返す
+    ///
+    /// escape.falloff が設定されている場合は、注目点から離れるほど小さくなる (最小1)
+    #[inline]
+    pub fn max_itr_at(&self, z: &Complex<T>) -> u16 {
+        match &self.escape.falloff {
+            Some(falloff) if falloff.rate > 0.0 => {
+                let d = (z.clone() - falloff.focus.clone()).abs().re / self.range.clone();
+                let cap = T::from_f64(self.max_itr as f64) / (T::one() + T::from_f64(falloff.rate) * d);
+                cap.to_i32().clamp(1, self.max_itr.max(1) as i32) as u16
+            }
+            _ => self.max_itr,
         }
     }
 
     #[inline]
-    fn get_complex(&self, x: i64, y: i64) -> Complex<T> {
+    pub fn get_complex(&self, x: i64, y: i64) -> Complex<T> {
         let size = self.size.clone();
         let range = self.range.clone();
         let center = self.center.clone();
@@ -114,67 +549,342 @@ where
         let x = T::from_f64((self.start.x + x) as f64);
         let y = T::from_f64((self.start.y + y) as f64);
 
-        Complex::new(
+        self.projection.project(Complex::new(
             (x / size.clone() - half.clone()) * range.clone() + center.re,
             (y / size - half) * range + center.im
-        )
+        ))
+    }
+
+    /// # 複素平面上の点zを含む、矩形領域内のピクセルの座標を返す (get_complex の逆変換)
+    ///
+    /// ## Returns
+    ///  - 矩形領域の外の場合はNone
+    pub fn get_pixel(&self, z: &Complex<T>) -> Option<(u32, u32)> {
+        let z = self.projection.unproject(z.clone());
+        let half = T::from_f64(0.5);
+        let to_pixel = |v: T, center: T, start: i64, len: u32| -> Option<u32> {
+            let p = ((v - center) / self.range.clone() + half.clone()) * self.size.clone() - T::from_f64(start as f64);
+            (p >= T::zero() && p < T::from_f64(len as f64)).then(|| p.to_i32() as u32)
+        };
+        Some((
+            to_pixel(z.re.clone(), self.center.re.clone(), self.start.x, self.width)?,
+            to_pixel(z.im.clone(), self.center.im.clone(), self.start.y, self.height)?,
+        ))
+    }
+
+    /// # ピクセル内の位置も含めた座標(x, y)の複素数を返す
+    ///
+    /// x, y が整数の場合は get_complex と一致する
+    pub fn get_subpixel_complex(&self, x: f64, y: f64) -> Complex<T> {
+        let half = T::from_f64(0.5);
+        let x = T::from_f64(self.start.x as f64 + x);
+        let y = T::from_f64(self.start.y as f64 + y);
+
+        self.projection.project(Complex::new(
+            (x / self.size.clone() - half.clone()) * self.range.clone() + self.center.re.clone(),
+            (y / self.size.clone() - half) * self.range.clone() + self.center.im.clone()
+        ))
+    }
+}
+
+/// ニュートン法の反復結果
+pub struct Escape<T> {
+    /// 収束までの反復回数 (未収束の場合は max_itr)
+    pub iter: u16,
+    /// 収束した点 (未収束の場合は None)
+    pub z: Option<Complex<T>>,
+    /// 最後の反復の収束比 `|z_n - z_{n-1}| / |z_{n-1} - z_{n-2}|`
+    ///
+    /// 比較できる反復が無い場合や、前回の移動量が0の場合 (厳密に収束した場合) は0とする
+    pub ratio: T,
+    /// 連続的な反復回数
+    ///
+    /// 収束判定の値が許容誤差を下回った位置を、直前と最後の反復の値の対数で補間する。
+    /// 反復回数が1つ変わる境界で連続になる。未収束の場合は iter と等しい
+    pub smooth: T,
+    /// 不連続な跳躍を検出して反復を打ち切ったか
+    pub jumped: bool,
+}
+
+/// 計算中に発見した根の一覧
+///
+/// 並列に計算するタスク毎に保持し、計算後にcalc_rectで1つに統合する
+#[derive(Debug, Clone)]
+pub struct Roots<T> {
+    roots: Vec<Complex<T>>,
+    /// rootsと同じ順番の重複度。計算中に発見した根は単根とみなす
+    multiplicities: Vec<u32>,
+}
+
+impl<T> Roots<T>
+where
+    T: Real + Send + Sync + 'static
+{
+    /// # 既知の根を、その順番で番号を割り当てた一覧を作成する
+    ///
+    /// ## Params
+    ///  - multiplicities: 各根の重複度。足りない分は単根とみなす
+    pub fn from_known(roots: &[Complex<T>], multiplicities: &[u32]) -> Self {
+        let multiplicities = (0..roots.len())
+            .map(|i| multiplicities.get(i).copied().unwrap_or(1))
+            .collect();
+        Self { roots: roots.to_vec(), multiplicities }
+    }
+
+    /// # zと同じ根の番号を返す
+    ///
+    /// 重根は収束先の誤差が大きいので、広い許容誤差で比較する。
+    /// 未発見の根の場合は、一覧に追加して新しい番号を返す。
+    /// 番号がBRANCH_JUMPに達した場合は、それ以上追加せずにUNCONVERGEDを返す
+    pub fn index_of(&mut self, z: &Complex<T>) -> u16 {
+        let found = self.roots.iter().zip(&self.multiplicities)
+            .position(|(root, &m)| is_same(root, z, root_relative_error(m)));
+        if let Some(idx) = found {
+            return idx as u16;
+        }
+        if self.roots.len() >= BRANCH_JUMP as usize {
+            return UNCONVERGED;
+        }
+        self.roots.push(z.clone());
+        self.multiplicities.push(1);
+        (self.roots.len() - 1) as u16
+    }
+
+    /// # 反復結果を、反復回数と根の番号の組に変換する
+    pub fn pixel(&mut self, escape: &Escape<T>) -> Pixel {
+        let root = match &escape.z {
+            Some(z) => self.index_of(z),
+            None if escape.jumped => BRANCH_JUMP,
+            None => UNCONVERGED,
+        };
+        Pixel { iter: escape.iter, root }
+    }
+
+    pub fn as_slice(&self) -> &[Complex<T>] {
+        &self.roots
     }
 }
 
 #[inline]
-fn newton_method<T>(z: Complex<T>, a: Complex<T>, func: &Func<T, ARITY>, deriv: &Func<T, ARITY>) -> Complex<T>
+fn is_finite<T>(z: &Complex<T>) -> bool
 where
     T: Real + Send + Sync + 'static
 {
-    z.clone() - func([z.clone()]) / deriv([z]) * a
+    // Realにis_finiteは無いので、inf - inf と NaN - NaN がNaNになることを利用する
+    (z.re.clone() - z.re.clone()).is_zero() && (z.im.clone() - z.im.clone()).is_zero()
 }
 
+/// Newton法 (または Halley法) の1ステップの計算結果
+struct NewtonStep<T> {
+    /// 次の反復点
+    next: Complex<T>,
+    /// 関数値 f(z)
+    fz: Complex<T>,
+    /// 緩和係数を掛ける前のステップ。Newton法では f / f'、Halley法では 2 f f' / (2 f'^2 - f f'')
+    delta: Complex<T>,
+}
+
+/// # Newton法の1ステップを計算する
+///
+/// ## Params
+///  - deriv2: 2階導関数。Someの場合は Halley法のステップとする
+///
+/// ## Returns
+///  - 関数値または微分値が有限でない場合 (数式の特異点など) はNone
 #[inline]
-fn is_same<T>(lhs: &Complex<T>, rhs: &Complex<T>, relative_error: T) -> bool
+fn newton_method<T>(
+    z: Complex<T>, a: Complex<T>,
+    func: &Func<T, ARITY>, deriv: &Func<T, ARITY>, deriv2: Option<&Func<T, ARITY>>,
+) -> Option<NewtonStep<T>>
+where
+    T: Real + Send + Sync + 'static
+{
+    let fz = func([z.clone()]);
+    let dfz = deriv([z.clone()]);
+    if !(is_finite(&fz) && is_finite(&dfz)) {
+        return None;
+    }
+    let delta = match deriv2 {
+        None => fz.clone() / dfz,
+        Some(deriv2) => {
+            let ddfz = deriv2([z.clone()]);
+            if !is_finite(&ddfz) {
+                return None;
+            }
+            let two = T::from_f64(2.0);
+            fz.clone() * dfz.clone() * two.clone() / (dfz.clone() * dfz * two - fz.clone() * ddfz)
+        },
+    };
+    Some(NewtonStep { next: z - delta.clone() * a, fz, delta })
+}
+
+/// # 2点の距離の、0でない方の絶対値に対する比を返す
+///
+/// 両方とも0の場合は0とする
+#[inline]
+fn relative_distance<T>(lhs: &Complex<T>, rhs: &Complex<T>) -> T
 where
     T: Real + Send + Sync + 'static
 {
     let delta = lhs - rhs;
 
     if !(lhs.re.is_zero() && lhs.im.is_zero()) {
-        (delta / lhs).abs().re < relative_error
+        (delta / lhs).abs().re
     } else if !(rhs.re.is_zero() && rhs.im.is_zero()) {
-        (delta / rhs).abs().re < relative_error
+        (delta / rhs).abs().re
     } else {
-        true
+        T::zero()
     }
 }
 
-fn calc_escape_time<T>(z: Complex<T>, a: &Complex<T>, func: &Func<T, ARITY>, deriv: &Func<T, ARITY>, max_itr: u16) -> u16
+#[inline]
+fn is_same<T>(lhs: &Complex<T>, rhs: &Complex<T>, relative_error: T) -> bool
+where
+    T: Real + Send + Sync + 'static
+{
+    relative_distance(lhs, rhs) < relative_error
+}
+
+/// # 1回の反復で |z| が不連続に跳んだか
+///
+/// 原点付近の点でも判定できるように、|z1| が1未満の場合は1を基準とする
+#[inline]
+fn is_jump<T>(z1: &Complex<T>, z2: &Complex<T>, threshold: &T) -> bool
+where
+    T: Real + Send + Sync + 'static
+{
+    let r1 = z1.clone().abs().re;
+    let r2 = z2.clone().abs().re;
+    let base = if r1 < T::one() { T::one() } else { r1.clone() };
+    (r2 - r1).abs() > base * threshold.clone()
+}
+
+/// ## Params
+///  - a: 緩和係数 (options.relaxation_tau > 0 の場合は、最終的に近づける値)
+///  - options: 反復の設定。収束判定の許容誤差は、IterateDeltaでは相対誤差、それ以外では絶対誤差とする
+fn calc_escape_time<T>(
+    z: Complex<T>, a: &Complex<T>, func: &Func<T, ARITY>, deriv: &Func<T, ARITY>,
+    max_itr: u16, options: &EscapeOptions<T>,
+) -> Escape<T>
+where
+    T: Real + Send + Sync + 'static
+{
+    calc_escape_time_observed(z, a, func, deriv, max_itr, options, |_, _, _| ())
+}
+
+/// # calc_escape_time と同じ反復を行い、各反復の (反復前の点, 緩和係数, Newton法の1ステップ) を observe に渡す
+fn calc_escape_time_observed<T>(
+    z: Complex<T>, a: &Complex<T>, func: &Func<T, ARITY>, deriv: &Func<T, ARITY>,
+    max_itr: u16, options: &EscapeOptions<T>,
+    mut observe: impl FnMut(&Complex<T>, &Complex<T>, &NewtonStep<T>),
+) -> Escape<T>
 where
     T: Real + Send + Sync + 'static
 {
     let mut z1 = z;
-    let epsilon: T = T::from_f64(10e-5);
+    let epsilon: T = T::from_f64(options.epsilon);
+    let mut prev_step: Option<T> = None;
+    let mut prev_measure: Option<T> = None;
+    let mut ratio = T::zero();
 
     for n in 0..max_itr {
-        let z2 = newton_method(z1.clone(), a.clone(), func, deriv);
+        let a_n = options.relaxation(a, n);
+        let Some(newton) = newton_method(z1.clone(), a_n.clone(), func, deriv, options.deriv2.as_ref()) else {
+            return Escape { iter: n, z: None, ratio, smooth: T::from_f64(n as f64), jumped: false };
+        };
+        observe(&z1, &a_n, &newton);
+        let z2 = newton.next;
+        if let Some(threshold) = &options.branch_jump
+            && is_jump(&z1, &z2, threshold)
+        {
+            return Escape { iter: n, z: None, ratio, smooth: T::from_f64(n as f64), jumped: true };
+        }
 
-        if is_same(&z1, &z2, epsilon.clone()) {
-            return n;
+        let step = (z2.clone() - z1.clone()).abs().re;
+        ratio = match prev_step {
+            Some(prev) if !prev.is_zero() => step.clone() / prev,
+            _ => T::zero(),
+        };
+        prev_step = Some(step);
+
+        let measure = match options.criterion {
+            Criterion::IterateDelta => relative_distance(&z1, &z2),
+            Criterion::StepSize => newton.delta.abs().re,
+            Criterion::Residual => newton.fz.abs().re,
+        };
+        if measure < epsilon {
+            let smooth = smooth_iter(n, prev_measure, measure, &epsilon);
+            return Escape { iter: n, z: Some(z2), ratio, smooth, jumped: false };
         }
+        prev_measure = Some(measure);
 
         z1 = z2;
     }
 
-    max_itr
+    Escape { iter: max_itr, z: None, ratio, smooth: T::from_f64(max_itr as f64), jumped: false }
+}
+
+/// # n回目の反復で収束した点の、連続的な反復回数を求める
+///
+/// 収束判定の値が epsilon を下回った位置 t を、直前の値 prev と今回の値 measure の対数で線形補間し、n - 1 + t を返す
+#[inline]
+fn smooth_iter<T>(n: u16, prev: Option<T>, measure: T, epsilon: &T) -> T
+where
+    T: Real + Send + Sync + 'static
+{
+    let n_t = T::from_f64(n as f64);
+    let Some(prev) = prev else {
+        return n_t;
+    };
+    // 厳密に収束した場合は対数が取れない
+    if measure.is_zero() {
+        return n_t;
+    }
+    let t = (epsilon.clone() / prev.clone()).ln() / (measure / prev).ln();
+    n_t - T::one() + t
+}
+
+/// # 座標(x, y)の反復回数と収束先の根の番号を計算する
+///
+/// info.deadline を過ぎた場合は計算せずに UNCALCULATED を返す
+#[inline]
+fn calc_pixel<T>(info: &CalcInfo<T>, roots: &mut Roots<T>, x: i64, y: i64) -> Pixel
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    if info.is_expired() {
+        return UNCALCULATED;
+    }
+    roots.pixel(&info.escape_time(info.get_complex(x, y)))
+}
+
+/// # 座標の一覧の反復を並列に計算する
+///
+/// 根の番号は発見した順に割り当てるので、Roots::pixel は呼び出し元で座標の順番に適用すること
+///
+/// ## Returns
+///  - coords と同じ順番の反復結果。info.deadline を過ぎた座標は None
+fn calc_escapes<T>(info: &CalcInfo<T>, coords: &[Coordinates]) -> Vec<Option<Escape<T>>>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    coords.par_iter()
+        .map(|coord| (!info.is_expired()).then(|| info.escape_time(info.get_complex(coord.x, coord.y))))
+        .collect()
 }
 
 /// # 値をセットし、境界条件ならqueueに追加
 #[inline]
 fn update_boundary(
-    buffer: &mut [u16],
+    buffer: &mut [Pixel],
     is_pushed: &mut PushedFlags,
     queue: &mut VecDeque<Coordinates>,
     idx: usize,
     prev_idx: usize,
     coord: Coordinates,
-    val: u16,
+    val: Pixel,
 ) {
     buffer[idx] = val;
     if !is_pushed[idx] && (val != buffer[prev_idx]) {
@@ -183,54 +893,64 @@ fn update_boundary(
     }
 }
 
+/// # 矩形領域 (origin から w * h) の辺を計算し、辺の上で値が変わる点を境界としてqueueに追加する
+///
+/// buffer の1行は info.width pixel とする
 fn calc_edge<T>(
-    buffer: &mut [u16],
+    buffer: &mut [Pixel],
+    computed: &mut ComputedFlags,
     is_pushed: &mut PushedFlags,
     boundaries: &mut VecDeque<Coordinates>,
     info: &CalcInfo<T>,
+    roots: &mut Roots<T>,
+    (origin, w, h): (Coordinates, i64, i64),
 )
 where
     T: Real + Send + Sync + 'static
         + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
 {
-    let w: i64 = info.width as i64;
-    let h = info.height as i64;
+    let stride = info.width as i64;
+    let at = |x: i64, y: i64| origin + Coordinates { x, y };
+    let y_bottom = h - 1;
 
-    // 上辺 (y=0)
-    let idx_start = 0;
-    buffer[idx_start] = calc_escape_time(info.get_complex(0, 0), &info.coeff, &info.func, &info.deriv, info.max_itr);
-    for x in 1..w {
-        let idx = x as usize;
-        let val = calc_escape_time(info.get_complex(x, 0), &info.coeff, &info.func, &info.deriv, info.max_itr);
-        update_boundary(buffer, is_pushed, boundaries, idx, idx - 1, Coordinates { x, y: 0 }, val);
+    // 辺のピクセルは互いに独立なので並列に計算する。根の番号は発見順に割り当てるので、結果は辺に沿った順番で適用する
+    let coords: Vec<Coordinates> = (0..w).map(|x| at(x, 0))
+        .chain((0..w).map(|x| at(x, y_bottom)))
+        .chain((1..(h - 1)).flat_map(|y| [at(0, y), at(w - 1, y)]))
+        .collect();
+    for (coord, escape) in coords.iter().zip(calc_escapes(info, &coords)) {
+        let idx = coord.to_index(stride);
+        buffer[idx] = escape.map_or(UNCALCULATED, |escape| roots.pixel(&escape));
+        computed.set(idx, true);
     }
 
-    // 下辺 (y=h-1)
-    let y_bottom = h - 1;
-    let offset_bottom = (y_bottom * w) as usize;
-    buffer[offset_bottom] = calc_escape_time(info.get_complex(0, y_bottom), &info.coeff, &info.func, &info.deriv, info.max_itr);
-    for x in 1..w {
-        let idx = offset_bottom + x as usize;
-        let val = calc_escape_time(info.get_complex(x, y_bottom), &info.coeff, &info.func, &info.deriv, info.max_itr);
-        update_boundary(buffer, is_pushed, boundaries, idx, idx - 1, Coordinates { x, y: y_bottom }, val);
+    // 上辺 (y=0)・下辺 (y=h-1) は左のマスと比較
+    for y in [0, y_bottom] {
+        for x in 1..w {
+            let coord = at(x, y);
+            let idx = coord.to_index(stride);
+            update_boundary(buffer, is_pushed, boundaries, idx, idx - 1, coord, buffer[idx]);
+        }
     }
 
-    // 右辺・左辺 (y=1..h-1)
-    for y in 1..(h - 1) {
+    // 右辺・左辺 (y=1..h) は上のマスと比較
+    // 下辺の両端も比較しないと、最後の2行の間で左右の辺を横切る境界を見落とし、内側を別の根の値で埋めてしまう
+    for y in 1..h {
         for x in [0, w - 1] {
-            let idx = ((y * w) + x) as usize;
-            let idx_above = idx - w as usize ; // 上のマスと比較
-            let val = calc_escape_time(info.get_complex(x, y), &info.coeff, &info.func, &info.deriv, info.max_itr);
-            update_boundary(buffer, is_pushed, boundaries, idx, idx_above, Coordinates { x, y }, val);
+            let coord = at(x, y);
+            let idx = coord.to_index(stride);
+            update_boundary(buffer, is_pushed, boundaries, idx, idx - stride as usize, coord, buffer[idx]);
         }
     }
 }
 
 fn track_boundary<T>(
-    buffer: &mut [u16],
+    buffer: &mut [Pixel],
+    computed: &mut ComputedFlags,
     is_pushed: &mut PushedFlags,
     boundaries: &mut VecDeque<Coordinates>,
     info: &CalcInfo<T>,
+    roots: &mut Roots<T>,
 )
 where
     T: Real + Send + Sync + 'static
@@ -240,6 +960,10 @@ where
     let h = info.height as i64;
 
     while let Some(boundary) = boundaries.pop_back() {
+        // 期限を過ぎた後は、残りの境界を追跡しても UNCALCULATED が増えるだけなので打ち切る
+        if info.is_expired() {
+            break;
+        }
         let boundary_val = buffer[boundary.to_index(w)];
 
         for d in Coordinates::all_directions() {
@@ -249,8 +973,9 @@ where
             }
 
             let idx = target.to_index(w);
-            if buffer[idx] == UNCALCULATED {
-                buffer[idx] = calc_escape_time(info.get_complex(target.x, target.y), &info.coeff, &info.func, &info.deriv, info.max_itr);
+            if !computed[idx] {
+                buffer[idx] = calc_pixel(info, roots, target.x, target.y);
+                computed.set(idx, true);
             }
             if (buffer[idx] != boundary_val) && !is_pushed[idx] {
                 is_pushed.set(idx, true);
@@ -260,21 +985,81 @@ where
     }
 }
 
-fn fill_in_the_rest(buffer: &mut [u16], width: u32, height: u32)
+/// # 1列に並んだセルのうち、両端の計算済みのセルが同じ値である未計算の区間を求める
+///
+/// ## Params
+///  - start: 先頭のセルのindex
+///  - step: 隣のセルとのindexの差
+///  - len: セルの数
+///
+/// ## Returns
+///  - 埋める区間 (列の中での先頭, 末尾の次) と、埋める値の一覧
+fn bounded_runs(buffer: &[Pixel], computed: &ComputedFlags, start: usize, step: usize, len: usize) -> Vec<(usize, usize, Pixel)> {
+    let mut runs = Vec::new();
+    let mut last: Option<Pixel> = None;
+    let mut run_start = 0; // 未計算の区間の先頭
+
+    for i in 0..len {
+        let idx = start + i * step;
+        if !computed[idx] {
+            continue;
+        }
+        let val = buffer[idx];
+        if last == Some(val) && run_start < i {
+            runs.push((run_start, i, val));
+        }
+        last = Some(val);
+        run_start = i + 1;
+    }
+
+    runs
+}
+
+/// # 行毎 (または列毎) に bounded_runs を並列に求め、その区間を埋める
+///
+/// 各列は互いに重ならないので、並列に求めても逐次に埋めた場合と結果は一致する
+///
+/// ## Params
+///  - lines: 列の番号の範囲
+///  - stride: 隣の列の先頭のセルとのindexの差
+///  - step, len: bounded_runs と同じ
+///
+/// ## Returns
+///  - 埋めたセルの数
+fn fill_bounded_runs(buffer: &mut [Pixel], computed: &mut ComputedFlags, lines: std::ops::Range<usize>, stride: usize, step: usize, len: usize) -> usize {
+    let runs: Vec<Vec<(usize, usize, Pixel)>> = lines.clone().into_par_iter()
+        .map(|line| bounded_runs(buffer, computed, line * stride, step, len))
+        .collect();
+
+    let mut filled = 0;
+    for (line, line_runs) in lines.zip(runs) {
+        let start = line * stride;
+        for (from, to, val) in line_runs {
+            for j in from..to {
+                buffer[start + j * step] = val;
+                computed.set(start + j * step, true);
+            }
+            filled += to - from;
+        }
+    }
+    filled
+}
+
+/// # 未計算のピクセルを、周囲の計算済みのピクセルの値で埋める
+///
+/// 行毎・列毎に、両端の値が一致する区間のみを埋める処理を、埋めるセルが無くなるか max_passes 回に達するまで繰り返す。
+/// 両端の値が一致しないため埋められなかったピクセルは未計算のまま残るので、呼び出し元で直接計算すること
+fn fill_in_the_rest(buffer: &mut [Pixel], computed: &mut ComputedFlags, width: u32, height: u32, max_passes: u32)
 {
     let w = width as usize;
     let h = height as usize;
 
-    for y in 1..(h - 1) {
-        let row_start = y * w;
-        let mut fill_value = buffer[row_start];
-
-        for x in 1..(w - 1) {
-            let idx = row_start + x;
-            match buffer[idx] {
-                UNCALCULATED => buffer[idx] = fill_value,
-                other => fill_value = other, // 計算済みのセルに当たったら、使用する値を更新
-            }
+    for _ in 0..max_passes {
+        let mut filled = 0;
+        filled += fill_bounded_runs(buffer, computed, 1..h.saturating_sub(1), w, 1, w);
+        filled += fill_bounded_runs(buffer, computed, 1..w.saturating_sub(1), 1, w, h);
+        if filled == 0 {
+            break;
         }
     }
 }
@@ -284,44 +1069,58 @@ where
     T: Real + Send + Sync + 'static
         + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
 {
-    let x = info.start.x as u32;
-    let y = info.start.y as u32;
     let mut infos = Vec::with_capacity(task_num);
 
     if is_horizontal_split {
         let dw = info.width / task_num as u32;
         for i in 0..task_num as u32 {
-            let x = x + dw * i;
             let w = if i as usize == (task_num - 1) {
                 info.width - (dw * i)
             } else {
                 dw
             };
-            infos.push(CalcInfo::new(
-                x, y, w, info.height, info.max_itr, info.size.clone(), info.center.clone(), info.range.clone(),
-                info.func.clone(), info.deriv.clone(), info.coeff.clone(),
-            ));
+            infos.push(info.sub_rect(dw * i, 0, w, info.height));
         }
     } else {
         let dh = info.height / task_num as u32;
         for i in 0..task_num as u32 {
-            let y = y + dh * i;
             let h = if i as usize == (task_num - 1) {
                 info.height - (dh * i)
             } else {
                 dh
             };
-            infos.push(CalcInfo::new(
-                x, y, info.width, h, info.max_itr, info.size.clone(), info.center.clone(), info.range.clone(),
-                info.func.clone(), info.deriv.clone(), info.coeff.clone(),
-            ));
+            infos.push(info.sub_rect(0, dh * i, info.width, h));
         }
     }
 
     infos
 }
 
-fn calc_rect_parallel<T>(info: &CalcInfo<T>) -> Vec<u16>
+fn calc_rect_parallel<T>(info: &CalcInfo<T>) -> (Vec<Pixel>, Roots<T>)
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    if !info.halo {
+        return trace_rect(info, 0);
+    }
+
+    // 外側1pixelの枠を含めた領域で計算し、枠を取り除く
+    let mut outer = info.sub_rect(0, 0, info.width + 2, info.height + 2);
+    outer.start = info.start + Coordinates { x: -1, y: -1 };
+    let (buffer, roots) = trace_rect(&outer, 1);
+
+    let (w, outer_w) = (info.width as usize, outer.width as usize);
+    let pixels = (1..=info.height as usize)
+        .flat_map(|y| buffer[(y * outer_w + 1)..(y * outer_w + 1 + w)].iter().copied())
+        .collect();
+    (pixels, roots)
+}
+
+/// # 矩形領域の辺から境界追跡を行い、境界に囲まれた領域を埋める
+///
+/// margin が1以上の場合は、辺から margin pixel 内側の矩形の辺も計算し、境界追跡の起点に加える
+fn trace_rect<T>(info: &CalcInfo<T>, margin: i64) -> (Vec<Pixel>, Roots<T>)
 where
     T: Real + Send + Sync + 'static
         + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
@@ -332,34 +1131,168 @@ where
 
     let mut boundaries = VecDeque::new();
     let mut is_pushed = bitvec![u8, Lsb0; 0 /* false  */; len];
+    let mut computed = bitvec![u8, Lsb0; 0 /* false  */; len];
     let mut buffer = vec![UNCALCULATED; len];
+    let mut roots = Roots::from_known(&info.known_roots, &info.known_multiplicities);
 
-    calc_edge(&mut buffer, &mut is_pushed, &mut boundaries, &info);
-    track_boundary(&mut buffer, &mut is_pushed, &mut boundaries, &info);
-    fill_in_the_rest(&mut buffer, info.width, info.height);
+    let (w_i, h_i) = (info.width as i64, info.height as i64);
+    calc_edge(&mut buffer, &mut computed, &mut is_pushed, &mut boundaries, info, &mut roots, (Coordinates { x: 0, y: 0 }, w_i, h_i));
+    if margin > 0 {
+        let origin = Coordinates { x: margin, y: margin };
+        calc_edge(&mut buffer, &mut computed, &mut is_pushed, &mut boundaries, info, &mut roots, (origin, w_i - 2 * margin, h_i - 2 * margin));
+    }
+    track_boundary(&mut buffer, &mut computed, &mut is_pushed, &mut boundaries, &info, &mut roots);
+    fill_in_the_rest(&mut buffer, &mut computed, info.width, info.height, info.fill_passes);
+
+    // 周囲の値で埋められなかったピクセルは直接計算する
+    let rest: Vec<Coordinates> = computed.iter_zeros()
+        .map(|idx| Coordinates { x: (idx % w) as i64, y: (idx / w) as i64 })
+        .collect();
+    for (coord, escape) in rest.iter().zip(calc_escapes(info, &rest)) {
+        buffer[coord.to_index(w as i64)] = escape.map_or(UNCALCULATED, |escape| roots.pixel(&escape));
+    }
+
+    (buffer, roots)
+}
+
+/// calc_rectの計算結果
+pub struct RectResult<T> {
+    /// 矩形領域の各ピクセルの計算結果 (y * width + x)
+    pub pixels: Vec<Pixel>,
+    /// Pixel::rootの番号に対応する根の座標
+    pub roots: Vec<Complex<T>>,
+    /// 全てのピクセルを計算したか。CalcInfo::deadline を過ぎて打ち切った場合や、CalcInfo::cancel で中断された場合はfalse
+    pub complete: bool,
+}
 
-    buffer
+impl<T> RectResult<T> {
+    /// 反復回数のみを取り出す
+    pub fn escape_times(&self) -> Vec<u16> {
+        self.pixels.iter().map(|p| p.iter).collect()
+    }
 }
 
-pub fn calc_rect<T>(info: CalcInfo<T>) -> Vec<u16>
+impl<T> RectResult<T>
 where
     T: Real + Send + Sync + 'static
-        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
 {
-    const TASKS_RATE: usize = 8; // フラクタルは場所によって計算コストが大幅に異なるので、タスクを細かく分割するように補正をかける
-    let n = (rayon::current_num_threads() * TASKS_RATE).max(1);
-    let w = info.width as usize;
-    let h = info.height as usize;
+    /// # 別の計算結果の根の番号を、この計算結果の根の番号に揃える
+    ///
+    /// 根の番号は発見した順に振られるので、設定を変えて計算した結果同士を比較する前に使用する。
+    /// この計算結果に無い根は、末尾に追加した番号となる
+    pub fn align_roots(&self, other: &mut RectResult<T>) {
+        let mut merged = Roots::from_known(&self.roots, &[]);
+        let roots = Roots::from_known(&std::mem::take(&mut other.roots), &[]);
+        merge_roots(&mut merged, &mut other.pixels, &roots);
+        other.roots = merged.roots;
+    }
 
-    let is_horizontal = info.width > info.height;
-    let infos = create_split_infos(&info, n, is_horizontal);
+    /// # 別の数式で計算した結果の根の番号を、近い根同士が同じ番号になるように揃える
+    ///
+    /// 数式を少し変えると根も少し動くので、align_roots のように同じ根かどうかでは対応が取れない。
+    /// 距離の近い組から順に1対1で対応させ、対応する根が無いものは末尾に追加した番号となる
+    pub fn pair_roots(&self, other: &mut RectResult<T>) {
+        let mut pairs: Vec<(T, usize, usize)> = self.roots.iter().enumerate()
+            .flat_map(|(i, a)| other.roots.iter().enumerate().map(move |(j, b)| ((a.clone() - b.clone()).abs().re, i, j)))
+            .collect();
+        pairs.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap_or(std::cmp::Ordering::Equal));
 
-    let mut buffer = vec![UNCALCULATED; w * h];
-    let results: Vec<Vec<u16>> = infos.into_par_iter()
-        .map(|info| calc_rect_parallel(&info))
-        .collect();
+        let mut taken = vec![false; self.roots.len()];
+        let mut table: Vec<Option<u16>> = vec![None; other.roots.len()];
+        for (_, i, j) in pairs {
+            if !taken[i] && table[j].is_none() {
+                taken[i] = true;
+                table[j] = Some(i as u16);
+            }
+        }
 
-    if is_horizontal {
+        let mut roots = self.roots.clone();
+        let table: Vec<u16> = table.iter().zip(&other.roots)
+            .map(|(paired, root)| match paired {
+                Some(i) => *i,
+                None if roots.len() >= BRANCH_JUMP as usize => UNCONVERGED,
+                None => {
+                    roots.push(root.clone());
+                    (roots.len() - 1) as u16
+                }
+            })
+            .collect();
+
+        for pixel in other.pixels.iter_mut() {
+            if let Some(root) = pixel.root_index() {
+                pixel.root = table[root];
+            }
+        }
+        other.roots = roots;
+    }
+}
+
+/// # width * height の矩形領域を calc_rect で計算する際に必要なメモリ量を見積もる
+///
+/// タスク毎の計算結果と統合後の計算結果の Pixel、及び境界追跡に使用する2種類のフラグ (1bit) を合計する
+pub fn required_memory(width: u32, height: u32) -> u64 {
+    let pixels = width as u64 * height as u64;
+    pixels.saturating_mul(2 * std::mem::size_of::<Pixel>() as u64).saturating_add(pixels.div_ceil(8).saturating_mul(2))
+}
+
+/// # 矩形領域の計算に必要なメモリ量が MEMORY_BUDGET 以下かを確認する
+///
+/// 巨大な画像を出力する場合に、計算前にメモリを確保しすぎて失敗しないように、計算前に呼び出す
+pub fn check_memory_budget(width: u32, height: u32) -> Result<(), String> {
+    const MIB: u64 = 1 << 20;
+    let required = required_memory(width, height);
+    if required > MEMORY_BUDGET {
+        return Err(format!(
+            "Rendering {}x{} pixels needs about {} MiB, which exceeds the memory budget of {} MiB.",
+            width, height, required / MIB, MEMORY_BUDGET / MIB,
+        ));
+    }
+    Ok(())
+}
+
+/// # タスク毎に発見した根の番号を、統合した根の一覧の番号に振り直す
+fn merge_roots<T>(merged: &mut Roots<T>, pixels: &mut [Pixel], roots: &Roots<T>)
+where
+    T: Real + Send + Sync + 'static
+{
+    let table: Vec<u16> = roots.as_slice().iter()
+        .map(|root| merged.index_of(root))
+        .collect();
+
+    for pixel in pixels.iter_mut() {
+        if let Some(root) = pixel.root_index() {
+            pixel.root = table[root];
+        }
+    }
+}
+
+pub fn calc_rect<T>(info: CalcInfo<T>) -> RectResult<T>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    const TASKS_RATE: usize = 8; // フラクタルは場所によって計算コストが大幅に異なるので、タスクを細かく分割するように補正をかける
+    let w = info.width as usize;
+    let h = info.height as usize;
+
+    let is_horizontal = info.width > info.height;
+    // 分割する方向の長さより多く分割すると、幅0の領域ができてしまう
+    let n = (rayon::current_num_threads() * TASKS_RATE).min(w.max(h)).max(1);
+    let infos = create_split_infos(&info, n, is_horizontal);
+
+    let mut buffer = vec![UNCALCULATED; w * h];
+    let mut roots = Roots::from_known(&info.known_roots, &info.known_multiplicities);
+    let results: Vec<Vec<Pixel>> = infos.into_par_iter()
+        .map(|info| calc_rect_parallel(&info))
+        .collect::<Vec<_>>()
+        .into_iter()
+        .map(|(mut result, sub_roots)| {
+            merge_roots(&mut roots, &mut result, &sub_roots);
+            result
+        })
+        .collect();
+
+    if is_horizontal {
         let mut x_offset = 0;
         for result in results {
             let sub_w = result.len() / h;
@@ -379,5 +1312,1203 @@ where
         }
     }
 
-    buffer
+    // 打ち切ったピクセルは UNCALCULATED のまま残る。
+    // max_itr = u16::MAX の未収束のピクセルとは区別できないので、期限を過ぎた場合のみ調べる
+    let complete = !info.is_expired() || !buffer.contains(&UNCALCULATED);
+    RectResult { pixels: buffer, roots: roots.roots, complete }
+}
+
+/// # stride毎に間引いた格子点の反復回数を、境界追跡を行わずに直接計算する
+///
+/// 段階的な描画の粗い段階で使用する。
+///
+/// ## Params
+///  - stride: 間引く間隔[pixel]
+///  - coarser: `stride * 2` で計算済みの格子点。重なる格子点は再計算せずに再利用する
+///
+/// ## Returns
+///  - `ceil(width / stride) * ceil(height / stride)` 個の格子点の反復回数
+pub fn calc_sparse<T>(info: &CalcInfo<T>, stride: u32, coarser: Option<&[u16]>) -> Vec<u16>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let stride = stride.max(1);
+    let cw = info.width.div_ceil(stride) as usize;
+    let ch = info.height.div_ceil(stride) as usize;
+    let coarser_w = info.width.div_ceil(stride * 2) as usize;
+
+    let mut grid = vec![0; cw * ch];
+    grid.par_chunks_mut(cw.max(1)).enumerate().for_each(|(cy, row)| {
+        for (cx, val) in row.iter_mut().enumerate() {
+            *val = match coarser {
+                Some(coarser) if cx % 2 == 0 && cy % 2 == 0 => coarser[(cy / 2) * coarser_w + cx / 2],
+                _ => {
+                    let z = info.get_complex((cx as u32 * stride) as i64, (cy as u32 * stride) as i64);
+                    info.escape_time(z).iter
+                },
+            };
+        }
+    });
+
+    grid
+}
+
+/// # 複素平面上の任意の点の反復回数を、境界追跡を行わずに直接計算する
+///
+/// info の矩形領域は使用せず、数式や最大反復回数などの計算の設定のみを使用する
+pub fn calc_points<T>(info: &CalcInfo<T>, points: &[Complex<T>]) -> Vec<u16>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    points.par_iter()
+        .map(|z| info.escape_time(z.clone()).iter)
+        .collect()
+}
+
+/// # 導関数の中心差分で、点zでの2階微分を近似する
+fn second_derivative<T>(deriv: &Func<T, ARITY>, z: &Complex<T>) -> Complex<T>
+where
+    T: Real + Send + Sync + 'static
+{
+    /// 数値微分の刻み幅 (|z| が1以上の場合は |z| に対する相対値)
+    const STEP: f64 = 1e-6;
+
+    let r = z.clone().abs().re;
+    let h = if r < T::one() { T::one() } else { r } * T::from_f64(STEP);
+    let dz = Complex::new(h.clone(), T::zero());
+    (deriv([z.clone() + dz.clone()]) - deriv([z.clone() - dz])) / Complex::new(h * T::from_f64(2.0), T::zero())
+}
+
+/// # 導関数の零点 (臨界点) を、矩形領域内から探す
+///
+/// 矩形領域を samples * samples の格子に分割し、各格子の中心から導関数にNewton法を適用する。
+/// 導関数の導関数はコンパイルされていないので、中心差分で近似する。
+/// 収束しなかった点と、矩形領域の外に収束した点 (投影している場合は、投影した矩形領域に表示されない点) は除外する
+///
+/// ## Returns
+///  - 重複を除いた臨界点
+pub fn critical_points<T>(info: &CalcInfo<T>, samples: u32) -> Vec<Complex<T>>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    /// 1回の反復での移動量がこれ未満になったら収束とみなす (|z| が1以上の場合は相対値)
+    const EPSILON: f64 = 1e-10;
+    /// 同じ臨界点とみなす、矩形領域の範囲に対する距離の比
+    const SAME_RATIO: f64 = 1e-6;
+
+    let scale = |z: &Complex<T>| {
+        let r = z.clone().abs().re;
+        if r < T::one() { T::one() } else { r }
+    };
+    let refine = |mut z: Complex<T>| -> Option<Complex<T>> {
+        for _ in 0..info.max_itr {
+            let d = (info.deriv)([z.clone()]);
+            let dd = second_derivative(&info.deriv, &z);
+            let next = z.clone() - d / dd;
+            if !is_finite(&next) {
+                return None;
+            }
+            let converged = (next.clone() - z.clone()).abs().re < scale(&z) * T::from_f64(EPSILON);
+            z = next;
+            if converged {
+                return Some(z);
+            }
+        }
+        None
+    };
+
+    let (w, h) = (info.width as f64, info.height as f64);
+    // 投影した表示領域では複素数平面上の矩形にならないので、ピクセルの座標に戻して判定する
+    let inside = |z: &Complex<T>| info.get_pixel(z).is_some();
+
+    let found: Vec<Complex<T>> = (0..samples * samples).into_par_iter()
+        .filter_map(|i| {
+            let x = ((i % samples) as f64 + 0.5) * w / samples as f64;
+            let y = ((i / samples) as f64 + 0.5) * h / samples as f64;
+            refine(info.get_subpixel_complex(x, y))
+        })
+        .filter(inside)
+        .collect();
+
+    let tolerance = info.range.clone() * T::from_f64(SAME_RATIO);
+    let mut points: Vec<Complex<T>> = Vec::new();
+    for z in found {
+        let duplicated = points.iter().any(|p| {
+            is_same(p, &z, T::from_f64(SAME_RATIO)) || (p.clone() - z.clone()).abs().re < tolerance
+        });
+        if !duplicated {
+            points.push(z);
+        }
+    }
+    points
+}
+
+/// # 根の重複度を、Newton法の収束比から推定する
+///
+/// 重複度mの根の近くではNewton法は1次収束になり、連続するステップ幅の比が (m - 1) / m に近づく。
+/// 根から少し離れた点から反復して比を測り、m = 1 / (1 - 比) を丸めた値を返す
+///
+/// ## Returns
+///  - 推定した重複度。反復が発散するなど推定できない場合は1 (単根)
+pub fn root_multiplicity<T>(func: &Func<T, ARITY>, deriv: &Func<T, ARITY>, root: &Complex<T>) -> u32
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    /// 反復を始める点の、根からの相対的な距離
+    const OFFSET: f64 = 1e-2;
+    /// 比を測るまでに反復する回数
+    const STEPS: usize = 3;
+    /// 推定する重複度の上限
+    const MAX_MULTIPLICITY: u32 = 64;
+
+    let r = root.clone().abs().re;
+    let scale = if r < T::one() { T::one() } else { r } * T::from_f64(OFFSET);
+    let mut z = root.clone() + Complex::new(scale.clone(), scale * T::from_f64(0.5));
+    let one = Complex::from(T::one());
+    let mut steps: Vec<T> = Vec::with_capacity(STEPS);
+    for _ in 0..STEPS {
+        // 重根での Newton法の線形収束の比から推定するので、反復の方法に依らず Newton法とする
+        let Some(newton) = newton_method(z, one.clone(), func, deriv, None) else {
+            return 1;
+        };
+        steps.push(newton.delta.abs().re);
+        z = newton.next;
+    }
+
+    // m = round(1 / (1 - ratio)) を、Tから整数に変換せずに求める
+    let ratio = steps[STEPS - 1].clone() / steps[STEPS - 2].clone();
+    (1..MAX_MULTIPLICITY)
+        .find(|&m| ratio < T::one() - T::from_f64(1.0 / (m as f64 + 0.5)))
+        .unwrap_or(if ratio < T::one() { MAX_MULTIPLICITY } else { 1 })
+}
+
+/// # 発見した根のうち、同じ根とみなせるものを統合し、重複度を推定する
+///
+/// 重根の近くでは収束が遅く、同じ根が僅かに異なる値で複数回発見されることがある。
+/// 重根は広い許容誤差で比較して1つにまとめ、最初に発見した値を残す
+///
+/// ## Returns
+///  - (統合した根, 各根の重複度)
+pub fn dedup_roots<T>(func: &Func<T, ARITY>, deriv: &Func<T, ARITY>, roots: &[Complex<T>]) -> (Vec<Complex<T>>, Vec<u32>)
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let mut merged: Vec<Complex<T>> = Vec::with_capacity(roots.len());
+    let mut multiplicities: Vec<u32> = Vec::with_capacity(roots.len());
+    for root in roots {
+        let m = root_multiplicity(func, deriv, root);
+        let found = merged.iter().zip(&multiplicities)
+            .position(|(r, &known)| is_same(r, root, root_relative_error(known.max(m))));
+        match found {
+            Some(idx) => multiplicities[idx] = multiplicities[idx].max(m),
+            None => {
+                merged.push(root.clone());
+                multiplicities.push(m);
+            }
+        }
+    }
+    (merged, multiplicities)
+}
+
+/// # 全てのピクセルについて、反復回数・根の番号と連続的な反復回数を計算する
+///
+/// 連続的な反復回数は連続的に変化するので、calc_convergence_ratios と同様に全てのピクセルを直接計算する。
+/// 根の番号は calc_rect と同様に info.known_roots の順番で割り当てる
+pub fn calc_smooth_rect<T>(info: &CalcInfo<T>) -> (RectResult<T>, Vec<T>)
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let w = info.width as i64;
+    let escapes: Vec<Escape<T>> = (0..w * info.height as i64).into_par_iter()
+        .map(|idx| info.escape_time(info.get_complex(idx % w, idx / w)))
+        .collect();
+
+    let mut roots = Roots::from_known(&info.known_roots, &info.known_multiplicities);
+    let pixels = escapes.iter().map(|escape| roots.pixel(escape)).collect();
+    let smooth = escapes.into_iter().map(|escape| escape.smooth).collect();
+
+    (RectResult { pixels, roots: roots.roots, complete: true }, smooth)
+}
+
+/// # 全てのピクセルについて、最後の反復の収束比を計算する
+///
+/// 収束比は連続的に変化するので、境界追跡法で内部を埋めることはできない。そのため全てのピクセルを直接計算する
+pub fn calc_convergence_ratios<T>(info: &CalcInfo<T>) -> Vec<T>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let w = info.width as usize;
+    let mut ratios = vec![T::zero(); w * info.height as usize];
+    ratios.par_chunks_mut(w.max(1)).enumerate().for_each(|(y, row)| {
+        for (x, val) in row.iter_mut().enumerate() {
+            let z = info.get_complex(x as i64, y as i64);
+            *val = info.escape_time(z).ratio;
+        }
+    });
+
+    ratios
+}
+
+/// # 各ピクセルについて、上下左右の隣接ピクセルとの反復回数の差の最大値を返す
+///
+/// 収束先の境界が明るい稜線として強調される。
+/// 画像の端のピクセルは、存在する隣接ピクセルのみと比較する
+pub fn iteration_gradient(escape_times: &[u16], width: u32, height: u32) -> Vec<u16> {
+    let w = width as i64;
+    let h = height as i64;
+    let mut gradient = vec![0; escape_times.len()];
+
+    for y in 0..h {
+        for x in 0..w {
+            let coord = Coordinates { x, y };
+            let val = escape_times[coord.to_index(w)];
+            gradient[coord.to_index(w)] = Coordinates::four_directions().iter()
+                .map(|&d| coord + d)
+                .filter(|target| target.is_in_rect(w, h))
+                .map(|target| val.abs_diff(escape_times[target.to_index(w)]))
+                .max()
+                .unwrap_or(0);
+        }
+    }
+
+    gradient
+}
+
+/// # 上下左右の隣接ピクセルのいずれかと計算結果が異なるピクセルを境界として返す
+///
+/// タイル毎に計算すると、タイルの境目で隣接ピクセルを参照できないので、
+/// 矩形領域全体の計算結果に対して使用すること
+///
+/// ## Returns
+///  - ピクセル毎の境界判定結果 (y * width + x)
+pub fn boundary_mask(pixels: &[Pixel], width: u32, height: u32) -> BitVec<u8, Lsb0> {
+    let w = width as i64;
+    let h = height as i64;
+    let mut mask = bitvec![u8, Lsb0; 0 /* false */; pixels.len()];
+
+    for y in 0..h {
+        for x in 0..w {
+            let coord = Coordinates { x, y };
+            let val = pixels[coord.to_index(w)];
+            let is_boundary = Coordinates::four_directions().iter()
+                .map(|&d| coord + d)
+                .filter(|target| target.is_in_rect(w, h))
+                .any(|target| pixels[target.to_index(w)] != val);
+            mask.set(coord.to_index(w), is_boundary);
+        }
+    }
+
+    mask
+}
+
+/// # 収束先の根が異なる領域の境界を、ピクセルの角を結ぶ折れ線として抽出する
+///
+/// 上下左右に隣接する2ピクセルの収束先の根が異なる場合、その間のピクセルの辺を境界とし、辺をつないで折れ線にする。
+/// 3本以上の境界が接する点や画像の端では折れ線を分ける。閉じた境界は始点と終点が一致する
+///
+/// ## Returns
+///  - 折れ線毎の頂点の列。座標はピクセルの角を (0, 0) から (width, height) で表す
+pub fn root_boundary_paths(pixels: &[Pixel], width: u32, height: u32) -> Vec<Vec<(f64, f64)>> {
+    // 格子点から出る辺の向き
+    const POS_X: u8 = 1 << 0;
+    const POS_Y: u8 = 1 << 1;
+    const NEG_X: u8 = 1 << 2;
+    const NEG_Y: u8 = 1 << 3;
+    #[inline]
+    const fn opposite(dir: u8) -> u8 {
+        ((dir << 2) | (dir >> 2)) & 0xF
+    }
+
+    let (w, h) = (width as usize, height as usize);
+    let vw = w + 1;
+    let mut edges = vec![0u8; vw * (h + 1)];
+    for y in 0..h {
+        for x in 0..w {
+            let root = pixels[y * w + x].root;
+            if x + 1 < w && pixels[y * w + x + 1].root != root {
+                edges[y * vw + x + 1] |= POS_Y;
+                edges[(y + 1) * vw + x + 1] |= NEG_Y;
+            }
+            if y + 1 < h && pixels[(y + 1) * w + x].root != root {
+                edges[(y + 1) * vw + x] |= POS_X;
+                edges[(y + 1) * vw + x + 1] |= NEG_X;
+            }
+        }
+    }
+    let degrees: Vec<u32> = edges.iter().map(|e| e.count_ones()).collect();
+
+    let mut walk = |start: usize| {
+        let mut path = vec![start];
+        let mut cur = start;
+        while edges[cur] != 0 {
+            let dir = edges[cur] & edges[cur].wrapping_neg();
+            let next = match dir {
+                POS_X => cur + 1,
+                POS_Y => cur + vw,
+                NEG_X => cur - 1,
+                _     => cur - vw,
+            };
+            edges[cur] &= !dir;
+            edges[next] &= !opposite(dir);
+            path.push(next);
+            cur = next;
+            if degrees[cur] != 2 || cur == start {
+                break;
+            }
+        }
+        path.iter().map(|&v| ((v % vw) as f64, (v / vw) as f64)).collect::<Vec<_>>()
+    };
+
+    let mut paths = Vec::new();
+    // 端点や分岐点から始まる折れ線を先に取り出し、残りの閉じた境界を後で取り出す
+    for v in (0..degrees.len()).filter(|&v| degrees[v] != 2) {
+        for _ in 0..degrees[v] {
+            let path = walk(v);
+            if path.len() > 1 {
+                paths.push(path);
+            }
+        }
+    }
+    for v in (0..degrees.len()).filter(|&v| degrees[v] == 2) {
+        let path = walk(v);
+        if path.len() > 1 {
+            paths.push(path);
+        }
+    }
+    paths
+}
+
+/// # Douglas–Peucker法で折れ線の頂点を間引く
+///
+/// 始点と終点は常に残し、残した頂点を結ぶ線分からの距離が tolerance 以下の頂点を取り除く
+///
+/// ## Params
+///  - tolerance: 許容誤差。0.0の場合は一直線上の頂点のみ取り除く
+pub fn simplify_polyline(points: &[(f64, f64)], tolerance: f64) -> Vec<(f64, f64)> {
+    if points.len() <= 2 {
+        return points.to_vec();
+    }
+
+    let mut keep = bitvec![u8, Lsb0; 0 /* false */; points.len()];
+    keep.set(0, true);
+    keep.set(points.len() - 1, true);
+    let mut stack = vec![(0, points.len() - 1)];
+    while let Some((first, last)) = stack.pop() {
+        let farthest = (first + 1..last)
+            .map(|i| (i, segment_distance(points[i], points[first], points[last])))
+            .max_by(|a, b| a.1.total_cmp(&b.1));
+        if let Some((i, distance)) = farthest && distance > tolerance {
+            keep.set(i, true);
+            stack.push((first, i));
+            stack.push((i, last));
+        }
+    }
+
+    points.iter().zip(keep.iter())
+        .filter(|(_, keep)| **keep)
+        .map(|(p, _)| *p)
+        .collect()
+}
+
+/// # 点 p から線分 ab までの距離を返す
+///
+/// a と b が一致する場合は、点 a までの距離を返す
+fn segment_distance(p: (f64, f64), a: (f64, f64), b: (f64, f64)) -> f64 {
+    let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+    let len_sq = dx * dx + dy * dy;
+    let t = if len_sq == 0.0 {
+        0.0
+    } else {
+        (((p.0 - a.0) * dx + (p.1 - a.1) * dy) / len_sq).clamp(0.0, 1.0)
+    };
+    (p.0 - a.0 - t * dx).hypot(p.1 - a.1 - t * dy)
+}
+
+/// 境界ピクセルから、隣接する別の根の領域との境界までの距離
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct BoundaryDistance {
+    /// 境界を挟んで隣接するピクセルのindex (y * width + x)
+    pub neighbor: usize,
+    /// ピクセル中心から境界までの距離 [pixel]。隣接するピクセルの中心までを1.0とする
+    pub distance: f64,
+}
+
+impl BoundaryDistance {
+    /// # ピクセルのうち、自身の根の領域が占める割合を返す
+    ///
+    /// 境界を、隣接するピクセルの方向に垂直な直線とみなす
+    ///
+    /// ## Returns
+    ///  - [0.5, 1.0] の値。ピクセル中心は自身の根の領域にあるので、0.5未満にはならない
+    pub fn coverage(&self) -> f64 {
+        (0.5 + self.distance).clamp(0.5, 1.0)
+    }
+}
+
+/// # 収束先の根が異なる隣接ピクセルとの境界までの距離を推定する
+///
+/// 隣接するピクセルの中心までの線分上で、収束先の根が切り替わる位置を二分法で求める。
+/// 複数の隣接ピクセルと根が異なる場合は、最も近い境界を返す
+///
+/// ## Params
+///  - info: result を計算した計算情報
+///  - result: calc_rect の計算結果
+///
+/// ## Returns
+///  - ピクセル毎の境界までの距離 (y * width + x)。境界ピクセルでない場合や、未収束のピクセルはNone
+pub fn boundary_distances<T>(info: &CalcInfo<T>, result: &RectResult<T>) -> Vec<Option<BoundaryDistance>>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    /// 二分法の反復回数 (精度は 1/2^n pixel)
+    const BISECTION_STEPS: u32 = 6;
+
+    let w = info.width as i64;
+    let h = info.height as i64;
+    let relative_error = T::from_f64(ROOT_RELATIVE_ERROR);
+    let root_at = |x: f64, y: f64| -> Option<usize> {
+        let z = info.escape_time(info.get_subpixel_complex(x, y)).z?;
+        result.roots.iter().position(|root| is_same(root, &z, relative_error.clone()))
+    };
+
+    (0..w * h).into_par_iter().map(|idx| {
+        let coord = Coordinates { x: idx % w, y: idx / w };
+        let own = result.pixels[idx as usize].root_index()?;
+
+        Coordinates::four_directions().iter()
+            .filter_map(|&d| {
+                let target = coord + d;
+                if !target.is_in_rect(w, h) {
+                    return None;
+                }
+                let neighbor = target.to_index(w);
+                let root = result.pixels[neighbor].root_index()?;
+                (root != own).then_some((d, neighbor))
+            })
+            .map(|(d, neighbor)| {
+                // [0, lo] は自身の根、hi は自身以外と判定された位置
+                let (mut lo, mut hi) = (0.0, 1.0);
+                for _ in 0..BISECTION_STEPS {
+                    let mid = (lo + hi) / 2.0;
+                    let root = root_at(coord.x as f64 + d.x as f64 * mid, coord.y as f64 + d.y as f64 * mid);
+                    if root == Some(own) {
+                        lo = mid;
+                    } else {
+                        hi = mid;
+                    }
+                }
+                BoundaryDistance { neighbor, distance: (lo + hi) / 2.0 }
+            })
+            .min_by(|a, b| a.distance.total_cmp(&b.distance))
+    }).collect()
+}
+
+/// # 境界のボックス次元を、ボックスカウント法で推定する
+///
+/// 一辺 1, 2, 4, ... pixel のボックスで画像を分割して境界を含むボックスの数 N(s) を数え、
+/// log N(s) と log(1 / s) の最小二乗法による傾きを返す。
+/// 推定値は解像度と最大反復回数の影響を受ける
+///
+/// ## Returns
+///  - 推定したボックス次元。境界が無い、もしくは画像が小さすぎて推定できない場合はNone
+pub fn box_counting_dimension(mask: &BitSlice<u8, Lsb0>, width: u32, height: u32) -> Option<f64> {
+    /// 最大のボックスでも、画像の一辺を少なくともこの数に分割する
+    const MIN_DIVISIONS: u32 = 4;
+
+    let (w, h) = (width as usize, height as usize);
+    let mut points: Vec<(f64, f64)> = Vec::new();
+
+    let mut box_size = 1;
+    while box_size * MIN_DIVISIONS <= width.min(height) {
+        let s = box_size as usize;
+        let (bw, bh) = (w.div_ceil(s), h.div_ceil(s));
+        let mut occupied = bitvec![u8, Lsb0; 0 /* false */; bw * bh];
+        for idx in mask.iter_ones() {
+            let (x, y) = (idx % w, idx / w);
+            occupied.set((y / s) * bw + x / s, true);
+        }
+
+        let count = occupied.count_ones();
+        if count == 0 {
+            return None;
+        }
+        points.push(((1.0 / box_size as f64).ln(), (count as f64).ln()));
+        box_size *= 2;
+    }
+
+    if points.len() < 2 {
+        return None;
+    }
+
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|p| p.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|p| p.1).sum::<f64>() / n;
+    let cov = points.iter().map(|p| (p.0 - mean_x) * (p.1 - mean_y)).sum::<f64>();
+    let var = points.iter().map(|p| (p.0 - mean_x).powi(2)).sum::<f64>();
+
+    Some(cov / var)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    const A: Pixel = Pixel { iter: 3, root: 0 };
+    const B: Pixel = Pixel { iter: 5, root: 1 };
+
+    fn compile(formula: &str) -> (Func<f64, ARITY>, Func<f64, ARITY>) {
+        let (f, df) = formulac::Builder::<f64, ARITY>::new(formula, ["z"])
+            .compile_with_derivative("z")
+            .unwrap();
+        (Arc::new(f), Arc::new(df))
+    }
+
+    /// (0, 0) から width * height pixel を計算する計算情報。一辺のピクセル数は width、最大反復回数は64
+    fn info(formula: &str, width: u32, height: u32, center: (f64, f64), range: f64) -> CalcInfo<f64> {
+        let (f, df) = compile(formula);
+        CalcInfo::new(
+            0, 0, width, height, 64, width as f64,
+            Complex::new(center.0, center.1), range,
+            f, df, Complex::new(1.0, 0.0),
+        )
+    }
+
+    /// UNCALCULATED 以外のピクセルを計算済みとするフラグ
+    fn computed_flags(buffer: &[Pixel]) -> BitVec<u8, Lsb0> {
+        buffer.iter().map(|&p| p != UNCALCULATED).collect()
+    }
+
+    #[test]
+    fn attracting_two_cycle_is_detected() {
+        // z^3 - 2z + 2 のNewton法は 0 と 1 を往復する吸引的な2周期軌道を持つ
+        let info = info("z^3 - 2*z + 2", 1, 1, (0.0, 0.0), 1.0);
+
+        assert_eq!(info.attracting_period(Complex::new(0.01, 0.0), DEFAULT_MAX_PERIOD), 2);
+        assert_eq!(info.attracting_period(Complex::new(-2.0, 0.0), DEFAULT_MAX_PERIOD), 1);
+        assert_eq!(info.attracting_period(Complex::new(0.01, 0.0), 1), 0);
+    }
+
+    #[test]
+    fn get_pixel_inverts_get_subpixel_complex() {
+        let mut info = info("z^3 - 1", 32, 24, (0.25, -0.5), 2.0);
+        info.start = Coordinates { x: 16, y: 8 };
+        info.size = 64.0;
+
+        for (x, y) in [(0, 0), (31, 0), (5, 23), (31, 23)] {
+            let z = info.get_subpixel_complex(x as f64 + 0.5, y as f64 + 0.5);
+            assert_eq!(info.get_pixel(&z), Some((x, y)));
+        }
+        assert_eq!(info.get_pixel(&info.get_subpixel_complex(-0.5, 0.5)), None);
+        assert_eq!(info.get_pixel(&info.get_subpixel_complex(0.5, 24.5)), None);
+    }
+
+    #[test]
+    fn get_pixel_inverts_projections() {
+        // [-1, 1] x [-1, 1] を表示する
+        let mut info = info("z^3 - 1", 32, 32, (0.0, 0.0), 2.0);
+
+        for projection in [Projection::Planar, Projection::Stereographic, Projection::Log] {
+            info.projection = projection;
+            for (x, y) in [(16, 16), (5, 20), (25, 9), (10, 10)] {
+                let z = info.get_subpixel_complex(x as f64 + 0.5, y as f64 + 0.5);
+                assert_eq!(info.get_pixel(&z), Some((x, y)), "{:?}", projection);
+            }
+        }
+    }
+
+    #[test]
+    fn stereographic_maps_unit_circle_to_infinity() {
+        let half = Complex::new(0.5, 0.0);
+        assert!((Projection::Stereographic.project(half) - Complex::new(1.0, 0.0)).norm() < 1e-12);
+        assert!(Projection::Stereographic.project(Complex::new(0.0, 0.999)).norm() > 600.0);
+        assert!(Projection::Stereographic.unproject(Complex::new(1e300, 0.0)).norm() <= 1.0);
+        assert_eq!(Projection::Log.project(Complex::new(0.0, 0.0)), Complex::new(1.0, 0.0));
+    }
+
+    #[test]
+    fn stops_when_function_is_not_finite() {
+        const MAX_ITER: u16 = 64;
+        let (f, df) = compile("1/z");
+        let one = Complex::new(1.0, 0.0);
+
+        let escape = calc_escape_time(Complex::new(0.0, 0.0), &one, &f, &df, MAX_ITER, &EscapeOptions::default());
+        assert_eq!(escape.iter, 0);
+        assert!(escape.z.is_none());
+
+        // 原点の近くから始めた場合も、発散して非有限になった時点で打ち切る
+        let escape = calc_escape_time(Complex::new(1e-300, 0.0), &one, &f, &df, MAX_ITER, &EscapeOptions::default());
+        assert!(escape.iter < MAX_ITER);
+        assert!(escape.z.is_none());
+    }
+
+    #[test]
+    fn converges_for_regular_formula() {
+        let (f, df) = compile("z^3 - 1");
+        let escape = calc_escape_time(Complex::new(2.0, 0.0), &Complex::new(1.0, 0.0), &f, &df, 64, &EscapeOptions::default());
+        let z = escape.z.unwrap();
+        assert!((z.re - 1.0).abs() < 1e-6 && z.im.abs() < 1e-6);
+    }
+
+    #[test]
+    fn criteria_agree_on_root_for_regular_formula() {
+        const MAX_ITER: u16 = 256;
+        const CRITERIA: [Criterion; 3] = [Criterion::IterateDelta, Criterion::StepSize, Criterion::Residual];
+        const STARTS: [(f64, f64); 5] = [(2.0, 0.0), (-1.0, 1.5), (-0.8, -0.9), (0.3, 0.2), (5.0, -4.0)];
+        let (f, df) = compile("z^3 - 1");
+
+        for a in [1.0, 0.5] {
+            let a = Complex::new(a, 0.0);
+            let mut iters: Vec<Vec<u16>> = vec![Vec::new(); CRITERIA.len()];
+            for start in STARTS {
+                let start = Complex::new(start.0, start.1);
+                let escapes: Vec<Escape<f64>> = CRITERIA.iter()
+                    .map(|&criterion| {
+                        let options = EscapeOptions { criterion, ..Default::default() };
+                        calc_escape_time(start, &a, &f, &df, MAX_ITER, &options)
+                    })
+                    .collect();
+
+                // 判定方法によらず、同じ根に収束する
+                let expected = escapes[0].z.unwrap();
+                for (escape, iters) in escapes.iter().zip(iters.iter_mut()) {
+                    let z = escape.z.unwrap();
+                    assert!((z - expected).norm() < 1e-3, "{start}: {z} != {expected}");
+                    assert!((z.powu(3) - 1.0).norm() < 1e-3);
+                    iters.push(escape.iter);
+                }
+            }
+
+            // 緩和係数が1未満の場合、反復点の移動量は Newton法のステップ幅の a 倍になり、
+            // 根の近くでは |f(z)| ≒ |f'(z)| |f(z)/f'(z)| = 3 |f(z)/f'(z)| なので、判定の厳しさの順に反復回数が増える
+            if a.re < 1.0 {
+                for i in 0..STARTS.len() {
+                    assert!(iters[0][i] <= iters[1][i] && iters[1][i] <= iters[2][i], "{iters:?}");
+                }
+                assert_ne!(iters[0], iters[2]);
+            }
+        }
+    }
+
+    #[test]
+    fn relaxation_ramp_approaches_target() {
+        let a = Complex::new(1.0, 0.0);
+        assert_eq!(EscapeOptions::<f64>::default().relaxation(&a, 0), a);
+
+        let options = EscapeOptions::<f64> { relaxation_tau: 4.0, ..Default::default() };
+        let ramp: Vec<f64> = (0..32).map(|n| options.relaxation(&a, n).re).collect();
+        assert!(ramp[0] > 0.0);
+        assert!(ramp.windows(2).all(|w| w[0] < w[1] && w[1] < 1.0));
+        assert!(1.0 - ramp[31] < 1e-3);
+
+        // 係数を徐々に大きくしても、同じ根に収束する
+        let (f, df) = compile("z^3 - 1");
+        let escape = calc_escape_time(Complex::new(2.0, 0.0), &a, &f, &df, 256, &options);
+        let z = escape.z.unwrap();
+        assert!((z.re - 1.0).abs() < 1e-3 && z.im.abs() < 1e-3);
+    }
+
+    #[test]
+    fn detects_sudden_jump_of_modulus() {
+        let jump_options = EscapeOptions { branch_jump: Some(1e3), ..Default::default() };
+        let (f, df) = compile("z^2 + 1");
+        let one = Complex::new(1.0, 0.0);
+
+        // 臨界点の近くでは、1回目の反復で |z| が約5000まで跳ぶ
+        let start = Complex::new(1e-4, 1e-4);
+        let escape = calc_escape_time(start, &one, &f, &df, 64, &jump_options);
+        assert!(escape.jumped);
+        assert!(escape.z.is_none());
+        assert_eq!(escape.iter, 0);
+
+        let escape = calc_escape_time(start, &one, &f, &df, 64, &EscapeOptions::default());
+        assert!(!escape.jumped);
+
+        // 通常の収束は跳躍とみなさない
+        let (f, df) = compile("z^3 - 1");
+        let escape = calc_escape_time(Complex::new(2.0, 0.0), &one, &f, &df, 64, &jump_options);
+        assert!(!escape.jumped);
+        assert!(escape.z.is_some());
+    }
+
+    #[test]
+    fn smaller_epsilon_iterates_closer_to_root() {
+        let (f, df) = compile("z^3 - 1");
+        let one = Complex::new(1.0, 0.0);
+        let start = Complex::new(2.0, 0.5);
+        let loose = calc_escape_time(start, &one, &f, &df, 64, &EscapeOptions { epsilon: 1e-2, ..Default::default() });
+        let tight = calc_escape_time(start, &one, &f, &df, 64, &EscapeOptions { epsilon: 1e-12, ..Default::default() });
+
+        assert!(loose.iter < tight.iter);
+        let error = |escape: &Escape<f64>| (escape.z.unwrap() - one).norm();
+        assert!(error(&tight) < 1e-12);
+        assert!(error(&tight) < error(&loose));
+    }
+
+    #[test]
+    fn converging_orbit_is_stable() {
+        let info = info("z^3 - 1", 64, 64, (0.0, 0.0), 4.0);
+        assert!(info.lyapunov_sum(Complex::new(0.9, 0.2)) < 0.0);
+    }
+
+    #[test]
+    fn superattracting_root_is_clamped() {
+        // z^2 - 1 の根 z = 1 では g'(1) = 0 だが、-inf にならない
+        let info = info("z^2 - 1", 64, 64, (0.0, 0.0), 4.0);
+        let sum = info.lyapunov_sum(Complex::new(1.0, 0.0));
+        assert!(sum.is_finite());
+        assert!(sum >= 1e-12f64.ln());
+    }
+
+    #[test]
+    fn linear_map_has_known_derivative() {
+        // f(z) = z - 3 では f'' = 0 なので g' = 1 - a となり、a = 0.5 では各反復で log 0.5
+        let mut info = info("z - 3", 64, 64, (0.0, 0.0), 4.0);
+        info.coeff = Complex::new(0.5, 0.0);
+        let escape = info.escape_time(Complex::new(1.0, 0.0));
+        let sum = info.lyapunov_sum(Complex::new(1.0, 0.0));
+        assert!((sum - (escape.iter as f64 + 1.0) * 0.5f64.ln()).abs() < 1e-6, "sum: {}", sum);
+    }
+
+    #[test]
+    fn halley_lyapunov_uses_the_halley_map_derivative() {
+        // 1回だけ反復させ、Halley写像 g の数値微分から求めた log|g'(z0)| と比べる
+        let (f, df) = compile("z^3 - 1");
+        let ddf = compile_deriv2("z^3 - 1");
+        let mut info = info("z^3 - 1", 64, 64, (0.0, 0.0), 4.0);
+        info.max_itr = 1;
+        info.coeff = Complex::new(0.7, 0.0);
+        info.escape.deriv2 = Some(ddf.clone());
+
+        let a = info.coeff;
+        let g = |z: Complex<f64>| newton_method(z, a, &f, &df, Some(&ddf)).unwrap().next;
+        let z0 = Complex::new(2.0, 0.5);
+        let h = 1e-6;
+        let dg = (g(z0 + h) - g(z0 - h)) / (2.0 * h);
+        let sum = info.lyapunov_sum(z0);
+        assert!((sum - dg.norm().ln()).abs() < 1e-5, "sum: {}, expected: {}", sum, dg.norm().ln());
+
+        // Newton写像の微分とは異なる値になる
+        info.escape.deriv2 = None;
+        assert!((info.lyapunov_sum(z0) - sum).abs() > 1e-3);
+    }
+
+    /// 最大反復回数100の z^3 - 1 に、反復回数の減衰を設定した計算情報
+    fn falloff_info(falloff: IterationFalloff<f64>) -> CalcInfo<f64> {
+        let mut info = info("z^3 - 1", 64, 64, (0.0, 0.0), 4.0);
+        info.max_itr = 100;
+        info.escape.falloff = Some(falloff);
+        info
+    }
+
+    #[test]
+    fn zero_rate_is_uniform() {
+        let info = falloff_info(IterationFalloff { focus: Complex::new(0.0, 0.0), rate: 0.0 });
+        assert_eq!(info.max_itr_at(&Complex::new(1.5, -1.5)), 100);
+    }
+
+    #[test]
+    fn cap_decreases_away_from_focus() {
+        let info = falloff_info(IterationFalloff { focus: Complex::new(0.5, 0.0), rate: 4.0 });
+        assert_eq!(info.max_itr_at(&Complex::new(0.5, 0.0)), 100);
+        // 範囲4.0の半分だけ離れると 100 / (1 + 4 * 0.5)
+        assert_eq!(info.max_itr_at(&Complex::new(0.5, 2.0)), 33);
+        assert_eq!(info.max_itr_at(&Complex::new(-3.5, 0.0)), 20);
+    }
+
+    fn find_critical_points(formula: &str) -> Vec<Complex<f64>> {
+        critical_points(&info(formula, 64, 64, (0.1, 0.2), 4.0), 8)
+    }
+
+    #[test]
+    fn cubic_has_single_critical_point_at_origin() {
+        // (z^3 - 1)' = 3z^2 は原点に重根を持つ
+        let points = find_critical_points("z^3 - 1");
+        assert_eq!(points.len(), 1);
+        assert!(points[0].norm() < 1e-6);
+    }
+
+    #[test]
+    fn distinct_critical_points_are_kept() {
+        // (z^3 - 3z)' = 3(z - 1)(z + 1)
+        let mut points = find_critical_points("z^3 - 3*z");
+        points.sort_by(|a, b| a.re.total_cmp(&b.re));
+        assert_eq!(points.len(), 2);
+        assert!((points[0] - Complex::new(-1.0, 0.0)).norm() < 1e-6);
+        assert!((points[1] - Complex::new(1.0, 0.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn no_critical_points_in_view() {
+        assert!(find_critical_points("exp(z) - 1").is_empty());
+    }
+
+    #[test]
+    fn critical_points_are_found_under_projections() {
+        // [-1, 1] x [-1, 1] を表示する。(z^3 - 3z)' の零点 ±1 は、Stereographic では半径0.5の位置に表示される
+        let mut info = info("z^3 - 3*z", 64, 64, (0.0, 0.0), 2.0);
+        info.projection = Projection::Stereographic;
+        assert_eq!(critical_points(&info, 8).len(), 2);
+
+        // Log では 1 は原点に表示されるが、-1 は arg = π なので表示領域の外となる
+        info.projection = Projection::Log;
+        let points = critical_points(&info, 8);
+        assert_eq!(points.len(), 1);
+        assert!((points[0] - Complex::new(1.0, 0.0)).norm() < 1e-6);
+    }
+
+    #[test]
+    fn straight_boundary_has_intermediate_coverage() {
+        const SIZE: u32 = 16;
+        const RANGE: f64 = 4.0;
+        // z^2 - 1 の収束先は実部の符号で決まるので、境界は虚軸になる。
+        // 中心を0.3pixel分ずらして、境界が x = 7.7 を通るようにする
+        const OFFSET: f64 = 0.3;
+        let info = || info("z^2 - 1", SIZE, SIZE, (OFFSET * RANGE / SIZE as f64, 0.1), RANGE);
+
+        let result = calc_rect(info());
+        assert_eq!(result.roots.len(), 2);
+        let distances = boundary_distances(&info(), &result);
+
+        let coverages: Vec<f64> = distances.iter().flatten().map(|d| d.coverage()).collect();
+        assert!(!coverages.is_empty());
+        assert!(coverages.iter().any(|&c| 0.5 < c && c < 1.0));
+
+        // x = 8 のピクセル中心から境界までは0.3pixelなので、被覆率は約0.8
+        let d = distances[8].unwrap();
+        assert_eq!(d.neighbor, 7);
+        assert!((d.coverage() - 0.8).abs() < 1.0 / 32.0);
+    }
+
+    #[test]
+    fn boundary_between_last_two_rows_is_pushed() {
+        const W: u32 = 6;
+        const H: u32 = 8;
+        // z^2 + 1 の収束先は虚部の符号で決まるので、境界は実軸になる。
+        // 1pixel = 1 とし、y = 6 と y = 7 の行の間を実軸が通るようにする
+        let mut info = info("z^2 + 1", W, H, (0.3, -2.5), H as f64);
+        info.size = H as f64;
+
+        let len = (W * H) as usize;
+        let mut buffer = vec![UNCALCULATED; len];
+        let mut computed = bitvec![u8, Lsb0; 0 /* false */; len];
+        let mut is_pushed = bitvec![u8, Lsb0; 0 /* false */; len];
+        let mut boundaries = VecDeque::new();
+        let mut roots = Roots::from_known(&[], &[]);
+        calc_edge(
+            &mut buffer, &mut computed, &mut is_pushed, &mut boundaries, &info, &mut roots,
+            (Coordinates { x: 0, y: 0 }, W as i64, H as i64),
+        );
+
+        let last = (H - 1) as i64;
+        assert_ne!(buffer[((H - 2) * W) as usize].root, buffer[((H - 1) * W) as usize].root);
+        assert!(boundaries.contains(&Coordinates { x: 0, y: last }));
+        assert!(boundaries.contains(&Coordinates { x: W as i64 - 1, y: last }));
+    }
+
+    #[test]
+    fn fills_gaps_only_reachable_from_above_and_below() {
+        const W: u32 = 5;
+        const H: u32 = 5;
+        let w = W as usize;
+
+        // 外周はAだが、左辺の中央は計算結果が未計算の初期値と一致した (u16::MAX 回で未収束の) ピクセル。
+        // 左右の値が異なるので、その行は上下からのみ埋められる
+        let mut buffer = vec![UNCALCULATED; w * H as usize];
+        for i in 0..w {
+            buffer[i] = A;
+            buffer[(H as usize - 1) * w + i] = A;
+        }
+        for y in 1..(H as usize - 1) {
+            buffer[y * w] = A;
+            buffer[y * w + w - 1] = A;
+        }
+        let mut computed = computed_flags(&buffer);
+        computed.set(2 * w, true);
+        buffer[2 * w] = UNCALCULATED;
+
+        fill_in_the_rest(&mut buffer, &mut computed, W, H, DEFAULT_FILL_PASSES);
+        for y in 1..(H as usize - 1) {
+            for x in 1..(w - 1) {
+                assert_eq!(buffer[y * w + x], A, "({x}, {y})");
+            }
+        }
+        assert!(computed.all());
+    }
+
+    #[test]
+    fn leaves_ambiguous_runs_uncalculated() {
+        // 左右も上下も両端の値が異なる中央のピクセルは、埋めずに直接計算させる
+        let mut buffer = vec![
+            A, A, B,
+            A, UNCALCULATED, B,
+            A, B, B,
+        ];
+        let mut computed = computed_flags(&buffer);
+        fill_in_the_rest(&mut buffer, &mut computed, 3, 3, DEFAULT_FILL_PASSES);
+        assert!(!computed[4]);
+    }
+
+    #[test]
+    fn computed_value_equal_to_initial_value_is_kept() {
+        // 中央は計算済みで、値がたまたま未計算の初期値と一致している
+        let mut buffer = vec![
+            A, A, A,
+            A, UNCALCULATED, A,
+            A, A, A,
+        ];
+        let mut computed = bitvec![u8, Lsb0; 1; 9];
+        fill_in_the_rest(&mut buffer, &mut computed, 3, 3, DEFAULT_FILL_PASSES);
+        assert_eq!(buffer[4], UNCALCULATED);
+    }
+
+    #[test]
+    fn zero_escape_time_is_not_recomputed() {
+        const SIZE: u32 = 8;
+        // (4, 4) のピクセルは根 z = 1 そのものなので、反復回数は0
+        let info = info("z^2 - 1", SIZE, SIZE, (1.0, 0.0), 1.0);
+        let center = (SIZE / 2) as i64;
+        assert_eq!(info.get_complex(center, center), Complex::new(1.0, 0.0));
+
+        let result = calc_rect(info);
+        let pixel = result.pixels[(center * SIZE as i64 + center) as usize];
+        assert_eq!(pixel.iter, 0);
+        assert!((result.roots[pixel.root as usize] - Complex::new(1.0, 0.0)).norm() < 1e-6);
+        assert!(result.pixels.iter().all(|&p| p != UNCALCULATED));
+    }
+
+    #[test]
+    fn trace_rect_matches_single_threaded_result() {
+        let info = info("z^4 - 1", 96, 80, (0.1, -0.2), 3.0);
+
+        let serial = rayon::ThreadPoolBuilder::new().num_threads(1).build().unwrap();
+        let (expected, expected_roots) = serial.install(|| trace_rect(&info, 0));
+        let (pixels, roots) = trace_rect(&info, 0);
+        assert_eq!(pixels, expected);
+        assert_eq!(roots.as_slice(), expected_roots.as_slice());
+    }
+
+    fn rect_result(roots: &[(f64, f64)]) -> RectResult<f64> {
+        RectResult {
+            pixels: (0..roots.len() as u16).chain([UNCONVERGED]).map(|root| Pixel { iter: 1, root }).collect(),
+            roots: roots.iter().map(|&(re, im)| Complex::new(re, im)).collect(),
+            complete: true,
+        }
+    }
+
+    #[test]
+    fn moved_roots_are_paired_with_the_nearest() {
+        let a = rect_result(&[(1.0, 0.0), (-0.5, 0.866), (-0.5, -0.866)]);
+        let mut b = rect_result(&[(-0.51, -0.87), (1.01, 0.0), (-0.51, 0.87)]);
+        a.pair_roots(&mut b);
+        let roots: Vec<u16> = b.pixels.iter().map(|p| p.root).collect();
+        assert_eq!(roots, vec![2, 0, 1, UNCONVERGED]);
+        assert_eq!(b.roots, a.roots);
+    }
+
+    #[test]
+    fn extra_roots_get_new_indices() {
+        let a = rect_result(&[(1.0, 0.0)]);
+        let mut b = rect_result(&[(5.0, 0.0), (1.1, 0.0)]);
+        a.pair_roots(&mut b);
+        let roots: Vec<u16> = b.pixels.iter().map(|p| p.root).collect();
+        assert_eq!(roots, vec![1, 0, UNCONVERGED]);
+        assert_eq!(b.roots[1], Complex::new(5.0, 0.0));
+    }
+
+    /// 外側1pixelの枠を含めた図 ('#' と '.' で異なる根に収束する)
+    const PICTURE: [&str; 6] = [
+        ".#..#",
+        "#...#",
+        "..#..",
+        "..#..",
+        "#....",
+        "....#",
+    ];
+
+    /// 枠の内側の 3x4 pixel を計算する。ピクセル (x, y) の座標は x + yi で、1回の反復で図の色に対応する根 (±100) に移る
+    fn picture_info(halo: bool) -> CalcInfo<f64> {
+        let class = |z: &Complex<f64>| {
+            if z.re.abs() > 50.0 {
+                return z.re > 0.0; // 根
+            }
+            let (x, y) = ((z.re.round() + 1.0) as usize, (z.im.round() + 1.0) as usize);
+            PICTURE[y].as_bytes()[x] == b'#'
+        };
+        let f: Func<f64, ARITY> = Arc::new(move |[z]: [Complex<f64>; ARITY]| {
+            let root = if class(&z) { 100.0 } else { -100.0 };
+            z - root
+        });
+        let df: Func<f64, ARITY> = Arc::new(|_| Complex::new(1.0, 0.0));
+        let mut info = CalcInfo::new(0, 0, 3, 4, 8, 1.0, Complex::new(0.5, 0.5), 1.0, f, df, Complex::new(1.0, 0.0));
+        info.halo = halo;
+        info
+    }
+
+    fn direct(info: &CalcInfo<f64>) -> Vec<u16> {
+        let mut roots = Roots::from_known(&[], &[]);
+        (0..4).flat_map(|y| (0..3).map(move |x| (x, y)))
+            .map(|(x, y)| calc_pixel(info, &mut roots, x, y).root)
+            .collect()
+    }
+
+    #[test]
+    fn interior_basin_is_missed_without_halo() {
+        // 内部の '#' (1, 1), (1, 2) は辺に接していないので、辺の計算からは境界が見つからない
+        let info = picture_info(false);
+        let (pixels, _) = calc_rect_parallel(&info);
+        let roots: Vec<u16> = pixels.iter().map(|p| p.root).collect();
+        assert_ne!(roots, direct(&info));
+        assert!(roots.iter().all(|&r| r == roots[0]));
+    }
+
+    #[test]
+    fn halo_detects_boundary_entering_from_outside() {
+        let info = picture_info(true);
+        let (pixels, _) = calc_rect_parallel(&info);
+        let roots: Vec<u16> = pixels.iter().map(|p| p.root).collect();
+        assert_eq!(pixels.len(), 3 * 4);
+        assert_eq!(roots, direct(&info));
+    }
+
+    #[test]
+    fn halo_keeps_the_result_of_real_formula() {
+        let calc = |halo: bool| {
+            let mut info = info("z^3 - 1", 64, 48, (0.0, 0.0), 4.0);
+            info.halo = halo;
+            info.fill_passes = 0; // 埋めずに直接計算すれば、枠の有無に関わらず一致する
+            calc_rect(info).pixels
+        };
+        assert_eq!(calc(true), calc(false));
+    }
+
+    #[test]
+    fn rejects_huge_rect_before_allocation() {
+        assert!(check_memory_budget(512, 512).is_ok());
+        assert!(check_memory_budget(8192, 8192).is_ok());
+        assert!(check_memory_budget(16384, 16384).is_err());
+        assert!(check_memory_budget(u32::MAX, u32::MAX).is_err());
+    }
+
+    #[test]
+    fn estimate_counts_flat_buffers() {
+        // 2つの Pixel のバッファ (4 byte * 2) と 1bit のフラグ * 2
+        assert_eq!(required_memory(8, 8), 8 * 8 * 8 + 8 * 2);
+        assert_eq!(required_memory(0, 100), 0);
+    }
+
+    #[test]
+    fn straight_boundary_is_single_segment() {
+        // 左2列が A、右2列が B の 4x3 の画像
+        let pixels: Vec<Pixel> = (0..12).map(|i| if i % 4 < 2 { A } else { B }).collect();
+        let paths = root_boundary_paths(&pixels, 4, 3);
+        assert_eq!(paths.len(), 1);
+        assert_eq!(paths[0].len(), 4);
+
+        let simplified = simplify_polyline(&paths[0], 0.0);
+        assert_eq!(simplified, vec![(2.0, 0.0), (2.0, 3.0)]);
+    }
+
+    #[test]
+    fn island_is_closed_loop() {
+        let mut pixels = vec![A; 9];
+        pixels[4] = B;
+        let paths = root_boundary_paths(&pixels, 3, 3);
+        assert_eq!(paths.len(), 1);
+        let path = &paths[0];
+        assert_eq!(path.len(), 5);
+        assert_eq!(path.first(), path.last());
+    }
+
+    #[test]
+    fn iteration_changes_are_not_boundaries() {
+        let pixels: Vec<Pixel> = (0..9).map(|i| Pixel { iter: i, root: 0 }).collect();
+        assert!(root_boundary_paths(&pixels, 3, 3).is_empty());
+    }
+
+    #[test]
+    fn simplify_keeps_points_beyond_tolerance() {
+        let points = [(0.0, 0.0), (1.0, 0.1), (2.0, 1.0), (3.0, 0.0)];
+        assert_eq!(simplify_polyline(&points, 0.5), vec![(0.0, 0.0), (2.0, 1.0), (3.0, 0.0)]);
+        assert_eq!(simplify_polyline(&points, 2.0), vec![(0.0, 0.0), (3.0, 0.0)]);
+    }
+
+    fn compile_deriv2(formula: &str) -> Func<f64, ARITY> {
+        let ddf = formulac::Builder::<f64, ARITY>::new(&format!("diff(diff({}, z), z)", formula), ["z"])
+            .compile()
+            .unwrap();
+        Arc::new(ddf)
+    }
+
+    #[test]
+    fn halley_step_matches_formula() {
+        let (f, df) = compile("z^2 - 1");
+        let ddf = compile_deriv2("z^2 - 1");
+        let one = Complex::new(1.0, 0.0);
+        // f = 3, f' = 4, f'' = 2 → 2 f f' / (2 f'^2 - f f'') = 24 / 26
+        let step = newton_method(Complex::new(2.0, 0.0), one, &f, &df, Some(&ddf)).unwrap();
+        assert!((step.delta.re - 24.0 / 26.0).abs() < 1e-12 && step.delta.im.abs() < 1e-12);
+
+        let step = newton_method(Complex::new(2.0, 0.0), one, &f, &df, None).unwrap();
+        assert!((step.delta.re - 0.75).abs() < 1e-12);
+    }
+
+    #[test]
+    fn halley_converges_faster_to_same_root() {
+        let (f, df) = compile("z^3 - 1");
+        let ddf = compile_deriv2("z^3 - 1");
+        let one = Complex::new(1.0, 0.0);
+        let halley = EscapeOptions { deriv2: Some(ddf), ..Default::default() };
+
+        for start in [(2.0, 0.0), (-1.0, 1.5), (5.0, -4.0)] {
+            let start = Complex::new(start.0, start.1);
+            let newton = calc_escape_time(start, &one, &f, &df, 64, &EscapeOptions::default());
+            let halley = calc_escape_time(start, &one, &f, &df, 64, &halley);
+            assert!(halley.iter < newton.iter, "{:?}: {} >= {}", start, halley.iter, newton.iter);
+            assert!((halley.z.unwrap() - newton.z.unwrap()).norm() < 1e-4);
+        }
+    }
+
+    fn cancel_info(cancel: CancelToken) -> CalcInfo<f64> {
+        let mut info = info("z^3 - 1", 32, 32, (0.0, 0.0), 4.0);
+        info.cancel = Some(cancel);
+        info
+    }
+
+    #[test]
+    fn token_is_cancelled_after_counter_advances() {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let token = CancelToken::new(&COUNTER);
+        assert!(!token.is_cancelled());
+        COUNTER.fetch_add(1, Ordering::SeqCst);
+        assert!(token.is_cancelled());
+        assert!(!CancelToken::new(&COUNTER).is_cancelled());
+
+        let advanced = CancelToken::advance(&COUNTER);
+        assert!(!advanced.is_cancelled());
+        CancelToken::advance(&COUNTER);
+        assert!(advanced.is_cancelled());
+    }
+
+    #[test]
+    fn cancelled_rect_is_left_uncalculated() {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let token = CancelToken::new(&COUNTER);
+        let result = calc_rect(cancel_info(token));
+        assert!(result.complete);
+        assert!(!result.pixels.contains(&UNCALCULATED));
+
+        COUNTER.fetch_add(1, Ordering::SeqCst);
+        let result = calc_rect(cancel_info(token));
+        assert!(!result.complete);
+        assert!(result.pixels.iter().all(|pixel| *pixel == UNCALCULATED));
+    }
 }
@@ -1,5 +1,9 @@
+use base64::Engine;
 use formulac;
-use formulac::core::Real;
+use formulac::core::{
+    ComplexMath,
+    Real,
+};
 use num_complex::Complex;
 use num_traits::{
     Zero,
@@ -7,10 +11,12 @@ use num_traits::{
 use once_cell::sync::Lazy;
 use png;
 use png::text_metadata::TEXtChunk;
+use rayon::prelude::*;
 use serde::{
     Serialize, Deserialize,
 };
 use serde::de::DeserializeOwned;
+use std::collections::BTreeMap;
 use std::marker::PhantomData;
 use std::ops::{
     AddAssign,
@@ -22,12 +28,30 @@ use std::ops::{
 use std::str::FromStr;
 use std::sync::{
     Arc,
+    Condvar,
     Mutex,
+    OnceLock,
+};
+use std::sync::atomic::{
+    AtomicU64,
+    Ordering,
 };
+use tauri::Emitter;
 
 use crate::btm;
+use crate::coloring::{
+    self,
+    AlphaSource,
+    Coloring,
+    ColoringMode,
+    ColoringSource,
+    Colormap,
+    Recolor,
+    ValueTransform,
+};
 use crate::multi_precision::{
     F106,
+    ToF64,
 };
 
 /// 初期値
@@ -36,6 +60,36 @@ mod default {
     pub const CANVAS_ZOOM_LEVEL: i32 = 0;
     pub const CANVAS_SIZE: u16 = 512;
     pub const FRACTAL_MAX_ITER: u16 = 64;
+    pub const TILE_SIZE: u32 = 256;
+    /// 1回の反復で |z| がこの倍率を超えて変化した場合に、不連続な跳躍とみなす
+    pub const BRANCH_JUMP_RATIO: f64 = 1e3;
+
+    pub fn tile_size() -> u32 {
+        TILE_SIZE
+    }
+
+    pub fn fill_passes() -> u32 {
+        crate::btm::DEFAULT_FILL_PASSES
+    }
+
+    pub fn epsilon() -> f64 {
+        crate::btm::DEFAULT_EPSILON
+    }
+
+    /// Newton法の係数 a (z - a f(z) / f'(z)) の既定値
+    pub fn coeff() -> num_complex::Complex<f64> {
+        num_complex::Complex::ONE
+    }
+
+    /// 数式の自由パラメータの既定値。参照しない数式にも影響しないので、`c` を常に使用できるようにする
+    pub fn params() -> super::FormulaParams {
+        super::FormulaParams::from([("c".to_string(), num_complex::Complex::ZERO)])
+    }
+
+    /// 未計算の根の一覧 (T: Default を要求しないように、serde の default に指定する)
+    pub fn roots<T>() -> std::sync::Arc<std::sync::OnceLock<super::CachedRoots<T>>> {
+        std::sync::Arc::default()
+    }
 }
 
 /// 静的ディスパッチ用ラッパ
@@ -62,6 +116,27 @@ pub type Func<T, const N: usize> = Arc<dyn Fn([Complex<T>; N]) -> Complex<T> + S
 /// formulacのarity
 pub(crate) const ARITY: usize = 1;
 
+/// 数式の変数名
+const VARIABLE: &str = "z";
+
+/// 数式の自由パラメータ (名前と値)
+///
+/// formulac には定数として渡すので、導関数は VARIABLE のみで微分したものとなる
+type FormulaParams = BTreeMap<String, Complex<f64>>;
+
+/// 利用者定義関数の引数の数の上限
+const MAX_FUNCTION_ARITY: usize = 4;
+
+/// 利用者定義関数の定義
+///
+/// `name(params...) = body` として、数式の中で組み込みの関数と同様に呼び出せる
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct UserFunction {
+    name:   String,
+    params: Vec<String>,
+    body:   String,
+}
+
 /// formulacの変数を保持する構造体
 #[derive(Clone)]
 struct FormulacInner<T: Real>
@@ -69,6 +144,18 @@ where
 {
     f: Func<T, ARITY>,
     df: Func<T, ARITY>,
+    /// 2階導関数。Halley法で反復する場合のみコンパイルする
+    ddf: Option<Func<T, ARITY>>,
+    /// 数式の設定時に2階導関数もコンパイルするか
+    second_derivative: bool,
+    /// コンパイルした導関数の式
+    ///
+    /// formulac は導関数をクロージャとしてのみ返すので、記号微分する前の `diff(数式, z)` の形で保持する
+    derivative: String,
+    /// 数式に定数として埋め込む自由パラメータ
+    params: Vec<(String, Complex<T>)>,
+    /// 数式から呼び出せる利用者定義関数 (登録順)
+    functions: Vec<UserFunction>,
 }
 
 impl<T> std::fmt::Debug for FormulacInner<T>
@@ -78,6 +165,7 @@ where
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("FormulacInner")
             .field("Generics", &std::any::type_name::<T>())
+            .field("derivative", &self.derivative)
             .finish_non_exhaustive()
     }
 }
@@ -91,13 +179,92 @@ where
         Self {
             f: Arc::new(|_: [Complex<T>; ARITY]| Complex::zero()),
             df: Arc::new(|_: [Complex<T>; ARITY]| Complex::zero()),
+            ddf: None,
+            second_derivative: false,
+            derivative: "0".to_string(),
+            params: Vec::new(),
+            functions: Vec::new(),
+        }.with_params(&default::params())
+    }
+
+    /// # 自由パラメータを設定する
+    ///
+    /// コンパイル済みの関数には反映されないので、設定後に数式をコンパイルすること
+    fn with_params(mut self, params: &FormulaParams) -> Self {
+        self.params = params.iter()
+            .map(|(name, value)| (name.clone(), Complex::new(T::from_f64(value.re), T::from_f64(value.im))))
+            .collect();
+        self
+    }
+
+    /// # 利用者定義関数を設定する
+    ///
+    /// 関数の本体は呼び出し毎に展開するので、ここでは各本体が単独でコンパイルできるかのみを検査する。
+    /// 本体からはそれより前に登録した関数を呼び出せる
+    fn with_functions(mut self, functions: &[UserFunction]) -> Result<Self, formulac::err::ParseError> {
+        for (i, function) in functions.iter().enumerate() {
+            let body = expand_functions(&function.body, &functions[..i])?;
+            match function.params.len() {
+                1 => self.check_function_body::<1>(function, &body)?,
+                2 => self.check_function_body::<2>(function, &body)?,
+                3 => self.check_function_body::<3>(function, &body)?,
+                4 => self.check_function_body::<4>(function, &body)?,
+                n => return Err(formulac::err::ParseError::InvalidFormula {
+                    reason: format!("{} takes {} parameters (limit: 1..={})", function.name, n, MAX_FUNCTION_ARITY),
+                    span:   formulac::lexer::Span::new(),
+                }),
+            }
+        }
+        self.functions = functions.to_vec();
+        Ok(self)
+    }
+
+    /// # 展開済みの関数の本体を、N個の引数の関数としてコンパイルできるかを検査する
+    fn check_function_body<const N: usize>(&self, function: &UserFunction, body: &str)
+        -> Result<(), formulac::err::ParseError>
+    {
+        let params: [&str; N] = std::array::from_fn(|i| function.params[i].as_str());
+        // 展開時は引数を優先して置き換えるので、引数と同じ名前の自由パラメータは渡さない
+        let constants = self.params.iter()
+            .filter(|(name, _)| !params.contains(&name.as_str()))
+            .cloned();
+        formulac::Builder::<T, N>::new(body, params)
+            .with_constants(constants)
+            .compile()
+            .map(|_| ())
+    }
+
+    /// # 数式の設定時に、Halley法のための2階導関数もコンパイルするかを設定する
+    fn with_second_derivative(mut self, enabled: bool) -> Self {
+        self.second_derivative = enabled;
+        self
+    }
+
+    /// # 2階導関数 `diff(diff(数式, z), z)` をコンパイルする
+    ///
+    /// ## Returns
+    ///  - 2階導関数が不要な場合はNone
+    fn compile_deriv2(&self, formula: &str) -> Result<Option<Func<T, ARITY>>, formulac::err::ParseError> {
+        if !self.second_derivative {
+            return Ok(None);
         }
+        let ddf = self.builder(&format!("diff(diff({}, {v}), {v})", formula, v = VARIABLE))?
+            .compile()?;
+        Ok(Some(Arc::new(ddf)))
+    }
+
+    /// # 利用者定義関数を展開し、自由パラメータを登録した Builder を作成する
+    fn builder(&self, formula: &str) -> Result<formulac::Builder<T, ARITY>, formulac::err::ParseError> {
+        let formula = expand_functions(formula, &self.functions)?;
+        Ok(formulac::Builder::new(&formula, [VARIABLE])
+            .with_constants(self.params.iter().cloned()))
     }
 
     fn set_formula(&mut self, formula: &str) -> Result<(), formulac::err::ParseError>
     {
-        let (f, df) = formulac::Builder::<T, ARITY>::new(formula, ["z"])
-            .compile_with_derivative("z")?;
+        let (f, df) = self.builder(formula)?
+            .compile_with_derivative(VARIABLE)?;
+        let ddf = self.compile_deriv2(formula)?;
 
         let f_arc = Arc::new(f);
         let df_arc = Arc::new(df);
@@ -110,10 +277,120 @@ where
             let df_holder = FuncHolder { func: df_arc.clone(), _marker: PhantomData };
             move |args| df_holder.call(args)
         });
+        self.ddf = ddf;
+        self.derivative = format!("diff({}, z)", formula);
+
+        Ok(())
+    }
+
+    /// # 条件式によって、反復毎に使用する数式を切り替える
+    ///
+    /// condition(z) の実部が0以上の場合は formula_true、負の場合は formula_false を使用する
+    fn set_conditional_formula(&mut self, condition: &str, formula_true: &str, formula_false: &str)
+        -> Result<(), formulac::err::ParseError>
+    {
+        let cond = Arc::new(self.builder(condition)?.compile()?);
+        let (f_true, df_true) = self.builder(formula_true)?
+            .compile_with_derivative(VARIABLE)?;
+        let (f_false, df_false) = self.builder(formula_false)?
+            .compile_with_derivative(VARIABLE)?;
+        let ddf = match (self.compile_deriv2(formula_true)?, self.compile_deriv2(formula_false)?) {
+            (Some(ddf_true), Some(ddf_false)) => {
+                let cond = cond.clone();
+                Some(Arc::new(move |args: [Complex<T>; ARITY]| {
+                    if cond(args.clone()).re >= T::zero() { ddf_true(args) } else { ddf_false(args) }
+                }) as Func<T, ARITY>)
+            },
+            _ => None,
+        };
+
+        self.f = Arc::new({
+            let cond = cond.clone();
+            move |args: [Complex<T>; ARITY]| if cond(args.clone()).re >= T::zero() { f_true(args) } else { f_false(args) }
+        });
+        self.df = Arc::new({
+            move |args: [Complex<T>; ARITY]| if cond(args.clone()).re >= T::zero() { df_true(args) } else { df_false(args) }
+        });
+        self.ddf = ddf;
+        self.derivative = format!("Re({}) >= 0 ? diff({}, z) : diff({}, z)", condition, formula_true, formula_false);
+
+        Ok(())
+    }
+
+    /// # 2つの数式を補間した関数を使用する
+    ///
+    /// `(1 - t) * f_a(z) + t * f_b(z)` とし、Newton法が一貫するように導関数も同じ比で補間する
+    fn set_morph_formula(&mut self, formula_a: &str, formula_b: &str, t: f64)
+        -> Result<(), formulac::err::ParseError>
+    {
+        let (f_a, df_a) = self.builder(formula_a)?
+            .compile_with_derivative(VARIABLE)?;
+        let (f_b, df_b) = self.builder(formula_b)?
+            .compile_with_derivative(VARIABLE)?;
+        let (weight_a, weight_b) = (T::from_f64(1.0 - t), T::from_f64(t));
+        let ddf = match (self.compile_deriv2(formula_a)?, self.compile_deriv2(formula_b)?) {
+            (Some(ddf_a), Some(ddf_b)) => {
+                let (weight_a, weight_b) = (weight_a.clone(), weight_b.clone());
+                Some(Arc::new(move |args: [Complex<T>; ARITY]| {
+                    ddf_a(args.clone()) * weight_a.clone() + ddf_b(args) * weight_b.clone()
+                }) as Func<T, ARITY>)
+            },
+            _ => None,
+        };
+
+        self.f = Arc::new({
+            let (weight_a, weight_b) = (weight_a.clone(), weight_b.clone());
+            move |args: [Complex<T>; ARITY]| f_a(args.clone()) * weight_a.clone() + f_b(args) * weight_b.clone()
+        });
+        self.df = Arc::new({
+            move |args: [Complex<T>; ARITY]| df_a(args.clone()) * weight_a.clone() + df_b(args) * weight_b.clone()
+        });
+        self.ddf = ddf;
+        self.derivative = format!("{} * diff({}, z) + {} * diff({}, z)", 1.0 - t, formula_a, t, formula_b);
 
         Ok(())
     }
 
+    /// # 根を直接指定した多項式 `Π(z - r_k)` を使用する
+    ///
+    /// 数式を経由せず、`p ← p(z - r)`, `p' ← p'(z - r) + p`, `p'' ← p''(z - r) + 2p'` の漸化式で、
+    /// 関数値と微分値を同時に求める。2階導関数はコンパイルが不要なので、常に設定する。
+    /// 重根ちょうどの点では p = p' = 0 となるため、p = 0 の場合は導関数を1として Newton法の移動量を0にする
+    fn from_roots(roots: Vec<Complex<T>>) -> Self {
+        let derivative = format!("diff(Π(z - r_k), z) (k = 1..{})", roots.len());
+        let eval = Arc::new(move |z: &Complex<T>| {
+            let mut p = Complex::from(T::one());
+            let mut dp = Complex::zero();
+            let mut ddp = Complex::zero();
+            for r in roots.iter() {
+                let factor = z.clone() - r.clone();
+                ddp = ddp * factor.clone() + dp.clone() * T::from_f64(2.0);
+                dp = dp * factor.clone() + p.clone();
+                p *= factor;
+            }
+            (p, dp, ddp)
+        });
+
+        Self {
+            f: Arc::new({
+                let eval = eval.clone();
+                move |[z]: [Complex<T>; ARITY]| eval(&z).0
+            }),
+            df: Arc::new({
+                let eval = eval.clone();
+                move |[z]: [Complex<T>; ARITY]| {
+                    let (p, dp, _) = eval(&z);
+                    if p.is_zero() { Complex::from(T::one()) } else { dp }
+                }
+            }),
+            ddf: Some(Arc::new(move |[z]: [Complex<T>; ARITY]| eval(&z).2)),
+            second_derivative: true,
+            derivative,
+            params: Vec::new(),
+            functions: Vec::new(),
+        }
+    }
+
     fn func(&self) -> &Func<T, ARITY> {
         &self.f
     }
@@ -121,6 +398,46 @@ where
     fn deriv(&self) -> &Func<T, ARITY> {
         &self.df
     }
+
+    fn deriv2(&self) -> Option<&Func<T, ARITY>> {
+        self.ddf.as_ref()
+    }
+
+    fn derivative(&self) -> &str {
+        &self.derivative
+    }
+
+    /// # コンパイル済みの関数と導関数を共有する
+    ///
+    /// Arcを複製するだけなので、再コンパイルせずに複数の描画で同じ関数を使用できる
+    fn shared_funcs(&self) -> (Func<T, ARITY>, Func<T, ARITY>) {
+        (Arc::clone(self.func()), Arc::clone(self.deriv()))
+    }
+
+    /// # 数式がNewton法に適さないかを、複数の標本点で調べる
+    ///
+    /// 定数関数や微分が恒等的に0となる関数では、Newton法が進まず一様な画像となってしまう
+    ///
+    /// ## Returns
+    ///  - Newton法に適さない場合はその理由
+    fn ill_conditioned_warning(&self) -> Option<&'static str> {
+        const SAMPLES: [(f64, f64); 5] = [
+            (0.5, 0.3), (-1.2, 0.7), (1.7, -1.1), (-0.4, -1.9), (2.3, 2.1),
+        ];
+
+        let points: Vec<Complex<T>> = SAMPLES.iter()
+            .map(|&(re, im)| Complex::new(T::from_f64(re), T::from_f64(im)))
+            .collect();
+
+        let values: Vec<Complex<T>> = points.iter().map(|z| (self.f)([z.clone()])).collect();
+        if values.iter().all(|v| *v == values[0]) {
+            return Some("function is constant");
+        }
+        if points.iter().all(|z| (self.df)([z.clone()]).is_zero()) {
+            return Some("derivative is zero everywhere");
+        }
+        None
+    }
 }
 
 impl<T: Real> Default for FormulacInner<T>
@@ -148,13 +465,71 @@ impl Default for Formulac {
     }
 }
 
+impl Formulac {
+    /// 数式をコンパイルしていないFormulacを作成する
+    fn uncompiled() -> Self {
+        Self::F64(FormulacInner::new())
+    }
+
+    fn derivative(&self) -> &str {
+        match self {
+            Self::F64(f) => f.derivative(),
+            Self::F106(f) => f.derivative(),
+        }
+    }
+}
+
+/// 2つの数式を補間した数式
+///
+/// 補間前の1つ目の数式は、FractalInner の formula に保持する
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct MorphFormula {
+    formula_b:  String,
+    /// 補間の比 (0の場合は1つ目、1の場合は2つ目の数式となる)
+    t:          f64,
+}
+
+/// 条件式によって切り替える数式
+///
+/// 条件式が真の場合の数式は、FractalInner の formula に保持する
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct ConditionalFormula {
+    condition:      String,
+    formula_false:  String,
+}
+
+/// 分岐切断などによる不連続な跳躍の扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum BranchHandling {
+    /// 検出せずに反復を続ける
+    #[default]
+    Ignore,
+    /// |z| が急に跳んだ点を、収束とは区別して BRANCH_JUMP とする
+    Detect,
+}
+
+/// 反復回数の上限を、注目点から離れるほど減らす設定
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct IterationFalloff {
+    /// 注目点 (re, im)
+    focus: (f64, f64),
+    /// 減衰率。0の場合は全ての点で max_iter とする
+    rate:  f64,
+}
+
 /// 複素数平面の情報を保持する構造体
 #[derive(Debug, Clone, Serialize, Deserialize)]
 struct Canvas<T: Real>
 {
     center: num_complex::Complex<T>,
     zoom_level:  i32,
+    /// 複素数平面の幅 width() に対応するピクセル数 (表示領域の長い方の辺)
     size: u16,
+    /// 表示領域の幅と高さ [pixel]。Noneの場合は size * size の正方形
+    #[serde(default)]
+    viewport: Option<(u16, u16)>,
 }
 
 impl<T: Real> Canvas<T>
@@ -167,16 +542,52 @@ where
         self.center.im = im;
     }
 
-    fn set_size(&mut self, size: u16) {
-        self.size = size;
+    /// # 表示領域の幅と高さを設定する
+    ///
+    /// width() は長い方の辺に対応させるので、ピクセルは正方形のまま、短い方の辺に表示される範囲が狭くなる
+    fn set_size(&mut self, width: u16, height: u16) {
+        self.size = width.max(height);
+        self.viewport = (width != height).then_some((width, height));
+    }
+
+    /// 表示領域の幅と高さ [pixel]
+    fn viewport(&self) -> (u16, u16) {
+        self.viewport.unwrap_or((self.size, self.size))
+    }
+
+    /// # 表示領域の幅と高さ [pixel] を、計算情報の作成に使用する u32 で返す
+    fn viewport_size(&self) -> (u32, u32) {
+        let (w, h) = self.viewport();
+        (w as u32, h as u32)
+    }
+
+    /// # 表示領域の縦横比を保ったまま、長い方の辺を max_side [pixel] 以下に縮小した幅と高さを返す
+    ///
+    /// 表示領域全体を間引いて集計する場合に、view_calc_info に渡す解像度として使用する
+    fn viewport_within(&self, max_side: u32) -> (u32, u32) {
+        let (w, h) = self.viewport_size();
+        let longer = w.max(h);
+        if longer <= max_side {
+            return (w, h);
+        }
+        let scale = |len: u32| ((len as u64 * max_side as u64 / longer as u64) as u32).max(1);
+        (scale(w), scale(h))
+    }
+
+    /// # 1辺 size の正方形の中での、表示領域の左上の位置を返す
+    ///
+    /// 表示領域は正方形の中央に置く
+    fn viewport_offset(&self) -> (u32, u32) {
+        let (w, h) = self.viewport();
+        (self.size.saturating_sub(w) as u32 / 2, self.size.saturating_sub(h) as u32 / 2)
     }
 
     /// # ズーム後にマウス位置が動かないようにズームする
     ///
     /// ## Params
     ///  - level: ズーム段階
-    ///  - mouse_x_ratio: マウスのx座標 [0.0, 1.0]
-    ///  - mouse_y_ratio: マウスのy座標 [0.0, 1.0]
+    ///  - mouse_x_ratio: 表示領域の幅に対するマウスのx座標 [0.0, 1.0]
+    ///  - mouse_y_ratio: 表示領域の高さに対するマウスのy座標 [0.0, 1.0]
     fn zoom_around_point(&mut self, level: i32, mouse_x_ratio: f64, mouse_y_ratio: f64) {
         let old_width = self.width();
 
@@ -185,10 +596,13 @@ where
 
         let d_width = old_width - new_width;
 
-        // 座標中心は0.5なので、マウスの座標の偏差によって補正する
+        // 座標中心は0.5なので、マウスの座標の偏差によって補正する。
+        // 表示領域の辺の長さは、width() に対応する size との比で換算する
+        let (w, h) = self.viewport();
+        let size = self.size.max(1) as f64;
         let delta = Complex::new(
-            d_width.clone() * T::from_f64(mouse_x_ratio - 0.5),
-            d_width * T::from_f64(0.5 - mouse_y_ratio)
+            d_width.clone() * T::from_f64((mouse_x_ratio - 0.5) * w as f64 / size),
+            d_width * T::from_f64((0.5 - mouse_y_ratio) * h as f64 / size)
         );
 
         self.center += delta;
@@ -220,6 +634,20 @@ where
     fn scale(&self) -> T {
         T::from_f64(2.0f64.powf(self.zoom_level as f64 * Self::zoom_step()))
     }
+
+    /// # 拡大率が scale の場合の表示領域の幅を返す
+    ///
+    /// width と異なり zoom_level の段階に量子化しないので、 scale = self.scale() の場合に width と一致する
+    fn width_at_scale(scale: f64) -> T {
+        T::from_f64(2.0 / scale)
+    }
+
+    /// # 隣接するピクセルの間隔の log2 を返す
+    ///
+    /// 深く拡大すると間隔が f64 で表せなくなるので、zoom_level から直接計算する
+    fn log2_pixel_step(&self) -> f64 {
+        -self.zoom_level as f64 * Self::zoom_step() + 1.0 - (self.size.max(1) as f64).log2()
+    }
 }
 
 impl<T: Real> Default for Canvas<T>
@@ -229,10 +657,18 @@ impl<T: Real> Default for Canvas<T>
             center: num_complex::Complex::<T>::new(T::zero(), T::zero()),
             zoom_level: default::CANVAS_ZOOM_LEVEL,
             size: default::CANVAS_SIZE,
+            viewport: None,
         }
     }
 }
 
+/// 数式の根の一覧と、各根の重複度
+#[derive(Debug, Clone)]
+struct CachedRoots<T> {
+    roots: Vec<Complex<T>>,
+    /// rootsと同じ順番の重複度
+    multiplicities: Vec<u32>,
+}
 
 /// フラクタル計算に使用する情報を保持する構造体
 #[derive(Clone, Serialize, Deserialize)]
@@ -244,8 +680,49 @@ where
     #[serde(skip)] // 数式文字列の情報のみで良いため、Formulacはserializeしない
     formulac:   Formulac,
     formula:    String,
+    #[serde(default)] // 条件式の無い数式として保存されたデータも読み込めるように
+    conditional: Option<ConditionalFormula>,
+    #[serde(default)]
+    morph:      Option<MorphFormula>,
+    /// 数式の自由パラメータ
+    #[serde(default = "default::params")]
+    params:     FormulaParams,
+    /// 利用者定義関数 (登録順)
+    #[serde(default)]
+    functions:  Vec<UserFunction>,
     canvas:     Canvas<T>,
     max_iter:   u16,
+    #[serde(skip, default = "default::tile_size")] // 描画性能の設定なので、フラクタルのパラメータとしては保存しない
+    tile_size:  u32,
+    #[serde(default)]
+    branch_handling: BranchHandling,
+    #[serde(skip, default = "default::fill_passes")] // tile_size と同様に描画性能の設定
+    fill_passes: u32,
+    #[serde(skip)] // fill_passes と同様に描画性能の設定
+    boundary_halo: bool,
+    #[serde(default)]
+    criterion:  btm::Criterion,
+    /// 収束判定の許容誤差
+    #[serde(default = "default::epsilon")]
+    epsilon:    f64,
+    #[serde(default)]
+    relaxation_tau: f64,
+    /// Newton法の係数 a (z - a f(z) / f'(z))
+    #[serde(default = "default::coeff")]
+    coeff:      Complex<f64>,
+    #[serde(default)]
+    iteration_falloff: Option<IterationFalloff>,
+    #[serde(default)]
+    projection: btm::Projection,
+    #[serde(default)]
+    method:     btm::IterationMethod,
+    /// 数式の根の一覧。最初に必要になった時に計算し、数式を変更すると破棄する。
+    /// snapshot_fractal で複製した場合も、同じ数式の間は共有する
+    #[serde(skip, default = "default::roots")]
+    roots:      Arc<OnceLock<CachedRoots<T>>>,
+    /// formulac がコンパイル済みか。false の場合の formulac は定数0の関数なので、計算してはならない
+    #[serde(skip)]
+    formula_loaded: bool,
 }
 
 impl<T> std::fmt::Debug for FractalInner<T>
@@ -256,8 +733,24 @@ where
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         f.debug_struct("FractalInner")
             .field("formula", &self.formula)
+            .field("conditional", &self.conditional)
+            .field("morph", &self.morph)
+            .field("params", &self.params)
+            .field("functions", &self.functions)
             .field("canvas", &self.canvas)
             .field("max_iter", &self.max_iter)
+            .field("tile_size", &self.tile_size)
+            .field("branch_handling", &self.branch_handling)
+            .field("fill_passes", &self.fill_passes)
+            .field("boundary_halo", &self.boundary_halo)
+            .field("criterion", &self.criterion)
+            .field("epsilon", &self.epsilon)
+            .field("relaxation_tau", &self.relaxation_tau)
+            .field("coeff", &self.coeff)
+            .field("iteration_falloff", &self.iteration_falloff)
+            .field("projection", &self.projection)
+            .field("method", &self.method)
+            .field("formula_loaded", &self.formula_loaded)
             .finish_non_exhaustive()
     }
 }
@@ -281,6 +774,137 @@ where
             Formulac::F106(f) => f.set_formula(formula)?,
         }
         self.formula = formula.to_string();
+        self.conditional = None;
+        self.morph = None;
+        self.invalidate_roots();
+        self.formula_loaded = true;
+        Ok(())
+    }
+
+    fn set_conditional_formula(&mut self, condition: &str, formula_true: &str, formula_false: &str)
+        -> Result<(), formulac::err::ParseError>
+    {
+        match self.formulac_mut() {
+            Formulac::F64(f) => f.set_conditional_formula(condition, formula_true, formula_false)?,
+            Formulac::F106(f) => f.set_conditional_formula(condition, formula_true, formula_false)?,
+        }
+        self.formula = formula_true.to_string();
+        self.conditional = Some(ConditionalFormula {
+            condition:      condition.to_string(),
+            formula_false:  formula_false.to_string(),
+        });
+        self.morph = None;
+        self.invalidate_roots();
+        self.formula_loaded = true;
+        Ok(())
+    }
+
+    fn set_morph_formula(&mut self, formula_a: &str, formula_b: &str, t: f64)
+        -> Result<(), formulac::err::ParseError>
+    {
+        match self.formulac_mut() {
+            Formulac::F64(f) => f.set_morph_formula(formula_a, formula_b, t)?,
+            Formulac::F106(f) => f.set_morph_formula(formula_a, formula_b, t)?,
+        }
+        self.formula = formula_a.to_string();
+        self.conditional = None;
+        self.morph = Some(MorphFormula {
+            formula_b:  formula_b.to_string(),
+            t,
+        });
+        self.invalidate_roots();
+        self.formula_loaded = true;
+        Ok(())
+    }
+
+    /// # 補間の比を変更する
+    ///
+    /// 補間した関数は比を埋め込んでコンパイルするので、2つの数式をコンパイルし直す
+    fn set_morph_t(&mut self, t: f64) -> Result<(), String> {
+        let Some(morph) = &self.morph else {
+            return Err("No morph is set. Call set_morph first.".to_string());
+        };
+        let (formula_a, formula_b) = (self.formula.clone(), morph.formula_b.clone());
+        self.set_morph_formula(&formula_a, &formula_b, t)
+            .map_err(|e| e.to_string())
+    }
+
+    /// # 自由パラメータの値を設定し、数式をコンパイルし直す
+    ///
+    /// 失敗した場合は、パラメータを設定前の値に戻す
+    fn set_param(&mut self, name: &str, value: Complex<f64>) -> Result<(), String> {
+        validate_param(name, value)?;
+        if self.functions.iter().any(|function| function.name == name) {
+            return Err(format!("\"{}\" is already used as a function.", name));
+        }
+        let previous = self.params.insert(name.to_string(), value);
+        if let Err(e) = self.recompile() {
+            match previous {
+                Some(previous) => self.params.insert(name.to_string(), previous),
+                None => self.params.remove(name),
+            };
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+
+    /// # 利用者定義関数を登録し、数式をコンパイルし直す
+    ///
+    /// 同じ名前の関数が登録済みの場合は、登録順を変えずに置き換える。
+    /// 失敗した場合は、登録前の状態に戻す
+    fn register_function(&mut self, function: UserFunction) -> Result<(), String> {
+        validate_function(&function)?;
+        if self.params.contains_key(&function.name) {
+            return Err(format!("\"{}\" is already used as a parameter.", function.name));
+        }
+
+        let previous = self.functions.clone();
+        match self.functions.iter_mut().find(|f| f.name == function.name) {
+            Some(registered) => *registered = function,
+            None => self.functions.push(function),
+        }
+        if let Err(e) = self.recompile() {
+            self.functions = previous;
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+
+    /// # 自由パラメータと利用者定義関数のみを設定した、U型のFormulacInnerを作成する
+    fn uncompiled_formulac<U>(&self) -> Result<FormulacInner<U>, formulac::err::ParseError>
+    where
+        U: Real + FromStr + Send + Sync + 'static
+            + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+    {
+        FormulacInner::new()
+            .with_params(&self.params)
+            .with_second_derivative(self.method == btm::IterationMethod::Halley)
+            .with_functions(&self.functions)
+    }
+
+    /// # 保持している数式文字列から、U型のFormulacInnerをコンパイルする
+    fn compile_formulac<U>(&self) -> Result<FormulacInner<U>, formulac::err::ParseError>
+    where
+        U: Real + FromStr + Send + Sync + 'static
+            + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+    {
+        let mut formulac = self.uncompiled_formulac()?;
+        match (&self.morph, &self.conditional) {
+            (Some(m), _) => formulac.set_morph_formula(&self.formula, &m.formula_b, m.t)?,
+            (None, Some(c)) => formulac.set_conditional_formula(&c.condition, &self.formula, &c.formula_false)?,
+            (None, None) => formulac.set_formula(&self.formula)?,
+        }
+        Ok(formulac)
+    }
+
+    /// # 保持している数式文字列から、数式をコンパイルし直す
+    fn recompile(&mut self) -> Result<(), formulac::err::ParseError> {
+        self.formulac = match self.formulac() {
+            Formulac::F64(_) => Formulac::F64(self.compile_formulac()?),
+            Formulac::F106(_) => Formulac::F106(self.compile_formulac()?),
+        };
+        self.invalidate_roots();
+        self.formula_loaded = true;
         Ok(())
     }
 
@@ -288,6 +912,66 @@ where
         &self.formula
     }
 
+    fn invalidate_roots(&mut self) {
+        self.roots = Arc::default();
+    }
+
+    /// # 数式の根の一覧を返す
+    ///
+    /// 未計算の場合は、表示領域に依らない原点周りの領域を粗い解像度で計算して見つかった根を保持する。
+    /// 各ピクセルの根の番号をこの順番で割り当てるので、タイル毎や表示領域の移動・拡大縮小で根の番号が変わらない
+    fn cached_roots(&self, fo: &FormulacInner<T>) -> &[Complex<T>] {
+        &self.roots_with_multiplicity(fo).roots
+    }
+
+    /// # cached_rootsと同じ順番で、各根の重複度を返す
+    fn root_multiplicities(&self, fo: &FormulacInner<T>) -> &[u32] {
+        &self.roots_with_multiplicity(fo).multiplicities
+    }
+
+    /// # 数式の根の一覧を計算し、重複して発見した根を統合する
+    ///
+    /// 重根の近くでは収束が遅いので、同じ根が僅かに異なる値で複数回発見され、1つの収束領域が複数の色に分かれることがある
+    fn roots_with_multiplicity(&self, fo: &FormulacInner<T>) -> &CachedRoots<T> {
+        /// 根を探す際の一辺のピクセル数
+        const SEARCH_SIZE: u32 = 64;
+        /// 根を探す、原点を中心とした正方形の一辺の長さ。原点から離れた根も見つかるように、狭い方から順に広げる
+        const SEARCH_WIDTHS: [f64; 3] = [4.0, 64.0, 1024.0];
+        /// 根を探す際の最大反復回数。表示用の最大反復回数に依らず、根を探す時間を抑える
+        const SEARCH_MAX_ITER: u16 = 256;
+
+        self.roots.get_or_init(|| {
+            let (func, deriv) = fo.shared_funcs();
+            let mut found = Vec::new();
+            for width in SEARCH_WIDTHS {
+                let mut info = btm::CalcInfo::new(
+                    0, 0, SEARCH_SIZE, SEARCH_SIZE,
+                    SEARCH_MAX_ITER,
+                    T::from_f64(SEARCH_SIZE as f64),
+                    Complex::new(T::zero(), T::zero()),
+                    T::from_f64(width),
+                    func.clone(),
+                    deriv.clone(),
+                    Complex::from(T::one()),
+                );
+                // 数式を変更した場合にのみ破棄するので、最大反復回数と同様に反復の設定 (収束判定など) も既定値で探す
+                info.escape = btm::EscapeOptions::default();
+                // 見つけた根を先頭に渡して、狭い領域で見つけた根の順番を保つ
+                info.known_roots = found;
+                found = btm::calc_rect(info).roots;
+            }
+            let (roots, multiplicities) = btm::dedup_roots(fo.func(), fo.deriv(), &found);
+            CachedRoots { roots, multiplicities }
+        })
+    }
+
+    fn formula_warning(&self) -> Option<&'static str> {
+        match self.formulac() {
+            Formulac::F64(f) => f.ill_conditioned_warning(),
+            Formulac::F106(f) => f.ill_conditioned_warning(),
+        }
+    }
+
     fn canvas(&self) -> &Canvas<T> {
         &self.canvas
     }
@@ -303,23 +987,208 @@ where
     fn max_iter(&self) -> u16 {
         self.max_iter
     }
+
+    fn set_tile_size(&mut self, tile_size: u32) {
+        self.tile_size = tile_size;
+    }
+
+    fn tile_size(&self) -> u32 {
+        self.tile_size
+    }
+
+    fn set_fill_passes(&mut self, passes: u32) {
+        self.fill_passes = passes;
+    }
+
+    fn fill_passes(&self) -> u32 {
+        self.fill_passes
+    }
+
+    fn set_boundary_halo(&mut self, enabled: bool) {
+        self.boundary_halo = enabled;
+    }
+
+    fn boundary_halo(&self) -> bool {
+        self.boundary_halo
+    }
+
+    fn set_criterion(&mut self, criterion: btm::Criterion) {
+        self.criterion = criterion;
+    }
+
+    fn set_epsilon(&mut self, epsilon: f64) {
+        self.epsilon = epsilon;
+    }
+
+    fn set_relaxation_tau(&mut self, tau: f64) {
+        self.relaxation_tau = tau;
+    }
+
+    fn set_coeff(&mut self, coeff: Complex<f64>) {
+        self.coeff = coeff;
+    }
+
+    /// # CalcInfo に設定する、T型に変換したNewton法の係数を返す
+    fn coeff(&self) -> Complex<T> {
+        Complex::new(T::from_f64(self.coeff.re), T::from_f64(self.coeff.im))
+    }
+
+    fn set_iteration_falloff(&mut self, falloff: Option<IterationFalloff>) {
+        self.iteration_falloff = falloff;
+    }
+
+    fn set_projection(&mut self, projection: btm::Projection) {
+        self.projection = projection;
+    }
+
+    /// # 反復の方法を設定し、必要な導関数をコンパイルし直す
+    ///
+    /// 2階導関数をコンパイルできない数式の場合は、設定前の方法に戻す
+    fn set_method(&mut self, method: btm::IterationMethod) -> Result<(), String> {
+        let previous = std::mem::replace(&mut self.method, method);
+        if let Err(e) = self.recompile() {
+            self.method = previous;
+            return Err(e.to_string());
+        }
+        Ok(())
+    }
+
+    /// # next の数式に関わる状態 (数式・パラメータ・関数・反復の方法と、そのコンパイル結果) で置き換える
+    ///
+    /// 表示領域や計算の設定は変更しない
+    fn adopt_formula(&mut self, next: Self) {
+        self.formulac = next.formulac;
+        self.formula = next.formula;
+        self.conditional = next.conditional;
+        self.morph = next.morph;
+        self.params = next.params;
+        self.functions = next.functions;
+        self.method = next.method;
+        self.roots = next.roots;
+        self.formula_loaded = next.formula_loaded;
+    }
+
+    /// # CalcInfo に設定する、反復の方法に応じた2階導関数を返す
+    fn iteration_deriv2(&self, fo: &FormulacInner<T>) -> Option<Func<T, ARITY>> {
+        match self.method {
+            btm::IterationMethod::Newton => None,
+            btm::IterationMethod::Halley => fo.deriv2().cloned(),
+        }
+    }
+
+    fn set_branch_handling(&mut self, mode: BranchHandling) {
+        self.branch_handling = mode;
+    }
+
+    /// # CalcInfo に設定する反復の設定を返す
+    fn escape_options(&self) -> btm::EscapeOptions<T> {
+        let branch_jump = match self.branch_handling {
+            BranchHandling::Ignore => None,
+            BranchHandling::Detect => Some(T::from_f64(default::BRANCH_JUMP_RATIO)),
+        };
+        btm::EscapeOptions {
+            branch_jump,
+            criterion: self.criterion,
+            epsilon: self.epsilon,
+            relaxation_tau: self.relaxation_tau,
+            falloff: self.iteration_falloff.map(|falloff| btm::IterationFalloff {
+                focus: Complex::new(T::from_f64(falloff.focus.0), T::from_f64(falloff.focus.1)),
+                rate:  falloff.rate,
+            }),
+            deriv2: None, // 数式に依存するので、CalcInfo の作成時に iteration_deriv2 で設定する
+        }
+    }
+
+    /// # 浮動小数点の型を変更したFractalInnerを作成する
+    ///
+    /// ## Params
+    ///  - formulac: 変更後の型でコンパイル済みのFormulac
+    ///  - center: 変更後の型に変換した中心座標
+    fn convert<U>(&self, formulac: Formulac, center: Complex<U>) -> FractalInner<U>
+    where
+        U: Real + FromStr + Send + Sync + 'static
+            + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+    {
+        let canvas = Canvas {
+            center,
+            zoom_level: self.canvas().zoom_level,
+            size: self.canvas().size(),
+            viewport: self.canvas().viewport,
+        };
+
+        FractalInner {
+            formulac,
+            formula:    self.formula.clone(),
+            conditional: self.conditional.clone(),
+            morph:      self.morph.clone(),
+            params:     self.params.clone(),
+            functions:  self.functions.clone(),
+            canvas,
+            max_iter:   self.max_iter(),
+            tile_size:  self.tile_size(),
+            branch_handling: self.branch_handling,
+            fill_passes: self.fill_passes(),
+            boundary_halo: self.boundary_halo(),
+            criterion:  self.criterion,
+            epsilon:    self.epsilon,
+            relaxation_tau: self.relaxation_tau,
+            coeff:      self.coeff,
+            iteration_falloff: self.iteration_falloff,
+            projection: self.projection,
+            method:     self.method,
+            roots:      Arc::default(),
+            formula_loaded: true, // formulac はコンパイル済み
+        }
+    }
 }
 
-impl<T> Default for FractalInner<T>
+impl<T> FractalInner<T>
 where
     T: Real + FromStr + Send + Sync + 'static
         + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
 {
-    fn default() -> Self {
+    /// # 数式をコンパイルせずにFractalInnerを作成する
+    ///
+    /// 数式のコンパイルは set_formula で行うこと
+    fn uncompiled() -> Self {
         Self {
-            formulac:   Formulac::default(),
+            formulac:   Formulac::uncompiled(),
             formula:    default::FORMULA.to_string(),
+            conditional: None,
+            morph:      None,
+            params:     default::params(),
+            functions:  Vec::new(),
             canvas:     Canvas::default(),
             max_iter:   default::FRACTAL_MAX_ITER,
+            tile_size:  default::TILE_SIZE,
+            branch_handling: BranchHandling::default(),
+            fill_passes: default::fill_passes(),
+            boundary_halo: false,
+            criterion:  btm::Criterion::default(),
+            epsilon:    default::epsilon(),
+            relaxation_tau: 0.0,
+            coeff:      default::coeff(),
+            iteration_falloff: None,
+            projection: btm::Projection::default(),
+            method:     btm::IterationMethod::default(),
+            roots:      Arc::default(),
+            formula_loaded: false,
         }
     }
 }
 
+impl<T> Default for FractalInner<T>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    fn default() -> Self {
+        let mut default = Self::uncompiled();
+        let _ = default.set_formula(default::FORMULA); // 失敗しないので
+        default
+    }
+}
+
 #[derive(Debug, Clone)]
 enum Fractal {
     F64(FractalInner<f64>),
@@ -333,6 +1202,39 @@ impl Default for Fractal {
 }
 
 impl Fractal {
+    /// # 数式の補間の比を変更した複製を作成する (アニメーションのフレーム用)
+    fn with_morph_t(&self, t: f64) -> Result<Self, String> {
+        let mut fractal = self.clone();
+        match &mut fractal {
+            Self::F64(f) => f.set_morph_t(t)?,
+            Self::F106(f) => f.set_morph_t(t)?,
+        }
+        Ok(fractal)
+    }
+
+    /// # 数式がコンパイル済みかを確認する
+    ///
+    /// 起動直後などで未コンパイルの場合は、定数0の関数で計算した無意味な結果を返さないようにエラーとする
+    fn ensure_formula_loaded(&self) -> Result<(), String> {
+        let loaded = match self {
+            Self::F64(f) => f.formula_loaded,
+            Self::F106(f) => f.formula_loaded,
+        };
+        if loaded {
+            Ok(())
+        } else {
+            Err("Formula is not loaded yet.".to_string())
+        }
+    }
+
+    /// 浮動小数点の仮数部のビット数
+    const fn mantissa_bits(&self) -> i32 {
+        match self {
+            Self::F64(_) => f64::MANTISSA_DIGITS as i32,
+            Self::F106(_) => 106,
+        }
+    }
+
     const fn to_index(&self) -> usize {
         match self {
             Self::F64(_) => 0,
@@ -359,20 +1261,13 @@ impl Fractal {
             Self::F64(_) => (), // 最低値
             // Self::F128(f) => {
             Self::F106(f) => {
-                let max_iter = f.max_iter();
-
                 let center = f.canvas().center();
                 let center = Complex::new(center.re.to_f64(), center.im.to_f64());
-                let zoom_level = f.canvas().zoom_level;
-                let size = f.canvas().size();
-                let canvas = Canvas { center, zoom_level, size };
 
-                let formula = f.formula.clone();
-                let mut formulac = FormulacInner::new();
-                formulac.set_formula(&formula).unwrap(); // 一回作成に成功しているので、失敗しない
+                let formulac = f.compile_formulac().unwrap(); // 一回作成に成功しているので、失敗しない
                 let formulac = Formulac::F64(formulac);
 
-                *self = Self::F64(FractalInner { formulac, formula, canvas, max_iter });
+                *self = Self::F64(f.convert(formulac, center));
             }
         }
     }
@@ -381,36 +1276,352 @@ impl Fractal {
     fn up(&mut self) {
         match self {
             Self::F64(f) => {
-                let max_iter = f.max_iter();
-
                 let center = f.canvas().center();
                 let center = Complex::new(F106::from_f64(center.re), F106::from_f64(center.im));
-                let zoom_level = f.canvas().zoom_level;
-                let size = f.canvas().size();
-                let canvas = Canvas { center, zoom_level, size };
 
-                let formula = f.formula.clone();
-                let mut formulac = FormulacInner::new();
-                formulac.set_formula(&formula).unwrap(); // 一回作成に成功しているので、失敗しない
+                let formulac = f.compile_formulac().unwrap(); // 一回作成に成功しているので、失敗しない
                 let formulac = Formulac::F106(formulac);
 
-                *self = Self::F106(FractalInner { formulac, formula, canvas, max_iter })
+                *self = Self::F106(f.convert(formulac, center))
             },
             Self::F106(_) => (), // 最大値
         }
     }
+
+    /// # ズーム段階に合わせて浮動小数点のビット幅を切り替える
+    fn fit_precision(&mut self) {
+        let std::ops::Range { start: zoom_min, end: zoom_max } = self.zoom_threshold();
+        let zoom_level = match self {
+            Self::F64(f) => f.canvas().zoom_level,
+            Self::F106(f) => f.canvas().zoom_level,
+        }.abs() as i64;
+
+        match self {
+            Self::F64(_) if zoom_level > zoom_max => self.up(),
+            Self::F106(_) if zoom_level > zoom_max => (), // TODO: 更に高精度な型の実装
+            Self::F106(_) if zoom_level < zoom_min => self.down(),
+            _ => (),
+        }
+    }
+
+    /// # next の数式に関わる状態だけを反映する
+    ///
+    /// ## Returns
+    ///  - 浮動小数点の型が異なり、反映しなかった場合はfalse
+    fn adopt_formula(&mut self, next: Fractal) -> bool {
+        match (self, next) {
+            (Self::F64(f), Self::F64(next)) => f.adopt_formula(next),
+            (Self::F106(f), Self::F106(next)) => f.adopt_formula(next),
+            _ => return false,
+        }
+        true
+    }
+
+    /// # undo_view・redo_view で戻せるように、現在の中心座標と拡大段階を返す
+    fn saved_view(&self) -> SavedView {
+        match self {
+            Self::F64(f) => {
+                let center = f.canvas().center();
+                SavedView {
+                    center:     Complex::new(F106::from_f64(center.re), F106::from_f64(center.im)),
+                    zoom_level: f.canvas().zoom_level,
+                }
+            },
+            Self::F106(f) => SavedView {
+                center:     *f.canvas().center(),
+                zoom_level: f.canvas().zoom_level,
+            },
+        }
+    }
+
+    /// # 中心座標と拡大段階だけを saved_view の状態に戻す
+    ///
+    /// 数式や計算の設定は変更しない。拡大段階に合わせて浮動小数点の型を切り替えてから、中心座標を設定する
+    fn apply_view(&mut self, view: SavedView) {
+        match self {
+            Self::F64(f) => f.canvas_mut().zoom_level = view.zoom_level,
+            Self::F106(f) => f.canvas_mut().zoom_level = view.zoom_level,
+        }
+        self.fit_precision();
+        match self {
+            Self::F64(f) => f.canvas_mut().set_center(view.center.re.to_f64(), view.center.im.to_f64()),
+            Self::F106(f) => f.canvas_mut().set_center(view.center.re, view.center.im),
+        }
+    }
+}
+
+/// undo_view・redo_view で戻す表示領域
+///
+/// 精度の型を切り替えても中心座標を失わないように、F106 で保持する
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct SavedView {
+    center:     Complex<F106>,
+    zoom_level: i32,
+}
+
+/// # Fractalの型に応じたFractalInnerとFormulacInnerを束縛して、処理を実行する
+///
+/// `with_fractal!(&*fractal, |fr, fo| render_tile_inner(fr, fo, ...))` のように使用する
+macro_rules! with_fractal {
+    ($fractal:expr, |$fr:ident, $fo:ident| $body:expr) => {
+        match $fractal {
+            Fractal::F64($fr) => {
+                let Formulac::F64($fo) = $fr.formulac() else { unreachable!() };
+                $body
+            },
+            Fractal::F106($fr) => {
+                let Formulac::F106($fo) = $fr.formulac() else { unreachable!() };
+                $body
+            },
+        }
+    };
 }
 
+/// 起動時の数式のコンパイルは warm_up で行うので、ここではコンパイルしない
 static FRACTAL: Lazy<Mutex<Fractal>> = Lazy::new(|| {
-    Mutex::new(Fractal::default())
+    Mutex::new(Fractal::F64(FractalInner::uncompiled()))
+});
+
+/// # 現在のFractalを複製して返す
+///
+/// コンパイル済みの関数はArcで共有するので再コンパイルは発生せず、FRACTALのlockも複製の間だけ保持する。
+/// 計算中に表示領域の変更などを受け付けたい描画で使用する
+fn snapshot_fractal() -> Fractal {
+    FRACTAL.lock().unwrap().clone()
+}
+
+/// # 計算を行うコマンドの前処理として、数式のコンパイルを待ってから現在のFractalを複製する
+///
+/// 計算中はFRACTALのlockを保持しないので、計算中も表示領域や数式の変更を受け付けられる
+fn prepare_render() -> Result<Fractal, String> {
+    wait_formula_ready();
+    let fractal = snapshot_fractal();
+    fractal.ensure_formula_loaded()?;
+    Ok(fractal)
+}
+
+/// サーバー側で画像を色付けする際の設定
+static COLORING: Lazy<Mutex<Coloring>> = Lazy::new(|| {
+    Mutex::new(Coloring::default())
+});
+
+/// 描画に使用するスレッドプール
+///
+/// Noneの場合は、rayonのグローバルプール (全てのコア) を使用する
+static RENDER_POOL: Lazy<Mutex<Option<Arc<rayon::ThreadPool>>>> = Lazy::new(|| {
+    Mutex::new(None)
+});
+
+/// iteration_range の計算結果
+struct IterationRangeCache {
+    /// 計算時の描画の世代番号。表示領域などが変わると世代番号が変わるので、再計算する
+    generation: u64,
+    samples:    u32,
+    range:      (u16, u16),
+}
+
+static ITERATION_RANGE_CACHE: Lazy<Mutex<Option<IterationRangeCache>>> = Lazy::new(|| {
+    Mutex::new(None)
+});
+
+/// 数式のコンパイルが完了したかどうか
+static FORMULA_READY: Lazy<(Mutex<bool>, Condvar)> = Lazy::new(|| {
+    (Mutex::new(false), Condvar::new())
 });
 
+/// 数式のコンパイルが完了したことを通知する
+fn notify_formula_ready() {
+    let (ready, cvar) = &*FORMULA_READY;
+    *ready.lock().unwrap() = true;
+    cvar.notify_all();
+}
+
+/// # 数式のコンパイルが完了するまで待つ
+///
+/// 起動直後にwarm_upが完了する前に描画すると、定数0の関数で計算してしまうので、
+/// 描画処理の前に呼び出すこと
+fn wait_formula_ready() {
+    let (ready, cvar) = &*FORMULA_READY;
+    let _ready = cvar.wait_while(ready.lock().unwrap(), |ready| !*ready).unwrap();
+}
+
+/// # 起動時に、初期値の数式をバックグラウンドでコンパイルする
+///
+/// コンパイルが完了したら "formula-ready" イベントを発行する
+pub fn warm_up(app: tauri::AppHandle) {
+    tauri::async_runtime::spawn(async move {
+        let result = tauri::async_runtime::spawn_blocking(|| {
+            let mut fractal = FRACTAL.lock().unwrap();
+            if is_formula_ready() {
+                return; // initializeやset_formulaで既にコンパイル済み
+            }
+            FORMULA_VERSION.fetch_add(1, Ordering::SeqCst);
+            match &mut *fractal {
+                Fractal::F64(f) => f.set_formula(default::FORMULA),
+                Fractal::F106(f) => f.set_formula(default::FORMULA),
+            }.unwrap(); // 初期値の数式なので、失敗しない
+            notify_formula_ready();
+        }).await;
+
+        match result {
+            Ok(_) => if let Err(e) = app.emit("formula-ready", ()) {
+                log::error!("Failed to emit formula-ready: {}", e);
+            },
+            Err(e) => log::error!("Failed to compile the default formula: {}", e),
+        }
+    });
+}
+
+/// 描画の世代番号
+///
+/// 描画中の処理は開始時の世代番号を保持し、世代番号が変わった場合は中断する
+static RENDER_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+fn render_generation() -> u64 {
+    RENDER_GENERATION.load(Ordering::SeqCst)
+}
+
+fn is_render_cancelled(generation: u64) -> bool {
+    render_generation() != generation
+}
+
+/// # 描画中の処理を中断する
+///
+/// 表示領域や数式が変更されると描画中の結果は不要になるので、それらの変更時にも呼び出す
+#[tauri::command]
+pub fn cancel_render() {
+    RENDER_GENERATION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// 数式の置き換えの世代番号
+///
+/// set_formula などの数式全体を置き換える呼び出し毎に進め、コンパイル中に新しい数式が設定された場合は古い数式の結果を破棄する
+static FORMULA_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// 数式の編集の世代番号
+///
+/// set_param などの現在の数式を編集する呼び出し毎に進める。編集は数式の置き換えを破棄せず、置き換え後の数式に反映する
+static FORMULA_EDIT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+/// FRACTAL の数式を変更した回数
+///
+/// FRACTAL をlockしている間に進める。複製してコンパイルしている間に他の変更が反映されたかを判定するために使用する
+static FORMULA_VERSION: AtomicU64 = AtomicU64::new(0);
+
+/// 新しい数式の変更によって破棄された場合に、set_formula などが返す値
+pub const FORMULA_SUPERSEDED: &str = "SUPERSEDED";
+
+/// # 実行中の数式の置き換えと編集を全て破棄する
+///
+/// FRACTAL 全体を置き換える場合に、FRACTAL をlockしたまま呼び出す
+fn supersede_formula_changes() {
+    btm::CancelToken::advance(&FORMULA_GENERATION);
+    btm::CancelToken::advance(&FORMULA_EDIT_GENERATION);
+    FORMULA_VERSION.fetch_add(1, Ordering::SeqCst);
+}
+
+/// # 数式を変更する。後から呼び出された変更がある場合は、結果を破棄する
+///
+/// 他のコマンドを待たせないように、複製した Fractal で数式をコンパイルし、反映する時だけ lock する。
+/// コンパイル中に他の変更が反映された場合は、反映後の状態に対して change を呼び出し直す
+///
+/// ## Params
+///  - fractal: 変更する Fractal
+///  - version: fractal の数式を変更した回数 (FORMULA_VERSION)
+///  - generation: 変更の呼び出し時に FORMULA_GENERATION (置き換え) または FORMULA_EDIT_GENERATION (編集) を進めて作成した中断フラグ
+///  - change: 数式を変更し、set_formula の戻り値を返す関数
+///
+/// ## Returns
+///  - 変更を破棄した場合は FORMULA_SUPERSEDED、それ以外は change の戻り値
+fn change_formula<F>(fractal: &Mutex<Fractal>, version: &AtomicU64, generation: btm::CancelToken, mut change: F) -> Result<String, String>
+where
+    F: FnMut(&mut Fractal) -> Result<String, String>,
+{
+    loop {
+        // 待っている間に新しい変更が呼び出された場合は、コンパイルせずに新しい変更に任せる
+        if generation.is_cancelled() {
+            return Ok(FORMULA_SUPERSEDED.to_string());
+        }
+        let (mut next, base) = {
+            let fractal = fractal.lock().unwrap();
+            (fractal.clone(), version.load(Ordering::SeqCst))
+        };
+        let status = change(&mut next)?;
+
+        let mut fractal = fractal.lock().unwrap();
+        if generation.is_cancelled() {
+            return Ok(FORMULA_SUPERSEDED.to_string());
+        }
+        // 複製した後に他の変更が反映された場合や、浮動小数点の型が変わった場合は、現在の状態に対して変更し直す
+        if version.load(Ordering::SeqCst) != base || !fractal.adopt_formula(next) {
+            continue;
+        }
+        version.fetch_add(1, Ordering::SeqCst);
+        notify_formula_ready();
+        return Ok(status);
+    }
+}
+
+/// # 描画用のスレッドプールを作成する
+///
+/// ## Params
+///  - threads: スレッド数。0の場合は全てのコアを使用する (グローバルプールを使用するのでNoneを返す)
+fn build_render_pool(threads: usize) -> Result<Option<Arc<rayon::ThreadPool>>, String> {
+    if threads == 0 {
+        return Ok(None);
+    }
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .thread_name(|i| format!("render-{}", i))
+        .build()
+        .map(|pool| Some(Arc::new(pool)))
+        .map_err(|e| e.to_string())
+}
+
+fn run_in_pool<R: Send>(pool: Option<&rayon::ThreadPool>, op: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(op),
+        None => op(),
+    }
+}
+
+/// # 描画用のスレッドプール上で処理を実行する
+///
+/// op 内の rayon の並列処理は、set_render_threads で設定したスレッド数で実行される
+fn in_render_pool<R: Send>(op: impl FnOnce() -> R + Send) -> R {
+    let pool = RENDER_POOL.lock().unwrap().clone();
+    run_in_pool(pool.as_deref(), op)
+}
+
+/// # 描画に使用するスレッド数を設定する
+///
+/// 全てのコアを描画に使用するとUIの応答が悪くなる環境向けに、描画専用のスレッドプールを作成する
+///
+/// ## Params
+///  - n: スレッド数。0の場合は全てのコアを使用する
+#[tauri::command]
+pub fn set_render_threads(n: usize) -> Result<(), String> {
+    let pool = build_render_pool(n)?;
+    *RENDER_POOL.lock().unwrap() = pool;
+    Ok(())
+}
+
+/// 数式のコンパイルが完了したかどうかを返す
+#[tauri::command]
+pub fn is_formula_ready() -> bool {
+    *FORMULA_READY.0.lock().unwrap()
+}
+
 /// FRACTALの初期化関数
 #[tauri::command]
 pub fn initialize() {
     let fractal = Fractal::default();
 
-    *FRACTAL.lock().unwrap() = fractal;
+    let mut guard = FRACTAL.lock().unwrap();
+    supersede_formula_changes();
+    *guard = fractal;
+    notify_formula_ready();
 }
 
 #[tauri::command]
@@ -471,6 +1682,18 @@ where
     )
 }
 
+/// # 導関数として使用している式を返す
+///
+/// 記号微分した結果ではなく、コンパイル時に formulac へ渡した `diff(数式, z)` の形で返す。
+/// 条件式や補間を使用している場合は、それぞれの数式の diff を組み合わせた式となる
+#[tauri::command]
+pub fn get_derivative_string() -> String {
+    match &*FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.formulac().derivative().to_string(),
+        Fractal::F106(f) => f.formulac().derivative().to_string(),
+    }
+}
+
 #[tauri::command]
 pub fn get_center_str() -> String {
     match &*FRACTAL.lock().unwrap() {
@@ -487,6 +1710,206 @@ pub fn get_scale_str() -> String {
     }
 }
 
+/// # 隣接するピクセルの間に、表現可能な値がいくつあるかの log2 を返す
+///
+/// 表示領域内で絶対値が最大となる座標での ulp (最下位ビットの重み) と、ピクセルの間隔を比較する
+///
+/// ## Params
+///  - log2_step: ピクセルの間隔の log2
+///  - max_abs: 表示領域内の座標の実部・虚部の絶対値の最大値
+///  - mantissa_bits: 仮数部のビット数
+fn log2_values_per_pixel(log2_step: f64, max_abs: f64, mantissa_bits: i32) -> f64 {
+    if max_abs <= 0.0 {
+        return f64::INFINITY; // 原点付近は非正規化数まで使えるので、精度は問題にならない
+    }
+    let log2_ulp = max_abs.log2().floor() - (mantissa_bits - 1) as f64;
+    log2_step - log2_ulp
+}
+
+fn precision_warning_inner<T>(f: &FractalInner<T>, mantissa_bits: i32) -> Option<String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    /// 隣接するピクセルの間に、最低限必要な表現可能な値の数 (の log2)
+    const MIN_LOG2_VALUES: f64 = 2.0; // 4個
+
+    let canvas = f.canvas();
+    let center = canvas.center();
+    let half_width = 2.0f64.powf(canvas.log2_pixel_step()) * canvas.size() as f64 / 2.0;
+    let max_abs = center.re.to_f64().abs().max(center.im.to_f64().abs()) + half_width;
+
+    let log2_values = log2_values_per_pixel(canvas.log2_pixel_step(), max_abs, mantissa_bits);
+    (log2_values < MIN_LOG2_VALUES).then(|| format!(
+        "Precision exhausted: adjacent pixels are only {:.1} representable values apart in {} bits of mantissa. Switch to a higher precision.",
+        2.0f64.powf(log2_values), mantissa_bits,
+    ))
+}
+
+/// # 現在の拡大率で、計算精度が不足していないかを返す
+///
+/// 隣接するピクセルの座標の差が ulp の数倍以下になると、隣接ピクセルが同じ座標に丸められて画像がブロック状になる。
+/// 表示領域内の座標の最大の絶対値での ulp とピクセルの間隔を比較し、4倍未満の場合に警告する
+///
+/// ## Returns
+///  - 精度が不足している場合は警告文。十分な場合はNone
+#[tauri::command]
+pub fn precision_warning() -> Option<String> {
+    let fractal = FRACTAL.lock().unwrap();
+    let bits = fractal.mantissa_bits();
+    match &*fractal {
+        Fractal::F64(f) => precision_warning_inner(f, bits),
+        Fractal::F106(f) => precision_warning_inner(f, bits),
+    }
+}
+
+/// # 点 near から収束するまでの軌道から、収束の次数を推定する
+///
+/// 根の近傍では移動量 d_n = |z_{n+1} - z_n| は誤差 e_n に比例するので、`d_{n+1} ≈ C d_n^p` の両辺の対数を取り、
+/// 最小二乗法で求めた傾きを次数 p とする。d_n / d_{n-1}^p が一定になる p を選ぶことと同じで、根の値そのものは必要ない
+///
+/// ## Returns
+///  - 推定した次数。収束しない場合や、根の近傍での反復が2回未満の場合は NaN
+fn convergence_order_inner<T>(info: &btm::CalcInfo<T>, near: (f64, f64), mantissa_bits: i32) -> f64
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    /// 収束判定の後も反復を続ける回数。許容誤差より小さな移動量も標本にする
+    const EXTRA_STEPS: usize = 4;
+    /// 根の近傍とみなす移動量 (根の絶対値との比)
+    const NEAR_RATIO: f64 = 0.1;
+    /// 丸め誤差に埋もれたとみなす移動量 (ulp の倍数)
+    const FLOOR_ULPS: f64 = 256.0;
+
+    let near = Complex::new(T::from_f64(near.0), T::from_f64(near.1));
+    let escape = info.escape_time(near.clone());
+    let Some(root) = escape.z else {
+        return f64::NAN;
+    };
+    let norm = |z: Complex<T>| z.re.to_f64().hypot(z.im.to_f64());
+    let scale = norm(root).max(1.0);
+    let upper = NEAR_RATIO * scale;
+    let floor = FLOOR_ULPS * scale * 2.0f64.powi(-mantissa_bits);
+
+    let orbit = info.newton_orbit(near, escape.iter as usize + 1 + EXTRA_STEPS);
+    let steps: Vec<f64> = orbit.windows(2)
+        .map(|w| norm(w[1].clone() - w[0].clone()))
+        .collect();
+    let samples: Vec<(f64, f64)> = steps.windows(2)
+        .filter(|d| d.iter().all(|&d| d > floor && d < upper))
+        .map(|d| (d[0].ln(), d[1].ln()))
+        .collect();
+    if samples.len() < 2 {
+        return f64::NAN;
+    }
+
+    let n = samples.len() as f64;
+    let mean_x = samples.iter().map(|s| s.0).sum::<f64>() / n;
+    let mean_y = samples.iter().map(|s| s.1).sum::<f64>() / n;
+    let (cov, var) = samples.iter().fold((0.0, 0.0), |(cov, var), &(x, y)| {
+        (cov + (x - mean_x) * (y - mean_y), var + (x - mean_x).powi(2))
+    });
+    if var == 0.0 { f64::NAN } else { cov / var }
+}
+
+/// # 点 near から反復した場合の、収束の次数を実験的に求める
+///
+/// 現在の数式と反復の設定 (緩和係数など) で反復する。Newton法では単根で2、重根で1に近い値となる
+///
+/// ## Params
+///  - near: 反復の開始点。根の近くの点を指定する
+///
+/// ## Returns
+///  - 推定した次数。収束しない場合は NaN
+#[tauri::command]
+pub async fn convergence_order(near: (f64, f64)) -> Result<f64, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let bits = fractal.mantissa_bits();
+        Ok(with_fractal!(&fractal, |fr, fo| convergence_order_inner(&calc_info(fr, fo, 0, 0, 1, 1), near, bits)))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 描画に関する全ての設定
+///
+/// 再読み込み後などに、フロントエンドの表示を設定と一致させるために使用する
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RenderSettings {
+    formula:        String,
+    /// 条件式によって数式を切り替えている場合の、条件式と偽の場合の数式
+    conditional:    Option<ConditionalFormula>,
+    /// 2つの数式を補間している場合の、2つ目の数式と補間の比
+    morph:          Option<MorphFormula>,
+    center_str:     String,
+    scale_str:      String,
+    zoom_level:     i32,
+    size:           u16,
+    /// 表示領域の幅と高さ [pixel]
+    viewport:       (u16, u16),
+    max_iter:       u16,
+    tile_size:      u32,
+    fill_passes:    u32,
+    boundary_halo:  bool,
+    branch_handling: BranchHandling,
+    criterion:      btm::Criterion,
+    epsilon:        f64,
+    relaxation_tau: f64,
+    coeff:          Complex<f64>,
+    iteration_falloff: Option<IterationFalloff>,
+    projection:     btm::Projection,
+    method:         btm::IterationMethod,
+    coloring:       Coloring,
+}
+
+fn render_settings_inner<T>(f: &FractalInner<T>, coloring: Coloring) -> RenderSettings
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + std::fmt::LowerExp,
+{
+    RenderSettings {
+        formula:        f.formula().to_string(),
+        conditional:    f.conditional.clone(),
+        morph:          f.morph.clone(),
+        center_str:     get_center_str_inner(f.canvas().center().clone()),
+        scale_str:      format_with_decimal(f.canvas().scale()),
+        zoom_level:     f.canvas().zoom_level,
+        size:           f.canvas().size(),
+        viewport:       f.canvas().viewport(),
+        max_iter:       f.max_iter(),
+        tile_size:      f.tile_size(),
+        fill_passes:    f.fill_passes(),
+        boundary_halo:  f.boundary_halo(),
+        branch_handling: f.branch_handling,
+        criterion:      f.criterion,
+        epsilon:        f.epsilon,
+        relaxation_tau: f.relaxation_tau,
+        coeff:          f.coeff,
+        iteration_falloff: f.iteration_falloff,
+        projection:     f.projection,
+        method:         f.method,
+        coloring,
+    }
+}
+
+/// # 描画に関する全ての設定を返す
+///
+/// 個別のgetterを何度も呼び出さずに済むように、FRACTALを1回lockして全ての設定を集める
+#[tauri::command]
+pub fn get_render_settings() -> RenderSettings {
+    let coloring = COLORING.lock().unwrap().clone();
+    match &*FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => render_settings_inner(f, coloring),
+        Fractal::F106(f) => render_settings_inner(f, coloring),
+    }
+}
+
 #[tauri::command]
 pub fn get_default_size() -> i32 {
     default::CANVAS_SIZE.into()
@@ -505,309 +1928,7012 @@ pub fn get_default_max_iter() -> i32 {
     default::FRACTAL_MAX_ITER.into()
 }
 
-/// 数式をformulacに設定する
+/// コンパイル前に数式を検査する際の上限
 ///
-/// # Returns:
-/// - 成功: "OK"
-/// - エラー: "<エラーメッセージ>"
-#[tauri::command]
-pub async fn set_formula(formula: String) -> Result<(), String> {
-    let result = tauri::async_runtime::spawn_blocking(move || -> String {
-        let result = match &mut *FRACTAL.lock().unwrap() {
-            Fractal::F64(f) => f.set_formula(&formula),
-            Fractal::F106(f) => f.set_formula(&formula),
-        };
-        match result {
-            Ok(_) => "".to_string(),
-            Err(e) => e.to_string(),
-        }
-    })
-    .await;
+/// 極端な数式によってコンパイルや反復計算が終わらなくなるのを防ぐ。通常の数式では上限に達しないように余裕を持たせる
+#[derive(Debug, Clone, Copy)]
+struct FormulaLimits {
+    /// 数式の文字数
+    max_length:     usize,
+    /// 括弧の入れ子の深さ
+    max_nesting:    usize,
+    /// `^` の直後に書かれた数値の絶対値
+    max_exponent:   f64,
+}
 
-    match result {
-        Ok(ret) => if ret.is_empty() {
-            Ok(())
-        } else {
-            Err(ret)
-        },
-        Err(e) => Err(e.to_string()),
+impl Default for FormulaLimits {
+    fn default() -> Self {
+        Self {
+            max_length:     1024,
+            max_nesting:    32,
+            max_exponent:   1024.0,
+        }
     }
 }
 
-#[tauri::command]
-pub fn set_max_iter(max_iter: u16) {
-    match &mut *FRACTAL.lock().unwrap() {
-        Fractal::F64(f) => f.set_max_iter(max_iter),
-        Fractal::F106(f) => f.set_max_iter(max_iter),
+/// # 文字列の先頭の、`1.5e-3` のような指数表記を含む数値リテラルを返す
+fn numeric_literal(text: &str) -> &str {
+    let mantissa = text.find(|c: char| !(c.is_ascii_digit() || c == '.')).unwrap_or(text.len());
+    let rest = &text[mantissa..];
+    if mantissa > 0 && rest.starts_with(['e', 'E']) {
+        let sign = usize::from(rest[1..].starts_with(['+', '-']));
+        let digits = rest[1 + sign..].find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len() - 1 - sign);
+        if digits > 0 {
+            return &text[..mantissa + 1 + sign + digits];
+        }
     }
+    &text[..mantissa]
 }
 
-#[tauri::command]
-pub fn set_size(size: u16) {
-    match &mut *FRACTAL.lock().unwrap() {
-        Fractal::F64(f) => f.canvas_mut().set_size(size),
-        Fractal::F106(f) => f.canvas_mut().set_size(size),
+/// # 文字列の先頭の括弧で囲まれた部分が、識別子を含まない定数式であれば、その括弧の内側を返す
+fn constant_group(text: &str) -> Option<&str> {
+    let inner = text.strip_prefix('(')?;
+    let mut depth = 1usize;
+    for (pos, c) in inner.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    let group = &inner[..pos];
+                    // 指数表記の e を除いて、識別子 (変数・定数・関数) を含む場合は値を決められない
+                    let has_identifier = group.char_indices().any(|(i, c)| {
+                        let exponent_mark = matches!(c, 'e' | 'E') && group[..i].ends_with(|d: char| d.is_ascii_digit() || d == '.');
+                        (c.is_alphabetic() || c == '_') && !exponent_mark
+                    });
+                    return (!has_identifier).then_some(group);
+                }
+            },
+            _ => (),
+        }
     }
+    None
 }
 
-// Tの差を吸収するために別関数にする
-fn move_view_inner<T>(f: &mut FractalInner<T>, dx: f64, dy: f64)
-where
-    T: Real + FromStr + Send + Sync + 'static
-        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
-{
-    let width = f.canvas().width();
-    let center = f.canvas().center().clone();
-    let re = center.re - T::from_f64(dx) * width.clone();
-    let im = center.im + T::from_f64(dy) * width;
-    f.canvas_mut().set_center(re, im);
+/// # 定数式の指数を評価する
+///
+/// ## Returns
+///  - 指数の絶対値。コンパイルできない場合は、数式のコンパイル時にエラーとなるのでNone
+fn const_exponent(group: &str) -> Option<f64> {
+    let f = formulac::Builder::<f64, ARITY>::new(group, [VARIABLE]).compile().ok()?;
+    Some(f([Complex::new(0.0, 0.0)]).norm())
 }
 
-/// 中心座標を移動させる
-#[tauri::command]
-pub async fn move_view(dx: f64, dy: f64) {
-    let _ = tauri::async_runtime::spawn_blocking(move || {
-        match &mut *FRACTAL.lock().unwrap() {
-            Fractal::F64(f) => move_view_inner(f, dx, dy),
-            Fractal::F106(f) => move_view_inner(f, dx, dy),
-        }
-    }).await;
-}
+/// # 数式が上限を超えていないかを、コンパイル前に検査する
+///
+/// `^` の直後の数値リテラルと、識別子を含まない括弧内の定数式の値を指数として検査する
+fn validate_formula(formula: &str, limits: &FormulaLimits) -> Result<(), String> {
+    let length = formula.chars().count();
+    if length > limits.max_length {
+        return Err(format!("Formula is too long: {} characters (limit: {}).", length, limits.max_length));
+    }
 
-/// # 縮尺を変更する
-#[tauri::command]
-pub async fn zoom_view(level: i32, x: f64, y: f64) {
-    let _ = tauri::async_runtime::spawn_blocking(move || {
-        let mut fractal = FRACTAL.lock().unwrap();
-        let std::ops::Range { start: zoom_min, end: zoom_max } = fractal.zoom_threshold();
-
-        enum Precision {
-            Up, Down, AsIs,
-        }
-        let mut is_precision = Precision::AsIs;
-
-        match &mut *fractal {
-            Fractal::F64(f) => {
-                f.canvas_mut().zoom_around_point(level, x, y);
-                if f.canvas().zoom_level.abs() as i64 > zoom_max {
-                    is_precision = Precision::Up;
-                }
-            },
-            Fractal::F106(f) => {
-                f.canvas_mut().zoom_around_point(level, x, y);
-                let zoom_level = f.canvas().zoom_level.abs() as i64;
-                if zoom_level > zoom_max {
-                    // TODO: 更に高精度な型の実装
-                } else if zoom_level < zoom_min {
-                    is_precision = Precision::Down;
+    let mut depth: usize = 0;
+    for c in formula.chars() {
+        match c {
+            '(' => {
+                depth += 1;
+                if depth > limits.max_nesting {
+                    return Err(format!("Parentheses are nested too deeply (limit: {}).", limits.max_nesting));
                 }
             },
+            ')' => depth = depth.saturating_sub(1),
+            _ => (),
         }
+    }
 
-        match is_precision {
-            Precision::AsIs => (),
-            Precision::Up => fractal.up(),
-            Precision::Down => fractal.down(),
+    for (pos, _) in formula.match_indices('^') {
+        let rest = formula[pos + 1..].trim_start();
+        let (text, exponent) = match constant_group(rest) {
+            Some(group) => (group, const_exponent(group)),
+            None => {
+                // `^ (-12.5)` のような括弧や符号の付いた数値も検査する
+                let rest = rest.trim_start_matches(|c: char| c.is_whitespace() || c == '(' || c == '+' || c == '-');
+                let number = numeric_literal(rest);
+                (number, number.parse::<f64>().ok())
+            },
+        };
+        if let Some(exponent) = exponent && (!exponent.is_finite() || exponent.abs() > limits.max_exponent) {
+            return Err(format!("Exponent {} is too large (limit: {}).", text, limits.max_exponent));
         }
-    }).await;
+    }
+
+    Ok(())
 }
 
-fn render_tile_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, x: u32, y: u32, w: u32, h: u32) -> Vec<u16>
-where
-    T: Real + FromStr + Send + Sync + 'static
-        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
-{
-    btm::calc_rect(btm::CalcInfo::new(
-            x, y, w, h,
-            fr.max_iter(),
-            T::from_f64(fr.canvas().size() as f64),
-            fr.canvas().center().clone(),
-            fr.canvas().width(),
-            fo.func().clone(),
-            fo.deriv().clone(),
-            Complex::from(T::one()),
-    ))
+/// # 数式の設定結果を、利用者に伝える文字列にする
+///
+/// 不適切な数式でもエラーとはせず、描画結果が一様になる理由を伝える
+fn formula_status(warning: Option<&str>) -> String {
+    match warning {
+        Some(warning) => format!("OK: warning: {}", warning),
+        None => "OK".to_string(),
+    }
 }
 
-/// # 指定された矩形領域のデータのみを生成して返す
+/// 数式をformulacに設定する
+///
+/// コンパイル中に set_param などで編集された場合は、編集を反映した状態に対してコンパイルし直す
+///
+/// # Returns:
+/// - 成功: "OK"
+/// - 成功 (Newton法に適さない数式): "OK: warning: <理由>"
+/// - 後から呼び出された数式の置き換え (set_formula など) に置き換えられた: "SUPERSEDED" (FORMULA_SUPERSEDED)
+/// - エラー: "<エラーメッセージ>"
+#[tauri::command]
+pub async fn set_formula(formula: String) -> Result<String, String> {
+    validate_formula(&formula, &FormulaLimits::default())?;
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        change_formula(&FRACTAL, &FORMULA_VERSION, generation, |fractal| {
+            let warning = match fractal {
+                Fractal::F64(f) => f.set_formula(&formula).map(|_| f.formula_warning()),
+                Fractal::F106(f) => f.set_formula(&formula).map(|_| f.formula_warning()),
+            }.map_err(|e| e.to_string())?;
+            Ok(formula_status(warning))
+        })
+    })
+    .await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 条件式によって、反復毎に使用する数式を切り替える
+///
+/// 3つの数式を全てコンパイルし、Newton法の各反復で condition(z) の実部の符号によって数式を選択する
 ///
 /// ## Params
-///  - x: 矩形領域の左上のX座標（canvas全体に対するオフセット）
-///  - y: 矩形領域の左上のY座標（canvas全体に対するオフセット）
-///  - w: 矩形領域の幅
-///  - h: 矩形領域の高さ
+///  - condition: 条件式。実部が0以上の場合に真とする
+///  - formula_true: 条件式が真の場合の数式
+///  - formula_false: 条件式が偽の場合の数式
+///
+/// ## Returns
+///  - set_formula と同じ
 #[tauri::command]
-pub async fn render_tile(x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u16>, String> {
+pub async fn set_conditional_formula(condition: String, formula_true: String, formula_false: String) -> Result<String, String> {
+    for formula in [&condition, &formula_true, &formula_false] {
+        validate_formula(formula, &FormulaLimits::default())?;
+    }
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        change_formula(&FRACTAL, &FORMULA_VERSION, generation, |fractal| {
+            let warning = match fractal {
+                Fractal::F64(f) => f.set_conditional_formula(&condition, &formula_true, &formula_false).map(|_| f.formula_warning()),
+                Fractal::F106(f) => f.set_conditional_formula(&condition, &formula_true, &formula_false).map(|_| f.formula_warning()),
+            }.map_err(|e| e.to_string())?;
+            Ok(formula_status(warning))
+        })
+    })
+    .await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 2つの数式を補間した関数を使用する
+///
+/// `(1 - t) * f_a(z) + t * f_b(z)` とその導関数でNewton法を反復する。
+/// t を連続的に変化させると、2つの数式のフラクタルの間を連続的に変化させることができる
+///
+/// ## Params
+///  - formula_a: t = 0 の場合の数式
+///  - formula_b: t = 1 の場合の数式
+///
+/// ## Returns
+///  - set_formula と同じ
+#[tauri::command]
+pub async fn set_morph(formula_a: String, formula_b: String) -> Result<String, String> {
+    for formula in [&formula_a, &formula_b] {
+        validate_formula(formula, &FormulaLimits::default())?;
+    }
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        change_formula(&FRACTAL, &FORMULA_VERSION, generation, |fractal| {
+            let warning = match fractal {
+                Fractal::F64(f) => f.set_morph_formula(&formula_a, &formula_b, 0.0).map(|_| f.formula_warning()),
+                Fractal::F106(f) => f.set_morph_formula(&formula_a, &formula_b, 0.0).map(|_| f.formula_warning()),
+            }.map_err(|e| e.to_string())?;
+            Ok(formula_status(warning))
+        })
+    })
+    .await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # set_morph で設定した2つの数式の補間の比を設定する
+///
+/// ## Params
+///  - t: 補間の比。[0, 1] の外側の値も外挿として使用できる
+///
+/// ## Returns
+///  - set_formula と同じ
+#[tauri::command]
+pub async fn set_morph_t(t: f64) -> Result<String, String> {
+    if !t.is_finite() {
+        return Err(format!("Invalid morph ratio: {}.", t));
+    }
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_EDIT_GENERATION);
     let result = tauri::async_runtime::spawn_blocking(move || {
-        match &*FRACTAL.lock().unwrap() {
-            Fractal::F64(fr) => {
-                let Formulac::F64(fo) = fr.formulac() else { unreachable!() };
-                render_tile_inner(fr, fo, x, y, w, h)
+        change_formula(&FRACTAL, &FORMULA_VERSION, generation, |fractal| {
+            let warning = match fractal {
+                Fractal::F64(f) => f.set_morph_t(t).map(|_| f.formula_warning()),
+                Fractal::F106(f) => f.set_morph_t(t).map(|_| f.formula_warning()),
+            }?;
+            Ok(formula_status(warning))
+        })
+    })
+    .await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 利用者定義関数を展開した数式の文字数の上限
+///
+/// 関数の本体で別の関数を複数回呼び出すと、展開後の数式は指数的に長くなる
+const MAX_EXPANDED_LENGTH: usize = 1 << 16;
+
+/// # formulac と同じ規則で字句を区切り、数式の中の名前の位置を列挙する
+///
+/// 数値の指数部の `e` や虚数単位の `i` は名前としない
+fn identifiers(formula: &str) -> Vec<std::ops::Range<usize>> {
+    let mut idents = Vec::new();
+    let mut chars = formula.char_indices().peekable();
+    while let Some((start, ch)) = chars.next() {
+        match ch {
+            '0'..='9' | '.' => {
+                let mut seen_e = false;
+                while let Some(&(_, ch)) = chars.peek() {
+                    let accept = match ch {
+                        d if d.is_ascii_digit() || d == '.' => true,
+                        'e' | 'E' if !seen_e => { seen_e = true; true },
+                        '+' | '-' if seen_e => true,
+                        formulac::lexer::IMAGINARY_UNIT => { chars.next(); false },
+                        _ => false,
+                    };
+                    if !accept {
+                        break;
+                    }
+                    chars.next();
+                }
             },
-            Fractal::F106(fr) => {
-                let Formulac::F106(fo) = fr.formulac() else { unreachable!() };
-                render_tile_inner(fr, fo, x, y, w, h)
+            'a'..='z' | 'A'..='Z' | '_' => {
+                let mut end = start + ch.len_utf8();
+                while let Some(&(idx, ch)) = chars.peek() && (ch.is_alphanumeric() || ch == '_') {
+                    end = idx + ch.len_utf8();
+                    chars.next();
+                }
+                idents.push(start..end);
             },
+            _ => (),
         }
-    }).await;
+    }
+    idents
+}
 
-    match result {
-        Ok(data) => Ok(data),
-        Err(e) => Err(e.to_string())
+/// # 関数名の直後の括弧の中の引数を、区切りのカンマで分割する
+///
+/// ## Returns
+///  - 引数の一覧と、閉じ括弧の次の位置。括弧が続かない場合や閉じていない場合は None
+fn call_args(formula: &str, from: usize) -> Option<(Vec<&str>, usize)> {
+    let open = from + formula[from..].find(|c: char| !c.is_whitespace())?;
+    if !formula[open..].starts_with('(') {
+        return None;
+    }
+
+    let mut args = Vec::new();
+    let (mut depth, mut arg_start) = (0usize, open + 1);
+    for (i, c) in formula[open..].char_indices().map(|(i, c)| (open + i, c)) {
+        match c {
+            '(' => depth += 1,
+            ',' if depth == 1 => {
+                args.push(&formula[arg_start..i]);
+                arg_start = i + 1;
+            },
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    let last = &formula[arg_start..i];
+                    if !(args.is_empty() && last.trim().is_empty()) {
+                        args.push(last);
+                    }
+                    return Some((args, i + 1));
+                }
+            },
+            _ => (),
+        }
+    }
+    None
+}
+
+/// # 数式の中の利用者定義関数の呼び出しを、引数を置き換えた関数の本体で置き換える
+///
+/// formulac は利用者定義関数の呼び出しを微分する際に連鎖律を適用しないので、展開した数式をコンパイルすることで、
+/// 導関数を数式全体の記号微分として求める。
+/// 引数と本体は括弧で囲んで置き換えるので、演算子の優先順位は変わらない
+fn expand_functions(formula: &str, functions: &[UserFunction]) -> Result<String, formulac::err::ParseError> {
+    let invalid = |reason: String, span: std::ops::Range<usize>| formulac::err::ParseError::InvalidFormula {
+        reason, span: span.into(),
+    };
+    let too_long = || invalid(format!("Formula is too long after expanding functions (limit: {})", MAX_EXPANDED_LENGTH), 0..formula.len());
+
+    let mut expanded = String::with_capacity(formula.len());
+    let mut copied = 0;
+    for ident in identifiers(formula) {
+        if ident.start < copied {
+            continue; // 展開した呼び出しの引数の中の名前
+        }
+        let Some(index) = functions.iter().position(|f| f.name == formula[ident.clone()]) else {
+            continue;
+        };
+        let function = &functions[index];
+        let Some((args, end)) = call_args(formula, ident.end) else {
+            return Err(invalid(format!("{} must be called with arguments", function.name), ident));
+        };
+        if args.len() != function.params.len() {
+            return Err(invalid(
+                format!("{} takes {} arguments but {} were given", function.name, function.params.len(), args.len()),
+                ident.start..end,
+            ));
+        }
+
+        let args = args.iter()
+            .map(|arg| expand_functions(arg, functions))
+            .collect::<Result<Vec<String>, _>>()?;
+        let mut body = String::with_capacity(function.body.len());
+        let mut body_copied = 0;
+        for param in identifiers(&function.body) {
+            if let Some(k) = function.params.iter().position(|p| *p == function.body[param.clone()]) {
+                body.push_str(&function.body[body_copied..param.start]);
+                body.push('(');
+                body.push_str(&args[k]);
+                body.push(')');
+                body_copied = param.end;
+                if body.len() > MAX_EXPANDED_LENGTH {
+                    return Err(too_long());
+                }
+            }
+        }
+        body.push_str(&function.body[body_copied..]);
+        let body = expand_functions(&body, &functions[..index])?;
+
+        expanded.push_str(&formula[copied..ident.start]);
+        expanded.push('(');
+        expanded.push_str(&body);
+        expanded.push(')');
+        copied = end;
+        if expanded.len() > MAX_EXPANDED_LENGTH {
+            return Err(too_long());
+        }
+    }
+    expanded.push_str(&formula[copied..]);
+    Ok(expanded)
+}
+
+/// # 自由パラメータや利用者定義関数の名前として使用できるかを検査する
+///
+/// formulac は数値、定数、変数、関数の順に名前を解決するので、次の名前は予約して使用できないようにする
+///  - 数値として解釈される名前 (`inf`, `nan` など) と虚数単位 `i`
+///  - 微分演算子 `diff`
+///  - 組み込みの定数 (`PI`, `E` など)、演算子、関数の名前
+///  - reserved で指定した名前 (変数 `z` など)
+fn validate_name(name: &str, kind: &str, reserved: &[&str]) -> Result<(), String> {
+    let mut chars = name.chars();
+    let is_ident = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !is_ident {
+        return Err(format!("Invalid {} name: \"{}\".", kind, name));
+    }
+
+    let imaginary = name.strip_suffix(formulac::lexer::IMAGINARY_UNIT);
+    let is_reserved = reserved.contains(&name)
+        || name == formulac::operators::DIFFERENTIAL_OPERATOR_STR
+        || name.parse::<f64>().is_ok()
+        || imaginary.is_some_and(|num| num.is_empty() || num.parse::<f64>().is_ok())
+        || formulac::constants::Constants::<f64>::symbols().contains(&name)
+        || formulac::operators::OperatorKind::symbols().contains(&name)
+        || formulac::functions::FunctionKind::symbols().contains(&name);
+    if is_reserved {
+        return Err(format!("\"{}\" is reserved and cannot be used as a {}.", name, kind));
     }
+    Ok(())
 }
 
-fn jet(t: f64) -> [u8; 4] {
-    const MIN: f64 = 0.0;
-    const MAX: f64 = 1.0;
-    let t = t.clamp(MIN, MAX);
-    let r = (1.5 - (4.0 * t - 3.0).abs()).clamp(MIN, MAX);
-    let g = (1.5 - (4.0 * t - 2.0).abs()).clamp(MIN, MAX);
-    let b = (1.5 - (4.0 * t - 1.0).abs()).clamp(MIN, MAX);
+/// # 自由パラメータとして使用できる名前と値かを検査する
+///
+/// 名前は validate_name の予約名に加えて、変数 `z` を使用できない
+fn validate_param(name: &str, value: Complex<f64>) -> Result<(), String> {
+    validate_name(name, "parameter", &[VARIABLE])?;
+    if !(value.re.is_finite() && value.im.is_finite()) {
+        return Err(format!("Invalid value of {}: ({}, {}).", name, value.re, value.im));
+    }
+    Ok(())
+}
 
-    [
-        (r * u8::MAX as f64) as u8,
-        (g * u8::MAX as f64) as u8,
-        (b * u8::MAX as f64) as u8,
-        u8::MAX, // Alphaは常に1.0
-    ]
+/// # 利用者定義関数の定義を、コンパイル前に検査する
+///
+/// 引数の名には変数 `z` も使用できる
+fn validate_function(function: &UserFunction) -> Result<(), String> {
+    validate_name(&function.name, "function", &[VARIABLE])?;
+    if !(1..=MAX_FUNCTION_ARITY).contains(&function.params.len()) {
+        return Err(format!("{} takes {} parameters (limit: 1..={}).", function.name, function.params.len(), MAX_FUNCTION_ARITY));
+    }
+    for (i, param) in function.params.iter().enumerate() {
+        validate_name(param, "parameter", &[])?;
+        if function.params[..i].contains(param) {
+            return Err(format!("Duplicate parameter \"{}\" in {}.", param, function.name));
+        }
+    }
+    validate_formula(&function.body, &FormulaLimits::default())
 }
 
-fn apply_color_map(buffer: Vec<u16>, max_iter: u16) -> Vec<u8> {
-    buffer.iter()
-        .flat_map(|&iter| jet(iter as f64 / max_iter as f64))
-        .collect()
+/// # 数式の自由パラメータの値を設定する
+///
+/// `z^3 - c` のように、数式の中で z 以外の名前を複素数の定数として使用できる。
+/// `c` は既定で 0 が設定されているので、設定せずに使用することもできる。
+/// 予約されている名前は validate_param を参照
+///
+/// ## Params
+///  - name: パラメータの名前
+///  - re: 値の実部
+///  - im: 値の虚部
+///
+/// ## Returns
+///  - set_formula と同じ
+#[tauri::command]
+pub async fn set_param(name: String, re: f64, im: f64) -> Result<String, String> {
+    validate_param(&name, Complex::new(re, im))?;
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_EDIT_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        change_formula(&FRACTAL, &FORMULA_VERSION, generation, |fractal| {
+            let warning = match fractal {
+                Fractal::F64(f) => f.set_param(&name, Complex::new(re, im)).map(|_| f.formula_warning()),
+                Fractal::F106(f) => f.set_param(&name, Complex::new(re, im)).map(|_| f.formula_warning()),
+            }?;
+            Ok(formula_status(warning))
+        })
+    })
+    .await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 利用者定義関数を登録する
+///
+/// `name(params...) = body` の関数を登録し、数式の中で `name(z)` のように呼び出せるようにする。
+/// 登録後に現在の数式をコンパイルし直すので、登録前に未知の関数としてエラーになった数式も再設定せずに使用できる
+///
+/// ## Params
+///  - name: 関数名。同じ名前で登録した場合は上書きする
+///  - params: 引数の名前 (1 ~ MAX_FUNCTION_ARITY 個)
+///  - body: 関数の本体。引数、自由パラメータ、先に登録した関数を使用できる
+///
+/// ## Returns
+///  - set_formula と同じ
+#[tauri::command]
+pub async fn register_function(name: String, params: Vec<String>, body: String) -> Result<String, String> {
+    let function = UserFunction { name, params, body };
+    validate_function(&function)?;
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_EDIT_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        change_formula(&FRACTAL, &FORMULA_VERSION, generation, |fractal| {
+            let warning = match fractal {
+                Fractal::F64(f) => f.register_function(function.clone()).map(|_| f.formula_warning()),
+                Fractal::F106(f) => f.register_function(function.clone()).map(|_| f.formula_warning()),
+            }?;
+            Ok(formula_status(warning))
+        })
+    })
+    .await;
+
+    result.map_err(|e| e.to_string())?
 }
 
-fn save_png_get_data<T>(f: &FractalInner<T>) -> Result<(String, u32, u16), String>
+fn sample_formula_inner<T>(fo: &FormulacInner<T>, points: &[(f64, f64)]) -> Vec<(f64, f64)>
 where
     T: Real + FromStr + Send + Sync + 'static
         + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
-        + Serialize,
+        + ToF64,
 {
-    let size = f.canvas().size() as u32;
-    let max_iter = f.max_iter();
-    let metadata = serde_json::to_string(f)
-        .map_err(|e| format!("Inner Error: JSON serialization failed: {}", e))?;
-    Ok((metadata, size, max_iter))
+    points.iter()
+        .map(|&(re, im)| {
+            let w = fo.func()([Complex::new(T::from_f64(re), T::from_f64(im))]);
+            (w.re.to_f64(), w.im.to_f64())
+        })
+        .collect()
 }
 
+/// # コンパイル済みの数式を、指定した点で評価した値を返す
+///
+/// 数式が意図通りにコンパイルされたかを確認するために使用する。
+/// 表示領域などの状態は変更しない
+///
+/// ## Params
+///  - points: 評価する点 (re, im) の配列
+///
+/// ## Returns
+///  - 各点での関数値 (re, im)。特異点などでは非有限の値となる
 #[tauri::command]
-pub async fn save_png(path: String) -> Result<(), String> {
-    let (metadata, size, max_iter) = {
-        match &*FRACTAL.lock().map_err(|e| format!("Inner Error: Mutex lock failed: {}", e))? {
-            Fractal::F64(f) => save_png_get_data(f)?,
-            Fractal::F106(f) => save_png_get_data(f)?,
-        }
-    };
-    let enum_index = FRACTAL.lock().map_err(|e| format!("Inner Error: Mutex lock failed: {}", e))?
-        .to_index();
+pub async fn sample_formula(points: Vec<(f64, f64)>) -> Result<Vec<(f64, f64)>, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(with_fractal!(&fractal, |_fr, fo| sample_formula_inner(fo, &points)))
+    }).await;
 
-    let calc_data = {
-        let mut raw_data = render_tile(0, 0, size, size).await?;
-        let size = size as usize;
-
-        // canvasとy軸が反転しているので、行の順番を逆転させる
-        // chunks(size).rev().flat_map(|row| row.iter().copied()).collect() でも実装可能だが、
-        // メモリ消費量を抑えるために、インプレースする
-        for i in 0..(size / 2) {
-            let top = i * size;
-            let bottom = (size - i - 1) * size;
-            for x in 0..size {
-                raw_data.swap(top + x, bottom + x);
-            }
-        }
+    result.map_err(|e| e.to_string())?
+}
 
-        raw_data
-    };
-    let rgba_data = apply_color_map(calc_data, max_iter);
+fn render_flow_density_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, seeds: u32, width: u32, height: u32) -> Vec<u32>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let info = view_calc_info(fr, fo, width, height);
+    let len = (width as usize) * (height as usize);
 
-    let file = std::fs::File::create(&path)
-        .map_err(|e| e.to_string())?;
-    let mut writer = std::io::BufWriter::new(file);
+    // 開始点はHalton列で表示領域内に散らすので、結果は毎回同じになる。index = 0 は左上の角なので使わない
+    (1..=seeds).into_par_iter()
+        .fold(|| vec![0u32; len], |mut density, i| {
+            let z = info.get_subpixel_complex(halton(i, 2) * width as f64, halton(i, 3) * height as f64);
+            info.escape_time_visiting(z, |z| {
+                if let Some((x, y)) = info.get_pixel(z) {
+                    let idx = y as usize * width as usize + x as usize;
+                    density[idx] = density[idx].saturating_add(1);
+                }
+            });
+            density
+        })
+        .reduce(|| vec![0u32; len], |mut a, b| {
+            for (a, b) in a.iter_mut().zip(b) {
+                *a = a.saturating_add(b);
+            }
+            a
+        })
+}
 
-    let mut encoder = png::Encoder::new(&mut writer, size, size);
-    encoder.set_color(png::ColorType::Rgba);
-    encoder.set_depth(png::BitDepth::Eight);
-    let _ = encoder.add_text_chunk("FractalParameters".to_string(), metadata);
-    let _ = encoder.add_text_chunk("Generics".to_string(), format!("{}", enum_index));
+/// # 表示領域内に散らした開始点からの軌道が、各ピクセルを通過した回数を返す
+///
+/// 反復の経路が集中する場所を可視化する。値の幅が大きいので、対数で色付けすることを想定している。
+/// 全ての開始点を収束まで反復するので遅く、最終的な画像の出力向け
+///
+/// ## Params
+///  - seeds: 開始点の数
+///  - width, height: 出力する画像の幅と高さ
+///
+/// ## Returns
+///  - 各ピクセルの通過回数 (y * width + x)
+#[tauri::command]
+pub async fn render_flow_density(seeds: u32, width: u32, height: u32) -> Result<Vec<u32>, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(width, height)?;
 
-    let mut png_writer = encoder.write_header().map_err(|e| e.to_string())?;
-    png_writer.write_image_data(&rgba_data).map_err(|e| e.to_string())?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_flow_density_inner(fr, fo, seeds, width, height))))
+    }).await;
 
-    Ok(())
+    result.map_err(|e| e.to_string())?
 }
 
-
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-pub struct ImportResult {
-    formula:    String,
-    size:       u32,
-    max_iter:   u16,
-    center_str: String,
-    scale_str:  String,
+/// 格子点でのNewton法の1ステップ
+#[derive(Debug, Serialize)]
+pub struct StepVector {
+    /// 格子点の座標 (re, im)
+    pos:    (f64, f64),
+    /// 緩和係数を掛けたステップ `-a * f(z) / f'(z)` (re, im)
+    step:   (f64, f64),
 }
 
-fn import_from_png_build_fractal_inner<T>(metadata_text: &TEXtChunk) -> Result<FractalInner<T>, String>
+fn step_field_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, grid: u32) -> Vec<StepVector>
 where
     T: Real + FromStr + Send + Sync + 'static
         + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
-        + DeserializeOwned,
+        + ToF64,
 {
-    let mut f: FractalInner<T> = serde_json::from_str(&metadata_text.text)
-        .map_err(|e| e.to_string())?;
+    let info = view_calc_info(fr, fo, grid, grid);
+    // 格子の間隔より長いステップは、向きを保ったまま格子の間隔に縮める
+    let spacing = (info.range.clone() / info.size.clone()).to_f64();
 
-    let formula = f.formula().to_string();
-    f.set_formula(&formula)
-        .map_err(|e| e.to_string())?;
-
-    Ok(f)
+    (0..grid * grid).into_par_iter()
+        .filter_map(|i| {
+            let z = info.get_subpixel_complex((i % grid) as f64 + 0.5, (i / grid) as f64 + 0.5);
+            let orbit = info.newton_orbit(z.clone(), 1);
+            let next = orbit.get(1)?;
+            let (re, im) = ((next.re.clone() - z.re.clone()).to_f64(), (next.im.clone() - z.im.clone()).to_f64());
+            let len = re.hypot(im);
+            if !len.is_finite() {
+                return None;
+            }
+            let scale = if len > spacing { spacing / len } else { 1.0 };
+            Some(StepVector { pos: (z.re.to_f64(), z.im.to_f64()), step: (re * scale, im * scale) })
+        })
+        .collect()
 }
 
-
+/// # 表示領域の grid * grid の格子点で、Newton法の1ステップの向きと大きさを返す
+///
+/// 反復が根へ流れる様子を矢印で重ね描きするために使用する。
+/// 臨界点の近くでは非常に大きなステップとなるので、格子の間隔より長いステップは格子の間隔に縮める。
+/// 関数値が有限でない格子点は含めない
+///
+/// ## Params
+///  - grid: 一辺あたりの格子点の数
 #[tauri::command]
-pub async fn import_from_png(path: String) -> Result<ImportResult, String> {
-    let file = std::fs::File::open(&path)
-        .map_err(|e| e.to_string())?;
-    let reader = std::io::BufReader::new(file);
+pub async fn step_field(grid: u32) -> Result<Vec<StepVector>, String> {
+    if grid == 0 {
+        return Err("Grid must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(grid, grid)?;
 
-    let decoder = png::Decoder::new(reader);
-    let reader = decoder.read_info()
-        .map_err(|e| e.to_string())?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| step_field_inner(fr, fo, grid))))
+    }).await;
 
-    let metadata_text = reader.info().uncompressed_latin1_text.iter()
-        .find(|chunk| chunk.keyword == "FractalParameters")
-        .ok_or(format!("Not Found Fractal Parameters in {}.", &path))?;
-    let enum_index = usize::from_str(&reader.info().uncompressed_latin1_text.iter()
-        .find(|chunk| chunk.keyword == "Generics")
-        .unwrap_or(&&TEXtChunk { keyword: "Generics".into(), text: "0".into() }) /* 存在しない場合は f64 とする */
-        .text
-    ).map_err(|e| e.to_string())?;
+    result.map_err(|e| e.to_string())?
+}
 
-    let (formula, size, max_iter) = {
-        // center_strとscale_strの取得時にFRACTALをlockするので、デッドロックしないように、
-        // FRACTALの設定時に応答値を同時に取得する
-        let mut f = FRACTAL.lock().map_err(|e| e.to_string())?;
-        match enum_index {
-            0 => {
-                let fo = import_from_png_build_fractal_inner::<f64>(metadata_text)?;
-                let formula = fo.formula().to_string();
-                let size = fo.canvas().size().into();
-                let max_iter = fo.max_iter();
+/// # 表示領域内の、数式の臨界点 (導関数の零点) を返す
+///
+/// Newton法の力学は臨界点の軌道に支配されるので、basin の構造を理解するための重ね描きに使用する。
+/// 表示領域を samples * samples の格子に分割し、各格子の中心から探索する
+///
+/// ## Params
+///  - samples: 一辺あたりの探索の開始点の数
+///
+/// ## Returns
+///  - 重複を除いた臨界点 (re, im)。表示領域内に存在しない場合は空
+#[tauri::command]
+pub async fn critical_points(samples: u32) -> Result<Vec<(f64, f64)>, String> {
+    if samples == 0 {
+        return Err("Samples must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(samples, samples)?;
 
-                *f = Fractal::F64(fo);
-                (formula, size, max_iter)
-            },
-            _ => return Err(format!("Invalid Generics Parameters ({}) in {}.", enum_index, &path)),
-        }
-    };
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            let (width, height) = fr.canvas().viewport_size();
+            btm::critical_points(&view_calc_info(fr, fo, width, height), samples)
+                .into_iter()
+                .map(|z| (z.re.to_f64(), z.im.to_f64()))
+                .collect()
+        })))
+    }).await;
 
-    Ok(ImportResult {
-        formula,
-        size,
-        max_iter,
-        center_str: get_center_str(),
-        scale_str: get_scale_str(),
+    result.map_err(|e| e.to_string())?
+}
+
+/// 数式の根と、その重複度
+#[derive(Debug, Serialize)]
+pub struct RootEntry {
+    re: f64,
+    im: f64,
+    /// 重複度。重根の近くではNewton法の収束が遅いので、着色で反復回数を補正するために使用する
+    multiplicity: u32,
+}
+
+/// # 根の番号 (Pixel::root) の順番で、数式の根と重複度を返す
+///
+/// 近い値で重複して発見された根は統合済みなので、重根の収束領域も1つの根として返す
+#[tauri::command]
+pub async fn get_roots() -> Result<Vec<RootEntry>, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            fr.cached_roots(fo).iter()
+                .zip(fr.root_multiplicities(fo))
+                .map(|(z, &multiplicity)| RootEntry { re: z.re.to_f64(), im: z.im.to_f64(), multiplicity })
+                .collect()
+        })))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+#[tauri::command]
+pub fn set_max_iter(max_iter: u16) {
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_max_iter(max_iter),
+        Fractal::F106(f) => f.set_max_iter(max_iter),
+    }
+}
+
+/// # 分岐切断などによる不連続な跳躍の扱いを設定する
+///
+/// Detect の場合、1回の反復で |z| が急に跳んだ点は収束とみなさず、basin の色付けで区別して表示する
+///
+/// ## Params
+///  - mode: 不連続な跳躍の扱い
+#[tauri::command]
+pub fn set_branch_handling(mode: BranchHandling) {
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_branch_handling(mode),
+        Fractal::F106(f) => f.set_branch_handling(mode),
+    }
+}
+
+/// # 反復が収束したかの判定方法を設定する
+///
+/// 判定方法によって、収束先の境界の構造が僅かに変化する
+///
+/// ## Params
+///  - criterion: "IterateDelta" (反復点の相対誤差)、"StepSize" (ステップ幅 |f/f'|)、または "Residual" (関数値 |f|)
+#[tauri::command]
+pub fn set_convergence_criterion(criterion: btm::Criterion) {
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_criterion(criterion),
+        Fractal::F106(f) => f.set_criterion(criterion),
+    }
+}
+
+/// # ピクセルの座標から、反復を始める複素平面上の点への写像を設定する
+///
+/// 表示領域の中心と幅は写像する前の座標に対して適用する。
+/// 既定の表示領域で "Stereographic" を選択すると、無限遠点も含めた全ての根を1枚の画像に表示できる
+///
+/// ## Params
+///  - projection: "Planar" (そのまま)、"Stereographic" (半径1の円にリーマン球面全体)、または "Log" (横軸 log|z|、縦軸 arg z)
+#[tauri::command]
+pub fn set_projection(projection: btm::Projection) {
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_projection(projection),
+        Fractal::F106(f) => f.set_projection(projection),
+    }
+}
+
+/// # 反復の方法を設定する
+///
+/// Halley法では2階導関数 `diff(diff(数式, z), z)` もコンパイルする。Newton法に戻した場合はコンパイルしない。
+/// コンパイル中に後から set_param などの数式の編集が呼び出された場合は、変更前の方法に戻す
+///
+/// ## Params
+///  - method: "Newton" (z - a f / f')、または "Halley" (z - a 2 f f' / (2 f'^2 - f f''))
+///
+/// ## Returns
+///  - set_formula と同じ
+#[tauri::command]
+pub async fn set_method(method: btm::IterationMethod) -> Result<String, String> {
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_EDIT_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        change_formula(&FRACTAL, &FORMULA_VERSION, generation, |fractal| {
+            let warning = match fractal {
+                Fractal::F64(f) => f.set_method(method).map(|_| f.formula_warning()),
+                Fractal::F106(f) => f.set_method(method).map(|_| f.formula_warning()),
+            }?;
+            Ok(formula_status(warning))
+        })
+    })
+    .await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # Newton法の係数 a (z - a f(z) / f'(z)) を設定する
+///
+/// 1 以外の実数では緩和 (0 < a < 1) や過緩和 (1 < a < 2) となり、虚部を持つ場合は basin が回転したように変形する。
+/// 根の位置は変わらないが、収束の速さや basin の形が変わる
+///
+/// ## Params
+///  - re, im: 係数の実部と虚部 (0 以外)
+#[tauri::command]
+pub fn set_coeff(re: f64, im: f64) -> Result<(), String> {
+    if !(re.is_finite() && im.is_finite()) || (re == 0.0 && im == 0.0) {
+        return Err(format!("Invalid coefficient: ({}, {}).", re, im));
+    }
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_coeff(Complex::new(re, im)),
+        Fractal::F106(f) => f.set_coeff(Complex::new(re, im)),
+    }
+    Ok(())
+}
+
+/// # Newton法の係数を、get_center_str と同じ形式の文字列で返す
+#[tauri::command]
+pub fn get_coeff_str() -> String {
+    let coeff = match &*FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.coeff,
+        Fractal::F106(f) => f.coeff,
+    };
+    get_center_str_inner(coeff)
+}
+
+/// # 収束判定の許容誤差を設定する
+///
+/// 判定の値が eps 未満となった反復で収束とみなす。判定の値は criterion によって異なり、IterateDelta では相対誤差となる。
+/// 小さくすると basin の境界が鮮明になる代わりに、反復回数が増える
+///
+/// ## Params
+///  - eps: 許容誤差 (正の有限値)。既定値は 1e-4
+#[tauri::command]
+pub fn set_epsilon(eps: f64) -> Result<(), String> {
+    if !(eps.is_finite() && eps > 0.0) {
+        return Err(format!("Invalid convergence tolerance: {}.", eps));
+    }
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_epsilon(eps),
+        Fractal::F106(f) => f.set_epsilon(eps),
+    }
+    Ok(())
+}
+
+/// # 緩和係数を反復毎に徐々に大きくする時定数を設定する
+///
+/// n回目の反復の緩和係数を `a_n = a * (1 - exp(-n / tau))` とする。
+/// 始点によっては a = 1 で収束しにくい数式でも、最初の数回の反復を小さな係数で進めることで収束しやすくなる。
+/// 反復回数の分布が変わるので、同じ数式・表示領域でも画像が変化する
+///
+/// ## Params
+///  - tau: 時定数。0の場合は常に a とする
+#[tauri::command]
+pub fn set_relaxation_schedule(tau: f64) -> Result<(), String> {
+    if !(tau.is_finite() && tau >= 0.0) {
+        return Err(format!("Invalid relaxation time constant: {}.", tau));
+    }
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_relaxation_tau(tau),
+        Fractal::F106(f) => f.set_relaxation_tau(tau),
+    }
+    Ok(())
+}
+
+/// # 反復回数の上限を、注目点からの距離に応じて減らす
+///
+/// 点毎の上限を `max_iter / (1 + rate * d)` (最小1) とする。d は注目点からの距離を、表示領域の幅で割った値となる。
+/// 注目点から離れた領域の計算を省略できるが、反復回数の分布が場所によって一様でなくなるので、
+/// 同じ反復回数でも場所によって意味が異なる (上限に達した未収束の点は、その点の上限の反復回数となる)
+///
+/// ## Params
+///  - focus: 注目点 (re, im)
+///  - rate: 減衰率 (0 <= rate)。0の場合は全ての点で max_iter とする
+#[tauri::command]
+pub fn set_iteration_falloff(focus: (f64, f64), rate: f64) -> Result<(), String> {
+    if !(focus.0.is_finite() && focus.1.is_finite()) {
+        return Err(format!("Invalid focus: ({}, {}).", focus.0, focus.1));
+    }
+    if !(rate.is_finite() && rate >= 0.0) {
+        return Err(format!("Invalid falloff rate: {}.", rate));
+    }
+    let falloff = (rate > 0.0).then_some(IterationFalloff { focus, rate });
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_iteration_falloff(falloff),
+        Fractal::F106(f) => f.set_iteration_falloff(falloff),
+    }
+    Ok(())
+}
+
+/// # 表示領域の幅と高さを設定する
+///
+/// 横長・縦長の場合も1pixelは正方形とし、複素数平面の幅は長い方の辺に合わせる。
+/// render_tile などの座標は、表示領域の左上を原点とする
+///
+/// ## Params
+///  - width, height: 表示領域の幅と高さ [pixel]
+#[tauri::command]
+pub fn set_size(width: u16, height: u16) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid size: {}x{}.", width, height));
+    }
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.canvas_mut().set_size(width, height),
+        Fractal::F106(f) => f.canvas_mut().set_size(width, height),
+    }
+    Ok(())
+}
+
+// Tの差を吸収するために別関数にする
+fn move_view_inner<T>(f: &mut FractalInner<T>, dx: f64, dy: f64)
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let width = f.canvas().width();
+    let center = f.canvas().center().clone();
+    let re = center.re - T::from_f64(dx) * width.clone();
+    let im = center.im + T::from_f64(dy) * width;
+    f.canvas_mut().set_center(re, im);
+}
+
+// Tの差を吸収するために別関数にする
+fn set_center_inner<T>(f: &mut FractalInner<T>, re: &str, im: &str) -> Result<(), String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let parse = |s: &str| -> Result<T, String> {
+        let value = T::from_str(s.trim()).map_err(|_| format!("Invalid coordinate: {}.", s))?;
+        if value.to_f64().is_finite() {
+            Ok(value)
+        } else {
+            Err(format!("Invalid coordinate: {}.", s))
+        }
+    };
+    let (re, im) = (parse(re)?, parse(im)?);
+    f.canvas_mut().set_center(re, im);
+    Ok(())
+}
+
+/// # 中心座標を指定した座標に移動させる
+///
+/// 現在の精度の型で直接パースするので、get_center_str が返した値を渡すと同じ座標に戻る
+///
+/// ## Params
+///  - re, im: 中心座標の実部と虚部 ("-5.0e-1" などの指数表記も可)
+#[tauri::command]
+pub fn set_center(re: String, im: String) -> Result<(), String> {
+    cancel_render();
+    let mut fractal = FRACTAL.lock().unwrap();
+    let before = fractal.clone();
+    match &mut *fractal {
+        Fractal::F64(f) => set_center_inner(f, &re, &im)?,
+        Fractal::F106(f) => set_center_inner(f, &re, &im)?,
+    }
+    record_navigation(&before, Navigation::Jump);
+    Ok(())
+}
+
+/// 中心座標を移動させる
+#[tauri::command]
+pub async fn move_view(dx: f64, dy: f64) {
+    cancel_render();
+    let _ = tauri::async_runtime::spawn_blocking(move || {
+        let mut fractal = FRACTAL.lock().unwrap();
+        record_navigation(&fractal, Navigation::Move);
+        match &mut *fractal {
+            Fractal::F64(f) => move_view_inner(f, dx, dy),
+            Fractal::F106(f) => move_view_inner(f, dx, dy),
+        }
+    }).await;
+}
+
+/// # 縮尺を変更する
+#[tauri::command]
+pub async fn zoom_view(level: i32, x: f64, y: f64) {
+    cancel_render();
+    let _ = tauri::async_runtime::spawn_blocking(move || {
+        let mut fractal = FRACTAL.lock().unwrap();
+        record_navigation(&fractal, Navigation::Zoom);
+        match &mut *fractal {
+            Fractal::F64(f) => f.canvas_mut().zoom_around_point(level, x, y),
+            Fractal::F106(f) => f.canvas_mut().zoom_around_point(level, x, y),
+        }
+        fractal.fit_precision();
+    }).await;
+}
+
+/// 表示領域を変更した操作の種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Navigation {
+    Move,
+    Zoom,
+    /// set_center・random_interesting_view による移動、及び状態の置き換え (restore・import_state・import_link)。続けて行っても1つにまとめない
+    Jump,
+}
+
+/// # 表示領域を変更する前の中心座標と拡大段階を、undo_view で戻せるように記録する
+///
+/// FRACTAL をlockしたまま、変更する前に呼び出す。続けて行った同じ種類の操作は1つにまとめる
+fn record_navigation(fractal: &Fractal, navigation: Navigation) {
+    STATE_STORE.lock().unwrap().record(navigation, std::time::Instant::now(), || fractal.saved_view());
+}
+
+/// undo_view・redo_view で戻した表示領域
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RestoredView {
+    center_str: String,
+    scale_str:  String,
+}
+
+fn restored_view(fractal: &Fractal) -> RestoredView {
+    with_fractal!(fractal, |fr, _fo| RestoredView {
+        center_str: get_center_str_inner(*fr.canvas().center()),
+        scale_str:  format_with_decimal(fr.canvas().scale()),
     })
 }
+
+/// # 表示領域を、move_view・zoom_view・set_center・random_interesting_view・restore・import_state・import_link で変更する前の状態に戻す
+///
+/// 戻すのは中心座標と拡大段階だけで、数式や色付けの設定は変更後のまま残す。
+/// ドラッグやホイールで続けて行った操作は、まとめて戻す。履歴は snapshot で保存した状態とは別に最大64個で、それより古いものは破棄する
+///
+/// ## Returns
+///  - 戻した表示領域の中心座標と拡大率。戻す履歴が無い場合はNone
+#[tauri::command]
+pub fn undo_view() -> Option<RestoredView> {
+    let mut fractal = FRACTAL.lock().unwrap();
+    let view = STATE_STORE.lock().unwrap().undo(|| fractal.saved_view())?;
+    cancel_render();
+    fractal.apply_view(view);
+    Some(restored_view(&fractal))
+}
+
+/// # undo_view で戻した表示領域をやり直す
+///
+/// undo_view の後に表示領域を変更した場合は、やり直しの履歴は破棄される
+///
+/// ## Returns
+///  - やり直した表示領域の中心座標と拡大率。やり直す履歴が無い場合はNone
+#[tauri::command]
+pub fn redo_view() -> Option<RestoredView> {
+    let mut fractal = FRACTAL.lock().unwrap();
+    let view = STATE_STORE.lock().unwrap().redo(|| fractal.saved_view())?;
+    cancel_render();
+    fractal.apply_view(view);
+    Some(restored_view(&fractal))
+}
+
+/// # 現在の設定から、矩形領域の計算情報を作成する
+///
+/// ## Params
+///  - x, y: 表示領域の左上からの、矩形領域の左上の位置
+fn calc_info<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, x: u32, y: u32, w: u32, h: u32) -> btm::CalcInfo<T>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let (func, deriv) = fo.shared_funcs();
+    let (dx, dy) = fr.canvas().viewport_offset();
+    let mut info = btm::CalcInfo::new(
+        x + dx, y + dy, w, h,
+        fr.max_iter(),
+        T::from_f64(fr.canvas().size() as f64),
+        fr.canvas().center().clone(),
+        fr.canvas().width(),
+        func,
+        deriv,
+        fr.coeff(),
+    );
+    info.escape = fr.escape_options();
+    info.escape.deriv2 = fr.iteration_deriv2(fo);
+    info.fill_passes = fr.fill_passes();
+    info.halo = fr.boundary_halo();
+    info.projection = fr.projection;
+    info.known_roots = fr.cached_roots(fo).to_vec();
+    info.known_multiplicities = fr.root_multiplicities(fo).to_vec();
+    info
+}
+
+/// # 数式の代わりに、根を指定した多項式で計算するための計算情報を作成する
+///
+/// 根の番号は roots の順番とし、重複した根には同じ番号を割り当てる
+fn roots_calc_info<T>(fr: &FractalInner<T>, roots: &[(f64, f64)], x: u32, y: u32, w: u32, h: u32) -> btm::CalcInfo<T>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let roots: Vec<Complex<T>> = roots.iter()
+        .map(|&(re, im)| Complex::new(T::from_f64(re), T::from_f64(im)))
+        .collect();
+    let mut known_roots: Vec<Complex<T>> = Vec::with_capacity(roots.len());
+    let mut multiplicities: Vec<u32> = Vec::with_capacity(roots.len());
+    for r in &roots {
+        match known_roots.iter().position(|known| known == r) {
+            Some(idx) => multiplicities[idx] += 1,
+            None => {
+                known_roots.push(r.clone());
+                multiplicities.push(1);
+            }
+        }
+    }
+
+    let fo = FormulacInner::from_roots(roots);
+    let (func, deriv) = fo.shared_funcs();
+    let (dx, dy) = fr.canvas().viewport_offset();
+    let mut info = btm::CalcInfo::new(
+        x + dx, y + dy, w, h,
+        fr.max_iter(),
+        T::from_f64(fr.canvas().size() as f64),
+        fr.canvas().center().clone(),
+        fr.canvas().width(),
+        func,
+        deriv,
+        fr.coeff(),
+    );
+    info.escape = fr.escape_options();
+    info.escape.deriv2 = fr.iteration_deriv2(&fo);
+    info.fill_passes = fr.fill_passes();
+    info.halo = fr.boundary_halo();
+    info.projection = fr.projection;
+    info.known_roots = known_roots;
+    info.known_multiplicities = multiplicities;
+    info
+}
+
+/// # 現在の表示領域全体を、指定した解像度で計算するための計算情報を作成する
+///
+/// 縦横比が1:1でない場合は、表示領域の中央を切り出す
+fn view_calc_info<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, width: u32, height: u32) -> btm::CalcInfo<T>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let size = width.max(height);
+    let mut info = calc_info(fr, fo, 0, 0, width, height);
+    // calc_info は表示領域の縦横比で中央に寄せるので、指定した解像度の縦横比で寄せ直す
+    info.start.x = ((size - width) / 2) as i64;
+    info.start.y = ((size - height) / 2) as i64;
+    info.size = T::from_f64(size as f64);
+    info
+}
+
+fn render_tile_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, x: u32, y: u32, w: u32, h: u32) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    btm::calc_rect(calc_info(fr, fo, x, y, w, h)).escape_times()
+}
+
+/// # render_tile_inner と同じ計算を、cancel で中断できるように行う
+///
+/// ## Returns
+///  - 中断された場合は空のVec
+fn render_tile_cancellable_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    (x, y, w, h): (u32, u32, u32, u32),
+    cancel: btm::CancelToken,
+) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let mut info = calc_info(fr, fo, x, y, w, h);
+    info.cancel = Some(cancel);
+    let result = btm::calc_rect(info);
+    if cancel.is_cancelled() {
+        return Vec::new();
+    }
+    result.escape_times()
+}
+
+/// # 指定された矩形領域のデータのみを生成して返す
+///
+/// 数式が未コンパイルの場合は、定数0の関数で計算した結果を返さずにエラーとする (render_tile_* も同様)
+///
+/// ## Params
+///  - x: 矩形領域の左上のX座標（canvas全体に対するオフセット）
+///  - y: 矩形領域の左上のY座標（canvas全体に対するオフセット）
+///  - w: 矩形領域の幅
+///  - h: 矩形領域の高さ
+///
+/// ## Returns
+///  - 計算中に cancel_render が呼び出された場合は空のVec
+#[tauri::command]
+pub async fn render_tile(x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u16>, String> {
+    btm::check_memory_budget(w, h)?;
+    let cancel = btm::CancelToken::new(&RENDER_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_cancellable_inner(fr, fo, (x, y, w, h), cancel))))
+    }).await;
+
+    match result {
+        Ok(data) => data,
+        Err(e) => Err(e.to_string())
+    }
+}
+
+/// 矩形領域の反復回数と、収束先の根の番号
+#[derive(Debug, Serialize)]
+pub struct RootTile {
+    /// render_tile と同じ反復回数
+    data:   Vec<u16>,
+    /// 根の番号 (Pixel::root と同じ。未収束の場合は UNCONVERGED、不連続な跳躍の場合は BRANCH_JUMP)
+    root:   Vec<u16>,
+}
+
+fn render_tile_roots_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, x: u32, y: u32, w: u32, h: u32) -> RootTile
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let result = btm::calc_rect(calc_info(fr, fo, x, y, w, h));
+    RootTile {
+        data: result.escape_times(),
+        root: result.pixels.iter().map(|p| p.root).collect(),
+    }
+}
+
+/// # 指定された矩形領域の反復回数と、各ピクセルの収束先の根の番号を返す
+///
+/// render_tile と同じく境界追跡で計算し、根の番号で basin 毎に色を分けられるようにする。
+/// 根の番号は数式の根の一覧 (get_roots) の順番なので、タイル毎に番号が変わらない
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+#[tauri::command]
+pub async fn render_tile_roots(x: u32, y: u32, w: u32, h: u32) -> Result<RootTile, String> {
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_roots_inner(fr, fo, x, y, w, h))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_tile_scale_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, (x, y, w, h): (u32, u32, u32, u32), scale: f64) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let mut info = calc_info(fr, fo, x, y, w, h);
+    info.range = Canvas::<T>::width_at_scale(scale);
+    btm::calc_rect(info).escape_times()
+}
+
+/// # zoom_level の代わりに連続的な拡大率を指定して、矩形領域を計算する
+///
+/// zoom_level は 2^(1/8) 倍毎の段階なので、連続的に拡大するアニメーションでは段階毎に跳んで見える。
+/// 拡大率を直接指定することで、アニメーションや出力で滑らかに拡大できる。表示領域の拡大率は変更しない
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///  - scale: 拡大率。zoom_level の場合の 2^(zoom_level / 8) に相当する
+#[tauri::command]
+pub async fn render_tile_scale(x: u32, y: u32, w: u32, h: u32, scale: f64) -> Result<Vec<u16>, String> {
+    if !(scale.is_finite() && scale > 0.0) {
+        return Err("Scale must be a positive finite number.".to_string());
+    }
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_scale_inner(fr, fo, (x, y, w, h), scale))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 計算時間の上限を指定して計算した矩形領域
+#[derive(Debug, Serialize)]
+pub struct BudgetedTile {
+    /// render_tile と同じ反復回数。打ち切った場合、未計算のピクセルは u16::MAX となる
+    data:       Vec<u16>,
+    /// 全てのピクセルを計算したか
+    complete:   bool,
+}
+
+fn render_tile_budgeted_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    (x, y, w, h): (u32, u32, u32, u32),
+    deadline: std::time::Instant,
+) -> BudgetedTile
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let mut info = calc_info(fr, fo, x, y, w, h);
+    info.deadline = Some(deadline);
+    let result = btm::calc_rect(info);
+    BudgetedTile { data: result.escape_times(), complete: result.complete }
+}
+
+/// # 計算時間の上限を指定して、矩形領域を計算する
+///
+/// 呼び出してから max_ms 経過すると境界追跡を打ち切り、それまでに計算した結果を返す。
+/// 計算中のピクセルの反復は中断しないので、1pixel分の計算時間だけ上限を超えることがある。
+/// 拡大率や数式に関わらず、プレビューの描画を一定時間で返すために使用する
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///  - max_ms: 計算時間の上限[ms]
+#[tauri::command]
+pub async fn render_tile_budgeted(x: u32, y: u32, w: u32, h: u32, max_ms: u64) -> Result<BudgetedTile, String> {
+    let deadline = std::time::Instant::now() + std::time::Duration::from_millis(max_ms);
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_budgeted_inner(fr, fo, (x, y, w, h), deadline))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 1回の計算で得た、矩形領域の反復回数・連続的な反復回数・根の番号
+#[derive(Debug, Serialize)]
+pub struct FullTile {
+    escape: Vec<u16>,
+    smooth: Vec<f32>,
+    /// 根の番号 (Pixel::root と同じ。未収束の場合は UNCONVERGED、不連続な跳躍の場合は BRANCH_JUMP)
+    root:   Vec<u16>,
+}
+
+fn render_tile_full_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, x: u32, y: u32, w: u32, h: u32) -> FullTile
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let (result, smooth) = btm::calc_smooth_rect(&calc_info(fr, fo, x, y, w, h));
+    FullTile {
+        escape: result.escape_times(),
+        smooth: smooth.iter().map(|s| s.to_f64() as f32).collect(),
+        root: result.pixels.iter().map(|p| p.root).collect(),
+    }
+}
+
+/// # 指定された矩形領域の反復回数・連続的な反復回数・根の番号を、1回の反復でまとめて返す
+///
+/// 段階的な配色・滑らかな配色・収束先の根による配色を、再計算せずに切り替えるために使用する。
+/// 連続的な反復回数を求めるために境界追跡を行わずに全てのピクセルを計算するので、render_tile より遅い。
+/// また1pixelあたりの転送量が render_tile の2byteから8byteに増えるので、転送量を抑えたい場合は render_tile を使用する
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+#[tauri::command]
+pub async fn render_tile_full(x: u32, y: u32, w: u32, h: u32) -> Result<FullTile, String> {
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_full_inner(fr, fo, x, y, w, h))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_tile_shaded_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    (x, y, w, h): (u32, u32, u32, u32),
+    light_dir: (f64, f64),
+) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let info = calc_info(fr, fo, x, y, w, h);
+    let values = coloring_values(coloring, &info);
+    let (result, smooth) = btm::calc_smooth_rect(&info);
+
+    let mut rgba = coloring.colorize(&result.pixels, &values, &roots_f64(&result.roots), info.max_itr);
+    let heights: Vec<f64> = smooth.iter().map(|s| s.to_f64()).collect();
+    coloring::apply_shading(&mut rgba, &heights, w, h, light_dir);
+    rgba
+}
+
+/// # 連続的な反復回数を高さとみなして陰影を付けた、矩形領域の色を返す
+///
+/// 色付けの設定に従った色に、連続的な反復回数の勾配から求めた法線と光源の向きによるLambert反射の明るさを掛ける。
+/// 勾配はタイル内の隣接ピクセルから求め、タイルの端は片側差分とする。
+/// render_tile_full と同様に全てのピクセルを計算するので、render_tile より遅い
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///  - light_dir: 光源の向き (x, y)。y は虚部の大きい向きを正とする
+///
+/// ## Returns
+///  - RGBAの画素データ
+#[tauri::command]
+pub async fn render_tile_shaded(x: u32, y: u32, w: u32, h: u32, light_dir: (f64, f64)) -> Result<Vec<u8>, String> {
+    if !(light_dir.0.is_finite() && light_dir.1.is_finite()) || light_dir == (0.0, 0.0) {
+        return Err(format!("Invalid light direction: ({}, {}).", light_dir.0, light_dir.1));
+    }
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            render_tile_shaded_inner(fr, fo, &coloring, (x, y, w, h), light_dir)
+        })))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_tile_from_roots_inner<T>(
+    fr: &FractalInner<T>,
+    roots: &[(f64, f64)],
+    (x, y, w, h): (u32, u32, u32, u32),
+    cancel: btm::CancelToken,
+) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let mut info = roots_calc_info(fr, roots, x, y, w, h);
+    info.cancel = Some(cancel);
+    let result = btm::calc_rect(info);
+    if cancel.is_cancelled() {
+        return Vec::new();
+    }
+    result.escape_times()
+}
+
+/// # 数式の代わりに、根を直接指定した多項式 `Π(z - r_k)` で矩形領域を計算する
+///
+/// 表示領域や反復の設定は現在の値を使用し、設定中の数式は変更しない。
+/// 根をドラッグして動かすUIから、移動の度に呼び出すことを想定している
+///
+/// ## Params
+///  - roots: 多項式の根 (re, im) 。重根を含んでもよい
+///  - x, y, w, h: render_tile と同じ
+///
+/// ## Returns
+///  - 計算中に cancel_render が呼び出された場合は空のVec
+#[tauri::command]
+pub async fn render_tile_from_roots(roots: Vec<(f64, f64)>, x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u16>, String> {
+    if roots.is_empty() {
+        return Err("At least one root is required.".to_string());
+    }
+    if roots.iter().any(|(re, im)| !(re.is_finite() && im.is_finite())) {
+        return Err("Roots must be finite.".to_string());
+    }
+    btm::check_memory_budget(w, h)?;
+    let cancel = btm::CancelToken::new(&RENDER_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = snapshot_fractal();
+        in_render_pool(|| with_fractal!(&fractal, |fr, _fo| render_tile_from_roots_inner(fr, &roots, (x, y, w, h), cancel)))
+    }).await;
+
+    result.map_err(|e| e.to_string())
+}
+
+fn render_tile_lyapunov_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, x: u32, y: u32, w: u32, h: u32) -> Vec<f32>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let info = calc_info(fr, fo, x, y, w, h);
+    (0..h as i64).into_par_iter().flat_map_iter(|py| {
+        let info = &info;
+        (0..w as i64).map(move |px| info.lyapunov_sum(info.get_complex(px, py)).to_f64() as f32)
+    }).collect()
+}
+
+/// # 指定された矩形領域の各ピクセルの、反復写像の微分の対数の和を返す
+///
+/// 反復写像 g(z) = z - a f(z) / f'(z) について Σ log|g'(z_n)| を軌道に沿って求める (Lyapunov指数に相当する量)。
+/// 負の値は反復が安定して収束すること、正の値は初期値に敏感であることを表す。
+/// 緩和 Newton法などで、収束する領域と混沌とした領域を区別して色付けするために使用する。
+/// 値が連続的に変化するため境界追跡は行わず、全てのピクセルを直接計算する
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///
+/// ## Returns
+///  - 各ピクセルの値 (y * w + x)
+#[tauri::command]
+pub async fn render_tile_lyapunov(x: u32, y: u32, w: u32, h: u32) -> Result<Vec<f32>, String> {
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_lyapunov_inner(fr, fo, x, y, w, h))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_tile_gradient_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, x: u32, y: u32, w: u32, h: u32) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    btm::iteration_gradient(&render_tile_inner(fr, fo, x, y, w, h), w, h)
+}
+
+/// # 指定された矩形領域の、反復回数の勾配を返す
+///
+/// 各ピクセルの値は、上下左右の隣接ピクセルとの反復回数の差の最大値となる。
+/// 矩形領域の端のピクセルは、矩形領域内に存在する隣接ピクセルのみと比較する
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+#[tauri::command]
+pub async fn render_tile_gradient(x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u16>, String> {
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_gradient_inner(fr, fo, x, y, w, h))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 表示領域全体を縮小して計算する (ミニマップ用)
+///
+/// 移動中に毎フレーム呼び出されることを想定し、FRACTALのlockは計算前の複製の間だけ保持する。
+/// 数式は再コンパイルせず、メインの描画とコンパイル済みの関数を共有する
+///
+/// ## Params
+///  - size: 縮小後の一辺のピクセル数
+#[tauri::command]
+pub async fn render_overview(size: u32) -> Result<Vec<u16>, String> {
+    if size == 0 {
+        return Err("Overview size must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(size, size)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| btm::calc_rect(view_calc_info(fr, fo, size, size)).escape_times())))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 指定された矩形領域を、サーバー側で色付けして返す
+///
+/// 色付けの設定は save_png などの画像の出力と共通で、フロントエンドで色付けせずにそのままcanvasに描画できる
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///
+/// ## Returns
+///  - RGBAの画素データ (行の順番は render_tile と同じ)
+#[tauri::command]
+pub async fn render_tile_rgba(x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u8>, String> {
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| colorize_rect(&coloring, calc_info(fr, fo, x, y, w, h)))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # render_tile などが返した反復回数を、再計算せずに色付けし直す
+///
+/// カラーマップなどを変更する度に再計算しなくて済むように、フロントエンドで保持した生データを色付けする。
+/// 反復回数の正規化には現在の最大反復回数を使用する。save_png などと共通の色付けの設定は使用しない
+///
+/// ## Params
+///  - data: 反復回数 (width * height 個)
+///  - width, height: data の幅と高さ
+///  - colormap: カラーマップ
+///  - gamma: 正規化した値 t を t^gamma に変換する (0 < gamma)
+///  - transform: 正規化する前に反復回数に適用する変換
+///  - period: 0以外の場合、反復回数を period 毎に周期的に色付けする
+///
+/// ## Returns
+///  - RGBAの画素データ (行の順番は data と同じ)
+#[tauri::command]
+pub async fn recolor(
+    data: Vec<u16>,
+    width: u32, height: u32,
+    colormap: Colormap,
+    gamma: f64,
+    transform: ValueTransform,
+    period: u16,
+) -> Result<Vec<u8>, String> {
+    if data.len() as u64 != width as u64 * height as u64 {
+        return Err(format!("Data length {} does not match {}x{}.", data.len(), width, height));
+    }
+    if !(gamma.is_finite() && gamma > 0.0) {
+        return Err(format!("Invalid gamma: {}.", gamma));
+    }
+
+    let max_iter = with_fractal!(&*FRACTAL.lock().unwrap(), |fr, _fo| fr.max_iter());
+    let recolor = Recolor { colormap, gamma, transform, period };
+    tauri::async_runtime::spawn_blocking(move || recolor.apply(&data, max_iter))
+        .await
+        .map_err(|e| e.to_string())
+}
+
+/// # 導関数と、関数の中心差分による数値微分の相対誤差の最大値を返す
+///
+/// 関数値・微分値が有限でない点は無視する。微分値が小さい点で相対誤差が発散しないように、分母は1以上とする
+fn derivative_discrepancy<T>(fo: &FormulacInner<T>, points: &[Complex<T>]) -> f64
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    /// 中心差分の刻み幅 (|z| < 1 の場合の値)。f64の丸め誤差と打ち切り誤差が釣り合う epsilon^(1/3) 程度とする
+    const STEP: f64 = 6e-6;
+
+    points.iter()
+        .filter_map(|z| {
+            let h = T::from_f64(STEP * z.norm_sqr().to_f64().sqrt().max(1.0));
+            let dh = Complex::new(h.clone(), T::zero());
+            let numerical = (fo.func()([z.clone() + dh.clone()]) - fo.func()([z.clone() - dh])) / (h * T::from_f64(2.0));
+            let symbolic = fo.deriv()([z.clone()]);
+
+            let error = (numerical - symbolic.clone()).norm_sqr().to_f64().sqrt();
+            let scale = symbolic.norm_sqr().to_f64().sqrt().max(1.0);
+            let discrepancy = error / scale;
+            discrepancy.is_finite().then_some(discrepancy)
+        })
+        .fold(0.0, f64::max)
+}
+
+fn check_derivative_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, samples: u32) -> f64
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    // 表示領域内に偏り無く標本点を置く
+    let width = fr.canvas().width();
+    let points: Vec<Complex<T>> = (1..=samples)
+        .map(|i| {
+            let re = T::from_f64(halton(i, 2) - 0.5) * width.clone();
+            let im = T::from_f64(halton(i, 3) - 0.5) * width.clone();
+            fr.canvas().center().clone() + Complex::new(re, im)
+        })
+        .collect();
+
+    derivative_discrepancy(fo, &points)
+}
+
+/// # コンパイルした導関数が正しいかを、数値微分と比較して確認する
+///
+/// 表示領域内の標本点で、導関数の値と中心差分による数値微分の値を比較する。
+/// 導関数が誤っているとNewton法の結果が全て誤るので、値が大きい場合はUIで警告する
+///
+/// ## Params
+///  - samples: 標本点の数
+///
+/// ## Returns
+///  - 相対誤差の最大値。正しい導関数であれば、丸め誤差程度 (f64で1e-6未満) となる
+#[tauri::command]
+pub async fn check_derivative(samples: u32) -> Result<f64, String> {
+    if samples == 0 {
+        return Err("samples must be greater than 0.".to_string());
+    }
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(with_fractal!(&fractal, |fr, fo| check_derivative_inner(fr, fo, samples)))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # Halton列のindex番目の値を返す
+///
+/// ## Returns
+///  - [0.0, 1.0) の値。index = 0 の場合は0.0
+fn halton(mut index: u32, base: u32) -> f64 {
+    let mut result = 0.0;
+    let mut f = 1.0;
+    while index > 0 {
+        f /= base as f64;
+        result += f * (index % base) as f64;
+        index /= base;
+    }
+    result
+}
+
+fn render_tile_taa_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    (x, y, w, h): (u32, u32, u32, u32),
+    passes: u8,
+) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let passes = passes.max(1);
+    let mut sum = vec![0u32; (w as usize) * (h as usize) * 4];
+
+    for pass in 0..passes as u32 {
+        // 1回目はずらさないので、passes = 1 の場合は通常の描画と一致する
+        let mut info = calc_info(fr, fo, x, y, w, h);
+        let pixel = info.range.clone() / info.size.clone();
+        info.center = Complex::new(
+            info.center.re.clone() + pixel.clone() * T::from_f64(halton(pass, 2)),
+            info.center.im.clone() + pixel * T::from_f64(halton(pass, 3)),
+        );
+
+        let rgba = colorize_rect(coloring, info);
+        for (acc, c) in sum.iter_mut().zip(rgba) {
+            *acc += c as u32;
+        }
+    }
+
+    sum.into_iter()
+        .map(|acc| ((acc + passes as u32 / 2) / passes as u32) as u8)
+        .collect()
+}
+
+/// # 標本点をピクセル内でずらしながら複数回描画し、色を平均して返す
+///
+/// 収束先の境界のジャギーを抑える。ずらす量はHalton列 (基数2, 3) で決めるので、結果は毎回同じになる
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///  - passes: 描画する回数。1の場合はずらさずに1回だけ描画する
+///
+/// ## Returns
+///  - RGBAの画素データ
+#[tauri::command]
+pub async fn render_tile_taa(x: u32, y: u32, w: u32, h: u32, passes: u8) -> Result<Vec<u8>, String> {
+    btm::check_memory_budget(w, h)?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_taa_inner(fr, fo, &coloring, (x, y, w, h), passes))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_tile_stochastic_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    (x, y, w, h): (u32, u32, u32, u32),
+    samples: u8,
+    (seed, jitter): (u64, f64),
+) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let samples = samples.max(1) as usize;
+    let len = (w as usize) * (h as usize);
+    let info = calc_info(fr, fo, x, y, w, h);
+
+    // 並列に計算しても結果が変わらないように、乱数によるずらし量は先に順番に決める
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let points: Vec<Complex<T>> = (0..samples * len)
+        .map(|i| {
+            let (px, py) = ((i % len) % w as usize, (i % len) / w as usize);
+            info.get_subpixel_complex(px as f64 + rng.f64() * jitter, py as f64 + rng.f64() * jitter)
+        })
+        .collect();
+    let escapes: Vec<btm::Escape<T>> = points.into_par_iter()
+        .map(|z| info.escape_time(z))
+        .collect();
+
+    // 全ての標本点で同じ根の番号を使うように、1つの一覧で番号を割り当てる
+    let mut roots = btm::Roots::from_known(&info.known_roots, &info.known_multiplicities);
+    let pixels: Vec<btm::Pixel> = escapes.iter().map(|escape| roots.pixel(escape)).collect();
+    let values: Vec<f64> = if coloring.needs_ratios() {
+        escapes.iter().map(|escape| escape.ratio.to_f64()).collect()
+    } else if coloring.needs_smooth() {
+        escapes.iter().map(|escape| escape.smooth.to_f64()).collect()
+    } else {
+        Vec::new()
+    };
+    let rgba = coloring.colorize(&pixels, &values, &roots_f64(roots.as_slice()), info.max_itr);
+
+    let mut sum = vec![0u32; len * 4];
+    for sample in rgba.chunks_exact(len * 4) {
+        for (acc, &c) in sum.iter_mut().zip(sample) {
+            *acc += c as u32;
+        }
+    }
+    sum.into_iter()
+        .map(|acc| ((acc + samples as u32 / 2) / samples as u32) as u8)
+        .collect()
+}
+
+/// # ピクセル毎に乱数でずらした標本点で複数回計算し、色を平均して返す
+///
+/// render_tile_taa はタイル全体を同じ量だけずらすが、こちらはピクセル毎に独立にずらすので、
+/// 標本点を増やすほどノイズと引き換えに滑らかになる。シードを指定するので、結果は毎回同じになる
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///  - samples: 1ピクセルあたりの標本点の数
+///  - seed: ずらし量を決める乱数のシード
+///
+/// ## Returns
+///  - RGBAの画素データ
+#[tauri::command]
+pub async fn render_tile_stochastic(x: u32, y: u32, w: u32, h: u32, samples: u8, seed: u64) -> Result<Vec<u8>, String> {
+    btm::check_memory_budget(w, h.saturating_mul(samples.max(1) as u32))?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            render_tile_stochastic_inner(fr, fo, &coloring, (x, y, w, h), samples, (seed, 1.0))
+        })))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_tile_coverage_aa_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    (x, y, w, h): (u32, u32, u32, u32),
+) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    // タイルの端のピクセルも隣接ピクセルと比較できるように、周囲1ピクセルを含めて計算する
+    let mut info = calc_info(fr, fo, x, y, w + 2, h + 2);
+    info.start.x -= 1;
+    info.start.y -= 1;
+    let values = coloring_values(coloring, &info);
+    let result = btm::calc_rect(info.clone());
+    let distances = btm::boundary_distances(&info, &result);
+    let rgba = coloring.colorize(&result.pixels, &values, &roots_f64(&result.roots), info.max_itr);
+
+    let margin_w = (w + 2) as usize;
+    let mut tile = Vec::with_capacity((w as usize) * (h as usize) * 4);
+    for ty in 1..=h as usize {
+        for tx in 1..=w as usize {
+            let idx = ty * margin_w + tx;
+            let own = &rgba[idx * 4..(idx + 1) * 4];
+            match distances[idx] {
+                Some(d) => {
+                    let coverage = d.coverage();
+                    let other = &rgba[d.neighbor * 4..(d.neighbor + 1) * 4];
+                    tile.extend(own.iter().zip(other)
+                        .map(|(&a, &b)| (a as f64 * coverage + b as f64 * (1.0 - coverage)).round() as u8));
+                },
+                None => tile.extend_from_slice(own),
+            }
+        }
+    }
+    tile
+}
+
+/// # 境界までの距離から被覆率を求めてアンチエイリアスした矩形領域を返す
+///
+/// 収束先の根の境界にあるピクセルは、境界の両側の色を被覆率で混ぜる。
+/// 標本点を増やす render_tile_taa より計算量が少なく、境界がぼけない
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+///
+/// ## Returns
+///  - RGBAの画素データ
+#[tauri::command]
+pub async fn render_tile_coverage_aa(x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u8>, String> {
+    btm::check_memory_budget(w.saturating_add(2), h.saturating_add(2))?;
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_coverage_aa_inner(fr, fo, &coloring, (x, y, w, h)))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 移動によって新たに表示される領域を返す
+///
+/// 角の領域を重複して計算しないように、横長の領域を先に確保し、縦長の領域はその残りとする
+///
+/// ## Returns
+///  - (x, y, w, h) の配列
+fn pan_delta_strips(dx: i64, dy: i64, width: i64, height: i64) -> Vec<(i64, i64, i64, i64)> {
+    let mut strips = Vec::new();
+
+    // dy > 0 の場合は下側、dy < 0 の場合は上側に空白ができる
+    let (row_start, row_end) = if dy > 0 {
+        strips.push((0, height - dy, width, dy));
+        (0, height - dy)
+    } else if dy < 0 {
+        strips.push((0, 0, width, -dy));
+        (-dy, height)
+    } else {
+        (0, height)
+    };
+
+    // dx > 0 の場合は左側、dx < 0 の場合は右側に空白ができる
+    if dx > 0 {
+        strips.push((0, row_start, dx, row_end - row_start));
+    } else if dx < 0 {
+        strips.push((width + dx, row_start, -dx, row_end - row_start));
+    }
+
+    strips
+}
+
+fn render_pan_delta_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    dx: i32, dy: i32,
+    prev: &[u16],
+    width: u32, height: u32,
+) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let (dx, dy) = (dx as i64, dy as i64);
+    let (w, h) = (width as i64, height as i64);
+
+    if dx.abs() >= w || dy.abs() >= h {
+        return btm::calc_rect(view_calc_info(fr, fo, width, height)).escape_times();
+    }
+
+    // 移動後の (x, y) には、移動前の (x - dx, y + dy) の値を移す
+    let mut data = vec![0; prev.len()];
+    let (dst_x, src_x, len) = if dx > 0 { (dx, 0, w - dx) } else { (0, -dx, w + dx) };
+    for y in 0..h {
+        let src_y = y + dy;
+        if !(0..h).contains(&src_y) {
+            continue;
+        }
+        let dst = (y * w + dst_x) as usize;
+        let src = (src_y * w + src_x) as usize;
+        data[dst..dst + len as usize].copy_from_slice(&prev[src..src + len as usize]);
+    }
+
+    for (x, y, sw, sh) in pan_delta_strips(dx, dy, w, h) {
+        let mut info = view_calc_info(fr, fo, width, height);
+        info.start.x += x;
+        info.start.y += y;
+        info.width = sw as u32;
+        info.height = sh as u32;
+
+        let strip = btm::calc_rect(info).escape_times();
+        for (row, values) in strip.chunks_exact(sw as usize).enumerate() {
+            let dst = ((y + row as i64) * w + x) as usize;
+            data[dst..dst + values.len()].copy_from_slice(values);
+        }
+    }
+
+    data
+}
+
+/// # 少しだけ移動した場合に、移動前の計算結果を再利用して表示領域全体を計算する
+///
+/// 移動前の計算結果をずらし、新たに表示される領域のみを計算して埋める。
+/// 移動量が表示領域以上の場合は、全体を計算し直す
+///
+/// ## Params
+///  - dx_px, dy_px: 移動したピクセル量 (move_view 後に呼び出す。符号は画面の移動と同じ)
+///  - prev: 移動前の表示領域全体の計算結果
+///  - width, height: 表示領域の幅と高さ
+#[tauri::command]
+pub async fn render_pan_delta(dx_px: i32, dy_px: i32, prev: Vec<u16>, width: u32, height: u32) -> Result<Vec<u16>, String> {
+    if prev.len() != (width as usize) * (height as usize) {
+        return Err(format!("Buffer length {} does not match {}x{}.", prev.len(), width, height));
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_pan_delta_inner(fr, fo, dx_px, dy_px, &prev, width, height))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 段階的な描画の途中結果
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TileRefinement {
+    x:      u32,
+    y:      u32,
+    w:      u32,
+    h:      u32,
+    /// 間引いた間隔[pixel]。dataは `ceil(w / stride) * ceil(h / stride)` 個の格子点の値
+    stride: u32,
+    data:   Vec<u16>,
+}
+
+fn render_tile_progressive_inner<T>(
+    app: &tauri::AppHandle,
+    info: btm::CalcInfo<T>,
+    generation: u64,
+    x: u32, y: u32, w: u32, h: u32,
+) -> Result<Vec<u16>, String>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    const STRIDES: [u32; 2] = [4, 2];
+
+    let mut coarser: Option<Vec<u16>> = None;
+
+    for stride in STRIDES {
+        if is_render_cancelled(generation) {
+            return Ok(Vec::new());
+        }
+
+        let data = btm::calc_sparse(&info, stride, coarser.as_deref());
+        app.emit("tile-refined", TileRefinement { x, y, w, h, stride, data: data.clone() })
+            .map_err(|e| e.to_string())?;
+        coarser = Some(data);
+    }
+
+    if is_render_cancelled(generation) {
+        return Ok(Vec::new());
+    }
+    Ok(btm::calc_rect(info).escape_times())
+}
+
+/// # 矩形領域を、粗い解像度から段階的に計算する
+///
+/// 4pixel間隔、2pixel間隔で間引いた格子点を計算する毎に "tile-refined" イベント (TileRefinement) を発行し、
+/// 最後に全てのピクセルを計算した結果を返す。
+/// 途中で cancel_render が呼ばれた場合は、次の段階に進まずに空の配列を返す
+///
+/// ## Params
+///  - x, y, w, h: render_tile と同じ
+#[tauri::command]
+pub async fn render_tile_progressive(app: tauri::AppHandle, x: u32, y: u32, w: u32, h: u32) -> Result<Vec<u16>, String> {
+    btm::check_memory_budget(w, h)?;
+    let generation = render_generation();
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_tile_progressive_inner(&app, calc_info(fr, fo, x, y, w, h), generation, x, y, w, h)))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # タイルサイズを設定する
+///
+/// ## Params
+///  - px: タイルの一辺のピクセル数 (1 <= px <= canvasのサイズ)
+///
+/// canvasのサイズで割り切れない場合は、右端・下端のタイルが小さくなる
+#[tauri::command]
+pub fn set_tile_size(px: u32) -> Result<(), String> {
+    let mut fractal = FRACTAL.lock().map_err(|e| format!("Inner Error: Mutex lock failed: {}", e))?;
+    let size = match &*fractal {
+        Fractal::F64(f) => f.canvas().size() as u32,
+        Fractal::F106(f) => f.canvas().size() as u32,
+    };
+
+    if px == 0 {
+        return Err("Tile size must be greater than 0.".to_string());
+    }
+    if px > size {
+        return Err(format!("Tile size ({}) must not exceed the canvas size ({}).", px, size));
+    }
+    if size % px != 0 {
+        log::warn!("Tile size {} does not divide the canvas size {}; edge tiles will be smaller.", px, size);
+    }
+
+    match &mut *fractal {
+        Fractal::F64(f) => f.set_tile_size(px),
+        Fractal::F106(f) => f.set_tile_size(px),
+    }
+    Ok(())
+}
+
+#[tauri::command]
+pub fn get_tile_size() -> u32 {
+    match &*FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.tile_size(),
+        Fractal::F106(f) => f.tile_size(),
+    }
+}
+
+/// # 境界追跡後に、未計算のピクセルを周囲の値で埋める処理の最大回数を設定する
+///
+/// 回数を増やすと直接計算するピクセルが減るが、埋める処理自体の時間が増える。
+/// 0の場合は埋めずに、境界追跡で計算しなかったピクセルを全て直接計算する
+///
+/// ## Params
+///  - passes: 行毎・列毎に埋める処理を繰り返す最大回数 (btm::MAX_FILL_PASSES 以下)
+#[tauri::command]
+pub fn set_fill_passes(passes: u32) -> Result<(), String> {
+    if passes > btm::MAX_FILL_PASSES {
+        return Err(format!("Fill passes ({}) must not exceed {}.", passes, btm::MAX_FILL_PASSES));
+    }
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_fill_passes(passes),
+        Fractal::F106(f) => f.set_fill_passes(passes),
+    }
+    Ok(())
+}
+
+/// # 境界追跡を、矩形領域の外側1pixelの枠も含めて行うかを設定する
+///
+/// 矩形領域の内部の basin が辺に接していない場合、辺を計算しただけでは境界を検出できず、周囲の値で埋められてしまう。
+/// 有効にすると枠の上で隣の領域から入ってくる境界を検出できるので、このような取りこぼしが減る。
+/// 代わりに、矩形領域 (calc_rect が分割した領域毎) に 2 * (幅 + 高さ) + 4 pixel を余分に計算する
+///
+/// ## Params
+///  - enabled: 枠を含めるか (初期値: false)
+#[tauri::command]
+pub fn set_boundary_halo(enabled: bool) {
+    cancel_render();
+    match &mut *FRACTAL.lock().unwrap() {
+        Fractal::F64(f) => f.set_boundary_halo(enabled),
+        Fractal::F106(f) => f.set_boundary_halo(enabled),
+    }
+}
+
+/// 矩形領域のデータ
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Tile {
+    x:      u32,
+    y:      u32,
+    w:      u32,
+    h:      u32,
+    data:   Vec<u16>,
+}
+
+/// # 表示領域をタイルサイズで分割した矩形領域の一覧を返す
+///
+/// 左上から右方向へ、行ごとに並べる。
+/// 表示領域の幅・高さで割り切れない場合は、右端・下端のタイルの幅・高さを切り詰める
+fn tile_rects(width: u32, height: u32, tile_size: u32) -> Vec<(u32, u32, u32, u32)> {
+    let tile_size = tile_size.clamp(1, width.max(height).max(1));
+    let mut rects = Vec::new();
+
+    for y in (0..height).step_by(tile_size as usize) {
+        for x in (0..width).step_by(tile_size as usize) {
+            let w = tile_size.min(width - x);
+            let h = tile_size.min(height - y);
+            rects.push((x, y, w, h));
+        }
+    }
+
+    rects
+}
+
+/// # タイルサイズで分割したcanvasのうち、指定された範囲のタイルを計算して返す
+///
+/// ## Params
+///  - start: 計算を開始するタイルの番号 (左上から行ごとに0, 1, 2, ...)
+///  - count: 計算するタイルの数
+///
+/// ## Returns
+///  - 計算したタイルの一覧。全てのタイルを計算し終えた場合は空になる
+#[tauri::command]
+pub async fn render_next_tiles(start: u32, count: u32) -> Result<Vec<Tile>, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let ((width, height), tile_size) = match &fractal {
+            Fractal::F64(f) => (f.canvas().viewport_size(), f.tile_size()),
+            Fractal::F106(f) => (f.canvas().viewport_size(), f.tile_size()),
+        };
+
+        Ok(in_render_pool(|| tile_rects(width, height, tile_size).into_iter()
+            .skip(start as usize)
+            .take(count as usize)
+            .map(|(x, y, w, h)| {
+                let data = with_fractal!(&fractal, |fr, fo| render_tile_inner(fr, fo, x, y, w, h));
+                Tile { x, y, w, h, data }
+            })
+            .collect()))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 収束先の根ごとの面積
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BasinArea {
+    /// 根の座標 (re, im)。未収束の領域の場合はNone
+    root:       Option<(f64, f64)>,
+    /// 表示領域全体に対する面積の割合 [0.0, 1.0]
+    fraction:   f64,
+}
+
+fn basin_areas_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>) -> Vec<BasinArea>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let (width, height) = fr.canvas().viewport_size();
+    let result = btm::calc_rect(calc_info(fr, fo, 0, 0, width, height));
+
+    // 末尾を未収束の領域 (不連続な跳躍を検出した領域を含む) として集計する
+    let mut counts = vec![0usize; result.roots.len() + 1];
+    for pixel in result.pixels.iter() {
+        match pixel.root_index() {
+            None => *counts.last_mut().unwrap() += 1,
+            Some(root) => counts[root] += 1,
+        }
+    }
+
+    let total = result.pixels.len().max(1) as f64;
+    let roots = result.roots.iter()
+        .map(|root| Some((root.re.to_f64(), root.im.to_f64())))
+        .chain(std::iter::once(None));
+
+    roots.zip(counts)
+        .map(|(root, count)| BasinArea { root, fraction: count as f64 / total })
+        .collect()
+}
+
+/// # 表示領域のうち、各根に収束する領域の面積の割合を返す
+///
+/// 表示領域のピクセル数で集計するので、移動・拡大縮小すると値が変化する。
+/// 未収束の領域は`root: None`として末尾に含める
+#[tauri::command]
+pub async fn basin_areas() -> Result<Vec<BasinArea>, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| basin_areas_inner(fr, fo))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 収束先の根ごとの反復回数の統計
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct BasinIterationStats {
+    /// 根の座標 (re, im)。未収束の領域の場合はNone
+    root:           Option<(f64, f64)>,
+    /// 集計した標本点の数
+    count:          usize,
+    /// 反復回数の平均。標本点が無い場合は0
+    mean_iter:      f64,
+    /// 反復回数の最大値
+    max_iter_seen:  u16,
+}
+
+fn basin_iteration_stats_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>) -> Vec<BasinIterationStats>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    /// 標本点の一辺の最大数。大きな表示領域でも、この解像度に間引いて集計する
+    const MAX_SAMPLES: u32 = 256;
+
+    let (width, height) = fr.canvas().viewport_within(MAX_SAMPLES);
+    let result = btm::calc_rect(view_calc_info(fr, fo, width, height));
+
+    // basin_areas と同様に、末尾を未収束の領域として集計する
+    let mut stats = vec![(0usize, 0u64, 0u16); result.roots.len() + 1];
+    for pixel in result.pixels.iter() {
+        let idx = pixel.root_index().unwrap_or(result.roots.len());
+        let (count, sum, max) = &mut stats[idx];
+        *count += 1;
+        *sum += pixel.iter as u64;
+        *max = (*max).max(pixel.iter);
+    }
+
+    let roots = result.roots.iter()
+        .map(|root| Some((root.re.to_f64(), root.im.to_f64())))
+        .chain(std::iter::once(None));
+
+    roots.zip(stats)
+        .map(|(root, (count, sum, max_iter_seen))| BasinIterationStats {
+            root,
+            count,
+            mean_iter: if count == 0 { 0.0 } else { sum as f64 / count as f64 },
+            max_iter_seen,
+        })
+        .collect()
+}
+
+/// # 収束先の根ごとに、反復回数の平均と最大値を返す
+///
+/// 根ごとの収束の速さ (難しさ) を比較するために使用する。
+/// 大きな表示領域では、表示領域全体を一辺最大256点に間引いて集計する。
+/// 未収束の領域は`root: None`として末尾に含める
+#[tauri::command]
+pub async fn basin_iteration_stats() -> Result<Vec<BasinIterationStats>, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| basin_iteration_stats_inner(fr, fo))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn period_map_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let (width, height) = fr.canvas().viewport_size();
+    let info = calc_info(fr, fo, 0, 0, width, height);
+    let (w, h) = (width as i64, height as i64);
+
+    // 周期軌道に引き込まれる領域は、根の番号では全て UNCONVERGED となり区別できないので、境界追跡を使わずに全てのピクセルを計算する
+    (0..w * h).into_par_iter()
+        .map(|idx| info.attracting_period(info.get_complex(idx % w, idx / w), btm::DEFAULT_MAX_PERIOD))
+        .collect()
+}
+
+/// # 周期毎に、表示領域に対する面積の割合を集計する
+///
+/// ## Returns
+///  - (周期, 面積の割合) を面積の大きい順に並べたもの
+fn period_fractions(periods: &[u16]) -> Vec<(u16, f64)> {
+    let mut counts = std::collections::BTreeMap::<u16, usize>::new();
+    for &period in periods {
+        *counts.entry(period).or_default() += 1;
+    }
+
+    let total = periods.len().max(1) as f64;
+    let mut fractions: Vec<(u16, f64)> = counts.into_iter()
+        .map(|(period, count)| (period, count as f64 / total))
+        .collect();
+    fractions.sort_by(|a, b| b.1.total_cmp(&a.1));
+    fractions
+}
+
+/// # 表示領域の各ピクセルについて、反復が引き込まれる周期軌道の周期を返す
+///
+/// 緩和係数を1以外にした場合などに現れる、根以外の吸引的な周期軌道の構造を調べるために使用する。
+/// 周期1は根への通常の収束を表す。16以下の周期軌道が見つからない場合は0とする
+///
+/// ## Returns
+///  - 各ピクセルの周期 (y * width + x)。width, height は表示領域の幅と高さ
+#[tauri::command]
+pub async fn period_map() -> Result<Vec<u16>, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| period_map_inner(fr, fo))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 表示領域に現れる周期と、その面積の割合を返す
+///
+/// period_map を周期毎に集計したもの。周期1は根への通常の収束、周期0は周期軌道が見つからなかった領域を表す
+///
+/// ## Returns
+///  - (周期, 面積の割合 [0.0, 1.0]) を面積の大きい順に並べたもの
+#[tauri::command]
+pub async fn dominant_periods() -> Result<Vec<(u16, f64)>, String> {
+    Ok(period_fractions(&period_map().await?))
+}
+
+/// # 表示領域全体を samples * samples に間引いて、max_iter で収束するピクセルの割合を返す
+fn converged_fraction<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, samples: u32, max_iter: u16) -> f64
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let mut info = view_calc_info(fr, fo, samples, samples);
+    info.max_itr = max_iter;
+    let result = btm::calc_rect(info);
+    let converged = result.pixels.iter().filter(|p| p.root_index().is_some()).count();
+    converged as f64 / result.pixels.len().max(1) as f64
+}
+
+fn min_iter_for_coverage_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, target: f64, samples: u32) -> u16
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let reached = |max_iter: u16| converged_fraction(fr, fo, samples, max_iter) >= target;
+
+    // 大きな max_iter は計算が重いので、倍々に増やして上限を見つけてから二分探索する
+    let mut hi: u16 = 1;
+    while !reached(hi) {
+        if hi == u16::MAX {
+            return u16::MAX;
+        }
+        hi = hi.saturating_mul(2);
+    }
+
+    let mut lo = hi / 2; // lo では届かない (hi = 1 の場合は lo = 0 として扱う)
+    while hi - lo > 1 {
+        let mid = lo + (hi - lo) / 2;
+        if reached(mid) { hi = mid } else { lo = mid }
+    }
+    hi
+}
+
+/// # 表示領域のうち target の割合のピクセルが収束する、最小の max_iter を返す
+///
+/// 表示領域全体を一辺最大128点に間引いて計算し、max_iter を変えながら二分探索する。
+/// max_iter を増やしても収束する割合は減らないことを前提とする。設定中の max_iter は変更しない
+///
+/// ## Params
+///  - target: 収束するピクセルの割合 [0.0, 1.0]
+///
+/// ## Returns
+///  - 最小の max_iter。u16::MAX でも届かない場合は u16::MAX
+#[tauri::command]
+pub async fn min_iter_for_coverage(target: f64) -> Result<u16, String> {
+    /// 一辺あたりの標本点の最大数
+    const MAX_SAMPLES: u32 = 128;
+
+    if !(0.0..=1.0).contains(&target) {
+        return Err("Target must be between 0.0 and 1.0.".to_string());
+    }
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            let samples = (fr.canvas().size() as u32).clamp(1, MAX_SAMPLES);
+            min_iter_for_coverage_inner(fr, fo, target, samples)
+        })))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 反復回数を [0, max_iter) を buckets 等分した区間毎に数える
+///
+/// ## Returns
+///  - buckets + 1 個の度数。末尾は最大反復回数に達した (未収束の) 点の数
+fn iteration_histogram(escape_times: &[u16], max_iter: u16, buckets: u32) -> Vec<u32> {
+    let buckets = buckets.max(1) as usize;
+    let max_iter = max_iter.max(1) as usize;
+    let mut counts = vec![0u32; buckets + 1];
+    for &iter in escape_times {
+        let bin = if iter as usize >= max_iter { buckets } else { iter as usize * buckets / max_iter };
+        counts[bin] += 1;
+    }
+    counts
+}
+
+/// # 現在の表示領域の反復回数のヒストグラムを返す
+///
+/// カラーマップの操作部の下に描画して、配色がどの反復回数の範囲に使われているかを確認するために使用する
+///
+/// ## Params
+///  - buckets: [0, max_iter) を分割する区間の数
+///
+/// ## Returns
+///  - buckets + 1 個の度数。末尾は最大反復回数に達した (未収束の) ピクセルの数
+#[tauri::command]
+pub async fn color_histogram(buckets: u32) -> Result<Vec<u32>, String> {
+    if buckets == 0 {
+        return Err("Buckets must be greater than 0.".to_string());
+    }
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            let (width, height) = fr.canvas().viewport_size();
+            let result = btm::calc_rect(calc_info(fr, fo, 0, 0, width, height));
+            iteration_histogram(&result.escape_times(), fr.max_iter(), buckets)
+        })))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 右と下の標本点と収束先の根が異なる箇所毎に、2つの標本点の座標 (x, y) を渡して f を呼び出す
+fn for_each_root_transition(pixels: &[btm::Pixel], width: usize, mut f: impl FnMut((usize, usize), (usize, usize))) {
+    let w = width.max(1);
+    let h = pixels.len() / w;
+    for y in 0..h {
+        for x in 0..w {
+            let root = pixels[y * w + x].root;
+            if x + 1 < w && pixels[y * w + x + 1].root != root {
+                f((x, y), (x + 1, y));
+            }
+            if y + 1 < h && pixels[(y + 1) * w + x].root != root {
+                f((x, y), (x, y + 1));
+            }
+        }
+    }
+}
+
+fn converged_bounds_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, samples: u32) -> (f64, f64, f64, f64)
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let info = view_calc_info(fr, fo, samples, samples);
+    let result = btm::calc_rect(info.clone());
+    let w = samples as usize;
+
+    // 収束先の根が切り替わる場合、両方の標本点を範囲に含める
+    let mut bounds: Option<(usize, usize, usize, usize)> = None;
+    let mut include = |(x, y): (usize, usize)| {
+        bounds = Some(match bounds {
+            Some((x0, y0, x1, y1)) => (x0.min(x), y0.min(y), x1.max(x), y1.max(y)),
+            None => (x, y, x, y),
+        });
+    };
+    for_each_root_transition(&result.pixels, w, |a, b| {
+        include(a);
+        include(b);
+    });
+
+    match bounds {
+        Some((x0, y0, x1, y1)) => {
+            let min = info.get_complex(x0 as i64, y0 as i64);
+            let max = info.get_complex(x1 as i64, y1 as i64);
+            (min.re.to_f64(), min.im.to_f64(), max.re.to_f64(), max.im.to_f64())
+        },
+        None => {
+            let center = fr.canvas().center();
+            let (re, im) = (center.re.to_f64(), center.im.to_f64());
+            let half = fr.canvas().width().to_f64() / 2.0;
+            (re - half, im - half, re + half, im + half)
+        },
+    }
+}
+
+/// # 収束先の根が切り替わる領域を囲む矩形を返す
+///
+/// 表示領域を samples * samples の格子点で標本化し、隣接する標本点の収束先の根が異なる箇所を全て囲む。
+/// 根が切り替わる箇所が無い場合は、表示領域全体を返す。
+/// 矩形は複素数平面上の範囲なので、投影 (set_projection) が "Planar" の場合のみ使用できる
+///
+/// ## Params
+///  - samples: 一辺あたりの標本点の数
+///
+/// ## Returns
+///  - (min_re, min_im, max_re, max_im)
+#[tauri::command]
+pub async fn converged_bounds(samples: u32) -> Result<(f64, f64, f64, f64), String> {
+    if samples < 2 {
+        return Err("samples must be 2 or more.".to_string());
+    }
+    btm::check_memory_budget(samples, samples)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        // 投影した表示領域は、2つの隅の座標で囲めない
+        if with_fractal!(&fractal, |fr, _fo| fr.projection) != btm::Projection::Planar {
+            return Err("Converged bounds are only available with the planar projection.".to_string());
+        }
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| converged_bounds_inner(fr, fo, samples))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// random_interesting_view で選んだ表示領域
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct InterestingView {
+    center_str: String,
+    zoom_level: i32,
+    /// 隣接する標本点の組のうち、収束先の根が異なる組の割合 [0.0, 1.0]
+    score:      f64,
+}
+
+/// # 標本点の格子のうち、隣接する標本点の収束先の根が異なる組の割合を返す
+fn transition_density(pixels: &[btm::Pixel], width: usize) -> f64 {
+    let height = pixels.len() / width.max(1);
+    let pairs = width.saturating_sub(1) * height + width * height.saturating_sub(1);
+    let mut transitions = 0usize;
+    for_each_root_transition(pixels, width, |_, _| transitions += 1);
+    transitions as f64 / pairs.max(1) as f64
+}
+
+/// # 現在の表示領域内から、収束先の根の境界が密な表示領域を選ぶ
+///
+/// ## Params
+///  - seed: 候補の中心座標を決める乱数のシード
+///  - max_zoom: ズーム段階の上限
+///
+/// ## Returns
+///  - (中心座標, ズーム段階, 評価値)
+fn choose_interesting_view<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    seed: u64, max_zoom: i32,
+) -> (Complex<T>, i32, f64)
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    /// 評価する中心座標の候補の数
+    const CANDIDATES: usize = 16;
+    /// 候補を評価する際の一辺あたりの標本点の数
+    const SAMPLES: u32 = 32;
+    /// 現在の表示領域から拡大するズーム段階 (16段階で4倍)
+    const ZOOM_LEVELS: i32 = 16;
+
+    let mut rng = fastrand::Rng::with_seed(seed);
+    let mut canvas = fr.canvas().clone();
+    canvas.zoom_level = (canvas.zoom_level + ZOOM_LEVELS).min(max_zoom);
+    let half_width = canvas.width() / T::from_f64(2.0);
+
+    let width = fr.canvas().width();
+    let center = fr.canvas().center().clone();
+    let candidates: Vec<Complex<T>> = (0..CANDIDATES)
+        .map(|_| Complex::new(
+            center.re.clone() + width.clone() * T::from_f64(rng.f64() - 0.5),
+            center.im.clone() + width.clone() * T::from_f64(rng.f64() - 0.5),
+        ))
+        .collect();
+
+    let (center, score) = candidates.into_iter()
+        .map(|c| {
+            let result = btm::calc_rect(figure_calc_info(fr, fo, c.clone(), half_width.clone(), SAMPLES, SAMPLES));
+            let score = transition_density(&result.pixels, SAMPLES as usize);
+            (c, score)
+        })
+        .max_by(|a, b| a.1.total_cmp(&b.1))
+        .unwrap(); // CANDIDATES > 0 なので、必ず存在する
+
+    (center, canvas.zoom_level, score)
+}
+
+/// # 収束先の根の境界が密な表示領域に移動する
+///
+/// 現在の表示領域内の中心座標の候補を乱数で選び、4倍に拡大した表示領域を粗く計算して、
+/// 隣接する標本点の収束先の根が異なる割合が最も高い候補に移動する。
+/// 同じシードと表示領域からは、常に同じ候補が選ばれる
+///
+/// ## Params
+///  - seed: 乱数のシード
+///
+/// ## Returns
+///  - 移動後の中心座標の文字列、ズーム段階、評価値
+#[tauri::command]
+pub async fn random_interesting_view(seed: u64) -> Result<InterestingView, String> {
+    cancel_render();
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<InterestingView, String> {
+        // 候補の計算中も他のコマンドを受け付けられるように、複製した状態で候補を選ぶ
+        let fractal = prepare_render()?;
+        let max_zoom = fractal.zoom_threshold().end as i32;
+        let (view, score) = in_render_pool(|| match &fractal {
+            Fractal::F64(f) => {
+                let Formulac::F64(fo) = f.formulac() else { unreachable!() };
+                let (center, zoom_level, score) = choose_interesting_view(f, fo, seed, max_zoom);
+                let center = Complex::new(F106::from_f64(center.re), F106::from_f64(center.im));
+                (SavedView { center, zoom_level }, score)
+            },
+            Fractal::F106(f) => {
+                let Formulac::F106(fo) = f.formulac() else { unreachable!() };
+                let (center, zoom_level, score) = choose_interesting_view(f, fo, seed, max_zoom);
+                (SavedView { center, zoom_level }, score)
+            },
+        });
+
+        let mut fractal = FRACTAL.lock().unwrap();
+        record_navigation(&fractal, Navigation::Jump);
+        fractal.apply_view(view);
+        Ok(InterestingView {
+            center_str: with_fractal!(&*fractal, |fr, _fo| get_center_str_inner(*fr.canvas().center())),
+            zoom_level: view.zoom_level,
+            score,
+        })
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn profile_along_line_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    from: (f64, f64), to: (f64, f64), samples: usize,
+) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let from = Complex::new(T::from_f64(from.0), T::from_f64(from.1));
+    let to = Complex::new(T::from_f64(to.0), T::from_f64(to.1));
+    let delta = to - from.clone();
+    let points: Vec<Complex<T>> = (0..samples)
+        .map(|i| {
+            // samples = 1 の場合は始点のみ
+            let t = T::from_f64(i as f64 / (samples.max(2) - 1) as f64);
+            from.clone() + delta.clone() * t
+        })
+        .collect();
+
+    btm::calc_points(&calc_info(fr, fo, 0, 0, 1, 1), &points)
+}
+
+/// # 複素平面上の線分に沿った反復回数を返す
+///
+/// 始点と終点を含む samples 個の点を等間隔に計算するので、フラクタルの断面をグラフとして表示できる
+///
+/// ## Params
+///  - from, to: 線分の始点と終点 (re, im)
+///  - samples: 計算する点の数
+///
+/// ## Returns
+///  - 始点から順に並べた各点の反復回数
+#[tauri::command]
+pub async fn profile_along_line(from: (f64, f64), to: (f64, f64), samples: usize) -> Result<Vec<u16>, String> {
+    if samples == 0 {
+        return Err("samples must be greater than 0.".to_string());
+    }
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| profile_along_line_inner(fr, fo, from, to, samples))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn escape_time_batch_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, points: &[(f64, f64)]) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let points: Vec<Complex<T>> = points.iter()
+        .map(|&(re, im)| Complex::new(T::from_f64(re), T::from_f64(im)))
+        .collect();
+
+    btm::calc_points(&calc_info(fr, fo, 0, 0, 1, 1), &points)
+}
+
+/// # 複素平面上の任意の点の集合について、反復回数をまとめて返す
+///
+/// 格子状でない点 (Monte Carlo法による basin の面積の推定や、散布図の重ね描きなど) を、並列に計算する。
+/// FRACTAL は複製する間だけlockし、コンパイル済みの関数は複製したものを全スレッドで共有する
+///
+/// ## Params
+///  - points: 計算する点 (re, im)
+///
+/// ## Returns
+///  - points と同じ順番の反復回数
+#[tauri::command]
+pub async fn escape_time_batch(points: Vec<(f64, f64)>) -> Result<Vec<u16>, String> {
+    if let Some((re, im)) = points.iter().find(|(re, im)| !(re.is_finite() && im.is_finite())) {
+        return Err(format!("Invalid point: ({}, {}).", re, im));
+    }
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| escape_time_batch_inner(fr, fo, &points))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_scanline_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, y: u32, width: u32) -> Vec<u16>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let info = calc_info(fr, fo, 0, y, width, 1);
+    let points: Vec<Complex<T>> = (0..width as i64)
+        .map(|x| info.get_complex(x, 0))
+        .collect();
+
+    btm::calc_points(&info, &points)
+}
+
+/// # canvasの1行分の反復回数を、境界追跡を行わずに直接計算する
+///
+/// 全てのピクセルを計算するので、境界追跡で計算を省略できない最悪の場合の1pixelあたりの計算コストを測定できる。
+/// タイルの分割や境界追跡の影響を受けないため、数式や拡大率による反復の速度の比較 (ベンチマーク) に使用する
+///
+/// ## Params
+///  - y: 計算する行 (canvas全体に対するY座標)
+///  - width: 計算するピクセル数 (canvasの左端から)
+#[tauri::command]
+pub async fn render_scanline(y: u32, width: u32) -> Result<Vec<u16>, String> {
+    if width == 0 {
+        return Err("Width must be greater than 0.".to_string());
+    }
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_scanline_inner(fr, fo, y, width))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn iteration_range_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, samples: u32) -> (u16, u16)
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let result = btm::calc_rect(view_calc_info(fr, fo, samples, samples));
+    let converged = result.pixels.iter()
+        .filter(|pixel| pixel.root_index().is_some())
+        .map(|pixel| pixel.iter);
+
+    match converged.clone().min().zip(converged.max()) {
+        Some(range) => range,
+        None => (0, fr.max_iter()),
+    }
+}
+
+fn render_iter_diff_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    max_iter_a: u16, max_iter_b: u16,
+    width: u32, height: u32,
+) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let calc = |max_iter: u16| {
+        let mut info = view_calc_info(fr, fo, width, height);
+        info.max_itr = max_iter;
+        btm::calc_rect(info)
+    };
+    let a = calc(max_iter_a);
+    let mut b = calc(max_iter_b);
+    a.align_roots(&mut b);
+
+    a.pixels.iter().zip(&b.pixels)
+        .map(|(pa, pb)| (pa.root != pb.root) as u8)
+        .collect()
+}
+
+/// # 2つの最大反復回数で表示領域を計算し、収束先の根の判定が異なるピクセルを返す
+///
+/// ほとんどのピクセルが0であれば、小さい方の最大反復回数で十分であることが分かる
+///
+/// ## Params
+///  - max_iter_a, max_iter_b: 比較する最大反復回数
+///  - width, height: 計算する解像度
+///
+/// ## Returns
+///  - 判定が異なるピクセルは1、同じピクセルは0 (y * width + x)
+#[tauri::command]
+pub async fn render_iter_diff(max_iter_a: u16, max_iter_b: u16, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_iter_diff_inner(fr, fo, max_iter_a, max_iter_b, width, height))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_formula_diff_inner<T>(
+    fr: &FractalInner<T>,
+    formula_a: &str, formula_b: &str,
+    width: u32, height: u32,
+) -> Result<Vec<u8>, String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let calc = |formula: &str| -> Result<btm::RectResult<T>, String> {
+        let mut fo = fr.uncompiled_formulac().map_err(|e| e.to_string())?;
+        fo.set_formula(formula).map_err(|e| e.to_string())?;
+        // 根のキャッシュは数式毎に必要なので、共有しない
+        let mut fr = fr.clone();
+        fr.invalidate_roots();
+        Ok(btm::calc_rect(view_calc_info(&fr, &fo, width, height)))
+    };
+    let a = calc(formula_a)?;
+    let mut b = calc(formula_b)?;
+    a.pair_roots(&mut b);
+
+    Ok(a.pixels.iter().zip(&b.pixels)
+        .map(|(pa, pb)| (pa.root != pb.root) as u8)
+        .collect())
+}
+
+/// # 2つの数式で現在の表示領域を計算し、収束先の根が異なるピクセルを返す
+///
+/// 2つの数式の根は、距離が近いもの同士を同じ根とみなして番号を揃える (RectResult::pair_roots)。
+/// `z^3 - 1` と `z^3 - 1.01` のような小さな摂動で、basin がどこで変わるかを調べるために使用する。
+/// 設定中の数式は変更しない
+///
+/// ## Params
+///  - formula_a, formula_b: 比較する数式
+///  - width, height: 計算する解像度
+///
+/// ## Returns
+///  - 収束先の根が異なるピクセルは1、同じピクセルは0 (y * width + x)
+#[tauri::command]
+pub async fn render_formula_diff(formula_a: String, formula_b: String, width: u32, height: u32) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    for formula in [&formula_a, &formula_b] {
+        validate_formula(formula, &FormulaLimits::default())?;
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = snapshot_fractal();
+        in_render_pool(|| with_fractal!(&fractal, |fr, _fo| render_formula_diff_inner(fr, &formula_a, &formula_b, width, height)))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 根の近傍の円板の半径を返す
+///
+/// 最も近い他の根までの距離の 1/10 とし、根が1つしか無い場合は 1/10 (|root| > 1 の場合は |root| / 10) とする
+fn root_disk_radius<T>(roots: &[Complex<T>], index: usize) -> T
+where
+    T: Real + Send + Sync + 'static,
+{
+    const RATIO: f64 = 0.1;
+    let root = &roots[index];
+    let nearest = roots.iter().enumerate()
+        .filter(|&(i, _)| i != index)
+        .map(|(_, r)| (r.clone() - root.clone()).abs().re)
+        .reduce(|a, b| if b < a { b } else { a });
+    let scale = nearest.unwrap_or_else(|| {
+        let norm = root.clone().abs().re;
+        if norm > T::one() { norm } else { T::one() }
+    });
+    scale * T::from_f64(RATIO)
+}
+
+fn render_preimage_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    root_index: usize, depth: u32,
+    width: u32, height: u32,
+) -> Result<Vec<u16>, String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let roots = fr.cached_roots(fo);
+    if root_index >= roots.len() {
+        return Err(format!("Root index {} is out of range ({} roots).", root_index, roots.len()));
+    }
+    let root = roots[root_index].clone();
+    let radius = root_disk_radius(roots, root_index);
+    let info = view_calc_info(fr, fo, width, height);
+
+    Ok((0..height as i64).into_par_iter().flat_map_iter(|y| {
+        let (info, root, radius) = (&info, &root, &radius);
+        (0..width as i64).map(move |x| {
+            info.newton_orbit(info.get_complex(x, y), depth as usize)
+                .iter()
+                .position(|z| (z.clone() - root.clone()).abs().re < *radius)
+                .map_or(btm::UNCONVERGED, |k| k as u16)
+        })
+    }).collect())
+}
+
+/// # 指定した根の近傍に入るまでの反復回数を、ピクセル毎に返す
+///
+/// 根を中心とする小さな円板 (半径は最も近い他の根までの距離の 1/10) に、何回目の反復で初めて入るかを数える。
+/// 脱出時間とは異なり特定の根のみを対象とするので、その根の basin に流れ込む点の構造 (逆像) が分かる
+///
+/// ## Params
+///  - root_index: 対象とする根の番号 (render_tile などの根の番号と同じ)
+///  - depth: 反復回数の上限 (最大 65534)
+///  - width, height: 計算する解像度
+///
+/// ## Returns
+///  - 円板に入った反復回数 (0 は最初から円板内)。depth 回以内に入らない場合は 65535 (y * width + x)
+#[tauri::command]
+pub async fn render_preimage(root_index: usize, depth: u32, width: u32, height: u32) -> Result<Vec<u16>, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    if depth >= btm::UNCONVERGED as u32 {
+        return Err(format!("Depth must be less than {}.", btm::UNCONVERGED));
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_preimage_inner(fr, fo, root_index, depth, width, height)))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 表示領域内で収束したピクセルの、反復回数の最小値と最大値を返す
+///
+/// カラーマップの定義域を [0, max_iter] ではなく実際の反復回数の範囲にするために使用する (set_colormap_domain)。
+/// 結果は表示領域・数式・最大反復回数が変わるまでキャッシュする
+///
+/// ## Params
+///  - samples: 一辺あたりの標本点の数
+///
+/// ## Returns
+///  - (min, max)。収束したピクセルが無い場合は (0, max_iter)
+#[tauri::command]
+pub async fn iteration_range(samples: u32) -> Result<(u16, u16), String> {
+    if samples == 0 {
+        return Err("samples must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(samples, samples)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        // 複製の前に世代番号を取得するので、複製の間に表示領域が変わった場合は次の呼び出しで再計算される
+        let generation = render_generation();
+        let fractal = prepare_render()?;
+        if let Some(cache) = &*ITERATION_RANGE_CACHE.lock().unwrap()
+            && cache.generation == generation && cache.samples == samples
+        {
+            return Ok(cache.range);
+        }
+
+        let range = in_render_pool(|| with_fractal!(&fractal, |fr, fo| iteration_range_inner(fr, fo, samples)));
+        *ITERATION_RANGE_CACHE.lock().unwrap() = Some(IterationRangeCache { generation, samples, range });
+        Ok(range)
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 行の順番を逆転させる
+///
+/// canvasとy軸が反転しているので、画像として出力する前に使用する。
+/// chunks(width).rev().flat_map(|row| row.iter().copied()).collect() でも実装可能だが、
+/// メモリ消費量を抑えるために、インプレースする
+fn flip_rows<P>(data: &mut [P], width: usize, height: usize) {
+    for i in 0..(height / 2) {
+        let top = i * width;
+        let bottom = (height - i - 1) * width;
+        for x in 0..width {
+            data.swap(top + x, bottom + x);
+        }
+    }
+}
+
+/// # 画像データをPNG形式で書き込む
+///
+/// ## Params
+///  - writer: 書き込み先
+///  - width, height: 画像の幅と高さ
+///  - color: dataの色の形式
+///  - data: 画素データ
+///  - texts: tEXtチャンクに書き込むキーワードと値の組
+fn write_png<W: std::io::Write>(
+    writer: W,
+    width: u32, height: u32,
+    color: png::ColorType,
+    data: &[u8],
+    texts: Vec<(String, String)>,
+) -> Result<(), String> {
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    for (keyword, text) in texts {
+        let _ = encoder.add_text_chunk(keyword, text);
+    }
+
+    let mut png_writer = encoder.write_header().map_err(|e| e.to_string())?;
+    png_writer.write_image_data(data).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// # RGBAの画素データを、画素の色から作成したパレットのインデックスカラーPNGに書き込む
+///
+/// パレットはメディアンカット法で作成するので、色付けの方法によらず描画した色に近い色になる。
+/// 不透明でない色がある場合は、パレットの不透明度をtRNSチャンクに書き込む
+///
+/// ## Params
+///  - rgba: RGBAの画素データ
+///  - palette_size: パレットの最大の色数 (2 <= palette_size <= 256)
+///  - texts: tEXtチャンクに書き込むキーワードと値の組
+fn write_indexed_png<W: std::io::Write>(
+    writer: W,
+    width: u32, height: u32,
+    rgba: &[u8],
+    palette_size: u16,
+    texts: Vec<(String, String)>,
+) -> Result<(), String> {
+    if !(2..=256).contains(&palette_size) {
+        return Err(format!("Palette size must be between 2 and 256: {}.", palette_size));
+    }
+
+    let palette = coloring::median_cut_palette(rgba, palette_size as usize);
+    let indices = coloring::quantize_to_palette(rgba, &palette);
+
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Indexed);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_palette(palette.iter().flat_map(|&[r, g, b, _]| [r, g, b]).collect::<Vec<u8>>());
+    if palette.iter().any(|color| color[3] != u8::MAX) {
+        encoder.set_trns(palette.iter().map(|color| color[3]).collect::<Vec<u8>>());
+    }
+    for (keyword, text) in texts {
+        let _ = encoder.add_text_chunk(keyword, text);
+    }
+
+    let mut png_writer = encoder.write_header().map_err(|e| e.to_string())?;
+    png_writer.write_image_data(&indices).map_err(|e| e.to_string())?;
+
+    Ok(())
+}
+
+/// # 画像データをPNG形式にエンコードし、base64文字列で返す
+fn encode_png_base64(width: u32, height: u32, color: png::ColorType, data: &[u8]) -> Result<String, String> {
+    let mut bytes = Vec::new();
+    write_png(&mut bytes, width, height, color, data, Vec::new())?;
+    Ok(base64::engine::general_purpose::STANDARD.encode(bytes))
+}
+
+/// # 根の座標を、色付けに渡すf64の複素数に変換する
+fn roots_f64<T: ToF64>(roots: &[Complex<T>]) -> Vec<Complex<f64>> {
+    roots.iter().map(|z| Complex::new(z.re.to_f64(), z.im.to_f64())).collect()
+}
+
+/// # 色付けに必要な、ピクセル毎の収束比または連続的な反復回数を計算する
+///
+/// どちらも連続的に変化するので、境界追跡を使わずに全てのピクセルを計算する。色付けに不要な場合は空
+fn coloring_values<T>(coloring: &Coloring, info: &btm::CalcInfo<T>) -> Vec<f64>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    if coloring.needs_ratios() {
+        btm::calc_convergence_ratios(info).iter().map(|ratio| ratio.to_f64()).collect()
+    } else if coloring.needs_smooth() {
+        btm::calc_smooth_rect(info).1.iter().map(|iter| iter.to_f64()).collect()
+    } else {
+        Vec::new()
+    }
+}
+
+/// # 矩形領域を計算し、色付けの設定に従ってRGBAの画素データを返す
+fn colorize_rect<T>(coloring: &Coloring, info: btm::CalcInfo<T>) -> Vec<u8>
+where
+    T: Real + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let max_iter = info.max_itr;
+    let values = coloring_values(coloring, &info);
+
+    let result = btm::calc_rect(info);
+    coloring.colorize(&result.pixels, &values, &roots_f64(&result.roots), max_iter)
+}
+
+/// # 表示領域を計算し、色付けの設定に従ってRGBAの画素データを返す
+///
+/// 画像として出力するため、行の順番は反転済み
+fn render_rgba_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, coloring: &Coloring, width: u32, height: u32) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let mut rgba = colorize_rect(coloring, view_calc_info(fr, fo, width, height));
+    flip_rows(&mut rgba, width as usize * 4, height as usize); // 1行は width * 4 byte
+    rgba
+}
+
+/// ## Returns
+///  - (PNGに書き込むパラメータ, (表示領域の幅, 高さ))
+fn save_png_get_data<T>(f: &FractalInner<T>) -> Result<(String, (u32, u32)), String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + Serialize,
+{
+    let metadata = serde_json::to_string(f)
+        .map_err(|e| format!("Inner Error: JSON serialization failed: {}", e))?;
+    Ok((metadata, f.canvas().viewport_size()))
+}
+
+/// # サーバー側で画像を色付けする際に使用する値を設定する
+///
+/// ## Params
+///  - mode: "Colormap" (カラーマップ)、 "Basin" (収束先の根) または "Formula" (set_color_formula で設定した式)
+#[tauri::command]
+pub fn set_coloring_mode(mode: ColoringMode) {
+    COLORING.lock().unwrap().set_mode(mode);
+}
+
+/// # 反復回数 n と収束した点 z の式で、色付けする値を設定する
+///
+/// 式の値の実部を [0.0, 1.0] に丸めてカラーマップに渡す。set_coloring_mode で "Formula" を選択した場合に使用する。
+/// 境界追跡で埋めたピクセルは最後の反復の点を持たないので、z には収束先の根の座標を渡す
+///
+/// ## Params
+///  - expr: 変数 n, z の式。空文字列の場合は式を解除する
+#[tauri::command]
+pub fn set_color_formula(expr: String) -> Result<(), String> {
+    let formula = match expr.trim() {
+        "" => None,
+        expr => {
+            validate_formula(expr, &FormulaLimits::default())?;
+            Some(coloring::ColorFormula::compile(expr)?)
+        }
+    };
+    COLORING.lock().unwrap().set_color_formula(formula);
+    Ok(())
+}
+
+/// # サーバー側で画像を色付けする際のカラーマップを設定する
+///
+/// render_tile_rgba などの色付けに使用する
+///
+/// ## Params
+///  - colormap: "Jet"、"Viridis"、"Magma"、"Grayscale" または "Hsv"
+#[tauri::command]
+pub fn set_colormap(colormap: Colormap) {
+    COLORING.lock().unwrap().set_colormap(colormap);
+}
+
+/// # カラーマップで色付けする値を設定する
+///
+/// ## Params
+///  - source: "EscapeTime" (反復回数)、"ConvergenceSpeed" (最後の反復の収束比) または "SmoothEscapeTime" (連続的な反復回数)
+#[tauri::command]
+pub fn set_coloring_source(source: ColoringSource) {
+    COLORING.lock().unwrap().set_source(source);
+}
+
+/// # サーバー側で出力する画像の不透明度の決め方を設定する
+///
+/// 複数の数式の画像を重ねて合成する用途向けに、反復回数に比例した不透明度を出力できる。
+/// 色は通常通り決まるので、Basin と組み合わせると収束先の根毎の色になる
+///
+/// ## Params
+///  - source: "Constant" (常に不透明) または "IterationCount" (反復回数に比例)
+#[tauri::command]
+pub fn set_alpha_source(source: AlphaSource) {
+    COLORING.lock().unwrap().set_alpha_source(source);
+}
+
+/// # 反復回数をカラーマップで色付けする際の定義域を設定する
+///
+/// ## Params
+///  - domain: (min, max)。Noneの場合は [0, max_iter] とする
+#[tauri::command]
+pub fn set_colormap_domain(domain: Option<(u16, u16)>) -> Result<(), String> {
+    if let Some((min, max)) = domain && min >= max {
+        return Err(format!("Invalid colormap domain: [{}, {}].", min, max));
+    }
+    COLORING.lock().unwrap().set_domain(domain);
+    Ok(())
+}
+
+/// # 収束先の根毎の色を設定する
+///
+/// 根のindexの順に色を指定する。根の数より少ない場合、残りの根には色相環を等間隔に分割した色を割り当てる
+///
+/// ## Params
+///  - colors: (R, G, B) の配列
+#[tauri::command]
+pub fn set_basin_colors(colors: Vec<(u8, u8, u8)>) {
+    COLORING.lock().unwrap()
+        .set_basin_colors(colors.into_iter().map(|(r, g, b)| [r, g, b]).collect());
+}
+
+/// # 根毎の色の明るさを、反復回数に応じて変化させるかを設定する
+#[tauri::command]
+pub fn set_basin_shading(shading: bool) {
+    COLORING.lock().unwrap().set_basin_shading(shading);
+}
+
+#[tauri::command]
+pub async fn save_png(path: String) -> Result<(), String> {
+    let (metadata, (width, height)) = {
+        match &*FRACTAL.lock().map_err(|e| format!("Inner Error: Mutex lock failed: {}", e))? {
+            Fractal::F64(f) => save_png_get_data(f)?,
+            Fractal::F106(f) => save_png_get_data(f)?,
+        }
+    };
+    let enum_index = FRACTAL.lock().map_err(|e| format!("Inner Error: Mutex lock failed: {}", e))?
+        .to_index();
+    btm::check_memory_budget(width, height)?;
+
+    let rgba_data = tauri::async_runtime::spawn_blocking(move || -> Result<Vec<u8>, String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_rgba_inner(fr, fo, &coloring, width, height))))
+    }).await.map_err(|e| e.to_string())??;
+
+    let file = std::fs::File::create(&path)
+        .map_err(|e| e.to_string())?;
+    let writer = std::io::BufWriter::new(file);
+
+    write_png(writer, width, height, png::ColorType::Rgba, &rgba_data, vec![
+        ("FractalParameters".to_string(), metadata),
+        ("Generics".to_string(), format!("{}", enum_index)),
+    ])
+}
+
+/// # 表示領域を width * height で計算し、save_png と同じパラメータを書き込んだPNGとして path に保存する
+///
+/// 計算に失敗した場合に空のファイルを残さないように、ファイルは計算後に作成する
+fn save_image_inner(fractal: &Fractal, coloring: &Coloring, path: &str, width: u32, height: u32) -> Result<(), String> {
+    fractal.ensure_formula_loaded()?;
+    let (metadata, _) = match fractal {
+        Fractal::F64(f) => save_png_get_data(f)?,
+        Fractal::F106(f) => save_png_get_data(f)?,
+    };
+    let rgba = in_render_pool(|| with_fractal!(fractal, |fr, fo| render_rgba_inner(fr, fo, coloring, width, height)));
+
+    let file = std::fs::File::create(path)
+        .map_err(|e| format!("Failed to create {}: {}", path, e))?;
+    write_png(std::io::BufWriter::new(file), width, height, png::ColorType::Rgba, &rgba, vec![
+        ("FractalParameters".to_string(), metadata),
+        ("Generics".to_string(), format!("{}", fractal.to_index())),
+    ])
+}
+
+/// # 表示領域を指定した解像度で計算し、PNGとして保存する
+///
+/// save_png と異なり、表示領域の大きさに関わらず width * height で計算する。
+/// 縦横比が1:1でない場合は、表示領域の中央を切り出す。save_png と同じく import_from_png で読み込める
+///
+/// ## Params
+///  - path: 保存先のパス
+///  - width, height: 出力する画像の幅と高さ [pixel]
+#[tauri::command]
+pub async fn save_image(path: String, width: u32, height: u32) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid image size: {}x{}.", width, height));
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        save_image_inner(&fractal, &coloring, &path, width, height)
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 表示領域をインデックスカラーのPNGとして保存する
+///
+/// 描画した画像を palette_size 色以下に減色するので、save_png よりファイルサイズが小さくなる。
+/// save_png と同じくフラクタルのパラメータを書き込むので、import_from_png で読み込める
+///
+/// ## Params
+///  - path: 保存先のパス
+///  - palette_size: パレットの最大の色数 (2 <= palette_size <= 256)
+#[tauri::command]
+pub async fn save_png_indexed(path: String, palette_size: u16) -> Result<(), String> {
+    if !(2..=256).contains(&palette_size) {
+        return Err(format!("Palette size must be between 2 and 256: {}.", palette_size));
+    }
+
+    // パラメータと画像が同じ状態を表すように、1つの複製から両方を作成する
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<(), String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        let (metadata, (width, height)) = match &fractal {
+            Fractal::F64(f) => save_png_get_data(f)?,
+            Fractal::F106(f) => save_png_get_data(f)?,
+        };
+        btm::check_memory_budget(width, height)?;
+        let rgba_data = in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_rgba_inner(fr, fo, &coloring, width, height)));
+
+        let file = std::fs::File::create(&path)
+            .map_err(|e| e.to_string())?;
+        let writer = std::io::BufWriter::new(file);
+
+        write_indexed_png(writer, width, height, &rgba_data, palette_size, vec![
+            ("FractalParameters".to_string(), metadata),
+            ("Generics".to_string(), format!("{}", fractal.to_index())),
+        ])
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn render_boundary_image_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, width: u32, height: u32) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    const BOUNDARY: u8 = 0;         // 黒
+    const BACKGROUND: u8 = u8::MAX; // 白
+
+    let result = btm::calc_rect(view_calc_info(fr, fo, width, height));
+    let mask = btm::boundary_mask(&result.pixels, width, height);
+
+    let mut gray: Vec<u8> = mask.iter()
+        .map(|is_boundary| if *is_boundary { BOUNDARY } else { BACKGROUND })
+        .collect();
+    flip_rows(&mut gray, width as usize, height as usize);
+    gray
+}
+
+/// # 収束先の境界のみを白黒で描画したPNG画像を返す
+///
+/// 上下左右の隣接ピクセルと反復回数または収束先の根が異なるピクセルを黒、それ以外を白とする
+///
+/// ## Params
+///  - width, height: 画像の幅と高さ
+///
+/// ## Returns
+///  - base64でエンコードしたPNG画像
+#[tauri::command]
+pub async fn render_boundary_image(width: u32, height: u32) -> Result<String, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let gray = tauri::async_runtime::spawn_blocking(move || -> Result<Vec<u8>, String> {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_boundary_image_inner(fr, fo, width, height))))
+    }).await.map_err(|e| e.to_string())??;
+
+    encode_png_base64(width, height, png::ColorType::Grayscale, &gray)
+}
+
+fn render_confidence_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, width: u32, height: u32, epsilon: f64) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let info = view_calc_info(fr, fo, width, height);
+    let w = width as usize;
+    let escapes: Vec<(btm::Escape<T>, btm::Escape<T>)> = (0..w * height as usize).into_par_iter()
+        .map(|idx| {
+            let (x, y) = ((idx % w) as f64, (idx / w) as f64);
+            (
+                info.escape_time(info.get_subpixel_complex(x, y)),
+                info.escape_time(info.get_subpixel_complex(x + epsilon, y + epsilon)),
+            )
+        })
+        .collect();
+
+    // 2回の計算で同じ根の番号を使うように、1つの一覧で番号を割り当てる
+    let mut roots = btm::Roots::from_known(&info.known_roots, &info.known_multiplicities);
+    escapes.iter()
+        .map(|(a, b)| u8::from(roots.pixel(a).root != roots.pixel(b).root))
+        .collect()
+}
+
+/// # 始点を僅かにずらすと収束先が変わるピクセルを返す
+///
+/// 各ピクセルを、そのままの始点と (epsilon, epsilon) pixel だけずらした始点から境界追跡を使わずに計算し、
+/// 収束先の根 (未収束も含む) を比較する。収束先の境界の近くではずらした始点が隣の basin に入るので、
+/// 不安定なピクセルが現れるのは正常な動作である。境界から離れた不安定なピクセルは、
+/// 最大反復回数や精度が足りない領域を示す
+///
+/// ## Params
+///  - width, height: 計算する解像度
+///  - epsilon: 始点をずらす量 [pixel]
+///
+/// ## Returns
+///  - 収束先が変わるピクセルは1、それ以外は0 (y * width + x)
+#[tauri::command]
+pub async fn render_confidence(width: u32, height: u32, epsilon: f64) -> Result<Vec<u8>, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    if !(epsilon.is_finite() && epsilon > 0.0) {
+        return Err("Epsilon must be a positive finite number.".to_string());
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_confidence_inner(fr, fo, width, height, epsilon))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn boundary_svg_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>, width: u32, height: u32, tolerance: f64) -> String
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let result = btm::calc_rect(view_calc_info(fr, fo, width, height));
+
+    let paths: Vec<String> = btm::root_boundary_paths(&result.pixels, width, height).iter()
+        .map(|path| btm::simplify_polyline(path, tolerance))
+        .filter_map(|path| {
+            let closed = path.len() > 2 && path.first() == path.last();
+            let points = if closed { &path[..path.len() - 1] } else { &path[..] };
+            if points.len() < 2 {
+                return None;
+            }
+            // 画像と同じく、虚部の大きい側を上にする
+            let d: Vec<String> = points.iter().enumerate()
+                .map(|(i, (x, y))| format!("{}{} {}", if i == 0 { "M" } else { "L" }, x, height as f64 - y))
+                .collect();
+            Some(format!(r#"    <path d="{}{}"/>"#, d.join(" "), if closed { " Z" } else { "" }))
+        })
+        .collect();
+
+    format!(
+        concat!(
+            r#"<?xml version="1.0" encoding="UTF-8"?>"#, "\n",
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{}" height="{}" viewBox="0 0 {} {}">"#, "\n",
+            r#"  <g fill="none" stroke="black" stroke-width="1" stroke-linejoin="round">"#, "\n",
+            "{}",
+            "  </g>\n",
+            "</svg>\n",
+        ),
+        width, height, width, height,
+        paths.iter().map(|path| format!("{}\n", path)).collect::<String>(),
+    )
+}
+
+/// # 収束先の境界を折れ線で表したSVG画像を出力する
+///
+/// 表示領域を width x height で計算し、収束先の根が異なる隣接ピクセルの間の辺をつないだ折れ線を、
+/// Douglas–Peucker法で間引いて <path> 要素として書き出す。SVGの座標はピクセル単位となる
+///
+/// ## Params
+///  - path: 出力先のファイルパス
+///  - width, height: 計算する画像の幅と高さ
+///  - tolerance: 間引きの許容誤差[pixel]。0.0の場合は一直線上の頂点のみ取り除く
+#[tauri::command]
+pub async fn export_boundary_svg(path: String, width: u32, height: u32, tolerance: f64) -> Result<(), String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    if !(tolerance.is_finite() && tolerance >= 0.0) {
+        return Err(format!("Invalid tolerance: {}.", tolerance));
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<(), String> {
+        let fractal = prepare_render()?;
+        let svg = in_render_pool(|| with_fractal!(&fractal, |fr, fo| boundary_svg_inner(fr, fo, width, height, tolerance)));
+        std::fs::write(&path, svg)
+            .map_err(|e| e.to_string())
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 数式テンプレートの `{c}` を複素数 (re, im) で置き換える
+fn substitute_param(template: &str, (re, im): (f64, f64)) -> String {
+    template.replace("{c}", &format!("({} + ({})*i)", re, im))
+}
+
+/// # count 個のセルを正方形に近い格子状に並べた画像の大きさを求める
+///
+/// 並べた画像全体が calc_rect のメモリの上限を超える場合はエラーとする
+///
+/// ## Returns
+///  - (列数, 画像の幅, 画像の高さ)
+fn montage_layout(count: usize, cell_px: u32) -> Result<(u32, u32, u32), String> {
+    let too_large = || format!("Montage is too large: {} cells of {} px.", count, cell_px);
+    let count = u32::try_from(count).map_err(|_| too_large())?;
+    let cols = (count as f64).sqrt().ceil() as u32;
+    let rows = count.div_ceil(cols);
+    let width = cols.checked_mul(cell_px).ok_or_else(too_large)?;
+    let height = rows.checked_mul(cell_px).ok_or_else(too_large)?;
+    btm::check_memory_budget(width, height)?;
+    Ok((cols, width, height))
+}
+
+fn render_montage_inner<T>(
+    fr: &FractalInner<T>,
+    coloring: &Coloring,
+    template: &str,
+    values: &[(f64, f64)],
+    cell_px: u32,
+) -> Result<(u32, u32, Vec<u8>), String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let (cols, width, height) = montage_layout(values.len(), cell_px)?;
+    let row_bytes = cell_px as usize * 4;
+
+    let mut image = vec![0u8; (width as usize) * (height as usize) * 4];
+    for (i, &value) in values.iter().enumerate() {
+        let formula = substitute_param(template, value);
+        validate_formula(&formula, &FormulaLimits::default())?;
+        let mut fo = fr.uncompiled_formulac::<T>().map_err(|e| e.to_string())?;
+        fo.set_formula(&formula)
+            .map_err(|e| format!("{}: {}", formula, e))?;
+        // 根のキャッシュは数式毎に必要なので、共有しない
+        let mut fr = fr.clone();
+        fr.invalidate_roots();
+
+        let mut cell = colorize_rect(coloring, view_calc_info(&fr, &fo, cell_px, cell_px));
+        flip_rows(&mut cell, row_bytes, cell_px as usize);
+
+        let (cx, cy) = (i as u32 % cols, i as u32 / cols);
+        for (y, row) in cell.chunks_exact(row_bytes).enumerate() {
+            let dst = (((cy * cell_px) as usize + y) * width as usize + (cx * cell_px) as usize) * 4;
+            image[dst..dst + row_bytes].copy_from_slice(row);
+        }
+    }
+
+    Ok((width, height, image))
+}
+
+/// # 数式テンプレートにパラメータを代入した数式を並べて描画する
+///
+/// values の各値をテンプレートの `{c}` に代入した数式について、現在の表示領域を cell_px * cell_px で描画し、
+/// 左上から行優先で正方形に近い格子状に並べる。セルが余る場合、余ったセルは透明とする
+///
+/// ## Params
+///  - param_formula_template: `{c}` を含む数式 (例: `z^3 - 1 + {c}`)
+///  - values: 代入する複素数 (re, im) の配列
+///  - cell_px: 1つのセルの一辺のピクセル数
+///
+/// ## Returns
+///  - base64でエンコードしたPNG画像
+#[tauri::command]
+pub async fn render_montage(param_formula_template: String, values: Vec<(f64, f64)>, cell_px: u32) -> Result<String, String> {
+    if values.is_empty() {
+        return Err("No parameter values are given.".to_string());
+    }
+    if cell_px == 0 {
+        return Err("Cell size must be greater than 0.".to_string());
+    }
+    if let Some(&(re, im)) = values.iter().find(|(re, im)| !(re.is_finite() && im.is_finite())) {
+        return Err(format!("Invalid parameter value: ({}, {}).", re, im));
+    }
+    montage_layout(values.len(), cell_px)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let coloring = COLORING.lock().unwrap().clone();
+        let fractal = snapshot_fractal();
+        in_render_pool(|| match &fractal {
+            Fractal::F64(fr) => render_montage_inner(fr, &coloring, &param_formula_template, &values, cell_px),
+            Fractal::F106(fr) => render_montage_inner(fr, &coloring, &param_formula_template, &values, cell_px),
+        })
+    }).await.map_err(|e| e.to_string())?;
+
+    let (width, height, image) = result?;
+    encode_png_base64(width, height, png::ColorType::Rgba, &image)
+}
+
+/// # 中心座標と複素数平面上の半幅を直接指定して、計算情報を作成する
+///
+/// zoom_level を介さないので、拡大率が 2^(1/8) 刻みに量子化されない。
+/// 横方向に `[center_re - half_width, center_re + half_width]` を width ピクセルで計算し、縦方向も同じ間隔で計算する
+fn figure_calc_info<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    center: Complex<T>, half_width: T,
+    width: u32, height: u32,
+) -> btm::CalcInfo<T>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let (func, deriv) = fo.shared_funcs();
+    let mut info = btm::CalcInfo::new(
+        0, 0, width, height,
+        fr.max_iter(),
+        T::from_f64(width as f64),
+        center,
+        half_width * T::from_f64(2.0),
+        func,
+        deriv,
+        fr.coeff(),
+    );
+    // 縦方向の中心を、表示領域の中心に合わせる
+    info.start.y = (width as i64 - height as i64) / 2;
+    info.escape = fr.escape_options();
+    info.escape.deriv2 = fr.iteration_deriv2(fo);
+    info.fill_passes = fr.fill_passes();
+    info.halo = fr.boundary_halo();
+    info.projection = fr.projection;
+    info.known_roots = fr.cached_roots(fo).to_vec();
+    info.known_multiplicities = fr.root_multiplicities(fo).to_vec();
+    info
+}
+
+/// 中心座標と半幅で指定した表示領域 (GIFアニメーションの1フレームなど)
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct FrameParams {
+    center_re:  f64,
+    center_im:  f64,
+    /// 横方向の半幅 (複素数平面上の長さ)
+    half_width: f64,
+    /// Newton法の係数 (re, im)。省略した場合は set_coeff で設定した係数とする
+    #[serde(default)]
+    coeff:      Option<(f64, f64)>,
+    /// set_morph で設定した数式の補間の比。省略した場合は現在の比とする
+    #[serde(default)]
+    morph_t:    Option<f64>,
+}
+
+/// # 指定した表示領域を描画し、RGBAの画素データを返す
+///
+/// 画像として出力するため、行の順番は反転済み
+fn render_frame_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    frame: &FrameParams,
+    width: u32, height: u32,
+) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let center = Complex::new(T::from_f64(frame.center_re), T::from_f64(frame.center_im));
+    let mut info = figure_calc_info(fr, fo, center, T::from_f64(frame.half_width), width, height);
+    if let Some((re, im)) = frame.coeff {
+        info.coeff = Complex::new(T::from_f64(re), T::from_f64(im));
+    }
+
+    let mut rgba = colorize_rect(coloring, info);
+    flip_rows(&mut rgba, width as usize * 4, height as usize); // 1行は width * 4 byte
+    rgba
+}
+
+/// # 中心座標と複素数平面上の半幅を指定して、PNG画像を描画する
+///
+/// 論文の図などで表示領域を数学的に指定したい場合に使用する。現在の表示領域は変更しない
+///
+/// ## Params
+///  - center_re, center_im: 画像の中心の座標
+///  - half_width: 画像の横方向の半幅 (複素数平面上の長さ)
+///  - width, height: 画像の幅と高さ
+///
+/// ## Returns
+///  - base64でエンコードしたPNG画像
+#[tauri::command]
+pub async fn render_figure(center_re: f64, center_im: f64, half_width: f64, width: u32, height: u32) -> Result<String, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    if !(half_width.is_finite() && half_width > 0.0) {
+        return Err(format!("Invalid half width: {}.", half_width));
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let rgba = tauri::async_runtime::spawn_blocking(move || -> Result<Vec<u8>, String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        let frame = FrameParams { center_re, center_im, half_width, coeff: None, morph_t: None };
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_frame_inner(fr, fo, &coloring, &frame, width, height))))
+    }).await.map_err(|e| e.to_string())??;
+
+    encode_png_base64(width, height, png::ColorType::Rgba, &rgba)
+}
+
+/// # 表示領域を変化させたGIFアニメーションを出力する
+///
+/// 拡大していくアニメーションや、Newton法の係数・set_morph の補間の比を変化させるアニメーションを作成できる。
+/// メモリ消費量を抑えるために、1フレームずつ描画してファイルに書き込む
+///
+/// ## Params
+///  - path: 出力先のファイルパス
+///  - frames_params: 各フレームの表示領域
+///  - width, height: 画像の幅と高さ (最大 65535)
+///  - delay_ms: 各フレームの表示時間[ms] (10ms単位に切り捨てる)
+#[tauri::command]
+pub async fn export_gif(path: String, frames_params: Vec<FrameParams>, width: u32, height: u32, delay_ms: u32) -> Result<(), String> {
+    if frames_params.is_empty() {
+        return Err("No frames are given.".to_string());
+    }
+    if width == 0 || height == 0 || width > u16::MAX as u32 || height > u16::MAX as u32 {
+        return Err(format!("Invalid image size: {}x{}.", width, height));
+    }
+    if let Some(frame) = frames_params.iter().find(|f| !(f.half_width.is_finite() && f.half_width > 0.0)) {
+        return Err(format!("Invalid half width: {}.", frame.half_width));
+    }
+    if let Some(t) = frames_params.iter().filter_map(|f| f.morph_t).find(|t| !t.is_finite()) {
+        return Err(format!("Invalid morph ratio: {}.", t));
+    }
+    btm::check_memory_budget(width, height)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<(), String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+
+        let file = std::fs::File::create(&path)
+            .map_err(|e| e.to_string())?;
+        let writer = std::io::BufWriter::new(file);
+        let mut encoder = gif::Encoder::new(writer, width as u16, height as u16, &[])
+            .map_err(|e| e.to_string())?;
+        encoder.set_repeat(gif::Repeat::Infinite)
+            .map_err(|e| e.to_string())?;
+
+        for frame_params in frames_params.iter() {
+            let morphed;
+            let frame_fractal = match frame_params.morph_t {
+                Some(t) => {
+                    morphed = fractal.with_morph_t(t)?;
+                    &morphed
+                },
+                None => &fractal,
+            };
+            let mut rgba = in_render_pool(|| with_fractal!(frame_fractal, |fr, fo| render_frame_inner(fr, fo, &coloring, frame_params, width, height)));
+            let mut frame = gif::Frame::from_rgba_speed(width as u16, height as u16, &mut rgba, 10);
+            frame.delay = (delay_ms / 10).min(u16::MAX as u32) as u16; // GIFの表示時間は10ms単位
+            encoder.write_frame(&frame)
+                .map_err(|e| e.to_string())?;
+        }
+
+        Ok(())
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// render_timeline のキーフレーム
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Keyframe {
+    /// 時刻[s]
+    time:   f64,
+    /// 表示領域の中心 (re, im)
+    center: (f64, f64),
+    /// zoom level (小数も可)。表示領域の幅は 2^(-zoom / 8 + 1)
+    zoom:   f64,
+    /// Newton法の係数 (re, im)。省略した場合は1とする
+    #[serde(default)]
+    coeff:  Option<(f64, f64)>,
+}
+
+/// render_timeline で描画した1フレーム
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct TimelineFrame {
+    index: u32,
+    /// 時刻[s]
+    time:  f64,
+    /// base64でエンコードしたPNG画像
+    png:   String,
+}
+
+fn validate_keyframes(keyframes: &[Keyframe]) -> Result<(), String> {
+    if keyframes.is_empty() {
+        return Err("No keyframes are given.".to_string());
+    }
+    for kf in keyframes {
+        let values = [kf.time, kf.center.0, kf.center.1, kf.zoom];
+        let coeff = kf.coeff.map_or([0.0; 2], |(re, im)| [re, im]);
+        if !values.iter().chain(coeff.iter()).all(|v| v.is_finite()) {
+            return Err(format!("Invalid keyframe at {}s.", kf.time));
+        }
+    }
+    if let Some(w) = keyframes.windows(2).find(|w| w[0].time >= w[1].time) {
+        return Err(format!("Keyframe times must be increasing: {}s after {}s.", w[1].time, w[0].time));
+    }
+    Ok(())
+}
+
+/// # キーフレームの間を補間して、fps に従った各フレームの表示領域を求める
+///
+/// 区間内の位置 s を smoothstep (3s^2 - 2s^3) で変換してから各値を線形補間するので、キーフレームの前後で速度が0になる。
+/// zoom level は対数の尺度なので、線形補間すると一定の比率で拡大していく
+///
+/// ## Returns
+///  - (時刻, 表示領域) の配列。最初のキーフレームの時刻から、最後のキーフレームの時刻まで
+fn timeline_frames(keyframes: &[Keyframe], fps: u32) -> Vec<(f64, FrameParams)> {
+    let lerp = |a: f64, b: f64, s: f64| a + (b - a) * s;
+    let first = keyframes[0].time;
+    let duration = keyframes[keyframes.len() - 1].time - first;
+    let count = (duration * fps as f64 + 1e-9).floor() as u32 + 1;
+
+    (0..count).map(|i| {
+        let time = first + i as f64 / fps as f64;
+        let k = keyframes.partition_point(|kf| kf.time <= time).clamp(1, keyframes.len()) - 1;
+        let (a, b) = (&keyframes[k], &keyframes[(k + 1).min(keyframes.len() - 1)]);
+        let s = if b.time > a.time { ((time - a.time) / (b.time - a.time)).clamp(0.0, 1.0) } else { 0.0 };
+        let s = s * s * (3.0 - 2.0 * s);
+
+        let (coeff_a, coeff_b) = (a.coeff.unwrap_or((1.0, 0.0)), b.coeff.unwrap_or((1.0, 0.0)));
+        let zoom = lerp(a.zoom, b.zoom, s);
+        let frame = FrameParams {
+            center_re:  lerp(a.center.0, b.center.0, s),
+            center_im:  lerp(a.center.1, b.center.1, s),
+            half_width: 2.0f64.powf(-zoom * Canvas::<f64>::zoom_step()),
+            coeff:      Some((lerp(coeff_a.0, coeff_b.0, s), lerp(coeff_a.1, coeff_b.1, s))),
+            morph_t:    None,
+        };
+        (time, frame)
+    }).collect()
+}
+
+/// # キーフレームで指定した、中心座標・拡大率・係数を同時に変化させるアニメーションを描画する
+///
+/// キーフレームの間を smoothstep で補間し、fps に従って1フレームずつ描画する。
+/// 描画する毎に "timeline-frame" イベント (TimelineFrame) を発行する。
+/// 途中で cancel_render が呼ばれた場合は、残りのフレームを描画せずに終了する
+///
+/// ## Params
+///  - keyframes: 時刻の昇順に並べたキーフレーム
+///  - fps: 1秒あたりのフレーム数
+///  - width, height: 画像の幅と高さ
+///
+/// ## Returns
+///  - 描画したフレーム数
+#[tauri::command]
+pub async fn render_timeline(app: tauri::AppHandle, keyframes: Vec<Keyframe>, fps: u32, width: u32, height: u32) -> Result<u32, String> {
+    if fps == 0 {
+        return Err("Fps must be greater than 0.".to_string());
+    }
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    validate_keyframes(&keyframes)?;
+    btm::check_memory_budget(width, height)?;
+    let generation = render_generation();
+
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<u32, String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+
+        let mut rendered = 0;
+        for (time, frame) in timeline_frames(&keyframes, fps) {
+            if is_render_cancelled(generation) {
+                break;
+            }
+            let rgba = in_render_pool(|| with_fractal!(&fractal, |fr, fo| render_frame_inner(fr, fo, &coloring, &frame, width, height)));
+            let png = encode_png_base64(width, height, png::ColorType::Rgba, &rgba)?;
+            app.emit("timeline-frame", TimelineFrame { index: rendered, time, png })
+                .map_err(|e| e.to_string())?;
+            rendered += 1;
+        }
+
+        Ok(rendered)
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// render_export_progressive で描画した画像
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ExportStage {
+    /// 最終的な品質の画像か (false の場合はプレビュー)
+    is_final:   bool,
+    width:      u32,
+    height:     u32,
+    /// base64でエンコードしたPNG画像
+    png:        String,
+}
+
+/// 大きな画像を書き出す際に、中断を確認しながら一度に計算する行数
+const BAND_ROWS: u32 = 128;
+
+/// # プレビューを描画した後、最終的な品質の画像を描画する
+///
+/// 最終的な画像は BAND_ROWS 行毎に計算し、その度に cancelled を確認する
+///
+/// ## Returns
+///  - 最終的な画像まで描画した場合はtrue、中断した場合はfalse
+fn render_export_stages<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    width: u32, height: u32,
+    mut emit: impl FnMut(ExportStage) -> Result<(), String>,
+    cancelled: impl Fn() -> bool,
+) -> Result<bool, String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    /// プレビューの解像度と最大反復回数を、最終的な画像の何分の1にするか
+    const PREVIEW_DIVISOR: u32 = 4;
+
+    let (pw, ph) = (width.div_ceil(PREVIEW_DIVISOR), height.div_ceil(PREVIEW_DIVISOR));
+    let mut info = view_calc_info(fr, fo, pw, ph);
+    info.max_itr = (info.max_itr / PREVIEW_DIVISOR as u16).max(1);
+    let mut preview = colorize_rect(coloring, info);
+    flip_rows(&mut preview, pw as usize * 4, ph as usize);
+    emit(ExportStage { is_final: false, width: pw, height: ph, png: encode_png_base64(pw, ph, png::ColorType::Rgba, &preview)? })?;
+
+    let mut rgba = Vec::with_capacity(width as usize * height as usize * 4);
+    for y in (0..height).step_by(BAND_ROWS as usize) {
+        if cancelled() {
+            return Ok(false);
+        }
+        let mut info = view_calc_info(fr, fo, width, height);
+        info.start.y += y as i64;
+        info.height = BAND_ROWS.min(height - y);
+        rgba.extend(colorize_rect(coloring, info));
+    }
+    flip_rows(&mut rgba, width as usize * 4, height as usize);
+    emit(ExportStage { is_final: true, width, height, png: encode_png_base64(width, height, png::ColorType::Rgba, &rgba)? })?;
+    Ok(true)
+}
+
+/// # 表示領域を width * height で BAND_ROWS 行毎に計算して色付けし、PNGとして書き込む
+///
+/// 帯毎の画素データをそのまま圧縮して書き込むので、画像全体の画素データは保持しない。
+/// 画像の行の順番は反転する (flip_rows) ので、下の帯から順に計算し、帯毎に反転して書き込む
+///
+/// ## Returns
+///  - 全ての帯を書き込んだ場合はtrue、cancelled で中断した場合はfalse
+fn write_high_res_png<T, W: std::io::Write>(
+    writer: W,
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    width: u32, height: u32,
+    cancelled: impl Fn() -> bool,
+) -> Result<bool, String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let mut encoder = png::Encoder::new(writer, width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut png_writer = encoder.write_header().map_err(|e| e.to_string())?;
+    let mut stream = png_writer.stream_writer().map_err(|e| e.to_string())?;
+
+    let bands: Vec<u32> = (0..height).step_by(BAND_ROWS as usize).collect();
+    for &y in bands.iter().rev() {
+        if cancelled() {
+            return Ok(false);
+        }
+        let rows = BAND_ROWS.min(height - y);
+        let mut info = view_calc_info(fr, fo, width, height);
+        info.start.y += y as i64;
+        info.height = rows;
+        let mut rgba = colorize_rect(coloring, info);
+        flip_rows(&mut rgba, width as usize * 4, rows as usize); // 1行は width * 4 byte
+        std::io::Write::write_all(&mut stream, &rgba).map_err(|e| e.to_string())?;
+    }
+    stream.finish().map_err(|e| e.to_string())?;
+    Ok(true)
+}
+
+/// # 表示領域を、画面の大きさに関わらず指定した解像度で描画する
+///
+/// 128行毎に境界追跡で計算して色付けし、圧縮しながらPNGに書き込むので、画像全体の画素データを一度に保持しない。
+/// 縦横比が1:1でない場合は、表示領域の中央を切り出す。途中で cancel_render が呼ばれた場合は中断する
+///
+/// ## Params
+///  - width, height: 画像の幅と高さ [pixel]
+///
+/// ## Returns
+///  - base64でエンコードしたPNG画像。中断した場合は空文字列
+#[tauri::command]
+pub async fn export_high_res(width: u32, height: u32) -> Result<String, String> {
+    if width == 0 || height == 0 {
+        return Err(format!("Invalid image size: {}x{}.", width, height));
+    }
+    // 画像全体ではなく、帯毎の画素データのみを保持する
+    btm::check_memory_budget(width, BAND_ROWS.min(height))?;
+    let generation = render_generation();
+
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<String, String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+
+        let mut bytes = Vec::new();
+        let completed = in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            write_high_res_png(&mut bytes, fr, fo, &coloring, width, height, || is_render_cancelled(generation))
+        }))?;
+        Ok(if completed { base64::engine::general_purpose::STANDARD.encode(bytes) } else { String::new() })
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # 書き出し用の画像を、プレビューを表示しながら描画する
+///
+/// 解像度と最大反復回数を 1/4 にしたプレビューを描画して "export-progress" イベント (ExportStage) を発行し、
+/// 最終的な品質の画像を描画し終えた時点で、もう一度 "export-progress" イベントを発行する。
+/// 途中で cancel_render が呼ばれた場合は、最終的な画像を描画せずに終了する
+///
+/// ## Params
+///  - width, height: 最終的な画像の幅と高さ
+///
+/// ## Returns
+///  - 最終的な画像まで描画した場合はtrue、中断した場合はfalse
+#[tauri::command]
+pub async fn render_export_progressive(app: tauri::AppHandle, width: u32, height: u32) -> Result<bool, String> {
+    if width == 0 || height == 0 {
+        return Err("Image size must be greater than 0.".to_string());
+    }
+    btm::check_memory_budget(width, height)?;
+    let generation = render_generation();
+
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<bool, String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        let emit = |stage: ExportStage| app.emit("export-progress", stage).map_err(|e| e.to_string());
+        in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            render_export_stages(fr, fo, &coloring, width, height, emit, || is_render_cancelled(generation))
+        }))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// Deep Zoom Image のタイル1枚
+struct DziTile {
+    col:    u32,
+    row:    u32,
+    /// タイルの左上の、この段階の画像上の座標
+    x:      u32,
+    y:      u32,
+    width:  u32,
+    height: u32,
+    /// この段階の画像の一辺の長さ
+    size:   u32,
+}
+
+/// # 一辺が full_size の Deep Zoom Image の、最も細かい段階の番号を返す
+///
+/// Deep Zoom の仕様に従い、段階0 は 1x1 ピクセルとなる
+fn dzi_max_level(full_size: u32) -> u32 {
+    u32::BITS - (full_size - 1).leading_zeros() // ceil(log2(full_size))
+}
+
+/// # Deep Zoom Image の1段階のタイルを列挙する
+///
+/// 最も細かい段階の一辺を full_size とし、1段階粗くなる毎に一辺を半分 (切り上げ) にする。
+/// 全段階のタイルを一度に確保しないように、段階毎に1枚ずつ生成する
+fn dzi_level_tiles(full_size: u32, tile_px: u32, level: u32) -> impl Iterator<Item = DziTile> {
+    let shift = dzi_max_level(full_size) - level;
+    let size = ((full_size as u64 + (1 << shift) - 1) >> shift) as u32;
+    let count = size.div_ceil(tile_px);
+    (0..count).flat_map(move |row| (0..count).map(move |col| {
+        let (x, y) = (col * tile_px, row * tile_px);
+        DziTile {
+            col, row, x, y,
+            width:  tile_px.min(size - x),
+            height: tile_px.min(size - y),
+            size,
+        }
+    }))
+}
+
+/// Deep Zoom Image の最も細かい段階のタイルの枚数の上限
+const MAX_DZI_TILES: u64 = 1 << 20;
+
+/// # Deep Zoom Image の最も細かい段階の一辺 tile_px * 2^max_level を求める
+///
+/// タイルは1枚ずつ計算するので、タイル1枚が calc_rect のメモリの上限を超える場合と、
+/// 最も細かい段階のタイルが MAX_DZI_TILES 枚を超える場合はエラーとする
+fn dzi_full_size(max_level: u32, tile_px: u32) -> Result<u32, String> {
+    if tile_px == 0 {
+        return Err("Tile size must be positive.".to_string());
+    }
+    btm::check_memory_budget(tile_px, tile_px)?;
+    let full_size = (tile_px as u64).checked_shl(max_level)
+        .filter(|&size| size <= u32::MAX as u64)
+        .ok_or_else(|| format!("Image is too large: {} px tiles with {} levels.", tile_px, max_level))? as u32;
+    let tiles = (full_size.div_ceil(tile_px) as u64).pow(2);
+    if tiles > MAX_DZI_TILES {
+        return Err(format!("Too many tiles: {} tiles at the finest level exceed the limit of {}.", tiles, MAX_DZI_TILES));
+    }
+    Ok(full_size)
+}
+
+/// # Deep Zoom Image のタイル1枚を描画し、RGBAの画素データを返す
+///
+/// 段階毎の画像全体が、現在の表示領域と一致するように計算する。画像として出力するため、行の順番は反転済み
+fn render_dzi_tile_inner<T>(
+    fr: &FractalInner<T>, fo: &FormulacInner<T>,
+    coloring: &Coloring,
+    tile: &DziTile,
+) -> Vec<u8>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + ToF64,
+{
+    let half_width = fr.canvas().width() / T::from_f64(2.0);
+    let mut info = figure_calc_info(fr, fo, fr.canvas().center().clone(), half_width, tile.size, tile.size);
+    // canvasとy軸が反転しているので、画像の下端から数える
+    info.start = btm::Coordinates {
+        x: tile.x as i64,
+        y: (tile.size - tile.y - tile.height) as i64,
+    };
+    info.width = tile.width;
+    info.height = tile.height;
+
+    let mut rgba = colorize_rect(coloring, info);
+    flip_rows(&mut rgba, tile.width as usize * 4, tile.height as usize); // 1行は width * 4 byte
+    rgba
+}
+
+/// # 現在の表示領域を、Deep Zoom Image (DZI) 形式のタイル群として出力する
+///
+/// output_dir に記述子 `fractal.dzi` と、タイルを格納する `fractal_files/{段階}/{列}_{行}.png` を書き込む。
+/// 段階が1つ細かくなる毎に解像度が2倍になり、最も細かい段階の一辺は tile_px * 2^max_level となる。
+/// タイルは段階毎に並列に計算する
+///
+/// ## Params
+///  - output_dir: 出力先のディレクトリ (存在しない場合は作成する)
+///  - max_level: 1タイルの段階から、解像度を何回2倍にするか
+///  - tile_px: タイルの一辺のピクセル数
+#[tauri::command]
+pub async fn export_dzi(output_dir: String, max_level: u32, tile_px: u32) -> Result<(), String> {
+    /// 記述子とタイルのディレクトリの名前
+    const NAME: &str = "fractal";
+
+    let full_size = dzi_full_size(max_level, tile_px)?;
+
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<(), String> {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+
+        let dir = std::path::Path::new(&output_dir);
+        let files_dir = dir.join(format!("{}_files", NAME));
+        for level in 0..=dzi_max_level(full_size) {
+            let level_dir = files_dir.join(level.to_string());
+            std::fs::create_dir_all(&level_dir)
+                .map_err(|e| e.to_string())?;
+
+            in_render_pool(|| dzi_level_tiles(full_size, tile_px, level).par_bridge().try_for_each(|tile| -> Result<(), String> {
+                let rgba = with_fractal!(&fractal, |fr, fo| render_dzi_tile_inner(fr, fo, &coloring, &tile));
+                let file = std::fs::File::create(level_dir.join(format!("{}_{}.png", tile.col, tile.row)))
+                    .map_err(|e| e.to_string())?;
+                write_png(std::io::BufWriter::new(file), tile.width, tile.height, png::ColorType::Rgba, &rgba, Vec::new())
+            }))?;
+        }
+
+        let descriptor = format!(
+            concat!(
+                r#"<?xml version="1.0" encoding="UTF-8"?>"#, "\n",
+                r#"<Image xmlns="http://schemas.microsoft.com/deepzoom/2008" TileSize="{}" Overlap="0" Format="png">"#, "\n",
+                r#"  <Size Width="{}" Height="{}"/>"#, "\n",
+                "</Image>\n",
+            ),
+            tile_px, full_size, full_size,
+        );
+        std::fs::write(dir.join(format!("{}.dzi", NAME)), descriptor)
+            .map_err(|e| e.to_string())
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+fn boundary_dimension_inner<T>(fr: &FractalInner<T>, fo: &FormulacInner<T>) -> f64
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    let (width, height) = fr.canvas().viewport_size();
+    let result = btm::calc_rect(view_calc_info(fr, fo, width, height));
+    let mask = btm::boundary_mask(&result.pixels, width, height);
+    btm::box_counting_dimension(&mask, width, height).unwrap_or(0.0)
+}
+
+/// # 表示領域内の収束先の境界の、ボックス次元を推定する
+///
+/// render_boundary_image と同じ方法で境界を検出し、ボックスカウント法で次元を推定する。
+/// あくまで推定値であり、画像サイズや最大反復回数によって値が変わる
+///
+/// ## Returns
+///  - 推定したボックス次元。境界が見つからない場合は0.0
+#[tauri::command]
+pub async fn boundary_dimension() -> Result<f64, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        Ok(in_render_pool(|| with_fractal!(&fractal, |fr, fo| boundary_dimension_inner(fr, fo))))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ImportResult {
+    formula:    String,
+    size:       u32,
+    max_iter:   u16,
+    center_str: String,
+    scale_str:  String,
+}
+
+fn import_from_png_build_fractal_inner<T>(metadata_text: &TEXtChunk) -> Result<FractalInner<T>, String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + DeserializeOwned,
+{
+    let mut f: FractalInner<T> = serde_json::from_str(&metadata_text.text)
+        .map_err(|e| e.to_string())?;
+
+    f.recompile()
+        .map_err(|e| e.to_string())?;
+
+    Ok(f)
+}
+
+
+#[tauri::command]
+pub async fn import_from_png(path: String) -> Result<ImportResult, String> {
+    let file = std::fs::File::open(&path)
+        .map_err(|e| e.to_string())?;
+    let reader = std::io::BufReader::new(file);
+
+    let decoder = png::Decoder::new(reader);
+    let reader = decoder.read_info()
+        .map_err(|e| e.to_string())?;
+
+    let metadata_text = reader.info().uncompressed_latin1_text.iter()
+        .find(|chunk| chunk.keyword == "FractalParameters")
+        .ok_or(format!("Not Found Fractal Parameters in {}.", &path))?;
+    let enum_index = usize::from_str(&reader.info().uncompressed_latin1_text.iter()
+        .find(|chunk| chunk.keyword == "Generics")
+        .unwrap_or(&&TEXtChunk { keyword: "Generics".into(), text: "0".into() }) /* 存在しない場合は f64 とする */
+        .text
+    ).map_err(|e| e.to_string())?;
+
+    let (formula, size, max_iter) = {
+        // center_strとscale_strの取得時にFRACTALをlockするので、デッドロックしないように、
+        // FRACTALの設定時に応答値を同時に取得する
+        let mut f = FRACTAL.lock().map_err(|e| e.to_string())?;
+        match enum_index {
+            0 => {
+                let fo = import_from_png_build_fractal_inner::<f64>(metadata_text)?;
+                let formula = fo.formula().to_string();
+                let size = fo.canvas().size().into();
+                let max_iter = fo.max_iter();
+
+                supersede_formula_changes();
+                *f = Fractal::F64(fo);
+                notify_formula_ready();
+                (formula, size, max_iter)
+            },
+            _ => return Err(format!("Invalid Generics Parameters ({}) in {}.", enum_index, &path)),
+        }
+    };
+
+    Ok(ImportResult {
+        formula,
+        size,
+        max_iter,
+        center_str: get_center_str(),
+        scale_str: get_scale_str(),
+    })
+}
+
+/// export_state で出力する形式のバージョン
+const STATE_VERSION: u32 = 1;
+
+/// export_state / import_state で読み書きする、フラクタルの状態
+///
+/// 数式・表示領域・最大反復回数・Newton法の係数などを含む。コンパイル済みの数式は含まないので、読み込み時にコンパイルし直す
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FractalState {
+    version:  u32,
+    /// 浮動小数点の型 (0: f64, 1: F106)
+    generics: usize,
+    /// FractalInner を serialize した値 (save_png で書き込むパラメータと同じ形式)
+    fractal:  serde_json::Value,
+}
+
+fn export_state_inner(fractal: &Fractal) -> Result<String, String> {
+    let value = match fractal {
+        Fractal::F64(f) => serde_json::to_value(f),
+        Fractal::F106(f) => serde_json::to_value(f),
+    }.map_err(|e| format!("Inner Error: JSON serialization failed: {}", e))?;
+
+    let state = FractalState { version: STATE_VERSION, generics: fractal.to_index(), fractal: value };
+    serde_json::to_string_pretty(&state)
+        .map_err(|e| format!("Inner Error: JSON serialization failed: {}", e))
+}
+
+/// # FractalState の fractal から FractalInner を復元し、数式をコンパイルする
+///
+/// Formulac は serialize しないので、wrap で T に対応する Formulac を作成する
+fn build_state_fractal_inner<T>(value: serde_json::Value, wrap: fn(FormulacInner<T>) -> Formulac) -> Result<FractalInner<T>, String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign
+        + DeserializeOwned,
+{
+    let mut f: FractalInner<T> = serde_json::from_value(value)
+        .map_err(|e| format!("Invalid state: {}", e))?;
+
+    let limits = FormulaLimits::default();
+    validate_formula(&f.formula, &limits)?;
+    if let Some(c) = &f.conditional {
+        validate_formula(&c.condition, &limits)?;
+        validate_formula(&c.formula_false, &limits)?;
+    }
+    if let Some(m) = &f.morph {
+        validate_formula(&m.formula_b, &limits)?;
+    }
+    for (name, value) in f.params.iter() {
+        validate_param(name, *value)?;
+    }
+    for function in f.functions.iter() {
+        validate_function(function)?;
+    }
+    if !(f.epsilon.is_finite() && f.epsilon > 0.0) {
+        return Err(format!("Invalid convergence tolerance: {}.", f.epsilon));
+    }
+    if !(f.coeff.re.is_finite() && f.coeff.im.is_finite()) || f.coeff == Complex::new(0.0, 0.0) {
+        return Err(format!("Invalid coefficient: ({}, {}).", f.coeff.re, f.coeff.im));
+    }
+    if !(f.relaxation_tau.is_finite() && f.relaxation_tau >= 0.0) {
+        return Err(format!("Invalid relaxation time constant: {}.", f.relaxation_tau));
+    }
+    if let Some(IterationFalloff { focus, rate }) = f.iteration_falloff {
+        if !(focus.0.is_finite() && focus.1.is_finite()) {
+            return Err(format!("Invalid focus: ({}, {}).", focus.0, focus.1));
+        }
+        if !(rate.is_finite() && rate >= 0.0) {
+            return Err(format!("Invalid falloff rate: {}.", rate));
+        }
+    }
+    if f.max_iter == 0 {
+        return Err("Max iteration must be greater than 0.".to_string());
+    }
+    // 表示領域の幅 2^(-zoom_level / 8 + 1) が f64 で表せる範囲に限る
+    let log2_width = -(f.canvas().zoom_level as f64) * Canvas::<T>::zoom_step() + 1.0;
+    if !(f64::MIN_EXP as f64..f64::MAX_EXP as f64).contains(&log2_width) {
+        return Err(format!("Invalid zoom level: {}.", f.canvas().zoom_level));
+    }
+    let (width, height) = f.canvas().viewport();
+    if width == 0 || height == 0 || width.max(height) != f.canvas().size() {
+        return Err(format!("Invalid size: {}x{}.", width, height));
+    }
+    // 描画性能の設定は保存しないので既定値になるが、set_tile_size・set_fill_passes と同じ範囲に収まることを確認する
+    if f.tile_size == 0 || f.tile_size > f.canvas().size() as u32 {
+        return Err(format!("Invalid tile size: {}.", f.tile_size));
+    }
+    if f.fill_passes > btm::MAX_FILL_PASSES {
+        return Err(format!("Invalid fill passes: {}.", f.fill_passes));
+    }
+
+    f.formulac = wrap(f.compile_formulac().map_err(|e| e.to_string())?);
+    f.invalidate_roots();
+    f.formula_loaded = true;
+    Ok(f)
+}
+
+fn import_state_inner(json: &str) -> Result<Fractal, String> {
+    let state: FractalState = serde_json::from_str(json)
+        .map_err(|e| format!("Invalid state: {}", e))?;
+    if state.version != STATE_VERSION {
+        return Err(format!("Unsupported state version: {}.", state.version));
+    }
+
+    match state.generics {
+        0 => Ok(Fractal::F64(build_state_fractal_inner(state.fractal, Formulac::F64)?)),
+        1 => Ok(Fractal::F106(build_state_fractal_inner(state.fractal, Formulac::F106)?)),
+        generics => Err(format!("Invalid Generics Parameters ({}).", generics)),
+    }
+}
+
+/// # 数式・表示領域・最大反復回数・Newton法の係数などのフラクタルの状態を、JSONで返す
+///
+/// import_state で読み込むと同じ表示領域を再現できる。描画性能の設定 (tile_size など) と色付けの設定は含まない
+#[tauri::command]
+pub fn export_state() -> Result<String, String> {
+    export_state_inner(&FRACTAL.lock().unwrap())
+}
+
+/// # export_state で出力したJSONから、フラクタルの状態を復元する
+///
+/// 数式を検査・コンパイルし終えてから置き換えるので、失敗した場合は現在の状態のまま変わらない
+///
+/// ## Params
+///  - json: export_state で出力したJSON
+#[tauri::command]
+pub async fn import_state(json: String) -> Result<(), String> {
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<(), String> {
+        let fractal = import_state_inner(&json)?;
+        cancel_render();
+        let mut guard = FRACTAL.lock().unwrap();
+        record_navigation(&guard, Navigation::Jump);
+        supersede_formula_changes();
+        *guard = fractal;
+        notify_formula_ready();
+        Ok(())
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 共有用リンクの形式のバージョン (ペイロードの先頭1byte)
+const LINK_VERSION: u8 = 1;
+
+/// # 共有用リンクに埋め込む、浮動小数点のバイト列表現
+trait LinkValue: Sized {
+    fn write_link(&self, out: &mut Vec<u8>);
+    /// bytes の先頭から読み込み、読み込んだ分だけ bytes を進める
+    fn read_link(bytes: &mut &[u8]) -> Option<Self>;
+}
+
+/// # bytes の先頭 N byte を取り出し、取り出した分だけ bytes を進める
+fn take_bytes<const N: usize>(bytes: &mut &[u8]) -> Option<[u8; N]> {
+    let (head, rest) = bytes.split_first_chunk::<N>()?;
+    *bytes = rest;
+    Some(*head)
+}
+
+impl LinkValue for f64 {
+    fn write_link(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(&self.to_le_bytes());
+    }
+
+    fn read_link(bytes: &mut &[u8]) -> Option<Self> {
+        take_bytes(bytes).map(f64::from_le_bytes)
+    }
+}
+
+impl LinkValue for F106 {
+    fn write_link(&self, out: &mut Vec<u8>) {
+        let (hi, lo) = self.hi_lo();
+        hi.write_link(out);
+        lo.write_link(out);
+    }
+
+    fn read_link(bytes: &mut &[u8]) -> Option<Self> {
+        Some(F106::from_parts(f64::read_link(bytes)?, f64::read_link(bytes)?))
+    }
+}
+
+/// 共有用リンクに含める表示状態
+#[derive(Debug, Clone, PartialEq)]
+struct ViewLink<T> {
+    formula:    String,
+    center:     Complex<T>,
+    zoom_level: i32,
+    max_iter:   u16,
+}
+
+impl<T: LinkValue> ViewLink<T> {
+    /// # バイト列に変換する
+    ///
+    /// `[バージョン][型のindex][zoom_level (i32)][max_iter (u16)][中心の実部][中心の虚部][数式 (UTF-8)]` の順で、
+    /// 数値はリトルエンディアンとする。数式は長さを持たず、残りのバイト列全体とする
+    fn to_bytes(&self, generics: u8) -> Vec<u8> {
+        let mut bytes = vec![LINK_VERSION, generics];
+        bytes.extend_from_slice(&self.zoom_level.to_le_bytes());
+        bytes.extend_from_slice(&self.max_iter.to_le_bytes());
+        self.center.re.write_link(&mut bytes);
+        self.center.im.write_link(&mut bytes);
+        bytes.extend_from_slice(self.formula.as_bytes());
+        bytes
+    }
+
+    /// # バージョンと型のindexを除いたバイト列から復元する
+    fn from_bytes(mut bytes: &[u8]) -> Option<Self> {
+        let bytes = &mut bytes;
+        let zoom_level = i32::from_le_bytes(take_bytes(bytes)?);
+        let max_iter = u16::from_le_bytes(take_bytes(bytes)?);
+        let center = Complex::new(T::read_link(bytes)?, T::read_link(bytes)?);
+        let formula = String::from_utf8(bytes.to_vec()).ok()?;
+        Some(Self { formula, center, zoom_level, max_iter })
+    }
+}
+
+/// 型ごとに復元した共有用リンク
+#[derive(Debug, Clone, PartialEq)]
+enum LinkedView {
+    F64(ViewLink<f64>),
+    F106(ViewLink<F106>),
+}
+
+impl LinkedView {
+    fn from_payload(generics: u8, payload: &[u8]) -> Result<Self, String> {
+        let invalid = || "Invalid link: broken payload.".to_string();
+        match generics {
+            0 => ViewLink::from_bytes(payload).map(Self::F64).ok_or_else(invalid),
+            1 => ViewLink::from_bytes(payload).map(Self::F106).ok_or_else(invalid),
+            _ => Err(format!("Invalid link: unknown precision ({}).", generics)),
+        }
+    }
+
+    fn formula(&self) -> &str {
+        match self {
+            Self::F64(link) => &link.formula,
+            Self::F106(link) => &link.formula,
+        }
+    }
+
+    /// Fractal::to_index と同じ対応
+    fn to_index(&self) -> usize {
+        match self {
+            Self::F64(_) => 0,
+            Self::F106(_) => 1,
+        }
+    }
+}
+
+/// # export_link の文字列を復号する
+fn decode_link(s: &str) -> Result<LinkedView, String> {
+    let bytes = base64::engine::general_purpose::URL_SAFE_NO_PAD.decode(s.trim())
+        .map_err(|e| format!("Invalid link: {}", e))?;
+    let [version, generics, payload @ ..] = bytes.as_slice() else {
+        return Err("Invalid link: too short.".to_string());
+    };
+    if *version != LINK_VERSION {
+        return Err(format!("Unsupported link version: {}.", version));
+    }
+    LinkedView::from_payload(*generics, payload)
+}
+
+fn view_link_inner<T>(f: &FractalInner<T>) -> ViewLink<T>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    ViewLink {
+        formula:    f.formula().to_string(),
+        center:     f.canvas().center().clone(),
+        zoom_level: f.canvas().zoom_level,
+        max_iter:   f.max_iter(),
+    }
+}
+
+fn apply_view_link_inner<T>(f: &mut FractalInner<T>, link: ViewLink<T>) -> Result<(), String>
+where
+    T: Real + FromStr + Send + Sync + 'static
+        + AddAssign + SubAssign + MulAssign + DivAssign + RemAssign,
+{
+    f.set_formula(&link.formula)
+        .map_err(|e| e.to_string())?;
+    f.canvas_mut().set_center(link.center.re, link.center.im);
+    f.canvas_mut().zoom_level = link.zoom_level;
+    f.set_max_iter(link.max_iter);
+    Ok(())
+}
+
+/// # 表示状態を、URLに埋め込める共有用の文字列に変換する
+///
+/// 数式・中心座標・ズーム段階・最大反復回数のみをバイト列にし、URL-safe base64 (パディング無し) で返す。
+/// 先頭1byteは形式のバージョンとする。条件式や補間の設定は含めない
+#[tauri::command]
+pub fn export_link() -> String {
+    let fractal = FRACTAL.lock().unwrap();
+    let generics = fractal.to_index() as u8;
+    let bytes = match &*fractal {
+        Fractal::F64(f) => view_link_inner(f).to_bytes(generics),
+        Fractal::F106(f) => view_link_inner(f).to_bytes(generics),
+    };
+    base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+}
+
+/// # リンクの表示状態を適用した Fractal を作成する
+///
+/// 複製に適用するので、数式のコンパイルに失敗した場合も fractal は変わらない。
+/// リンクを作成した時の型で中心座標を設定してから、ズーム段階に合った精度に切り替える
+fn import_link_inner(fractal: &Fractal, link: LinkedView) -> Result<Fractal, String> {
+    let mut next = fractal.clone();
+    while next.to_index() < link.to_index() {
+        next.up();
+    }
+    while next.to_index() > link.to_index() {
+        next.down();
+    }
+    match (&mut next, link) {
+        (Fractal::F64(f), LinkedView::F64(link)) => apply_view_link_inner(f, link)?,
+        (Fractal::F106(f), LinkedView::F106(link)) => apply_view_link_inner(f, link)?,
+        _ => unreachable!(), // 型は上で揃えている
+    }
+    next.fit_precision();
+    Ok(next)
+}
+
+/// # export_link で作成した文字列から、表示状態を復元する
+///
+/// リンクに含まれない設定 (収束判定の方法など) は現在の値のままとする。
+/// 全ての設定に成功した場合のみ置き換えるので、失敗した場合は現在の状態のまま変わらない
+#[tauri::command]
+pub async fn import_link(s: String) -> Result<(), String> {
+    let link = decode_link(&s)?;
+    validate_formula(link.formula(), &FormulaLimits::default())?;
+
+    cancel_render();
+    let generation = btm::CancelToken::advance(&FORMULA_GENERATION);
+    let result = tauri::async_runtime::spawn_blocking(move || -> Result<(), String> {
+        // コンパイル中も他のコマンドを受け付けられるように、複製から作成する
+        let next = import_link_inner(&snapshot_fractal(), link)?;
+        let mut fractal = FRACTAL.lock().unwrap();
+        if generation.is_cancelled() {
+            return Ok(()); // 後から呼び出された数式の変更に任せる
+        }
+        record_navigation(&fractal, Navigation::Jump);
+        supersede_formula_changes();
+        *fractal = next;
+        notify_formula_ready();
+        Ok(())
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// 描画に関わる全ての状態
+///
+/// Fractal はコンパイル済みの数式と根のキャッシュを含むので、数式文字列と不整合になることが無い
+#[derive(Debug, Clone)]
+struct RendererState {
+    fractal:  Fractal,
+    coloring: Coloring,
+}
+
+/// snapshot で保存した状態を識別する番号
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StateHandle(u64);
+
+/// # snapshot で保存した状態の一覧と、undo_view・redo_view で戻す表示領域の履歴
+///
+/// snapshot で保存した状態は、drop_snapshot で破棄するまで保持する。
+/// 履歴は保存した状態とは別に持ち、古いものから破棄して最大 MAX_HISTORY 個を保持する
+#[derive(Debug, Default)]
+struct StateStore {
+    next:      u64,
+    snapshots: std::collections::BTreeMap<u64, RendererState>,
+    /// 表示領域を変更する前の表示領域 (新しいものが末尾)
+    undo:      std::collections::VecDeque<SavedView>,
+    /// undo で戻す前の表示領域 (新しいものが末尾)
+    redo:      Vec<SavedView>,
+    /// 最後に記録した操作と、その時刻
+    last:      Option<(Navigation, std::time::Instant)>,
+}
+
+impl StateStore {
+    const MAX_HISTORY: usize = 64;
+    /// この間隔以内に続けて行った同じ種類の操作は、1つの履歴にまとめる (ドラッグやホイールで連続するイベント)
+    const COALESCE_INTERVAL: std::time::Duration = std::time::Duration::from_millis(500);
+
+    fn save(&mut self, state: RendererState) -> StateHandle {
+        let handle = StateHandle(self.next);
+        self.next += 1;
+        self.snapshots.insert(handle.0, state);
+        handle
+    }
+
+    fn get(&self, handle: StateHandle) -> Option<&RendererState> {
+        self.snapshots.get(&handle.0)
+    }
+
+    /// # 保存した状態を破棄する
+    ///
+    /// ## Returns
+    ///  - 保存されていない番号の場合はNone
+    fn remove(&mut self, handle: StateHandle) -> Option<RendererState> {
+        self.snapshots.remove(&handle.0)
+    }
+
+    /// # 戻す表示領域を履歴に追加し、MAX_HISTORY 個を超えた古い履歴を破棄する
+    fn push_undo(&mut self, view: SavedView) {
+        self.undo.push_back(view);
+        while self.undo.len() > Self::MAX_HISTORY {
+            self.undo.pop_front();
+        }
+    }
+
+    /// # 表示領域を変更する前に、変更前の表示領域を履歴に保存する
+    ///
+    /// 直前の操作とまとめる場合は before を呼び出さない。新しく保存した場合は、やり直しの履歴を破棄する
+    fn record(&mut self, navigation: Navigation, now: std::time::Instant, before: impl FnOnce() -> SavedView) {
+        let coalesced = matches!(self.last, Some((last, at))
+            if last == navigation && navigation != Navigation::Jump
+                && now.saturating_duration_since(at) < Self::COALESCE_INTERVAL);
+        self.last = Some((navigation, now));
+        if !coalesced {
+            self.redo.clear();
+            self.push_undo(before());
+        }
+    }
+
+    /// # 1つ前の表示領域を取り出し、現在の表示領域をやり直しの履歴に保存する
+    fn undo(&mut self, current: impl FnOnce() -> SavedView) -> Option<SavedView> {
+        let view = self.undo.pop_back()?;
+        self.redo.push(current());
+        self.last = None;
+        Some(view)
+    }
+
+    /// # undo で戻した表示領域を取り出し、現在の表示領域を履歴に保存する
+    fn redo(&mut self, current: impl FnOnce() -> SavedView) -> Option<SavedView> {
+        let view = self.redo.pop()?;
+        self.push_undo(current());
+        self.last = None;
+        Some(view)
+    }
+}
+
+static STATE_STORE: Lazy<Mutex<StateStore>> = Lazy::new(|| {
+    Mutex::new(StateStore::default())
+});
+
+/// # 描画に関わる全ての状態 (数式・表示領域・計算の設定・色付けの設定) を保存する
+///
+/// FRACTAL と COLORING を同時にlockして複製するので、設定の途中の状態を保存することは無い。
+/// 保存した状態は、drop_snapshot で破棄するまで保持する
+///
+/// ## Returns
+///  - restore に渡す番号
+#[tauri::command]
+pub fn snapshot() -> StateHandle {
+    let fractal = FRACTAL.lock().unwrap();
+    let coloring = COLORING.lock().unwrap();
+    let state = RendererState { fractal: fractal.clone(), coloring: coloring.clone() };
+    STATE_STORE.lock().unwrap().save(state)
+}
+
+/// # 保存した状態で、FRACTAL と COLORING を置き換える
+///
+/// 全ての状態を1度に置き換えるため、数式だけが変わって根のキャッシュが古いままになるような途中の状態は生じない。
+/// 実行中の描画は中断し、実行中の数式の変更は破棄する
+fn install_state(fractal: &mut Fractal, coloring: &mut Coloring, state: RendererState) {
+    cancel_render();
+    supersede_formula_changes();
+    *fractal = state.fractal;
+    *coloring = state.coloring;
+    if fractal.ensure_formula_loaded().is_ok() {
+        notify_formula_ready();
+    }
+}
+
+/// # snapshot で保存した状態を破棄する
+///
+/// 破棄した番号は restore に渡せなくなる
+#[tauri::command]
+pub fn drop_snapshot(handle: StateHandle) -> Result<(), String> {
+    STATE_STORE.lock().unwrap().remove(handle)
+        .map(|_| ())
+        .ok_or_else(|| format!("Unknown state handle: {}.", handle.0))
+}
+
+/// # snapshot で保存した状態に戻す
+///
+/// 置き換える前の表示領域を履歴に保存するので、undo_view で表示領域を元に戻せる
+#[tauri::command]
+pub fn restore(handle: StateHandle) -> Result<(), String> {
+    let mut fractal = FRACTAL.lock().unwrap();
+    let mut coloring = COLORING.lock().unwrap();
+    let mut store = STATE_STORE.lock().unwrap();
+    let state = store.get(handle).cloned()
+        .ok_or_else(|| format!("Unknown state handle: {}.", handle.0))?;
+    store.record(Navigation::Jump, std::time::Instant::now(), || fractal.saved_view());
+    drop(store);
+    install_state(&mut fractal, &mut coloring, state);
+    Ok(())
+}
+
+/// # pin_current_render で保存した画像の一覧
+///
+/// StateStore の履歴と同様に古いものから破棄する。画像は大きいので、保持する数を少なくする
+#[derive(Debug, Default)]
+struct PinnedRenders {
+    next:   u64,
+    images: std::collections::BTreeMap<u64, String>,
+}
+
+impl PinnedRenders {
+    const MAX_PINS: usize = 8;
+
+    fn pin(&mut self, png: String) -> u64 {
+        let handle = self.next;
+        self.next += 1;
+        self.images.insert(handle, png);
+        while self.images.len() > Self::MAX_PINS {
+            self.images.pop_first();
+        }
+        handle
+    }
+
+    fn get(&self, handle: u64) -> Option<&String> {
+        self.images.get(&handle)
+    }
+}
+
+static PINNED_RENDERS: Lazy<Mutex<PinnedRenders>> = Lazy::new(|| {
+    Mutex::new(PinnedRenders::default())
+});
+
+/// # 現在の表示領域を色付けした画像を保存する
+///
+/// 数式を編集する前の描画結果を、再計算せずに並べて比較するために使用する。
+/// 保存できるのは最大8枚で、それより古いものは破棄する
+///
+/// ## Returns
+///  - get_pinned_render に渡す番号
+#[tauri::command]
+pub async fn pin_current_render() -> Result<u64, String> {
+    let result = tauri::async_runtime::spawn_blocking(move || {
+        let fractal = prepare_render()?;
+        let coloring = COLORING.lock().unwrap().clone();
+        let ((width, height), rgba) = in_render_pool(|| with_fractal!(&fractal, |fr, fo| {
+            let (width, height) = fr.canvas().viewport_size();
+            let mut rgba = colorize_rect(&coloring, calc_info(fr, fo, 0, 0, width, height));
+            flip_rows(&mut rgba, width as usize * 4, height as usize); // 1行は width * 4 byte
+            ((width, height), rgba)
+        }));
+        let png = encode_png_base64(width, height, png::ColorType::Rgba, &rgba)?;
+        Ok(PINNED_RENDERS.lock().unwrap().pin(png))
+    }).await;
+
+    result.map_err(|e| e.to_string())?
+}
+
+/// # pin_current_render で保存した画像を、base64でエンコードしたPNG画像として返す
+#[tauri::command]
+pub fn get_pinned_render(handle: u64) -> Result<String, String> {
+    PINNED_RENDERS.lock().unwrap().get(handle).cloned()
+        .ok_or_else(|| format!("Unknown pinned render: {}.", handle))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::time::{Duration, Instant};
+
+    /// 数式を設定した f64 の FractalInner を作成する
+    fn fractal(formula: &str) -> FractalInner<f64> {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.set_formula(formula).unwrap();
+        fractal
+    }
+
+    /// f64 の数式を取り出す
+    fn fo(fractal: &FractalInner<f64>) -> &FormulacInner<f64> {
+        let Formulac::F64(fo) = fractal.formulac() else { unreachable!() };
+        fo
+    }
+
+    /// 点 z での関数値と導関数値を返す
+    fn eval(fractal: &FractalInner<f64>, z: Complex<f64>) -> (Complex<f64>, Complex<f64>) {
+        let fo = fo(fractal);
+        (fo.func()([z]), fo.deriv()([z]))
+    }
+
+    const RED: [u8; 3] = [255, 0, 0];
+    const GREEN: [u8; 3] = [0, 255, 0];
+    const BLUE: [u8; 3] = [0, 0, 255];
+
+    fn render_basins(colors: Vec<[u8; 3]>) -> Vec<[u8; 4]> {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+
+        let mut coloring = Coloring::default();
+        coloring.set_mode(ColoringMode::Basin);
+        coloring.set_basin_colors(colors);
+        coloring.set_basin_shading(false);
+
+        render_rgba_inner(&fractal, fo, &coloring, 64, 64)
+            .chunks_exact(4)
+            .map(|c| [c[0], c[1], c[2], c[3]])
+            .collect()
+    }
+
+    fn dominant_colors(pixels: &[[u8; 4]], n: usize) -> Vec<[u8; 3]> {
+        let mut counts: HashMap<[u8; 3], usize> = HashMap::new();
+        for p in pixels {
+            *counts.entry([p[0], p[1], p[2]]).or_default() += 1;
+        }
+        let mut counts: Vec<_> = counts.into_iter().collect();
+        counts.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        counts.into_iter().take(n).map(|(color, _)| color).collect()
+    }
+
+    #[test]
+    fn three_roots_get_three_colors() {
+        let pixels = render_basins(vec![RED, GREEN, BLUE]);
+        let mut dominant = dominant_colors(&pixels, 3);
+        dominant.sort();
+
+        let mut expected = vec![RED, GREEN, BLUE];
+        expected.sort();
+        assert_eq!(dominant, expected);
+    }
+
+    #[test]
+    fn missing_colors_fall_back_to_hue_wheel() {
+        let pixels = render_basins(vec![RED]);
+        let dominant = dominant_colors(&pixels, 3);
+
+        assert!(dominant.contains(&RED));
+        assert_eq!(dominant.iter().filter(|c| **c != RED).count(), 2);
+        assert_ne!(dominant[0], dominant[1]);
+        assert_ne!(dominant[1], dominant[2]);
+        assert_ne!(dominant[0], dominant[2]);
+    }
+
+    const SIZE: u32 = 64;
+
+    fn full_render(fractal: &FractalInner<f64>) -> Vec<u16> {
+        let fo = fo(fractal);
+        btm::calc_rect(view_calc_info(fractal, fo, SIZE, SIZE)).escape_times()
+    }
+
+    fn assert_matches_full_render(dx: i32, dy: i32) {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.canvas_mut().set_size(SIZE as u16, SIZE as u16);
+        let prev = full_render(&fractal);
+
+        move_view_inner(&mut fractal, dx as f64 / SIZE as f64, dy as f64 / SIZE as f64);
+        let fo = fo(&fractal);
+        let delta = render_pan_delta_inner(&fractal, fo, dx, dy, &prev, SIZE, SIZE);
+
+        // 境界追跡法は計算する矩形の辺の近くで結果がわずかに変わるため、完全一致は求めない
+        let full = full_render(&fractal);
+        let mismatches = delta.iter().zip(&full).filter(|(a, b)| a != b).count();
+        assert!(mismatches * 100 <= full.len(), "dx = {}, dy = {}: {} pixels differ", dx, dy, mismatches);
+    }
+
+    #[test]
+    fn shifted_buffer_matches_full_render() {
+        for (dx, dy) in [(8, 0), (-8, 0), (0, 8), (0, -8), (4, -16), (-16, 4), (-1, 1)] {
+            assert_matches_full_render(dx, dy);
+        }
+    }
+
+    #[test]
+    fn large_delta_falls_back_to_full_render() {
+        assert_matches_full_render(SIZE as i32, 0);
+        assert_matches_full_render(0, -(SIZE as i32) * 2);
+    }
+
+    #[test]
+    fn single_thread_pool_matches_default_pool() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let render = || render_tile_inner(&fractal, fo, 0, 0, 128, 128);
+
+        let pool = build_render_pool(1).unwrap();
+        assert!(pool.is_some());
+        assert_eq!(run_in_pool(pool.as_deref(), render), run_in_pool(None, render));
+    }
+
+    #[test]
+    fn zero_threads_uses_global_pool() {
+        assert!(build_render_pool(0).unwrap().is_none());
+    }
+
+    #[test]
+    fn halton_sequence() {
+        assert_eq!(halton(0, 2), 0.0);
+        assert_eq!(halton(1, 2), 0.5);
+        assert_eq!(halton(2, 2), 0.25);
+        assert_eq!(halton(3, 2), 0.75);
+        assert!((halton(1, 3) - 1.0 / 3.0).abs() < 1e-12);
+        assert!((halton(2, 3) - 2.0 / 3.0).abs() < 1e-12);
+    }
+
+    #[test]
+    fn single_pass_equals_plain_render() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let coloring = Coloring::default();
+
+        let result = btm::calc_rect(calc_info(&fractal, fo, 16, 32, 96, 64));
+        let plain = coloring.colorize(&result.pixels, &[], &roots_f64(&result.roots), fractal.max_iter());
+
+        assert_eq!(render_tile_taa_inner(&fractal, fo, &coloring, (16, 32, 96, 64), 1), plain);
+    }
+
+    #[test]
+    fn single_unjittered_sample_equals_plain_render() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let coloring = Coloring::default();
+
+        let result = btm::calc_rect(calc_info(&fractal, fo, 16, 32, 96, 64));
+        let plain = coloring.colorize(&result.pixels, &[], &roots_f64(&result.roots), fractal.max_iter());
+
+        assert_eq!(render_tile_stochastic_inner(&fractal, fo, &coloring, (16, 32, 96, 64), 1, (0, 0.0)), plain);
+    }
+
+    #[test]
+    fn stochastic_render_is_reproducible_for_a_seed() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let coloring = Coloring::default();
+
+        let render = |seed| render_tile_stochastic_inner(&fractal, fo, &coloring, (0, 0, 64, 64), 4, (seed, 1.0));
+        assert_eq!(render(7), render(7));
+        assert_ne!(render(7), render(8));
+    }
+
+    #[test]
+    fn accepts_ordinary_formulas() {
+        let limits = FormulaLimits::default();
+        for formula in ["z^3 - 1", "sin(z) - z^(-2)", "(z^8 + 15*z^4 - 16) / z^2.5", "exp(z^ 12) - 1"] {
+            assert!(validate_formula(formula, &limits).is_ok(), "{}", formula);
+        }
+    }
+
+    #[test]
+    fn non_holomorphic_formulas_fail_to_compile() {
+        // conj と abs は導関数を作れないので、Newton法で誤った画像を描く前にコンパイルの時点で拒否される
+        for formula in ["conj(z)^3 - 1", "abs(z) - 1"] {
+            let mut fractal = FractalInner::<f64>::default();
+            let err = fractal.set_formula(formula).unwrap_err().to_string();
+            assert!(err.contains("not differentiable"), "{formula}: {err}");
+        }
+    }
+
+    #[test]
+    fn rejects_over_long_formula() {
+        let limits = FormulaLimits::default();
+        let formula = vec!["z"; limits.max_length].join("+");
+        assert!(validate_formula(&formula, &limits).is_err());
+    }
+
+    #[test]
+    fn rejects_absurd_exponent() {
+        let limits = FormulaLimits::default();
+        assert!(validate_formula("z^999999999 - 1", &limits).is_err());
+        assert!(validate_formula("z^(-999999999) - 1", &limits).is_err());
+        assert!(validate_formula("z^1e9 - 1", &limits).is_err());
+        assert!(validate_formula("z^(2*999999) - 1", &limits).is_err());
+        assert!(validate_formula("z^(4e3/2) - 1", &limits).is_err());
+        assert!(validate_formula("z^1.5e2 - 1", &limits).is_ok());
+        assert!(validate_formula("z^(3*2) - exp(z)^2", &limits).is_ok());
+        assert!(validate_formula("z^(z + 1) - 1", &limits).is_ok());
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let limits = FormulaLimits::default();
+        let depth = limits.max_nesting + 1;
+        let formula = format!("{}z{}", "(".repeat(depth), ")".repeat(depth));
+        assert!(validate_formula(&formula, &limits).is_err());
+    }
+
+    /// 左下と右上のピクセルの座標の差
+    fn sampled_span(half_width: f64, width: u32, height: u32) -> Complex<f64> {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let info = figure_calc_info(&fractal, fo, Complex::new(0.25, -0.5), half_width, width, height);
+        info.get_complex(width as i64 - 1, height as i64 - 1) - info.get_complex(0, 0)
+    }
+
+    #[test]
+    fn doubling_half_width_doubles_span() {
+        for (width, height) in [(64, 64), (96, 48), (40, 80)] {
+            let span = sampled_span(0.5, width, height);
+            let doubled = sampled_span(1.0, width, height);
+            assert!((doubled.re - span.re * 2.0).abs() < 1e-12);
+            assert!((doubled.im - span.im * 2.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn image_is_centered() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let center = Complex::new(0.25, -0.5);
+        let info = figure_calc_info(&fractal, fo, center, 1.0, 96, 48);
+
+        assert_eq!(info.get_complex(0, 0).re, -0.75);
+        assert_eq!(info.get_complex(48, 24), center);
+    }
+
+    const MAX_ZOOM: i32 = 350;
+
+    #[test]
+    fn same_seed_chooses_same_view() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+
+        let a = choose_interesting_view(&fractal, fo, 42, MAX_ZOOM);
+        let b = choose_interesting_view(&fractal, fo, 42, MAX_ZOOM);
+        assert_eq!(a, b);
+    }
+
+    #[test]
+    fn chosen_view_has_basin_transitions() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+
+        let (center, zoom_level, score) = choose_interesting_view(&fractal, fo, 7, MAX_ZOOM);
+        assert!(score > 0.0);
+        assert_eq!(zoom_level, fractal.canvas().zoom_level + 16);
+
+        let half = fractal.canvas().width() / 2.0;
+        assert!(center.re.abs() <= half && center.im.abs() <= half);
+    }
+
+    #[test]
+    fn flat_region_has_zero_density() {
+        let pixels = vec![btm::Pixel { iter: 3, root: 0 }; 16];
+        assert_eq!(transition_density(&pixels, 4), 0.0);
+    }
+
+    #[test]
+    fn evaluates_compiled_formula() {
+        let mut fo = FormulacInner::<f64>::new();
+        fo.set_formula("z^3 - 1").unwrap();
+
+        let values = sample_formula_inner(&fo, &[(0.0, 0.0), (1.0, 0.0), (0.0, 1.0)]);
+        assert_eq!(values, vec![(-1.0, 0.0), (0.0, 0.0), (-1.0, -1.0)]);
+    }
+
+    #[test]
+    fn writes_palette_with_at_most_n_colors() {
+        const WIDTH: u32 = 64;
+        const HEIGHT: u32 = 4;
+        const PALETTE_SIZE: u16 = 8;
+
+        // カラーマップの全域にわたるグラデーション
+        let rgba: Vec<u8> = (0..WIDTH * HEIGHT)
+            .flat_map(|i| coloring::jet((i % WIDTH) as f64 / (WIDTH - 1) as f64))
+            .collect();
+        let mut bytes = Vec::new();
+        write_indexed_png(&mut bytes, WIDTH, HEIGHT, &rgba, PALETTE_SIZE, Vec::new()).unwrap();
+
+        // パレットを展開せずに読み込み、インデックスを直接調べる
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::IDENTITY);
+        let mut reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+        let palette_len = reader.info().palette.as_ref().unwrap().len();
+        assert_eq!(palette_len, PALETTE_SIZE as usize * 3);
+
+        let mut indices = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut indices).unwrap();
+        let distinct: std::collections::BTreeSet<u8> = indices.iter().copied().collect();
+        assert!(distinct.len() <= PALETTE_SIZE as usize);
+        assert!(distinct.len() > 1);
+        assert!(distinct.iter().all(|&idx| idx < PALETTE_SIZE as u8));
+    }
+
+    #[test]
+    fn indexed_png_keeps_the_rendered_colors_and_alpha() {
+        // 収束先毎の色 (カラーマップにない色) と半透明の画素
+        let rgba: Vec<u8> = [[230, 25, 75, 255], [60, 180, 75, 255], [0, 130, 200, 96], [230, 25, 75, 255]].concat();
+        let mut bytes = Vec::new();
+        write_indexed_png(&mut bytes, 2, 2, &rgba, 16, Vec::new()).unwrap();
+
+        let mut decoder = png::Decoder::new(std::io::Cursor::new(bytes));
+        decoder.set_transformations(png::Transformations::EXPAND);
+        let mut reader = decoder.read_info().unwrap();
+        assert_eq!(reader.info().color_type, png::ColorType::Indexed);
+        let mut decoded = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut decoded).unwrap();
+        assert_eq!(decoded, rgba);
+    }
+
+    #[test]
+    fn rejects_invalid_palette_size() {
+        for size in [0, 1, 257] {
+            assert!(write_indexed_png(Vec::new(), 1, 1, &[0; 4], size, Vec::new()).is_err());
+        }
+    }
+
+    const SAMPLES: u32 = 64;
+
+    #[test]
+    fn correct_derivative_has_tiny_discrepancy() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        assert_eq!(fractal.formula(), "z^3 - 1");
+
+        let discrepancy = check_derivative_inner(&fractal, fo, SAMPLES);
+        assert!(discrepancy < 1e-6, "{discrepancy}");
+    }
+
+    #[test]
+    fn wrong_derivative_is_detected() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let mut wrong = fo.clone();
+        wrong.df = Arc::clone(&wrong.f);
+
+        assert!(check_derivative_inner(&fractal, &wrong, SAMPLES) > 1e-1);
+    }
+
+    #[test]
+    fn levels_halve_down_to_a_single_pixel() {
+        assert_eq!(dzi_max_level(1000), 10);
+
+        let size_of = |level: u32| dzi_level_tiles(1000, 256, level).next().unwrap().size;
+        assert_eq!(size_of(0), 1);
+        assert_eq!(size_of(9), 500);
+        assert_eq!(size_of(10), 1000);
+
+        let finest: Vec<DziTile> = dzi_level_tiles(1000, 256, 10).collect();
+        assert_eq!(finest.len(), 16);
+        let covered: u64 = finest.iter().map(|t| t.width as u64 * t.height as u64).sum();
+        assert_eq!(covered, 1000 * 1000);
+    }
+
+    #[test]
+    fn huge_dzi_is_rejected_before_rendering() {
+        assert!(dzi_full_size(31, 1).unwrap_err().contains("Too many tiles"));
+        assert!(dzi_full_size(0, 1 << 16).unwrap_err().contains("memory budget"));
+        assert!(dzi_full_size(40, 256).is_err());
+        assert_eq!(dzi_full_size(2, 256), Ok(1024));
+    }
+
+    #[test]
+    fn dzi_larger_than_one_render_is_accepted() {
+        // 画像全体は calc_rect のメモリの上限を超えるが、タイル毎に計算するので出力できる
+        let full_size = dzi_full_size(8, 256).unwrap();
+        assert_eq!(full_size, 1 << 16);
+        assert!(btm::check_memory_budget(full_size, full_size).is_err());
+    }
+
+    #[test]
+    fn montage_layout_is_checked_against_the_memory_budget() {
+        assert_eq!(montage_layout(5, 64), Ok((3, 192, 128)));
+        assert!(montage_layout(4, 8192).unwrap_err().contains("memory budget"));
+        assert!(montage_layout(4, u32::MAX).is_err());
+        assert!(montage_layout(1 << 40, 1).is_err());
+    }
+
+    #[test]
+    fn montage_cells_use_their_own_roots() {
+        const CELL: u32 = 32;
+        let live = fractal("z^3 - 1");
+        let roots = roots_of(&live);
+        let coloring = Coloring::default();
+        let values = [(0.0, 0.0), (3.0, 0.0)];
+
+        let (width, _, image) = render_montage_inner(&live, &coloring, "z^2 - 1 + {c}", &values, CELL).unwrap();
+        assert_eq!(roots_of(&live), roots);
+
+        let row_bytes = CELL as usize * 4;
+        for (i, &value) in values.iter().enumerate() {
+            let own = fractal(&substitute_param("z^2 - 1 + {c}", value));
+            let mut expected = colorize_rect(&coloring, view_calc_info(&own, fo(&own), CELL, CELL));
+            flip_rows(&mut expected, row_bytes, CELL as usize);
+            for (y, row) in expected.chunks_exact(row_bytes).enumerate() {
+                let src = (y * width as usize + i * CELL as usize) * 4;
+                assert_eq!(&image[src..src + row_bytes], row, "cell {}, row {}", i, y);
+            }
+        }
+
+        // 根を未計算の場合も、セルの数式の根を書き込まない
+        let live = fractal("z^3 - 1");
+        render_montage_inner(&live, &coloring, "z^2 - 1 + {c}", &values, CELL).unwrap();
+        assert!(live.roots.get().is_none());
+    }
+
+    #[test]
+    fn linear_recolor_matches_colored_render() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+
+        let direct = colorize_rect(&Coloring::default(), calc_info(&fractal, fo, 0, 0, 64, 48));
+        let raw = btm::calc_rect(calc_info(&fractal, fo, 0, 0, 64, 48)).escape_times();
+        let recolor = Recolor {
+            colormap:  Colormap::Jet,
+            gamma:     1.0,
+            transform: ValueTransform::Linear,
+            period:    0,
+        };
+        assert_eq!(recolor.apply(&raw, fractal.max_iter()), direct);
+    }
+
+    #[test]
+    fn period_repeats_colors() {
+        let recolor = Recolor {
+            colormap:  Colormap::Grayscale,
+            gamma:     1.0,
+            transform: ValueTransform::Linear,
+            period:    8,
+        };
+        let rgba = recolor.apply(&[3, 11, 19], 64);
+        assert_eq!(rgba[0..4], rgba[4..8]);
+        assert_eq!(rgba[0..4], rgba[8..12]);
+    }
+
+    fn roots_of(fractal: &FractalInner<f64>) -> Vec<Complex<f64>> {
+        let fo = fo(fractal);
+        fractal.cached_roots(fo).to_vec()
+    }
+
+    #[test]
+    fn changing_formula_invalidates_roots() {
+        let mut fractal = FractalInner::<f64>::default(); // z^3 - 1
+        assert_eq!(roots_of(&fractal).len(), 3);
+
+        fractal.set_formula("z^2 - 1").unwrap();
+        let roots = roots_of(&fractal);
+        assert_eq!(roots.len(), 2);
+        assert!(roots.iter().all(|z| (z.re.abs() - 1.0).abs() < 1e-3 && z.im.abs() < 1e-3));
+    }
+
+    #[test]
+    fn snapshot_shares_roots_with_original() {
+        let fractal = FractalInner::<f64>::default();
+        let snapshot = fractal.clone();
+        roots_of(&snapshot);
+        assert!(fractal.roots.get().is_some());
+    }
+
+    #[test]
+    fn tiles_use_the_same_root_indices() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let roots = fractal.cached_roots(fo).to_vec();
+
+        // 異なる領域のタイルでも、同じ根には同じ番号が割り当てられる
+        for (x, y) in [(0, 0), (256, 0), (128, 384)] {
+            let result = btm::calc_rect(calc_info(&fractal, fo, x, y, 64, 64));
+            assert_eq!(result.roots[..roots.len()], roots[..]);
+        }
+    }
+
+    #[test]
+    fn roots_do_not_depend_on_the_iteration_settings() {
+        let mut fractal = fractal("z^5 - 1");
+        fractal.set_max_iter(4);
+        let roots = roots_of(&fractal);
+        assert_eq!(roots.len(), 5);
+
+        // 根を求めた後に最大反復回数を変更しても、同じ根の一覧を使用する
+        fractal.set_max_iter(1000);
+        let mut fresh = fractal.clone();
+        fresh.invalidate_roots();
+        assert_eq!(roots_of(&fractal), roots);
+        assert_eq!(roots_of(&fresh), roots);
+    }
+
+    #[test]
+    fn roots_do_not_depend_on_the_first_view() {
+        let mut moved = fractal("z^2 - 100");
+        let mut fractal = moved.clone();
+        fractal.invalidate_roots();
+
+        // 根 (±10) から離れた場所を拡大した表示領域で、初めて根を求める
+        moved.invalidate_roots();
+        moved.canvas_mut().set_center(0.3, 0.2);
+        moved.canvas_mut().zoom_level = 40;
+        let roots = roots_of(&moved);
+        assert_eq!(roots, roots_of(&fractal));
+        assert_eq!(roots.len(), 2);
+        assert!(roots.iter().all(|z| (z.re.abs() - 10.0).abs() < 1e-3 && z.im.abs() < 1e-3));
+    }
+
+    #[test]
+    fn uncompiled_fractal_is_rejected_until_formula_is_set() {
+        let mut inner = FractalInner::<f64>::uncompiled();
+        assert!(Fractal::F64(inner.clone()).ensure_formula_loaded().is_err());
+
+        inner.set_formula("z^3 - 1").unwrap();
+        assert!(Fractal::F64(inner).ensure_formula_loaded().is_ok());
+    }
+
+    #[test]
+    fn failed_compile_keeps_the_flag_unset() {
+        let mut inner = FractalInner::<f64>::uncompiled();
+        assert!(inner.set_formula("z^").is_err());
+        assert!(!inner.formula_loaded);
+    }
+
+    #[test]
+    fn blends_function_and_derivative() {
+        let z = Complex::new(0.7, -0.4);
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.set_morph_formula("z^3 - 1", "z^4 - 1", 0.0).unwrap();
+        let (f_a, df_a) = eval(&fractal, z);
+        fractal.set_morph_t(1.0).unwrap();
+        let (f_b, df_b) = eval(&fractal, z);
+        fractal.set_morph_t(0.25).unwrap();
+        let (f, df) = eval(&fractal, z);
+
+        assert!((f_a - (z.powu(3) - 1.0)).norm() < 1e-12);
+        assert!((f_b - (z.powu(4) - 1.0)).norm() < 1e-12);
+        assert!((f - (f_a * 0.75 + f_b * 0.25)).norm() < 1e-12);
+        assert!((df - (df_a * 0.75 + df_b * 0.25)).norm() < 1e-12);
+    }
+
+    #[test]
+    fn recompile_keeps_morph_and_set_formula_clears_it() {
+        let z = Complex::new(0.7, -0.4);
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.set_morph_formula("z^3 - 1", "z^4 - 1", 0.5).unwrap();
+        let before = eval(&fractal, z);
+        fractal.recompile().unwrap();
+        assert_eq!(eval(&fractal, z), before);
+
+        fractal.set_formula("z^2 - 1").unwrap();
+        assert!(fractal.morph.is_none());
+        assert!(fractal.set_morph_t(0.5).is_err());
+    }
+
+    fn warning_at(zoom_level: i32, center: Complex<f64>) -> Option<String> {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.canvas_mut().zoom_level = zoom_level;
+        fractal.canvas_mut().center = center;
+        precision_warning_inner(&fractal, f64::MANTISSA_DIGITS as i32)
+    }
+
+    #[test]
+    fn shallow_zoom_has_enough_precision() {
+        assert!(warning_at(0, Complex::new(-0.5, 0.0)).is_none());
+        assert!(warning_at(300, Complex::new(-0.5, 0.0)).is_none());
+    }
+
+    #[test]
+    fn deep_zoom_in_f64_warns() {
+        assert!(warning_at(400, Complex::new(-0.5, 0.0)).is_some());
+        // 中心の絶対値が大きいほど ulp が大きいので、浅い拡大率で警告する
+        assert!(warning_at(300, Complex::new(1024.0, 0.0)).is_some());
+    }
+
+    #[test]
+    fn adjacent_pixels_are_distinct_doubles_when_not_warned() {
+        // 512 px, zoom 320 では間隔は 2^-48 、|z| < 2 での ulp は 2^-52 なので16個
+        let log2 = log2_values_per_pixel(-320.0 / 8.0 + 1.0 - 9.0, 1.5, 53);
+        assert_eq!(log2, 4.0);
+    }
+
+    fn round_trip<T: LinkValue>(link: &ViewLink<T>, generics: u8) -> LinkedView {
+        let s = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(link.to_bytes(generics));
+        assert!(s.bytes().all(|c| c.is_ascii_alphanumeric() || c == b'-' || c == b'_'));
+        decode_link(&s).unwrap()
+    }
+
+    #[test]
+    fn f64_view_round_trips() {
+        let mut fractal = fractal("z^3 - 2*z + 2");
+        fractal.canvas_mut().set_center(-0.123456789, 0.987654321);
+        fractal.canvas_mut().zoom_level = 42;
+        fractal.set_max_iter(777);
+        let link = view_link_inner(&fractal);
+        assert_eq!(round_trip(&link, 0), LinkedView::F64(link.clone()));
+
+        let mut restored = FractalInner::<f64>::default();
+        apply_view_link_inner(&mut restored, link).unwrap();
+        assert_eq!(restored.formula(), "z^3 - 2*z + 2");
+        assert_eq!(restored.canvas().center(), fractal.canvas().center());
+        assert_eq!(restored.canvas().zoom_level, 42);
+        assert_eq!(restored.max_iter(), 777);
+    }
+
+    #[test]
+    fn failed_link_import_leaves_the_fractal_unchanged() {
+        let fractal = Fractal::F64(fractal("z^3 - 1"));
+        let link = LinkedView::F106(ViewLink {
+            formula:    "z^3 - w".to_string(), // 未定義の変数
+            center:     Complex::new(F106::from_f64(0.5), F106::zero()),
+            zoom_level: 500,
+            max_iter:   64,
+        });
+        assert!(import_link_inner(&fractal, link).is_err());
+        assert!(matches!(&fractal, Fractal::F64(f) if f.formula() == "z^3 - 1"));
+    }
+
+    #[test]
+    fn imported_link_precision_fits_the_zoom_level() {
+        let fractal = Fractal::F64(fractal("z^3 - 1"));
+        let link = |zoom_level| LinkedView::F106(ViewLink {
+            formula:    "z^4 - 1".to_string(),
+            center:     Complex::new(F106::from_f64(0.25), F106::zero()),
+            zoom_level,
+            max_iter:   64,
+        });
+        let shallow = import_link_inner(&fractal, link(3)).unwrap();
+        assert!(matches!(&shallow, Fractal::F64(f) if f.formula() == "z^4 - 1" && f.canvas().center().re == 0.25));
+        let deep = import_link_inner(&fractal, link(500)).unwrap();
+        assert!(matches!(&deep, Fractal::F106(f) if f.canvas().zoom_level == 500));
+    }
+
+    #[test]
+    fn f106_center_keeps_low_part() {
+        let re = F106::from_parts(-0.75, 1e-20);
+        let link = ViewLink {
+            formula:    "z^4 - 1".to_string(),
+            center:     Complex::new(re, F106::from_f64(0.1)),
+            zoom_level: 500,
+            max_iter:   64,
+        };
+        let LinkedView::F106(restored) = round_trip(&link, 1) else { panic!("wrong precision") };
+        assert_eq!(restored.center.re.hi_lo(), (-0.75, 1e-20));
+        assert_eq!(restored.zoom_level, 500);
+    }
+
+    #[test]
+    fn broken_payload_is_rejected() {
+        let bytes = ViewLink { formula: "z".to_string(), center: Complex::new(0.0, 0.0), zoom_level: 0, max_iter: 1 }
+            .to_bytes(0);
+        assert!(LinkedView::from_payload(0, &bytes[2..5]).is_err());
+        assert!(LinkedView::from_payload(2, &bytes[2..]).is_err());
+
+        let mut other_version = bytes.clone();
+        other_version[0] = LINK_VERSION + 1;
+        let s = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(other_version);
+        assert!(decode_link(&s).unwrap_err().contains("version"));
+    }
+
+    #[test]
+    fn polynomial_and_derivative_match_the_roots() {
+        let roots = vec![Complex::new(1.0, 0.0), Complex::new(-0.5, 2.0), Complex::new(0.0, -1.0)];
+        let fo = FormulacInner::<f64>::from_roots(roots.clone());
+        let z = Complex::new(0.3, 0.7);
+        let f = roots.iter().fold(Complex::new(1.0, 0.0), |p, r| p * (z - r));
+        let df = (0..roots.len())
+            .map(|k| roots.iter().enumerate().filter(|&(j, _)| j != k).fold(Complex::new(1.0, 0.0), |p, (_, r)| p * (z - r)))
+            .sum::<Complex<f64>>();
+        assert!((fo.func()([z]) - f).norm() < 1e-12);
+        assert!((fo.deriv()([z]) - df).norm() < 1e-12);
+    }
+
+    #[test]
+    fn repeated_root_converges_without_nan() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.set_max_iter(200);
+        let roots = [(1.0, 0.0), (1.0, 0.0), (-1.0, 0.0)];
+        let info = roots_calc_info(&fractal, &roots, 0, 0, 64, 64);
+        assert_eq!(info.known_roots.len(), 2);
+
+        // 重根ちょうどの点でも移動量は0で、すぐに収束する
+        let escape = info.escape_time(Complex::new(1.0, 0.0));
+        assert_eq!(escape.z, Some(Complex::new(1.0, 0.0)));
+
+        let result = btm::calc_rect(info);
+        assert!(result.roots.len() <= 2);
+        assert!(result.escape_times().iter().any(|&n| n < 200));
+    }
+
+    fn order(formula: &str, near: (f64, f64)) -> f64 {
+        let fractal = fractal(formula);
+        let fo = fo(&fractal);
+        convergence_order_inner(&calc_info(&fractal, fo, 0, 0, 1, 1), near, f64::MANTISSA_DIGITS as i32)
+    }
+
+    #[test]
+    fn simple_root_is_quadratic() {
+        let p = order("z^3 - 1", (1.3, 0.1));
+        assert!((p - 2.0).abs() < 0.3, "order: {}", p);
+    }
+
+    #[test]
+    fn double_root_is_linear() {
+        let p = order("(z - 1)^2 * (z + 1)", (1.3, 0.0));
+        assert!((p - 1.0).abs() < 0.2, "order: {}", p);
+    }
+
+    #[test]
+    fn unconverged_seed_is_nan() {
+        // z^2 + 1 は実軸上の点から実軸を出られず、収束しない
+        assert!(order("z^2 + 1", (0.5, 0.0)).is_nan());
+    }
+
+    fn keyframe(time: f64, center: (f64, f64), zoom: f64) -> Keyframe {
+        Keyframe { time, center, zoom, coeff: None }
+    }
+
+    #[test]
+    fn frames_pass_through_keyframes_at_fps() {
+        let keyframes = [
+            keyframe(0.0, (0.0, 0.0), 0.0),
+            keyframe(1.0, (1.0, -1.0), 16.0),
+            keyframe(1.5, (1.0, -1.0), 24.0),
+        ];
+        let frames = timeline_frames(&keyframes, 10);
+        assert_eq!(frames.len(), 16);
+
+        let (time, frame) = &frames[10];
+        assert_eq!(*time, 1.0);
+        assert_eq!((frame.center_re, frame.center_im), (1.0, -1.0));
+        assert_eq!(frame.half_width, 2.0f64.powi(-2));
+        assert_eq!(frames[15].1.half_width, 2.0f64.powi(-3));
+        assert_eq!(frames[0].1.coeff, Some((1.0, 0.0)));
+    }
+
+    #[test]
+    fn smoothstep_eases_in_and_out() {
+        let keyframes = [keyframe(0.0, (0.0, 0.0), 0.0), keyframe(1.0, (1.0, 0.0), 0.0)];
+        let x: Vec<f64> = timeline_frames(&keyframes, 4).iter().map(|(_, f)| f.center_re).collect();
+        assert_eq!(x, vec![0.0, 0.15625, 0.5, 0.84375, 1.0]);
+    }
+
+    #[test]
+    fn single_keyframe_is_one_frame() {
+        let frames = timeline_frames(&[keyframe(2.0, (0.5, 0.5), 8.0)], 30);
+        assert_eq!(frames.len(), 1);
+        assert_eq!(frames[0].1.center_re, 0.5);
+    }
+
+    #[test]
+    fn unordered_keyframes_are_rejected() {
+        let keyframes = [keyframe(1.0, (0.0, 0.0), 0.0), keyframe(1.0, (1.0, 0.0), 0.0)];
+        assert!(validate_keyframes(&keyframes).is_err());
+        assert!(validate_keyframes(&[]).is_err());
+        assert!(validate_keyframes(&[keyframe(0.0, (f64::NAN, 0.0), 0.0)]).is_err());
+    }
+
+    fn diff(formula_a: &str, formula_b: &str) -> Vec<u8> {
+        render_formula_diff_inner(&FractalInner::<f64>::default(), formula_a, formula_b, 64, 64).unwrap()
+    }
+
+    #[test]
+    fn same_formula_has_no_difference() {
+        assert!(diff("z^3 - 1", "z^3 - 1").iter().all(|&d| d == 0));
+    }
+
+    #[test]
+    fn small_perturbation_changes_only_part_of_the_view() {
+        let mask = diff("z^3 - 1", "z^3 - 1.01");
+        let changed = mask.iter().filter(|&&d| d == 1).count();
+        assert!(changed < mask.len() / 4, "changed: {}", changed);
+    }
+
+    #[test]
+    fn roots_are_paired_by_position_not_by_discovery_order() {
+        // 根の座標が同じなので、発見の順番が異なっても差は無い
+        let mask = diff("z^3 - 1", "(z^3 - 1) * 2");
+        assert!(mask.iter().all(|&d| d == 0));
+        assert!(diff("z^3 - 1", "z^2").contains(&1));
+    }
+
+    #[test]
+    fn invalid_formula_is_an_error() {
+        assert!(render_formula_diff_inner(&FractalInner::<f64>::default(), "z^3 - 1", "z^", 8, 8).is_err());
+    }
+
+    fn render(state: &RendererState) -> Vec<u8> {
+        with_fractal!(&state.fractal, |fr, fo| colorize_rect(&state.coloring, view_calc_info(fr, fo, 48, 32)))
+    }
+
+    #[test]
+    fn restore_renders_identically_to_before_modification() {
+        let inner = fractal("z^3 - 1");
+        let mut state = RendererState { fractal: Fractal::F64(inner), coloring: Coloring::default() };
+        let before = render(&state);
+
+        let mut store = StateStore::default();
+        let handle = store.save(state.clone());
+
+        let Fractal::F64(inner) = &mut state.fractal else { unreachable!() };
+        inner.set_formula("z^5 - z + 1").unwrap();
+        inner.canvas_mut().zoom_level = 12;
+        state.coloring.set_basin_shading(false);
+        assert_ne!(render(&state), before);
+
+        state = store.get(handle).unwrap().clone();
+        assert_eq!(render(&state), before);
+        assert_eq!(with_fractal!(&state.fractal, |fr, _fo| fr.formula().to_string()), "z^3 - 1");
+    }
+
+    #[test]
+    fn dropped_states_cannot_be_restored() {
+        let state = RendererState { fractal: Fractal::F64(FractalInner::default()), coloring: Coloring::default() };
+        let mut store = StateStore::default();
+        let first = store.save(state.clone());
+        let second = store.save(state);
+        assert!(store.remove(first).is_some());
+        assert!(store.get(first).is_none());
+        assert!(store.remove(first).is_none());
+        assert!(store.get(second).is_some());
+    }
+
+    fn preimage(root_index: usize, depth: u32) -> Result<Vec<u16>, String> {
+        let fractal = fractal("z^3 - 1");
+        let fo = fo(&fractal);
+        render_preimage_inner(&fractal, fo, root_index, depth, 32, 32)
+    }
+
+    #[test]
+    fn counts_steps_into_the_disk() {
+        let data = preimage(0, 32).unwrap();
+        assert_eq!(data.len(), 32 * 32);
+        assert!(data.iter().any(|&n| n < 32));
+        // 他の根の basin の点は、対象の根の円板に入らない
+        assert!(data.contains(&btm::UNCONVERGED));
+
+        // 上限を小さくすると、遠い点は円板に届かない
+        let shallow = preimage(0, 1).unwrap();
+        assert!(shallow.iter().all(|&n| n <= 1 || n == btm::UNCONVERGED));
+        assert!(shallow.iter().filter(|&&n| n == btm::UNCONVERGED).count() > data.iter().filter(|&&n| n == btm::UNCONVERGED).count());
+    }
+
+    #[test]
+    fn disk_radius_is_a_tenth_of_the_nearest_root() {
+        let roots = [Complex::new(1.0, 0.0), Complex::new(-1.0, 0.0), Complex::new(5.0, 0.0)];
+        assert_eq!(root_disk_radius(&roots, 0), 0.2);
+        assert_eq!(root_disk_radius(&roots[..1], 0), 0.1);
+    }
+
+    #[test]
+    fn out_of_range_root_is_an_error() {
+        assert!(preimage(3, 8).is_err());
+    }
+
+    #[test]
+    fn batch_matches_per_point_calls() {
+        let fractal = fractal("z^4 - 1");
+        let fo = fo(&fractal);
+
+        let mut rng = fastrand::Rng::with_seed(158);
+        let points: Vec<(f64, f64)> = (0..500)
+            .map(|_| (rng.f64() * 4.0 - 2.0, rng.f64() * 4.0 - 2.0))
+            .chain([(0.0, 0.0), (1.0, 0.0)])
+            .collect();
+        let batch = escape_time_batch_inner(&fractal, fo, &points);
+
+        let info = calc_info(&fractal, fo, 0, 0, 1, 1);
+        let single: Vec<u16> = points.iter()
+            .map(|&(re, im)| info.escape_time(Complex::new(re, im)).iter)
+            .collect();
+        assert_eq!(batch, single);
+    }
+
+    #[test]
+    fn scanline_matches_the_row_of_a_tile() {
+        let fractal = fractal("z^3 - 1");
+        let fo = fo(&fractal);
+
+        let (y, width) = (200, 128);
+        let tile = render_tile_inner(&fractal, fo, 0, y, width, 1);
+        assert_eq!(render_scanline_inner(&fractal, fo, y, width), tile);
+    }
+
+    #[test]
+    fn double_root_is_reported_as_one_basin() {
+        let fractal = fractal("(z-1)^2*(z+1)");
+        let fo = fo(&fractal);
+
+        let roots = fractal.cached_roots(fo).to_vec();
+        let multiplicities = fractal.root_multiplicities(fo).to_vec();
+        assert_eq!(roots.len(), 2);
+        for (z, m) in roots.iter().zip(multiplicities) {
+            let expected = if z.re > 0.0 { 2 } else { 1 };
+            assert_eq!(m, expected, "root {z}");
+        }
+
+        // 表示領域全体を細かく計算しても、重根の収束領域が分かれない
+        let size = fractal.canvas().size() as u32;
+        let result = btm::calc_rect(calc_info(&fractal, fo, 0, 0, size, size));
+        assert_eq!(result.roots.len(), 2);
+    }
+
+    #[test]
+    fn near_duplicates_of_a_double_root_are_merged() {
+        let fractal = fractal("(z-1)^2*(z+1)");
+        let fo = fo(&fractal);
+
+        // 重根の1次収束で打ち切られた、僅かに異なる値
+        let found = [Complex::new(1.0, 0.0), Complex::new(-1.0, 0.0), Complex::new(1.004, 0.002)];
+        let (roots, multiplicities) = btm::dedup_roots(fo.func(), fo.deriv(), &found);
+        assert_eq!(roots, found[..2]);
+        assert_eq!(multiplicities, [2, 1]);
+    }
+
+    fn stats_of(formula: &str) -> Vec<BasinIterationStats> {
+        let fractal = fractal(formula);
+        let fo = fo(&fractal);
+        basin_iteration_stats_inner(&fractal, fo)
+    }
+
+    #[test]
+    fn unconverged_bucket_is_last_and_counts_add_up() {
+        let stats = stats_of("z^3 - 1");
+        assert_eq!(stats.len(), 4);
+        assert!(stats.last().unwrap().root.is_none());
+        assert!(stats[..3].iter().all(|s| s.root.is_some() && s.count > 0));
+
+        let size = (default::CANVAS_SIZE as u32).min(256) as usize;
+        assert_eq!(stats.iter().map(|s| s.count).sum::<usize>(), size * size);
+        assert!(stats.iter().all(|s| s.mean_iter <= s.max_iter_seen as f64));
+    }
+
+    #[test]
+    fn double_root_converges_more_slowly() {
+        // 重根の近くではNewton法が1次収束になる
+        let stats = stats_of("(z-1)^2*(z+1)");
+        let mean_at = |re: f64| stats.iter()
+            .find(|s| s.root.is_some_and(|(r, _)| (r - re).abs() < 1e-2))
+            .unwrap()
+            .mean_iter;
+        assert!(mean_at(1.0) > mean_at(-1.0));
+    }
+
+    #[test]
+    fn values_are_binned_over_the_iteration_range() {
+        let counts = iteration_histogram(&[0, 1, 24, 25, 49, 50, 99, 100, 100], 100, 4);
+        assert_eq!(counts, [3, 2, 1, 1, 2]);
+    }
+
+    #[test]
+    fn every_pixel_of_the_view_is_counted() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let result = btm::calc_rect(calc_info(&fractal, fo, 0, 0, 128, 128));
+
+        let counts = iteration_histogram(&result.escape_times(), fractal.max_iter(), 16);
+        assert_eq!(counts.len(), 17);
+        assert_eq!(counts.iter().sum::<u32>(), 128 * 128);
+    }
+
+    #[test]
+    fn outputs_agree_with_the_separate_renders() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+
+        let full = render_tile_full_inner(&fractal, fo, 32, 64, 96, 48);
+        let result = btm::calc_rect(calc_info(&fractal, fo, 32, 64, 96, 48));
+        assert_eq!(full.escape, result.escape_times());
+        assert_eq!(full.root, result.pixels.iter().map(|p| p.root).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn smooth_value_lies_within_one_iteration_below_escape_time() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+
+        let full = render_tile_full_inner(&fractal, fo, 0, 0, 64, 64);
+        for (&iter, &smooth) in full.escape.iter().zip(&full.smooth) {
+            assert!(iter as f32 - 1.0 <= smooth && smooth <= iter as f32, "{iter} {smooth}");
+        }
+    }
+
+    #[test]
+    fn pinned_images_are_returned_by_handle() {
+        let mut pins = PinnedRenders::default();
+        let a = pins.pin("a".to_string());
+        let b = pins.pin("b".to_string());
+        assert_ne!(a, b);
+        assert_eq!(pins.get(a).map(String::as_str), Some("a"));
+        assert_eq!(pins.get(b).map(String::as_str), Some("b"));
+    }
+
+    #[test]
+    fn oldest_pins_are_evicted() {
+        let mut pins = PinnedRenders::default();
+        let first = pins.pin(String::new());
+        for _ in 0..PinnedRenders::MAX_PINS {
+            pins.pin(String::new());
+        }
+        assert!(pins.get(first).is_none());
+        assert_eq!(pins.images.len(), PinnedRenders::MAX_PINS);
+    }
+
+    #[test]
+    fn generous_budget_completes_like_render_tile() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+
+        let deadline = Instant::now() + Duration::from_secs(60);
+        let tile = render_tile_budgeted_inner(&fractal, fo, (0, 0, 64, 64), deadline);
+        assert!(tile.complete);
+        assert_eq!(tile.data, render_tile_inner(&fractal, fo, 0, 0, 64, 64));
+    }
+
+    #[test]
+    fn tiny_budget_returns_promptly_on_a_heavy_view() {
+        // 根が無いので、全てのピクセルが最大反復回数まで反復する
+        let mut fractal = fractal("exp(z)");
+        fractal.set_max_iter(u16::MAX);
+        let fo = fo(&fractal);
+
+        let start = Instant::now();
+        let tile = render_tile_budgeted_inner(&fractal, fo, (0, 0, 256, 256), start + Duration::from_millis(1));
+        assert!(start.elapsed() < Duration::from_secs(2), "took {:?}", start.elapsed());
+        assert!(!tile.complete);
+        assert_eq!(tile.data.len(), 256 * 256);
+    }
+
+    #[test]
+    fn steps_point_toward_the_root_and_are_clamped() {
+        let fractal = fractal("z - 1");
+        let fo = fo(&fractal);
+
+        let grid = 8;
+        let field = step_field_inner(&fractal, fo, grid);
+        assert_eq!(field.len(), (grid * grid) as usize);
+
+        let spacing = fractal.canvas().width() / grid as f64;
+        for v in &field {
+            let (to_re, to_im) = (1.0 - v.pos.0, -v.pos.1);
+            let (re, im) = v.step;
+            assert!(re.hypot(im) <= spacing * (1.0 + 1e-12));
+            // 1次式のステップは、根への向きと一致する
+            assert!((re * to_im - im * to_re).abs() < 1e-9 && re * to_re + im * to_im > 0.0);
+        }
+    }
+
+    #[test]
+    fn orbits_concentrate_near_the_roots() {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+
+        let (seeds, size) = (2000, 64);
+        let density = render_flow_density_inner(&fractal, fo, seeds, size, size);
+        assert_eq!(density, render_flow_density_inner(&fractal, fo, seeds, size, size));
+
+        // 各開始点自身は表示領域内にあるので、少なくとも1回は数える
+        let total: u64 = density.iter().map(|&d| d as u64).sum();
+        assert!(total >= seeds as u64);
+
+        let info = view_calc_info(&fractal, fo, size, size);
+        let (x, y) = info.get_pixel(&Complex::new(-0.5, 0.75f64.sqrt())).unwrap();
+        let at_root = density[(y * size + x) as usize] as u64;
+        assert!(at_root * (size * size) as u64 > total * 10, "{at_root} / {total}");
+    }
+
+    #[test]
+    fn scale_of_an_integer_level_matches_the_level_based_render() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.canvas_mut().zoom_level = 12;
+        let fo = fo(&fractal);
+
+        let scale = 2.0f64.powf(12.0 / 8.0);
+        let rect = (64, 128, 96, 80);
+        assert_eq!(render_tile_scale_inner(&fractal, fo, rect, scale), render_tile_inner(&fractal, fo, 64, 128, 96, 80));
+    }
+
+    #[test]
+    fn fractional_scale_differs_from_both_neighbouring_levels() {
+        let mut fractal = FractalInner::<f64>::default();
+        let Formulac::F64(fo) = fractal.formulac().clone() else { unreachable!() };
+        let rect = (0, 0, 128, 128);
+
+        let between = render_tile_scale_inner(&fractal, &fo, rect, 2.0f64.powf(4.5 / 8.0));
+        for level in [4, 5] {
+            fractal.canvas_mut().zoom_level = level;
+            assert_ne!(between, render_tile_inner(&fractal, &fo, 0, 0, 128, 128));
+        }
+    }
+
+    #[test]
+    fn fractions_are_sorted_by_area() {
+        assert_eq!(period_fractions(&[1, 2, 1, 0, 1, 2, 1, 1]), [(1, 0.625), (2, 0.25), (0, 0.125)]);
+    }
+
+    #[test]
+    fn two_cycle_basin_appears_in_the_view() {
+        // z^3 - 2z + 2 は原点付近に、根に収束しない2周期軌道の領域を持つ
+        let mut fractal = fractal("z^3 - 2*z + 2");
+        fractal.canvas_mut().size = 64;
+        let fo = fo(&fractal);
+
+        let periods = period_map_inner(&fractal, fo);
+        assert_eq!(periods.len(), 64 * 64);
+        let fractions = period_fractions(&periods);
+        assert_eq!(fractions[0].0, 1);
+        assert!(fractions.iter().any(|&(period, fraction)| period == 2 && fraction > 0.0));
+    }
+
+    #[test]
+    fn returns_the_smallest_sufficient_max_iter() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+
+        let samples = 32;
+        let max_iter = min_iter_for_coverage_inner(&fractal, fo, 0.99, samples);
+        assert!(max_iter > 1);
+        assert!(converged_fraction(&fractal, fo, samples, max_iter) >= 0.99);
+        assert!(converged_fraction(&fractal, fo, samples, max_iter - 1) < 0.99);
+    }
+
+    #[test]
+    fn unreachable_target_returns_the_ceiling() {
+        // 根が無いので、どれだけ反復しても収束しない
+        let fractal = fractal("exp(z)");
+        let fo = fo(&fractal);
+
+        assert_eq!(min_iter_for_coverage_inner(&fractal, fo, 0.5, 4), u16::MAX);
+    }
+
+    #[test]
+    fn svg_contains_boundary_paths() {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+
+        let svg = boundary_svg_inner(&fractal, fo, 64, 64, 0.0);
+        assert!(svg.contains(r#"viewBox="0 0 64 64""#));
+        assert!(svg.trim_end().ends_with("</svg>"));
+        assert!(svg.contains("<path d=\"M"));
+    }
+
+    #[test]
+    fn larger_tolerance_shrinks_output() {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+
+        let exact = boundary_svg_inner(&fractal, fo, 64, 64, 0.0);
+        let coarse = boundary_svg_inner(&fractal, fo, 64, 64, 2.0);
+        assert!(coarse.len() < exact.len());
+    }
+
+    #[test]
+    fn calc_info_uses_projection() {
+        let mut fractal = FractalInner::<f64>::default(); // z^3 - 1, [-1, 1] x [-1, 1]
+        let Formulac::F64(fo) = fractal.formulac().clone() else { unreachable!() };
+        let size = fractal.canvas().size() as u32;
+
+        let planar = view_calc_info(&fractal, &fo, size, size).get_complex(size as i64 - 1, size as i64 / 2);
+        fractal.set_projection(btm::Projection::Stereographic);
+        let info = view_calc_info(&fractal, &fo, size, size);
+        let stereographic = info.get_complex(size as i64 - 1, size as i64 / 2);
+
+        // 円周の近くのピクセルは、無限遠点の近くの点になる
+        assert!(planar.norm() < 1.0);
+        assert!(stereographic.norm() > 100.0);
+        assert_eq!(info.get_pixel(&stereographic), Some((size - 1, size / 2)));
+    }
+
+    fn unstable_count(epsilon: f64) -> usize {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+        render_confidence_inner(&fractal, fo, SIZE, SIZE, epsilon).iter().filter(|&&v| v == 1).count()
+    }
+
+    #[test]
+    fn only_boundary_pixels_are_unstable() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let mask = render_confidence_inner(&fractal, fo, SIZE, SIZE, 0.5);
+        assert_eq!(mask.len(), (SIZE * SIZE) as usize);
+
+        let unstable = mask.iter().filter(|&&v| v == 1).count();
+        assert!(0 < unstable && unstable < mask.len() / 2);
+
+        // 根の近くのピクセルは、始点をずらしても同じ根に収束する
+        let info = view_calc_info(&fractal, fo, SIZE, SIZE);
+        let (x, y) = info.get_pixel(&Complex::new(-0.5, 0.75f64.sqrt())).unwrap();
+        assert_eq!(mask[(y * SIZE + x) as usize], 0);
+    }
+
+    #[test]
+    fn smaller_perturbation_is_more_stable() {
+        assert!(unstable_count(1e-3) <= unstable_count(0.5));
+    }
+
+    #[test]
+    fn derivative_string_follows_set_formula() {
+        let mut fractal = FractalInner::<f64>::uncompiled();
+        assert_eq!(fractal.formulac().derivative(), "0");
+
+        fractal.set_formula("z^4 - 1").unwrap();
+        let derivative = fractal.formulac().derivative();
+        assert!(!derivative.is_empty());
+        assert_eq!(derivative, "diff(z^4 - 1, z)");
+    }
+
+    #[test]
+    fn shading_only_darkens_colors() {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+        let coloring = Coloring::default();
+        let rect = (0, 0, 32, 32);
+
+        let info = calc_info(&fractal, fo, rect.0, rect.1, rect.2, rect.3);
+        let plain = colorize_rect(&coloring, info);
+        let shaded = render_tile_shaded_inner(&fractal, fo, &coloring, rect, (1.0, 1.0));
+
+        assert_eq!(shaded.len(), plain.len());
+        assert!(shaded.iter().zip(&plain).all(|(s, p)| s <= p));
+        assert_ne!(shaded, plain);
+    }
+
+    fn stages(cancel_after_preview: bool) -> (bool, Vec<ExportStage>) {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+        let mut stages = Vec::new();
+        let completed = render_export_stages(
+            &fractal, fo, &Coloring::default(), 300, 200,
+            |stage| { stages.push(stage); Ok(()) },
+            || cancel_after_preview,
+        ).unwrap();
+        (completed, stages)
+    }
+
+    #[test]
+    fn preview_precedes_final_render() {
+        let (completed, stages) = stages(false);
+        assert!(completed);
+        assert_eq!(stages.len(), 2);
+        assert!(!stages[0].is_final);
+        assert_eq!((stages[0].width, stages[0].height), (75, 50));
+        assert!(stages[1].is_final);
+        assert_eq!((stages[1].width, stages[1].height), (300, 200));
+    }
+
+    #[test]
+    fn banded_render_matches_single_pass() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let coloring = Coloring::default();
+        let (_, stages) = stages(false);
+
+        let expected = encode_png_base64(300, 200, png::ColorType::Rgba, &render_rgba_inner(&fractal, fo, &coloring, 300, 200)).unwrap();
+        assert_eq!(stages[1].png, expected);
+    }
+
+    #[test]
+    fn cancellation_skips_final_render() {
+        let (completed, stages) = stages(true);
+        assert!(!completed);
+        assert_eq!(stages.len(), 1);
+        assert!(!stages[0].is_final);
+    }
+
+    #[test]
+    fn each_basin_has_its_own_root() {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+        let size = fractal.canvas().size() as u32;
+
+        let tile = render_tile_roots_inner(&fractal, fo, 0, 0, size, size);
+        assert_eq!(tile.data, render_tile_inner(&fractal, fo, 0, 0, size, size));
+
+        let roots: std::collections::BTreeSet<u16> = tile.root.iter()
+            .copied()
+            .filter(|&root| root != btm::UNCONVERGED && root != btm::BRANCH_JUMP)
+            .collect();
+        assert_eq!(roots.into_iter().collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn root_ids_agree_across_tiles() {
+        let fractal = FractalInner::<f64>::default();
+        let fo = fo(&fractal);
+        let size = fractal.canvas().size() as u32;
+
+        let whole = render_tile_roots_inner(&fractal, fo, 0, 0, size, size);
+        let half = size / 2;
+        let tile = render_tile_roots_inner(&fractal, fo, half, half, half, half);
+        for (i, &root) in tile.root.iter().enumerate() {
+            let (x, y) = (half + i as u32 % half, half + i as u32 / half);
+            assert_eq!(root, whole.root[(y * size + x) as usize]);
+        }
+    }
+
+    fn distinct_colors(source: ColoringSource) -> usize {
+        let fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let fo = fo(&fractal);
+        let mut coloring = Coloring::default();
+        coloring.set_source(source);
+
+        let rgba = colorize_rect(&coloring, calc_info(&fractal, fo, 0, 0, 128, 128));
+        rgba.chunks_exact(4).collect::<std::collections::HashSet<_>>().len()
+    }
+
+    #[test]
+    fn smooth_coloring_removes_bands() {
+        assert!(distinct_colors(ColoringSource::SmoothEscapeTime) > 2 * distinct_colors(ColoringSource::EscapeTime));
+    }
+
+    #[test]
+    fn default_coeff_is_one() {
+        let fractal = FractalInner::<f64>::default();
+        assert_eq!(fractal.coeff(), Complex::ONE);
+        assert_eq!(get_center_str_inner(fractal.coeff), "(1.0e0, 0.0e0)");
+    }
+
+    #[test]
+    fn coeff_changes_the_render() {
+        let mut fractal = FractalInner::<f64>::default(); // z^3 - 1
+        let Formulac::F64(fo) = fractal.formulac().clone() else { unreachable!() };
+        let plain = render_tile_inner(&fractal, &fo, 0, 0, 64, 64);
+
+        fractal.set_coeff(Complex::new(1.0, 0.2));
+        let info = calc_info(&fractal, &fo, 0, 0, 64, 64);
+        assert_eq!(info.coeff, Complex::new(1.0, 0.2));
+        assert_ne!(render_tile_inner(&fractal, &fo, 0, 0, 64, 64), plain);
+    }
+
+    #[test]
+    fn square_size_keeps_the_square_canvas() {
+        let mut fractal = FractalInner::<f64>::default(); // z^3 - 1
+        fractal.canvas_mut().set_size(256, 256);
+        assert_eq!(fractal.canvas().viewport, None);
+        assert_eq!(fractal.canvas().size(), 256);
+        assert_eq!(fractal.canvas().viewport_offset(), (0, 0));
+        assert!(set_size(0, 256).is_err());
+    }
+
+    #[test]
+    fn wide_viewport_keeps_pixels_square_and_centered() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.canvas_mut().set_size(64, 36);
+        let fo = fo(&fractal);
+
+        let info = calc_info(&fractal, fo, 0, 0, 64, 36);
+        let origin = info.get_complex(0, 0);
+        let dx = info.get_complex(1, 0) - origin;
+        let dy = info.get_complex(0, 1) - origin;
+        assert_eq!(dx.re, dy.im);
+        assert_eq!(dx.im, 0.0);
+        assert_eq!(info.get_complex(32, 18), *fractal.canvas().center());
+
+        // 書き出しの計算情報も、同じ解像度なら表示領域と一致する
+        let view = view_calc_info(&fractal, fo, 64, 36);
+        for (x, y) in [(0, 0), (63, 0), (0, 35), (40, 20)] {
+            assert_eq!(view.get_complex(x, y), info.get_complex(x, y));
+        }
+    }
+
+    #[test]
+    fn zoom_keeps_the_point_under_the_mouse_on_a_tall_viewport() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.canvas_mut().set_size(36, 64);
+        let re_at = |fractal: &FractalInner<f64>| {
+            let fo = fo(fractal);
+            calc_info(fractal, fo, 0, 0, 36, 64).get_complex(9, 0).re
+        };
+
+        let before = re_at(&fractal);
+        fractal.canvas_mut().zoom_around_point(8, 0.25, 0.5);
+        assert!((re_at(&fractal) - before).abs() < 1e-12);
+    }
+
+    #[test]
+    fn whole_view_analyses_use_the_wide_viewport() {
+        let mut fractal = fractal("z^3 - 1");
+        fractal.canvas_mut().set_size(96, 48);
+        let fo = fo(&fractal);
+
+        let view = btm::calc_rect(view_calc_info(&fractal, fo, 96, 48));
+        let areas = basin_areas_inner(&fractal, fo);
+        assert_eq!(areas.len(), view.roots.len() + 1);
+        for (area, root) in areas.iter().zip(view.roots.iter()) {
+            let count = view.pixels.iter().filter(|p| p.root_index().is_some_and(|idx| view.roots[idx] == *root)).count();
+            assert_eq!(area.root, Some((root.re, root.im)));
+            assert_eq!(area.fraction, count as f64 / (96 * 48) as f64);
+        }
+
+        assert_eq!(period_map_inner(&fractal, fo).len(), 96 * 48);
+        assert_eq!(tile_rects(96, 48, 32).len(), 3 * 2);
+        assert_eq!(fractal.canvas().viewport_within(32), (32, 16));
+    }
+
+    /// get_center_str の "(re, im)" を実部と虚部に分ける
+    fn split(center_str: &str) -> (String, String) {
+        let (re, im) = center_str.trim_matches(['(', ')']).split_once(", ").unwrap();
+        (re.to_string(), im.to_string())
+    }
+
+    #[test]
+    fn center_str_round_trips_in_f64() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.canvas_mut().set_center(-0.123456789012345, 1.0e-300);
+        let (re, im) = split(&get_center_str_inner(*fractal.canvas().center()));
+
+        let mut restored = FractalInner::<f64>::default();
+        set_center_inner(&mut restored, &re, &im).unwrap();
+        assert_eq!(restored.canvas().center(), fractal.canvas().center());
+    }
+
+    #[test]
+    fn center_str_round_trips_in_f106() {
+        let mut fractal = FractalInner::<F106>::default();
+        let third = F106::from_f64(1.0) / F106::from_f64(3.0);
+        fractal.canvas_mut().set_center(third, -third);
+        let (re, im) = split(&get_center_str_inner(*fractal.canvas().center()));
+
+        let mut restored = FractalInner::<F106>::default();
+        set_center_inner(&mut restored, &re, &im).unwrap();
+        let error = (*restored.canvas().center() - *fractal.canvas().center()).abs().re.to_f64();
+        // f64 を経由すると 1e-17 程度の誤差が残る
+        assert!(error < 1e-28, "{}", error);
+    }
+
+    #[test]
+    fn rejects_unparsable_and_non_finite_values() {
+        let mut fractal = FractalInner::<f64>::default();
+        for (re, im) in [("abc", "0"), ("0", ""), ("inf", "0"), ("0", "NaN")] {
+            assert!(set_center_inner(&mut fractal, re, im).is_err());
+        }
+        assert_eq!(*fractal.canvas().center(), Complex::new(0.0, 0.0));
+        set_center_inner(&mut fractal, " -5.0e-1 ", "2.5e0").unwrap();
+        assert_eq!(*fractal.canvas().center(), Complex::new(-0.5, 2.5));
+    }
+
+    /// 拡大段階 zoom_level の表示領域
+    fn state(zoom_level: i32) -> SavedView {
+        SavedView { center: Complex::new(F106::from_f64(0.0), F106::from_f64(0.0)), zoom_level }
+    }
+
+    fn zoom_of(view: Option<SavedView>) -> Option<i32> {
+        view.map(|view| view.zoom_level)
+    }
+
+    #[test]
+    fn undo_and_redo_walk_the_history() {
+        let mut store = StateStore::default();
+        let now = Instant::now();
+        store.record(Navigation::Zoom, now, || state(0));
+        store.record(Navigation::Move, now, || state(1));
+
+        assert_eq!(zoom_of(store.undo(|| state(2))), Some(1));
+        assert_eq!(zoom_of(store.undo(|| state(1))), Some(0));
+        assert_eq!(zoom_of(store.undo(|| state(0))), None);
+        assert_eq!(zoom_of(store.redo(|| state(0))), Some(1));
+        assert_eq!(zoom_of(store.redo(|| state(1))), Some(2));
+        assert_eq!(zoom_of(store.redo(|| state(2))), None);
+    }
+
+    #[test]
+    fn new_navigation_after_undo_clears_redo() {
+        let mut store = StateStore::default();
+        let now = Instant::now();
+        store.record(Navigation::Zoom, now, || state(0));
+        store.undo(|| state(1));
+        store.record(Navigation::Move, now, || state(0));
+        assert_eq!(zoom_of(store.redo(|| state(5))), None);
+        // 破棄したやり直しの状態は保持しない
+        assert_eq!(store.undo.len(), 1);
+        assert!(store.redo.is_empty());
+    }
+
+    #[test]
+    fn rapid_events_of_the_same_kind_are_coalesced() {
+        let mut store = StateStore::default();
+        let now = Instant::now();
+        // 1回のドラッグで続けて発生した移動は、最初の1回だけ状態を保存する
+        store.record(Navigation::Move, now, || state(0));
+        for i in 1..10 {
+            store.record(Navigation::Move, now + Duration::from_millis(i * 16), || unreachable!());
+        }
+        // 間隔を空けた移動と、種類の異なる操作は別の履歴とする
+        store.record(Navigation::Move, now + Duration::from_secs(5), || state(20));
+        store.record(Navigation::Zoom, now + Duration::from_secs(5), || state(21));
+        store.record(Navigation::Jump, now + Duration::from_secs(5), || state(22));
+        store.record(Navigation::Jump, now + Duration::from_secs(5), || state(23));
+
+        let undone: Vec<i32> = std::iter::from_fn(|| zoom_of(store.undo(|| state(99)))).collect();
+        assert_eq!(undone, [23, 22, 21, 20, 0]);
+    }
+
+    #[test]
+    fn history_is_bounded_without_evicting_snapshots() {
+        let mut store = StateStore::default();
+        let now = Instant::now();
+        let snapshot = RendererState { fractal: Fractal::F64(FractalInner::default()), coloring: Coloring::default() };
+        let handle = store.save(snapshot);
+        for i in 0..(StateStore::MAX_HISTORY as i32 + 10) {
+            store.record(Navigation::Jump, now, || state(i));
+        }
+        assert_eq!(store.undo.len(), StateStore::MAX_HISTORY);
+        assert_eq!(zoom_of(store.undo(|| state(99))), Some(StateStore::MAX_HISTORY as i32 + 9));
+
+        // 履歴が上限を超えても、snapshot で保存した状態は破棄しない
+        assert!(store.get(handle).is_some());
+    }
+
+    #[test]
+    fn undo_restores_a_deep_view_without_losing_the_center() {
+        let third = F106::from_f64(1.0) / F106::from_f64(3.0);
+        let mut deep = FractalInner::<F106>::default();
+        deep.canvas_mut().zoom_level = 400;
+        deep.canvas_mut().set_center(third, -third);
+
+        let mut store = StateStore::default();
+        store.record(Navigation::Jump, Instant::now(), || Fractal::F106(deep).saved_view());
+        let mut fractal = Fractal::F64(FractalInner::default());
+        let view = store.undo(|| fractal.saved_view()).unwrap();
+        fractal.apply_view(view);
+        let Fractal::F106(f) = &fractal else { panic!("precision was not raised") };
+        assert_eq!(*f.canvas().center(), Complex::new(third, -third));
+        assert_eq!(f.canvas().zoom_level, 400);
+    }
+
+    #[test]
+    fn undo_keeps_the_formula_changed_after_navigation() {
+        let mut store = StateStore::default();
+        let mut fractal = Fractal::F64(fractal("z^3 - 1"));
+        store.record(Navigation::Zoom, Instant::now(), || fractal.saved_view());
+        let Fractal::F64(f) = &mut fractal else { unreachable!() };
+        f.canvas_mut().zoom_around_point(12, 0.25, 0.75);
+        f.set_formula("z^5 - z + 1").unwrap();
+
+        let before = store.undo.back().copied().unwrap();
+        let view = store.undo(|| fractal.saved_view()).unwrap();
+        fractal.apply_view(view);
+        assert_eq!(fractal.saved_view(), before);
+        let Fractal::F64(f) = &fractal else { unreachable!() };
+        assert_eq!(f.formula(), "z^5 - z + 1");
+    }
+
+    #[test]
+    fn writes_the_view_at_the_requested_resolution() {
+        let inner = fractal("z^3 - 1");
+        let fractal = Fractal::F64(inner);
+        let coloring = Coloring::default();
+        let path = std::env::temp_dir().join(format!("newton-scope-save-image-{}.png", std::process::id()));
+
+        save_image_inner(&fractal, &coloring, path.to_str().unwrap(), 48, 32).unwrap();
+        let bytes = std::fs::read(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        let mut reader = png::Decoder::new(std::io::Cursor::new(bytes)).read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (48, 32));
+        assert!(reader.info().uncompressed_latin1_text.iter().any(|t| t.keyword == "FractalParameters"));
+        let mut rgba = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut rgba).unwrap();
+
+        let expected = with_fractal!(&fractal, |fr, fo| render_rgba_inner(fr, fo, &coloring, 48, 32));
+        assert_eq!(rgba, expected);
+    }
+
+    #[test]
+    fn unwritable_path_is_an_error() {
+        let fractal = Fractal::F64(FractalInner::default());
+        let path = std::env::temp_dir().join("newton-scope-missing-dir").join("image.png");
+        let result = save_image_inner(&fractal, &Coloring::default(), path.to_str().unwrap(), 8, 8);
+        assert!(result.unwrap_err().starts_with("Failed to create"));
+    }
+
+    /// 埋めずに直接計算すれば、帯の分け方に関わらず結果が一致する
+    fn unfilled_fractal() -> FractalInner<f64> {
+        let mut fractal = fractal("z^3 - 1");
+        fractal.set_fill_passes(0);
+        fractal
+    }
+
+    fn export(width: u32, height: u32, cancelled: bool) -> (bool, Vec<u8>) {
+        let fractal = unfilled_fractal();
+        let fo = fo(&fractal);
+        let mut bytes = Vec::new();
+        let completed = write_high_res_png(&mut bytes, &fractal, fo, &Coloring::default(), width, height, || cancelled).unwrap();
+        (completed, bytes)
+    }
+
+    #[test]
+    fn banded_stream_matches_single_pass() {
+        // 帯の行数で割り切れない高さ
+        let (completed, bytes) = export(200, 300, false);
+        assert!(completed);
+
+        let mut reader = png::Decoder::new(std::io::Cursor::new(bytes)).read_info().unwrap();
+        assert_eq!((reader.info().width, reader.info().height), (200, 300));
+        let mut rgba = vec![0; reader.output_buffer_size().unwrap()];
+        reader.next_frame(&mut rgba).unwrap();
+
+        let fractal = unfilled_fractal();
+        let fo = fo(&fractal);
+        assert_eq!(rgba, render_rgba_inner(&fractal, fo, &Coloring::default(), 200, 300));
+    }
+
+    #[test]
+    fn cancellation_stops_before_the_first_band() {
+        let (completed, _) = export(64, 64, true);
+        assert!(!completed);
+    }
+
+    #[test]
+    fn f64_state_round_trips() {
+        let mut inner = FractalInner::<f64>::default();
+        inner.set_conditional_formula("z + conj(z)", "z^3 - 1", "z^4 - 1").unwrap();
+        inner.canvas_mut().set_center(-0.123456789, 0.5);
+        inner.canvas_mut().zoom_level = 24;
+        inner.canvas_mut().set_size(320, 180);
+        inner.set_max_iter(321);
+        inner.set_coeff(Complex::new(0.8, 0.1));
+        let fractal = Fractal::F64(inner);
+
+        let restored = import_state_inner(&export_state_inner(&fractal).unwrap()).unwrap();
+        let (Fractal::F64(before), Fractal::F64(after)) = (&fractal, &restored) else { panic!("precision changed") };
+        assert_eq!(after.formula(), "z^3 - 1");
+        assert_eq!(after.conditional.as_ref().unwrap().formula_false, "z^4 - 1");
+        assert_eq!(after.canvas().center(), before.canvas().center());
+        assert_eq!(after.canvas().zoom_level, 24);
+        assert_eq!(after.canvas().viewport(), (320, 180));
+        assert_eq!(after.max_iter(), 321);
+        assert_eq!(after.coeff, Complex::new(0.8, 0.1));
+        assert!(restored.ensure_formula_loaded().is_ok());
+
+        // コンパイルし直した数式で、同じ結果を描画できる
+        let render = |f: &Fractal| with_fractal!(f, |fr, fo| render_tile_inner(fr, fo, 0, 0, 32, 32));
+        assert_eq!(render(&restored), render(&fractal));
+    }
+
+    #[test]
+    fn f106_state_keeps_precision_and_type() {
+        let mut inner = FractalInner::<F106>::default();
+        let third = F106::from_f64(1.0) / F106::from_f64(3.0);
+        inner.canvas_mut().set_center(third, -third);
+        inner.canvas_mut().zoom_level = 400;
+        let fractal = Fractal::F106(inner);
+
+        let restored = import_state_inner(&export_state_inner(&fractal).unwrap()).unwrap();
+        let Fractal::F106(after) = &restored else { panic!("precision changed") };
+        assert_eq!(*after.canvas().center(), Complex::new(third, -third));
+        assert!(matches!(after.formulac(), Formulac::F106(_)));
+    }
+
+    #[test]
+    fn invalid_states_are_rejected() {
+        let valid = export_state_inner(&Fractal::F64(FractalInner::default())).unwrap();
+        let with = |edit: fn(&mut serde_json::Value)| {
+            let mut state: serde_json::Value = serde_json::from_str(&valid).unwrap();
+            edit(&mut state);
+            import_state_inner(&state.to_string())
+        };
+
+        assert!(import_state_inner("not json").is_err());
+        assert!(with(|s| s["version"] = 99.into()).is_err());
+        assert!(with(|s| s["generics"] = 7.into()).is_err());
+        assert!(with(|s| s["fractal"]["formula"] = "z^^2".into()).is_err());
+        assert!(with(|s| s["fractal"]["formula"] = "z".repeat(100_000).into()).is_err());
+        assert!(with(|s| s["fractal"]["coeff"] = serde_json::json!([0.0, 0.0])).is_err());
+        assert!(with(|s| s["fractal"]["canvas"]["size"] = 0.into()).is_err());
+        assert!(with(|s| s["fractal"]["canvas"]["zoom_level"] = i32::MAX.into()).is_err());
+        assert!(with(|s| s["fractal"]["canvas"]["zoom_level"] = i32::MIN.into()).is_err());
+        assert!(with(|s| s["fractal"]["max_iter"] = 0.into()).is_err());
+        assert!(with(|s| s["fractal"]["relaxation_tau"] = (-1.0).into()).is_err());
+        assert!(with(|s| s["fractal"]["iteration_falloff"] = serde_json::json!({ "focus": [0.0, 0.0], "rate": -2.0 })).is_err());
+        assert!(with(|s| s["fractal"]["iteration_falloff"] = serde_json::json!({ "focus": [0.0, 0.0], "rate": 2.0 })).is_ok());
+        // 描画性能の設定は読み込まない
+        let Fractal::F64(f) = with(|s| s["fractal"]["tile_size"] = 0.into()).unwrap() else { unreachable!() };
+        assert_eq!(f.tile_size(), default::tile_size());
+        assert!(with(|_| ()).is_ok());
+    }
+
+    #[test]
+    fn c_is_available_without_setting() {
+        let fractal = fractal("z^3 - c");
+        assert_eq!(eval(&fractal, Complex::new(2.0, 0.0)).0, Complex::new(8.0, 0.0));
+    }
+
+    #[test]
+    fn set_param_recompiles_and_differentiates_by_z_only() {
+        let mut fractal = fractal("c * z^2 - c");
+        fractal.set_param("c", Complex::new(0.0, 2.0)).unwrap();
+
+        let (f, df) = eval(&fractal, Complex::new(3.0, 0.0));
+        assert_eq!(f, Complex::new(0.0, 16.0));
+        assert_eq!(df, Complex::new(0.0, 12.0));
+    }
+
+    #[test]
+    fn other_names_can_be_used() {
+        let mut fractal = FractalInner::<f64>::default();
+        assert!(fractal.set_formula("z^3 - a").is_err());
+        fractal.set_param("a", Complex::new(1.0, 0.0)).unwrap();
+        fractal.set_formula("z^3 - a").unwrap();
+        assert_eq!(eval(&fractal, Complex::new(1.0, 0.0)).0, Complex::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn reserved_names_and_invalid_values_are_rejected() {
+        for name in ["z", "diff", "i", "2i", "inf", "nan", "PI", "E", "sin", "exp", "", "2a", "c d"] {
+            assert!(validate_param(name, Complex::ZERO).is_err(), "{:?} was accepted", name);
+        }
+        assert!(validate_param("c", Complex::new(f64::NAN, 0.0)).is_err());
+        assert!(validate_param("c_1", Complex::ZERO).is_ok());
+
+        let mut fractal = FractalInner::<f64>::default();
+        assert!(fractal.set_param("z", Complex::ONE).is_err());
+        assert_eq!(fractal.params, default::params());
+    }
+
+    #[test]
+    fn params_survive_precision_change_and_state() {
+        let mut fractal = fractal("z^3 - c");
+        fractal.set_param("c", Complex::new(8.0, 0.0)).unwrap();
+
+        let mut fractal = Fractal::F64(fractal);
+        fractal.up();
+        let Fractal::F106(f) = &fractal else { unreachable!() };
+        let Formulac::F106(fo) = f.formulac() else { unreachable!() };
+        let z = Complex::new(F106::from_f64(2.0), F106::zero());
+        assert!(fo.func()([z]).is_zero());
+
+        let imported = import_state_inner(&export_state_inner(&fractal).unwrap()).unwrap();
+        let Fractal::F106(f) = &imported else { unreachable!() };
+        assert_eq!(f.params["c"], Complex::new(8.0, 0.0));
+    }
+
+    fn function(name: &str, params: &[&str], body: &str) -> UserFunction {
+        UserFunction {
+            name:   name.to_string(),
+            params: params.iter().map(|p| p.to_string()).collect(),
+            body:   body.to_string(),
+        }
+    }
+
+    #[test]
+    fn registered_function_is_usable_and_differentiable() {
+        let mut fractal = FractalInner::<f64>::default();
+        assert!(fractal.set_formula("mylogistic(z) - 1").is_err());
+
+        fractal.register_function(function("mylogistic", &["x"], "4 * x * (1 - x)")).unwrap();
+        fractal.set_formula("mylogistic(z) - 1").unwrap();
+        let (f, df) = eval(&fractal, Complex::new(2.0, 0.0));
+        assert_eq!(f, Complex::new(-9.0, 0.0));
+        assert_eq!(df, Complex::new(-12.0, 0.0));
+
+        // formulac の UserFn と異なり、引数が式の場合も連鎖律で微分する
+        fractal.set_formula("mylogistic(2 * z) + mylogistic(z^2)").unwrap();
+        let (f, df) = eval(&fractal, Complex::new(2.0, 0.0));
+        assert_eq!(f, Complex::new(-48.0 - 48.0, 0.0));
+        assert_eq!(df, Complex::new(2.0 * -28.0 + 4.0 * -28.0, 0.0));
+    }
+
+    #[test]
+    fn expansion_keeps_numbers_and_precedence() {
+        let functions = [
+            UserFunction { name: "e1".to_string(), params: vec!["x".to_string()], body: "x^2".to_string() },
+        ];
+        assert_eq!(expand_functions("2e1 + e1(z - 1)", &functions).unwrap(), "2e1 + ((z - 1)^2)");
+        assert!(expand_functions("e1 + 1", &functions).is_err());
+        assert!(expand_functions("e1(z, z)", &functions).is_err());
+
+        let doubling: Vec<UserFunction> = (0..20).map(|i| UserFunction {
+            name:   format!("f{}", i),
+            params: vec!["x".to_string()],
+            body:   if i == 0 { "x".to_string() } else { format!("f{0}(x) + f{0}(x)", i - 1) },
+        }).collect();
+        assert!(expand_functions("f19(z)", &doubling).is_err());
+    }
+
+    #[test]
+    fn multiple_parameters_and_params_and_earlier_functions() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.set_param("a", Complex::new(3.0, 0.0)).unwrap();
+        fractal.register_function(function("sq", &["z"], "z^2")).unwrap();
+        // 引数名 c は自由パラメータ c より優先する
+        fractal.register_function(function("mix", &["x", "c"], "a * sq(x) + c")).unwrap();
+        fractal.set_formula("mix(z, 2)").unwrap();
+
+        let (f, df) = eval(&fractal, Complex::new(1.0, 0.0));
+        assert_eq!(f, Complex::new(5.0, 0.0));
+        assert_eq!(df, Complex::new(6.0, 0.0));
+    }
+
+    #[test]
+    fn reregistering_overwrites_and_recompiles_formula() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.register_function(function("g", &["x"], "x - 1")).unwrap();
+        fractal.set_formula("g(z)").unwrap();
+        fractal.register_function(function("g", &["x", "y"], "x - 2")).unwrap_err(); // 呼び出しの引数の数が合わない
+        assert_eq!(eval(&fractal, Complex::ONE).0, Complex::ZERO);
+
+        fractal.register_function(function("g", &["x"], "x - 2")).unwrap();
+        assert_eq!(fractal.functions.len(), 1);
+        assert_eq!(eval(&fractal, Complex::ONE).0, Complex::new(-1.0, 0.0));
+    }
+
+    #[test]
+    fn invalid_definitions_are_rejected() {
+        let mut fractal = FractalInner::<f64>::default();
+        for f in [
+            function("z", &["x"], "x"),
+            function("sin", &["x"], "x"),
+            function("c", &["x"], "x"), // 自由パラメータと衝突する
+            function("f", &[], "1"),
+            function("f", &["a", "b", "c", "d", "e"], "a"),
+            function("f", &["x", "x"], "x"),
+            function("f", &["PI"], "PI"),
+            function("f", &["x"], "y"),
+        ] {
+            assert!(fractal.register_function(f.clone()).is_err(), "{:?} was accepted", f);
+        }
+        assert!(fractal.functions.is_empty());
+
+        fractal.register_function(function("f", &["x"], "x")).unwrap();
+        assert!(fractal.set_param("f", Complex::ONE).is_err());
+    }
+
+    #[test]
+    fn functions_survive_precision_change_and_state() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.register_function(function("cube", &["x"], "x^3")).unwrap();
+        fractal.set_formula("cube(z) - 8").unwrap();
+
+        let mut fractal = Fractal::F64(fractal);
+        fractal.up();
+        let imported = import_state_inner(&export_state_inner(&fractal).unwrap()).unwrap();
+        let Fractal::F106(f) = &imported else { unreachable!() };
+        let Formulac::F106(fo) = f.formulac() else { unreachable!() };
+        let z = Complex::new(F106::from_f64(2.0), F106::zero());
+        assert!(fo.func()([z]).is_zero());
+    }
+
+    #[test]
+    fn second_derivative_is_compiled_only_for_halley() {
+        let mut fractal = FractalInner::<f64>::default();
+        assert!(fo(&fractal).deriv2().is_none());
+        assert!(fractal.iteration_deriv2(fo(&fractal)).is_none());
+
+        fractal.set_method(btm::IterationMethod::Halley).unwrap();
+        let ddf = fo(&fractal).deriv2().unwrap();
+        assert_eq!(ddf([Complex::new(2.0, 0.0)]), Complex::new(12.0, 0.0)); // diff(diff(z^3 - 1, z), z) = 6z
+
+        // 数式を変更しても、2階導関数をコンパイルし続ける
+        fractal.set_morph_formula("z^3 - 1", "z^4 - 1", 0.5).unwrap();
+        let ddf = fo(&fractal).deriv2().unwrap();
+        assert_eq!(ddf([Complex::new(1.0, 0.0)]), Complex::new(0.5 * 6.0 + 0.5 * 12.0, 0.0));
+
+        fractal.set_method(btm::IterationMethod::Newton).unwrap();
+        assert!(fo(&fractal).deriv2().is_none());
+    }
+
+    #[test]
+    fn halley_changes_render_and_survives_state() {
+        let mut fractal = FractalInner::<f64>::default();
+        let newton = btm::calc_rect(calc_info(&fractal, fo(&fractal), 0, 0, 32, 32)).escape_times();
+        fractal.set_method(btm::IterationMethod::Halley).unwrap();
+        let halley = btm::calc_rect(calc_info(&fractal, fo(&fractal), 0, 0, 32, 32)).escape_times();
+        assert_ne!(newton, halley);
+        assert!(halley.iter().map(|&n| u32::from(n)).sum::<u32>() < newton.iter().map(|&n| u32::from(n)).sum::<u32>());
+
+        let imported = import_state_inner(&export_state_inner(&Fractal::F64(fractal)).unwrap()).unwrap();
+        let Fractal::F64(f) = &imported else { unreachable!() };
+        assert_eq!(f.method, btm::IterationMethod::Halley);
+        assert!(f.iteration_deriv2(fo(f)).is_some());
+    }
+
+    #[test]
+    fn roots_polynomial_second_derivative() {
+        let roots = vec![Complex::new(1.0, 0.0), Complex::new(-1.0, 0.0), Complex::new(0.0, 2.0)];
+        let fo = FormulacInner::from_roots(roots);
+        // (z^2 - 1)(z - 2i) の2階導関数は 6z - 4i
+        let z = Complex::new(0.5, 0.25);
+        let expected = Complex::new(6.0, 0.0) * z - Complex::new(0.0, 4.0);
+        assert!((fo.deriv2().unwrap()([z]) - expected).norm() < 1e-12);
+    }
+
+    #[test]
+    fn epsilon_is_passed_to_escape_options() {
+        let mut fractal = FractalInner::<f64>::default();
+        assert_eq!(fractal.escape_options().epsilon, btm::DEFAULT_EPSILON);
+        fractal.set_epsilon(1e-9);
+        let fo = fo(&fractal);
+        assert_eq!(calc_info(&fractal, fo, 0, 0, 1, 1).escape.epsilon, 1e-9);
+    }
+
+    #[test]
+    fn state_keeps_epsilon_and_rejects_invalid() {
+        let mut fractal = FractalInner::<f64>::default();
+        fractal.set_epsilon(1e-7);
+        let json = export_state_inner(&Fractal::F64(fractal)).unwrap();
+        let Fractal::F64(f) = import_state_inner(&json).unwrap() else { unreachable!() };
+        assert_eq!(f.epsilon, 1e-7);
+
+        // 許容誤差の無いデータは既定値とする
+        let mut state: serde_json::Value = serde_json::from_str(&json).unwrap();
+        state["fractal"].as_object_mut().unwrap().remove("epsilon");
+        let Fractal::F64(f) = import_state_inner(&state.to_string()).unwrap() else { unreachable!() };
+        assert_eq!(f.epsilon, btm::DEFAULT_EPSILON);
+
+        for invalid in [0.0, -1e-9] {
+            state["fractal"]["epsilon"] = invalid.into();
+            assert!(import_state_inner(&state.to_string()).is_err());
+        }
+    }
+
+    fn set(fractal: &mut Fractal, formula: &str) -> Result<String, String> {
+        let Fractal::F64(f) = fractal else { unreachable!() };
+        f.set_formula(formula).map_err(|e| e.to_string())?;
+        Ok("OK".to_string())
+    }
+
+    fn formula(fractal: &Fractal) -> &str {
+        let Fractal::F64(f) = fractal else { unreachable!() };
+        f.formula()
+    }
+
+    #[test]
+    fn superseded_formula_is_discarded() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        static VERSION: AtomicU64 = AtomicU64::new(0);
+        let fractal = Mutex::new(Fractal::F64(FractalInner::default()));
+        let generation = btm::CancelToken::advance(&GENERATION);
+        assert_eq!(change_formula(&fractal, &VERSION, generation, |f| set(f, "z^3 - 1")).unwrap(), "OK");
+        assert_eq!(formula(&fractal.lock().unwrap()), "z^3 - 1");
+
+        // コンパイル中に新しい変更が呼び出された場合は、変更前の数式に戻す
+        let stale = btm::CancelToken::advance(&GENERATION);
+        let status = change_formula(&fractal, &VERSION, stale, |f| {
+            btm::CancelToken::advance(&GENERATION);
+            set(f, "z^5 - 1")
+        });
+        assert_eq!(status.unwrap(), FORMULA_SUPERSEDED);
+        assert_eq!(formula(&fractal.lock().unwrap()), "z^3 - 1");
+
+        // 呼び出し前に新しい変更があった場合は、コンパイルしない
+        let status = change_formula(&fractal, &VERSION, stale, |_| panic!("compiled a superseded formula"));
+        assert_eq!(status.unwrap(), FORMULA_SUPERSEDED);
+    }
+
+    #[test]
+    fn superseded_param_is_reverted() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        static VERSION: AtomicU64 = AtomicU64::new(0);
+        let fractal = Mutex::new(Fractal::F64(fractal("z^3 - c")));
+        let stale = btm::CancelToken::advance(&GENERATION);
+        let status = change_formula(&fractal, &VERSION, stale, |f| {
+            let Fractal::F64(f) = f else { unreachable!() };
+            f.set_param("c", Complex::new(8.0, 0.0))?;
+            btm::CancelToken::advance(&GENERATION);
+            Ok("OK".to_string())
+        });
+        assert_eq!(status.unwrap(), FORMULA_SUPERSEDED);
+        let Fractal::F64(f) = &*fractal.lock().unwrap() else { unreachable!() };
+        assert_eq!(eval(f, Complex::new(2.0, 0.0)).0, Complex::new(8.0, 0.0));
+    }
+
+    #[test]
+    fn superseded_function_is_unregistered() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        static VERSION: AtomicU64 = AtomicU64::new(0);
+        let fractal = Mutex::new(Fractal::F64(fractal("z^3 - 1")));
+        let stale = btm::CancelToken::advance(&GENERATION);
+        let status = change_formula(&fractal, &VERSION, stale, |f| {
+            let Fractal::F64(f) = f else { unreachable!() };
+            let function = UserFunction { name: "sq".to_string(), params: vec!["x".to_string()], body: "x^2".to_string() };
+            f.register_function(function)?;
+            btm::CancelToken::advance(&GENERATION);
+            Ok("OK".to_string())
+        });
+        assert_eq!(status.unwrap(), FORMULA_SUPERSEDED);
+        let Fractal::F64(f) = &mut *fractal.lock().unwrap() else { unreachable!() };
+        assert!(f.set_formula("sq(z) - 1").is_err());
+    }
+
+    #[test]
+    fn superseded_morph_ratio_is_reverted() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        static VERSION: AtomicU64 = AtomicU64::new(0);
+        let mut inner = FractalInner::<f64>::default();
+        inner.set_morph_formula("z^3 - 1", "z^4 - 1", 0.25).unwrap();
+        let fractal = Mutex::new(Fractal::F64(inner));
+        let stale = btm::CancelToken::advance(&GENERATION);
+        let status = change_formula(&fractal, &VERSION, stale, |f| {
+            let Fractal::F64(f) = f else { unreachable!() };
+            f.set_morph_t(0.75)?;
+            btm::CancelToken::advance(&GENERATION);
+            Ok("OK".to_string())
+        });
+        assert_eq!(status.unwrap(), FORMULA_SUPERSEDED);
+        let Fractal::F64(f) = &*fractal.lock().unwrap() else { unreachable!() };
+        assert_eq!(f.morph.as_ref().unwrap().t, 0.25);
+    }
+
+    #[test]
+    fn superseded_method_is_reverted() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        static VERSION: AtomicU64 = AtomicU64::new(0);
+        let fractal = Mutex::new(Fractal::F64(fractal("z^3 - 1")));
+        let stale = btm::CancelToken::advance(&GENERATION);
+        let status = change_formula(&fractal, &VERSION, stale, |f| {
+            let Fractal::F64(f) = f else { unreachable!() };
+            f.set_method(btm::IterationMethod::Halley)?;
+            btm::CancelToken::advance(&GENERATION);
+            Ok("OK".to_string())
+        });
+        assert_eq!(status.unwrap(), FORMULA_SUPERSEDED);
+        let Fractal::F64(f) = &*fractal.lock().unwrap() else { unreachable!() };
+        assert_eq!(f.method, btm::IterationMethod::Newton);
+        assert!(fo(f).deriv2().is_none());
+    }
+
+    #[test]
+    fn param_set_during_formula_compile_is_kept() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        static EDIT_GENERATION: AtomicU64 = AtomicU64::new(0);
+        static VERSION: AtomicU64 = AtomicU64::new(0);
+        let fractal = Mutex::new(Fractal::F64(fractal("z^3 - c")));
+        let generation = btm::CancelToken::advance(&GENERATION);
+        let mut edited = false;
+        let status = change_formula(&fractal, &VERSION, generation, |f| {
+            // 数式のコンパイル中に、FRACTAL をlockしてパラメータを変更する
+            if !std::mem::replace(&mut edited, true) {
+                let edit = btm::CancelToken::advance(&EDIT_GENERATION);
+                let status = change_formula(&fractal, &VERSION, edit, |f| {
+                    let Fractal::F64(f) = f else { unreachable!() };
+                    f.set_param("c", Complex::new(8.0, 0.0))?;
+                    Ok("OK".to_string())
+                });
+                assert_eq!(status.unwrap(), "OK");
+            }
+            set(f, "z^2 - c")
+        });
+
+        // 数式の置き換えは破棄されず、変更したパラメータを反映した状態でコンパイルし直す
+        assert_eq!(status.unwrap(), "OK");
+        let Fractal::F64(f) = &*fractal.lock().unwrap() else { unreachable!() };
+        assert_eq!(f.formula(), "z^2 - c");
+        assert_eq!(eval(f, Complex::new(2.0, 0.0)).0, Complex::new(-4.0, 0.0));
+    }
+
+    #[test]
+    fn view_changed_during_compile_is_kept() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        static VERSION: AtomicU64 = AtomicU64::new(0);
+        let fractal = Mutex::new(Fractal::F64(fractal("z^3 - 1")));
+        let generation = btm::CancelToken::advance(&GENERATION);
+        let status = change_formula(&fractal, &VERSION, generation, |f| {
+            if let Fractal::F64(live) = &mut *fractal.lock().unwrap() {
+                live.canvas_mut().zoom_level = 5;
+            }
+            set(f, "z^5 - 1")
+        });
+        assert_eq!(status.unwrap(), "OK");
+        let Fractal::F64(f) = &*fractal.lock().unwrap() else { unreachable!() };
+        assert_eq!(f.formula(), "z^5 - 1");
+        assert_eq!(f.canvas().zoom_level, 5);
+    }
+
+    #[test]
+    fn cancelled_tile_is_empty() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        let fractal = fractal("z^3 - 1");
+        let fo = fo(&fractal);
+
+        let cancel = btm::CancelToken::new(&GENERATION);
+        let tile = render_tile_cancellable_inner(&fractal, fo, (0, 0, 32, 32), cancel);
+        assert_eq!(tile, render_tile_inner(&fractal, fo, 0, 0, 32, 32));
+
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+        assert!(render_tile_cancellable_inner(&fractal, fo, (0, 0, 32, 32), cancel).is_empty());
+    }
+
+    #[test]
+    fn cancelled_roots_tile_is_empty() {
+        static GENERATION: AtomicU64 = AtomicU64::new(0);
+        let fractal = FractalInner::<f64>::default();
+        let roots = [(1.0, 0.0), (-0.5, 0.8), (-0.5, -0.8)];
+
+        let cancel = btm::CancelToken::new(&GENERATION);
+        let tile = render_tile_from_roots_inner(&fractal, &roots, (0, 0, 32, 32), cancel);
+        assert_eq!(tile, btm::calc_rect(roots_calc_info(&fractal, &roots, 0, 0, 32, 32)).escape_times());
+
+        GENERATION.fetch_add(1, Ordering::SeqCst);
+        assert!(render_tile_from_roots_inner(&fractal, &roots, (0, 0, 32, 32), cancel).is_empty());
+    }
+}
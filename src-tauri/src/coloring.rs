@@ -0,0 +1,750 @@
+use num_complex::Complex;
+use serde::{
+    Serialize, Deserialize,
+};
+
+use crate::btm::{
+    Pixel,
+    BRANCH_JUMP,
+    UNCONVERGED,
+};
+use crate::calculate::Func;
+
+/// 色付けの方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColoringMode {
+    /// ColoringSource の値をカラーマップで色付けする
+    #[default]
+    Colormap,
+    /// 収束先の根毎に色を割り当てる
+    Basin,
+    /// ColorFormula の値の実部をカラーマップで色付けする。色付けの式が未設定の場合は Colormap と同じ
+    Formula,
+}
+
+/// カラーマップで色付けする値
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ColoringSource {
+    /// 収束までの反復回数
+    #[default]
+    EscapeTime,
+    /// 最後の反復の収束比 (小さいほど速く収束している)
+    ConvergenceSpeed,
+    /// 連続的な反復回数
+    ///
+    /// 収束判定の値 d_k (既定では |z_k - z_{k-1}| / |z_{k-1}|) が n 回目の反復で許容誤差 ε を下回った場合に、
+    /// `n - 1 + ln(ε / d_{n-1}) / ln(d_n / d_{n-1})` とする。
+    /// d_k の対数が反復毎にほぼ線形に変化するので、反復回数が1つ変わる境界で連続になり、縞模様が消える
+    SmoothEscapeTime,
+}
+
+/// 出力する画素の不透明度
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AlphaSource {
+    /// 常に不透明
+    #[default]
+    Constant,
+    /// 反復回数に比例させる (速く収束した領域ほど透明)
+    IterationCount,
+}
+
+/// 値を [0.0, 1.0] から色に変換するカラーマップ
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum Colormap {
+    #[default]
+    Jet,
+    /// 知覚的に均一で、明るさが単調に増加する (matplotlib の viridis)
+    Viridis,
+    /// 知覚的に均一で、黒から明るい黄色に変化する (matplotlib の magma)
+    Magma,
+    Grayscale,
+    /// 色相を1周させる。0.0 と 1.0 は同じ色 (赤) になるので、周期的な値に向いている
+    Hsv,
+}
+
+impl Colormap {
+    pub fn color(self, t: f64) -> [u8; 4] {
+        match self {
+            Self::Jet => jet(t),
+            Self::Viridis => interpolate(&VIRIDIS, t),
+            Self::Magma => interpolate(&MAGMA, t),
+            Self::Grayscale => {
+                let v = (t.clamp(0.0, 1.0) * u8::MAX as f64) as u8;
+                [v, v, v, u8::MAX]
+            }
+            Self::Hsv => {
+                let [r, g, b] = hsv(t);
+                [r, g, b, u8::MAX]
+            }
+        }
+    }
+}
+
+/// viridis を等間隔に標本化した色
+const VIRIDIS: [[u8; 3]; 9] = [
+    [68, 1, 84], [71, 44, 122], [59, 81, 139], [44, 113, 142], [33, 144, 141],
+    [39, 173, 129], [92, 200, 99], [170, 220, 50], [253, 231, 37],
+];
+
+/// magma を等間隔に標本化した色
+const MAGMA: [[u8; 3]; 9] = [
+    [0, 0, 4], [28, 16, 68], [79, 18, 123], [129, 37, 129], [181, 54, 122],
+    [229, 80, 100], [251, 135, 97], [254, 194, 135], [252, 253, 191],
+];
+
+/// # 等間隔に標本化した色の間を線形補間する
+fn interpolate(samples: &[[u8; 3]], t: f64) -> [u8; 4] {
+    let pos = t.clamp(0.0, 1.0) * (samples.len() - 1) as f64;
+    let i = (pos as usize).min(samples.len() - 2);
+    let frac = pos - i as f64;
+    let mix = |c: usize| {
+        let (a, b) = (samples[i][c] as f64, samples[i + 1][c] as f64);
+        (a + (b - a) * frac).round() as u8
+    };
+    [mix(0), mix(1), mix(2), u8::MAX]
+}
+
+/// 反復回数をカラーマップに渡す前に適用する変換
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum ValueTransform {
+    #[default]
+    Linear,
+    /// 平方根 (反復回数が少ない領域の差を強調する)
+    Sqrt,
+    /// ln(1 + n)
+    Log,
+}
+
+impl ValueTransform {
+    fn apply(self, n: f64) -> f64 {
+        match self {
+            Self::Linear => n,
+            Self::Sqrt => n.sqrt(),
+            Self::Log => n.ln_1p(),
+        }
+    }
+}
+
+/// 計算済みの反復回数を、再計算せずに色付けし直す際の設定
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Recolor {
+    pub colormap:  Colormap,
+    /// 正規化した値 t を t^gamma に変換する
+    pub gamma:     f64,
+    pub transform: ValueTransform,
+    /// 0以外の場合、反復回数を period 毎に周期的に色付けする
+    pub period:    u16,
+}
+
+impl Recolor {
+    /// # 反復回数をRGBAの画素データに変換する
+    ///
+    /// 値は `t = (transform(n) / transform(m))^gamma` でカラーマップに渡す。
+    /// n は反復回数、m は最大反復回数とし、period が0以外の場合はそれぞれ n % period と period で置き換える。
+    /// 線形・gamma=1・周期なしの場合は、Coloring の既定の色付けと一致する
+    ///
+    /// ## Params
+    ///  - data: render_tile などが返した反復回数
+    ///  - max_iter: 最大反復回数
+    pub fn apply(&self, data: &[u16], max_iter: u16) -> Vec<u8> {
+        let (max, period) = match self.period {
+            0 => (max_iter, None),
+            period => (period, Some(period)),
+        };
+        let denominator = self.transform.apply(max.max(1) as f64);
+
+        data.iter()
+            .flat_map(|&iter| {
+                let n = period.map_or(iter, |period| iter % period);
+                let t = (self.transform.apply(n as f64) / denominator).clamp(0.0, 1.0);
+                self.colormap.color(t.powf(self.gamma))
+            })
+            .collect()
+    }
+}
+
+/// 反復回数 n と収束した点 z から、カラーマップに渡す値を計算する色付けの式
+#[derive(Clone)]
+pub struct ColorFormula {
+    expr: String,
+    func: Func<f64, 2>,
+}
+
+impl std::fmt::Debug for ColorFormula {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ColorFormula").field("expr", &self.expr).finish()
+    }
+}
+
+impl ColorFormula {
+    /// # 変数 n, z の式としてコンパイルする
+    pub fn compile(expr: &str) -> Result<Self, String> {
+        let func = formulac::Builder::<f64, 2>::new(expr, ["n", "z"])
+            .compile()
+            .map_err(|e| e.to_string())?;
+        Ok(Self { expr: expr.to_string(), func: std::sync::Arc::new(func) })
+    }
+
+    /// # 反復回数と収束した点から、カラーマップに渡す値を返す
+    ///
+    /// ## Returns
+    ///  - 式の値の実部を [0.0, 1.0] に丸めた値。有限でない場合はNone
+    fn value(&self, iter: u16, z: Complex<f64>) -> Option<f64> {
+        let re = (self.func)([Complex::new(iter as f64, 0.0), z]).re;
+        re.is_finite().then(|| re.clamp(0.0, 1.0))
+    }
+}
+
+/// サーバー側で画像を色付けする際の設定
+#[derive(Debug, Clone, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Coloring {
+    mode:          ColoringMode,
+    source:        ColoringSource,
+    alpha:         AlphaSource,
+    /// 反復回数をカラーマップで色付けする際の定義域 (min, max)。Noneの場合は [0, max_iter]
+    domain:        Option<(u16, u16)>,
+    /// 根のindex毎の色。根の数より少ない場合は hue_wheel の色を使用する
+    basin_colors:  Vec<[u8; 3]>,
+    /// 根毎の色の明るさを反復回数で変化させるか
+    basin_shading: bool,
+    /// ColoringMode::Formula で使用する色付けの式
+    #[serde(skip)]
+    color_formula: Option<ColorFormula>,
+    /// ColoringMode::Colormap と ColoringMode::Formula で使用するカラーマップ
+    colormap:      Colormap,
+}
+
+impl Default for Coloring {
+    fn default() -> Self {
+        Self {
+            mode:          ColoringMode::default(),
+            source:        ColoringSource::default(),
+            alpha:         AlphaSource::default(),
+            domain:        None,
+            basin_colors:  Vec::new(),
+            basin_shading: true,
+            color_formula: None,
+            colormap:      Colormap::default(),
+        }
+    }
+}
+
+impl Coloring {
+    pub fn set_mode(&mut self, mode: ColoringMode) {
+        self.mode = mode;
+    }
+
+    pub fn set_source(&mut self, source: ColoringSource) {
+        self.source = source;
+    }
+
+    pub fn set_alpha_source(&mut self, alpha: AlphaSource) {
+        self.alpha = alpha;
+    }
+
+    pub fn set_domain(&mut self, domain: Option<(u16, u16)>) {
+        self.domain = domain;
+    }
+
+    /// # 反復回数を、カラーマップに渡す [0.0, 1.0] の値に変換する
+    fn normalize_iter(&self, iter: u16, max_iter: u16) -> f64 {
+        self.normalize_smooth(iter as f64, max_iter)
+    }
+
+    /// # 連続的な反復回数を、normalize_iter と同じ定義域で [0.0, 1.0] の値に変換する
+    fn normalize_smooth(&self, iter: f64, max_iter: u16) -> f64 {
+        let (min, max) = self.domain.unwrap_or((0, max_iter));
+        ((iter - min as f64) / max.saturating_sub(min).max(1) as f64).clamp(0.0, 1.0)
+    }
+
+    /// 色付けに収束比が必要か
+    pub fn needs_ratios(&self) -> bool {
+        self.mode == ColoringMode::Colormap && self.source == ColoringSource::ConvergenceSpeed
+    }
+
+    /// 色付けに連続的な反復回数が必要か
+    pub fn needs_smooth(&self) -> bool {
+        self.mode == ColoringMode::Colormap && self.source == ColoringSource::SmoothEscapeTime
+    }
+
+    pub fn set_basin_colors(&mut self, colors: Vec<[u8; 3]>) {
+        self.basin_colors = colors;
+    }
+
+    pub fn set_basin_shading(&mut self, shading: bool) {
+        self.basin_shading = shading;
+    }
+
+    pub fn set_color_formula(&mut self, formula: Option<ColorFormula>) {
+        self.color_formula = formula;
+    }
+
+    pub fn set_colormap(&mut self, colormap: Colormap) {
+        self.colormap = colormap;
+    }
+
+    /// # 根のindexに対応する色を返す
+    ///
+    /// ## Params
+    ///  - root: 根のindex
+    ///  - root_count: 見つかった根の数
+    pub fn basin_color(&self, root: u16, root_count: usize) -> [u8; 3] {
+        match self.basin_colors.get(root as usize) {
+            Some(color) => *color,
+            None => hue_wheel(root as usize, root_count),
+        }
+    }
+
+    /// # 計算結果をRGBAの画素データに変換する
+    ///
+    /// ## Params
+    ///  - pixels: calc_rect の計算結果
+    ///  - values: 各ピクセルの収束比 (needs_ratios の場合) または連続的な反復回数 (needs_smooth の場合)。どちらでもない場合は使用しない
+    ///  - roots: Pixel::root の番号に対応する根の座標
+    ///  - max_iter: 最大反復回数
+    pub fn colorize(&self, pixels: &[Pixel], values: &[f64], roots: &[Complex<f64>], max_iter: u16) -> Vec<u8> {
+        let mut rgba: Vec<u8> = match (self.mode, &self.color_formula) {
+            (ColoringMode::Formula, Some(formula)) => pixels.iter()
+                .flat_map(|p| self.colorize_formula(formula, p, roots))
+                .collect(),
+            _ if self.needs_ratios() => values.iter()
+                .flat_map(|&ratio| self.colormap.color(convergence_speed(ratio)))
+                .collect(),
+            _ if self.needs_smooth() => values.iter()
+                .flat_map(|&iter| self.colormap.color(self.normalize_smooth(iter, max_iter)))
+                .collect(),
+            (ColoringMode::Colormap | ColoringMode::Formula, _) => pixels.iter()
+                .flat_map(|p| self.colormap.color(self.normalize_iter(p.iter, max_iter)))
+                .collect(),
+            (ColoringMode::Basin, _) => pixels.iter()
+                .flat_map(|p| self.colorize_basin(p, roots.len(), max_iter))
+                .collect(),
+        };
+
+        if self.alpha == AlphaSource::IterationCount {
+            for (color, pixel) in rgba.chunks_exact_mut(4).zip(pixels) {
+                color[3] = iteration_alpha(pixel.iter, max_iter);
+            }
+        }
+        rgba
+    }
+
+    /// # 色付けの式の値で色付けする
+    ///
+    /// 境界追跡で埋めたピクセルには最後の反復の点が無いので、収束した点として収束先の根の座標を使用する。
+    /// 収束しなかったピクセルと、式の値が有限でないピクセルは黒とする
+    fn colorize_formula(&self, formula: &ColorFormula, pixel: &Pixel, roots: &[Complex<f64>]) -> [u8; 4] {
+        pixel.root_index()
+            .and_then(|root| roots.get(root))
+            .and_then(|&z| formula.value(pixel.iter, z))
+            .map_or([0, 0, 0, u8::MAX], |t| self.colormap.color(t))
+    }
+
+    fn colorize_basin(&self, pixel: &Pixel, root_count: usize, max_iter: u16) -> [u8; 4] {
+        /// 反復回数が最大の場合の明るさ
+        const MIN_BRIGHTNESS: f64 = 0.3;
+
+        /// 不連続な跳躍を検出した点の色
+        const BRANCH_JUMP_COLOR: [u8; 4] = [128, 128, 128, u8::MAX];
+
+        let root = match pixel.root {
+            UNCONVERGED => return [0, 0, 0, u8::MAX],
+            BRANCH_JUMP => return BRANCH_JUMP_COLOR,
+            root => root,
+        };
+
+        let [r, g, b] = self.basin_color(root, root_count);
+        let brightness = if self.basin_shading {
+            let t = (pixel.iter as f64 / max_iter as f64).clamp(0.0, 1.0);
+            1.0 - (1.0 - MIN_BRIGHTNESS) * t
+        } else {
+            1.0
+        };
+        let shade = |c: u8| (c as f64 * brightness) as u8;
+
+        [shade(r), shade(g), shade(b), u8::MAX]
+    }
+}
+
+/// # 反復回数に比例した不透明度を返す
+fn iteration_alpha(iter: u16, max_iter: u16) -> u8 {
+    let t = (iter as f64 / max_iter.max(1) as f64).clamp(0.0, 1.0);
+    (t * u8::MAX as f64).round() as u8
+}
+
+/// # 収束比を、カラーマップに渡す [0.0, 1.0] の値に変換する
+///
+/// 収束比の桁数を使用するので、厳密に収束した (収束比が0の) 場合は1.0となる
+fn convergence_speed(ratio: f64) -> f64 {
+    /// 1.0 に対応する収束比の桁数
+    const DIGITS: f64 = 8.0;
+
+    if ratio <= 0.0 {
+        return 1.0;
+    }
+    (-ratio.log10() / DIGITS).clamp(0.0, 1.0)
+}
+
+pub fn jet(t: f64) -> [u8; 4] {
+    const MIN: f64 = 0.0;
+    const MAX: f64 = 1.0;
+    let t = t.clamp(MIN, MAX);
+    let r = (1.5 - (4.0 * t - 3.0).abs()).clamp(MIN, MAX);
+    let g = (1.5 - (4.0 * t - 2.0).abs()).clamp(MIN, MAX);
+    let b = (1.5 - (4.0 * t - 1.0).abs()).clamp(MIN, MAX);
+
+    [
+        (r * u8::MAX as f64) as u8,
+        (g * u8::MAX as f64) as u8,
+        (b * u8::MAX as f64) as u8,
+        u8::MAX, // Alphaは常に1.0
+    ]
+}
+
+/// # RGBAの画素データを、メディアンカット法で最大size色のパレットに減色する
+///
+/// 画素の色 (不透明度を含む) の集合を、値の幅が最も大きいチャンネルの画素数の中央で分割することを繰り返し、
+/// 各集合の画素数で重み付けした平均色をパレットの色とする。
+/// 色の種類が size 以下の場合は、それぞれの色をそのままパレットとする
+///
+/// ## Returns
+///  - RGBAのパレット。画素データが空の場合は空
+pub fn median_cut_palette(rgba: &[u8], size: usize) -> Vec<[u8; 4]> {
+    let mut histogram: std::collections::HashMap<[u8; 4], u64> = std::collections::HashMap::new();
+    for color in rgba.chunks_exact(4) {
+        *histogram.entry([color[0], color[1], color[2], color[3]]).or_default() += 1;
+    }
+    let mut colors: Vec<([u8; 4], u64)> = histogram.into_iter().collect();
+    colors.sort_unstable();
+
+    // (チャンネル, 値の幅) が最も大きいチャンネル
+    let widest = |colors: &[([u8; 4], u64)]| -> (usize, u8) {
+        (0..4)
+            .map(|ch| {
+                let (min, max) = colors.iter()
+                    .fold((u8::MAX, 0), |(min, max), (color, _)| (min.min(color[ch]), max.max(color[ch])));
+                (ch, max.saturating_sub(min))
+            })
+            .max_by_key(|&(_, range)| range)
+            .unwrap_or((0, 0))
+    };
+
+    let mut boxes = if colors.is_empty() { Vec::new() } else { vec![colors] };
+    while boxes.len() < size {
+        let Some((idx, ch)) = boxes.iter()
+            .enumerate()
+            .map(|(idx, colors)| (idx, widest(colors)))
+            .filter(|(_, (_, range))| *range > 0)
+            .max_by_key(|(_, (_, range))| *range)
+            .map(|(idx, (ch, _))| (idx, ch))
+        else {
+            break;
+        };
+
+        let mut colors = boxes.swap_remove(idx);
+        colors.sort_unstable_by_key(|&(color, _)| (color[ch], color));
+        let total: u64 = colors.iter().map(|(_, count)| count).sum();
+        let mut seen = 0;
+        let median = colors.iter()
+            .position(|(_, count)| {
+                seen += count;
+                seen * 2 >= total
+            })
+            .unwrap_or(0);
+        // 両側に1色以上残るように分割する
+        let upper = colors.split_off((median + 1).min(colors.len() - 1));
+        boxes.push(colors);
+        boxes.push(upper);
+    }
+
+    boxes.iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|(_, count)| count).sum();
+            let mut color = [0; 4];
+            for (ch, value) in color.iter_mut().enumerate() {
+                let sum: u64 = colors.iter().map(|(color, count)| color[ch] as u64 * count).sum();
+                *value = ((sum + total / 2) / total) as u8;
+            }
+            color
+        })
+        .collect()
+}
+
+/// # RGBAの画素データの各画素を、最も近いパレットの色のindexに変換する
+///
+/// 不透明度も含めた距離で比べる。同じ色の画素が多いので、変換結果を色毎に再利用する
+pub fn quantize_to_palette(rgba: &[u8], palette: &[[u8; 4]]) -> Vec<u8> {
+    let distance = |a: &[u8; 4], b: &[u8; 4]| -> u32 {
+        a.iter().zip(b).map(|(&x, &y)| (x as i32 - y as i32).pow(2) as u32).sum()
+    };
+    let mut cache: std::collections::HashMap<[u8; 4], u8> = std::collections::HashMap::new();
+
+    rgba.chunks_exact(4)
+        .map(|color| {
+            let color = [color[0], color[1], color[2], color[3]];
+            *cache.entry(color).or_insert_with(|| {
+                palette.iter()
+                    .enumerate()
+                    .min_by_key(|(_, p)| distance(&color, p))
+                    .map_or(0, |(idx, _)| idx as u8)
+            })
+        })
+        .collect()
+}
+
+/// # 値を高さとみなしたLambert反射の明るさを、RGBAの画素データに掛ける
+///
+/// 上下左右の隣接ピクセルとの中心差分で勾配を求め、法線と光源の向きの内積を明るさとする。
+/// 光源の高度は45°とし、平坦な領域は一様に 1/√2 の明るさとなる。
+/// 画像の端のピクセルは片側差分とする
+///
+/// ## Params
+///  - heights: ピクセル毎の高さ (y * width + x)
+///  - light_dir: 光源の向き (x, y)。0ベクトルの場合は真上から照らす
+pub fn apply_shading(rgba: &mut [u8], heights: &[f64], width: u32, height: u32, light_dir: (f64, f64)) {
+    use std::f64::consts::FRAC_1_SQRT_2;
+
+    let (w, h) = (width as usize, height as usize);
+    let len = light_dir.0.hypot(light_dir.1);
+    let light = if len > 0.0 {
+        let (lx, ly) = (light_dir.0 / len, light_dir.1 / len);
+        (lx * FRAC_1_SQRT_2, ly * FRAC_1_SQRT_2, FRAC_1_SQRT_2)
+    } else {
+        (0.0, 0.0, 1.0)
+    };
+    // 端のピクセルでは、存在する側の隣接ピクセルとの差分とする
+    let diff = |idx: usize, pos: usize, len: usize, stride: usize| -> f64 {
+        let prev = if pos > 0 { idx - stride } else { idx };
+        let next = if pos + 1 < len { idx + stride } else { idx };
+        match next - prev {
+            0 => 0.0,
+            span => (heights[next] - heights[prev]) / (span / stride) as f64,
+        }
+    };
+
+    for (idx, color) in rgba.chunks_exact_mut(4).enumerate().take(w * h) {
+        let (x, y) = (idx % w, idx / w);
+        let (gx, gy) = (diff(idx, x, w, 1), diff(idx, y, h, w));
+        let norm = (gx * gx + gy * gy + 1.0).sqrt();
+        let brightness = ((-gx * light.0 - gy * light.1 + light.2) / norm).clamp(0.0, 1.0);
+        for c in &mut color[..3] {
+            *c = (*c as f64 * brightness).round() as u8;
+        }
+    }
+}
+
+/// # 色相環を等間隔に分割した色を返す
+///
+/// ## Params
+///  - index: 何番目の色か
+///  - count: 分割数
+pub fn hue_wheel(index: usize, count: usize) -> [u8; 3] {
+    let count = count.max(index + 1);
+    hsv(index as f64 / count as f64)
+}
+
+/// # 色相 t (1.0 で1周) の、彩度・明度が最大の色を返す
+fn hsv(t: f64) -> [u8; 3] {
+    let h = t.clamp(0.0, 1.0) * 6.0;
+    let x = 1.0 - (h % 2.0 - 1.0).abs();
+    let (r, g, b) = match h as u32 {
+        0 => (1.0, x, 0.0),
+        1 => (x, 1.0, 0.0),
+        2 => (0.0, 1.0, x),
+        3 => (0.0, x, 1.0),
+        4 => (x, 0.0, 1.0),
+        _ => (1.0, 0.0, x),
+    };
+
+    [
+        (r * u8::MAX as f64) as u8,
+        (g * u8::MAX as f64) as u8,
+        (b * u8::MAX as f64) as u8,
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_ITER: u16 = 64;
+
+    fn alphas(coloring: &Coloring, pixels: &[Pixel]) -> Vec<u8> {
+        coloring.colorize(pixels, &[], &[Complex::new(1.0, 0.0)], MAX_ITER)
+            .chunks_exact(4)
+            .map(|color| color[3])
+            .collect()
+    }
+
+    #[test]
+    fn iteration_count_alpha_differs_between_converged_and_max_iter() {
+        let pixels = [
+            Pixel { iter: 0, root: 0 },
+            Pixel { iter: MAX_ITER, root: UNCONVERGED },
+        ];
+
+        let mut coloring = Coloring::default();
+        coloring.set_alpha_source(AlphaSource::IterationCount);
+        let alpha = alphas(&coloring, &pixels);
+        assert_ne!(alpha[0], alpha[1]);
+        assert_eq!(alpha[0], 0);
+        assert_eq!(alpha[1], u8::MAX);
+
+        coloring.set_mode(ColoringMode::Basin);
+        assert_eq!(alphas(&coloring, &pixels), alpha);
+    }
+
+    #[test]
+    fn constant_alpha_is_opaque() {
+        let pixels = [
+            Pixel { iter: 0, root: 0 },
+            Pixel { iter: MAX_ITER, root: UNCONVERGED },
+        ];
+        assert_eq!(alphas(&Coloring::default(), &pixels), vec![u8::MAX; 2]);
+    }
+
+    fn formula_coloring(expr: &str) -> Coloring {
+        let mut coloring = Coloring::default();
+        coloring.set_mode(ColoringMode::Formula);
+        coloring.set_color_formula(Some(ColorFormula::compile(expr).unwrap()));
+        coloring
+    }
+
+    #[test]
+    fn value_of_the_formula_selects_the_colormap_entry() {
+        let roots = [Complex::new(1.0, 0.0), Complex::new(-1.0, 0.0)];
+        let pixels = [Pixel { iter: 0, root: 0 }, Pixel { iter: 0, root: 1 }, Pixel { iter: 8, root: 1 }];
+
+        // 根の実部で色付けし、反復回数は使わない
+        let rgba = formula_coloring("(z + conj(z)) / 4 + 0.5").colorize(&pixels, &[], &roots, MAX_ITER);
+        assert_eq!(rgba[0..4], jet(1.0));
+        assert_eq!(rgba[4..8], jet(0.0));
+        assert_eq!(rgba[4..8], rgba[8..12]);
+
+        let rgba = formula_coloring("n / 8").colorize(&pixels, &[], &roots, MAX_ITER);
+        assert_eq!(rgba[4..8], jet(0.0));
+        assert_eq!(rgba[8..12], jet(1.0));
+    }
+
+    #[test]
+    fn non_finite_and_unconverged_pixels_are_black() {
+        let roots = [Complex::new(0.0, 0.0)];
+        let pixels = [Pixel { iter: 3, root: 0 }, Pixel { iter: MAX_ITER, root: UNCONVERGED }];
+
+        let rgba = formula_coloring("1 / z").colorize(&pixels, &[], &roots, MAX_ITER);
+        assert_eq!(rgba, [0, 0, 0, u8::MAX, 0, 0, 0, u8::MAX]);
+    }
+
+    #[test]
+    fn expressions_with_unknown_variables_are_rejected() {
+        assert!(ColorFormula::compile("n + w").is_err());
+        assert!(ColorFormula::compile("n * abs(z)").is_ok());
+    }
+
+    #[test]
+    fn flat_region_is_uniformly_shaded() {
+        let (w, h) = (4, 3);
+        let mut rgba = [200, 100, 50, u8::MAX].repeat(w * h);
+        apply_shading(&mut rgba, &vec![5.0; w * h], w as u32, h as u32, (1.0, 1.0));
+
+        let expected = [141, 71, 35, u8::MAX];
+        assert!(rgba.chunks_exact(4).all(|color| color == expected));
+    }
+
+    #[test]
+    fn slope_facing_light_is_brighter() {
+        // x 方向に高くなる斜面を、-x 側と +x 側から照らす
+        let (w, h) = (4, 2);
+        let heights: Vec<f64> = (0..w * h).map(|i| (i % w) as f64).collect();
+        let shade = |light_dir| {
+            let mut rgba = [200, 200, 200, u8::MAX].repeat(w * h);
+            apply_shading(&mut rgba, &heights, w as u32, h as u32, light_dir);
+            rgba[0]
+        };
+        assert!(shade((-1.0, 0.0)) > shade((1.0, 0.0)));
+    }
+
+    #[test]
+    fn smooth_values_fill_in_between_bands() {
+        const MAX_ITER: u16 = 20;
+        let roots = [Complex::new(1.0, 0.0)];
+        let pixels = [Pixel { iter: 10, root: 0 }; 3];
+
+        let mut coloring = Coloring::default();
+        coloring.set_source(ColoringSource::SmoothEscapeTime);
+        assert!(coloring.needs_smooth() && !coloring.needs_ratios());
+        let smooth = coloring.colorize(&pixels, &[9.0, 9.5, 10.0], &roots, MAX_ITER);
+
+        // 整数の値は反復回数による色付けと一致し、その間の値は異なる色になる
+        let banded = Coloring::default().colorize(&pixels[..1], &[], &roots, MAX_ITER);
+        assert_eq!(&smooth[8..12], &banded[..]);
+        assert_ne!(&smooth[0..4], &smooth[4..8]);
+        assert_ne!(&smooth[4..8], &smooth[8..12]);
+    }
+
+    #[test]
+    fn sampled_colormaps_hit_their_endpoints() {
+        assert_eq!(Colormap::Viridis.color(0.0), [68, 1, 84, u8::MAX]);
+        assert_eq!(Colormap::Viridis.color(1.0), [253, 231, 37, u8::MAX]);
+        assert_eq!(Colormap::Magma.color(0.0), [0, 0, 4, u8::MAX]);
+        assert_eq!(Colormap::Magma.color(1.0), [252, 253, 191, u8::MAX]);
+        // 範囲外の値は両端に丸める
+        assert_eq!(Colormap::Viridis.color(-1.0), Colormap::Viridis.color(0.0));
+        assert_eq!(Colormap::Viridis.color(2.0), Colormap::Viridis.color(1.0));
+    }
+
+    #[test]
+    fn viridis_brightness_increases_monotonically() {
+        let luma = |[r, g, b, _]: [u8; 4]| 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+        let samples: Vec<f64> = (0..=64).map(|i| luma(Colormap::Viridis.color(i as f64 / 64.0))).collect();
+        assert!(samples.windows(2).all(|w| w[0] < w[1]));
+    }
+
+    #[test]
+    fn hsv_is_cyclic() {
+        assert_eq!(Colormap::Hsv.color(0.0), [u8::MAX, 0, 0, u8::MAX]);
+        assert_eq!(Colormap::Hsv.color(1.0), Colormap::Hsv.color(0.0));
+        assert_eq!(Colormap::Hsv.color(0.5), [0, u8::MAX, u8::MAX, u8::MAX]);
+    }
+
+    #[test]
+    fn coloring_uses_the_selected_colormap() {
+        let pixels = [Pixel { iter: 0, root: 0 }, Pixel { iter: 64, root: 0 }];
+        let mut coloring = Coloring::default();
+        assert_eq!(coloring.colorize(&pixels, &[], &[], 64)[0..4], jet(0.0));
+
+        coloring.set_colormap(Colormap::Viridis);
+        let rgba = coloring.colorize(&pixels, &[], &[], 64);
+        assert_eq!(rgba[0..4], Colormap::Viridis.color(0.0));
+        assert_eq!(rgba[4..8], Colormap::Viridis.color(1.0));
+    }
+
+    #[test]
+    fn few_colors_are_kept_as_they_are() {
+        // カラーマップにない色と半透明の色も、そのままパレットの色になる
+        let colors = [[255, 0, 0, 255], [0, 0, 255, 128], [255, 0, 0, 255], [10, 20, 30, 0]];
+        let rgba = colors.concat();
+        let palette = median_cut_palette(&rgba, 8);
+        assert_eq!(palette.len(), 3);
+        let indices = quantize_to_palette(&rgba, &palette);
+        for (color, &idx) in colors.iter().zip(&indices) {
+            assert_eq!(&palette[idx as usize], color);
+        }
+    }
+
+    #[test]
+    fn median_cut_reduces_to_the_palette_size() {
+        let rgba: Vec<u8> = (0..256).flat_map(|i| jet(i as f64 / 255.0)).collect();
+        let palette = median_cut_palette(&rgba, 16);
+        assert_eq!(palette.len(), 16);
+
+        // 各画素は、同じ集合の平均色に近い色に変換される
+        let indices = quantize_to_palette(&rgba, &palette);
+        for (color, &idx) in rgba.chunks_exact(4).zip(&indices) {
+            let error: i32 = color.iter().zip(palette[idx as usize]).map(|(&x, y)| (x as i32 - y as i32).abs()).max().unwrap();
+            assert!(error < 64, "{:?} -> {:?}", color, palette[idx as usize]);
+        }
+        assert!(median_cut_palette(&[], 16).is_empty());
+    }
+}
@@ -1,38 +1,136 @@
 mod calculate;
 mod btm;
+mod coloring;
+mod logging;
 mod multi_precision;
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-  calculate::initialize();
-
   tauri::Builder::default()
     .invoke_handler(tauri::generate_handler![
+      logging::set_log_level,
       calculate::set_formula,
+      calculate::set_conditional_formula,
+      calculate::set_morph,
+      calculate::set_morph_t,
+      calculate::set_param,
+      calculate::register_function,
+      calculate::sample_formula,
+      calculate::critical_points,
+      calculate::get_roots,
+      calculate::step_field,
+      calculate::render_flow_density,
+      calculate::convergence_order,
+      calculate::check_derivative,
       calculate::set_max_iter,
+      calculate::set_branch_handling,
+      calculate::set_convergence_criterion,
+      calculate::set_epsilon,
+      calculate::set_projection,
+      calculate::set_method,
+      calculate::set_relaxation_schedule,
+      calculate::set_coeff,
+      calculate::get_coeff_str,
+      calculate::set_iteration_falloff,
       calculate::set_size,
       calculate::initialize,
+      calculate::is_formula_ready,
       calculate::import_from_png,
+      calculate::export_state,
+      calculate::import_state,
+      calculate::export_link,
+      calculate::import_link,
+      calculate::snapshot,
+      calculate::restore,
+      calculate::drop_snapshot,
+      calculate::pin_current_render,
+      calculate::get_pinned_render,
       calculate::save_png,
+      calculate::save_png_indexed,
+      calculate::save_image,
+      calculate::set_coloring_mode,
+      calculate::set_coloring_source,
+      calculate::set_colormap,
+      calculate::set_color_formula,
+      calculate::set_alpha_source,
+      calculate::set_colormap_domain,
+      calculate::set_basin_colors,
+      calculate::set_basin_shading,
       calculate::get_available_syntax,
       calculate::get_default_formula,
       calculate::get_default_size,
       calculate::get_default_max_iter,
+      calculate::get_derivative_string,
       calculate::get_center_str,
       calculate::get_scale_str,
+      calculate::precision_warning,
+      calculate::get_render_settings,
       calculate::get_size,
       calculate::move_view,
+      calculate::set_center,
       calculate::zoom_view,
+      calculate::undo_view,
+      calculate::redo_view,
       calculate::render_tile,
+      calculate::render_tile_roots,
+      calculate::render_tile_full,
+      calculate::render_tile_shaded,
+      calculate::render_tile_budgeted,
+      calculate::render_tile_scale,
+      calculate::render_tile_from_roots,
+      calculate::render_tile_progressive,
+      calculate::render_pan_delta,
+      calculate::render_tile_gradient,
+      calculate::render_tile_lyapunov,
+      calculate::render_tile_rgba,
+      calculate::recolor,
+      calculate::render_tile_taa,
+      calculate::render_tile_stochastic,
+      calculate::render_tile_coverage_aa,
+      calculate::render_overview,
+      calculate::cancel_render,
+      calculate::set_tile_size,
+      calculate::get_tile_size,
+      calculate::set_fill_passes,
+      calculate::set_boundary_halo,
+      calculate::set_render_threads,
+      calculate::render_next_tiles,
+      calculate::basin_areas,
+      calculate::basin_iteration_stats,
+      calculate::color_histogram,
+      calculate::period_map,
+      calculate::dominant_periods,
+      calculate::min_iter_for_coverage,
+      calculate::converged_bounds,
+      calculate::random_interesting_view,
+      calculate::iteration_range,
+      calculate::profile_along_line,
+      calculate::escape_time_batch,
+      calculate::render_scanline,
+      calculate::render_iter_diff,
+      calculate::render_formula_diff,
+      calculate::render_preimage,
+      calculate::render_boundary_image,
+      calculate::export_boundary_svg,
+      calculate::render_confidence,
+      calculate::boundary_dimension,
+      calculate::render_montage,
+      calculate::render_figure,
+      calculate::export_gif,
+      calculate::render_timeline,
+      calculate::render_export_progressive,
+      calculate::export_high_res,
+      calculate::export_dzi,
     ])
     .setup(|app| {
-      if cfg!(debug_assertions) {
-        app.handle().plugin(
-          tauri_plugin_log::Builder::default()
-            .level(log::LevelFilter::Info)
-            .build(),
-        )?;
-      }
+      // set_log_level で実行中に変更できるように、ロガーは全てのレベルを通し、log::set_max_level で絞る
+      app.handle().plugin(
+        tauri_plugin_log::Builder::default()
+          .level(log::LevelFilter::Trace)
+          .build(),
+      )?;
+      log::set_max_level(logging::DEFAULT_LEVEL);
+      calculate::warm_up(app.handle().clone());
       Ok(())
     })
     .plugin(tauri_plugin_dialog::init())
@@ -0,0 +1,49 @@
+use log::LevelFilter;
+
+/// 起動時のログの出力レベル (debug build では Info 、release build では Warn)
+pub const DEFAULT_LEVEL: LevelFilter = if cfg!(debug_assertions) {
+    LevelFilter::Info
+} else {
+    LevelFilter::Warn
+};
+
+/// # ログの出力レベルを表す文字列を解析する
+///
+/// 大文字・小文字は区別しない
+fn parse_level(level: &str) -> Result<LevelFilter, String> {
+    level.trim().parse::<LevelFilter>()
+        .map_err(|_| format!("Unknown log level: {} (expected one of off, error, warn, info, debug, trace).", level))
+}
+
+/// # ログの出力レベルを、実行中に変更する
+///
+/// ロガー自体は全てのレベルを出力するように初期化し、log::set_max_level で出力するレベルを絞る。
+/// release build で描画がおかしい場合などに、詳細なログを出力して不具合の報告に使用する
+///
+/// ## Params
+///  - level: "off", "error", "warn", "info", "debug", "trace" のいずれか
+#[tauri::command]
+pub fn set_log_level(level: String) -> Result<(), String> {
+    let filter = parse_level(&level)?;
+    log::set_max_level(filter);
+    log::info!("Log level is set to {}.", filter);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accepts_known_levels_in_any_case() {
+        assert_eq!(parse_level("debug"), Ok(LevelFilter::Debug));
+        assert_eq!(parse_level("WARN"), Ok(LevelFilter::Warn));
+        assert_eq!(parse_level(" off "), Ok(LevelFilter::Off));
+    }
+
+    #[test]
+    fn rejects_unknown_levels() {
+        assert!(parse_level("verbose").is_err());
+        assert!(parse_level("").is_err());
+    }
+}
@@ -7,3 +7,14 @@ pub(crate) mod twofloat;
 
 pub(crate) type MD<const N: usize> = dashu::MD<N>;
 pub(crate) type F106 = twofloat::F106;
+
+/// 表示・解析用にf64へ変換する
+pub(crate) trait ToF64 {
+    fn to_f64(&self) -> f64;
+}
+
+impl ToF64 for f64 {
+    fn to_f64(&self) -> f64 {
+        *self
+    }
+}
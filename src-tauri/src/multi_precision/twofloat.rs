@@ -22,7 +22,10 @@ use twofloat::{
     TwoFloatError,
 };
 
-use crate::multi_precision::MD;
+use crate::multi_precision::{
+    MD,
+    ToF64,
+};
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd, Serialize, Deserialize)]
 pub(crate) struct F106 {
@@ -36,6 +39,20 @@ impl F106 {
     pub fn to_f64(&self) -> f64 {
         self.inner.hi() + self.inner.lo()
     }
+    /// 上位と下位の f64 (バイト列への変換用)
+    pub fn hi_lo(&self) -> (f64, f64) {
+        (self.inner.hi(), self.inner.lo())
+    }
+    /// hi_lo の逆変換
+    pub fn from_parts(hi: f64, lo: f64) -> Self {
+        Self::from(TwoFloat::new_add(hi, lo))
+    }
+}
+
+impl ToF64 for F106 {
+    fn to_f64(&self) -> f64 {
+        F106::to_f64(self)
+    }
 }
 
 impl LowerExp for F106 {
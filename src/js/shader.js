@@ -1,6 +1,7 @@
 // shader.js
 
 const invoke = window.__TAURI__.core.invoke;
+const { listen } = window.__TAURI__.event;
 
 let gl;
 let program;
@@ -332,10 +333,7 @@ export async function updateTile(pixelDx, pixelDy) {
 
     // 移動量が画面サイズ以上の場合はBlitできないので、全画面書き換えとする
     if (Math.abs(pixelDx) >= totalSize || Math.abs(pixelDy) >= totalSize) {
-        const data = await invoke("render_tile", { x: 0, y: 0, w: totalSize, h: totalSize });
-        const array = new Uint16Array(data);
-        updateTexture(array, totalSize, totalSize, 0, 0);
-        renderFrame();
+        await renderTileProgressive(0, 0, totalSize, totalSize);
         return;
     }
 
@@ -357,6 +355,8 @@ export async function updateTile(pixelDx, pixelDy) {
         const data = await invoke("render_tile", {
             x: req.x, y: req.y, w: req.w, h: req.h
         });
+        // 描画が中断された場合は空の配列が返るので、テクスチャを更新しない
+        if (data.length === 0) return;
 
         const array = new Uint16Array(data);
         updateTexture(array, req.w, req.h, req.x, req.y);
@@ -367,6 +367,58 @@ export async function updateTile(pixelDx, pixelDy) {
     renderFrame();
 }
 
+/**
+ * 間引いて計算された格子点の値を、最近傍補間で元の解像度に拡大する
+ * @param {Uint16Array} data 格子点の値
+ * @param {number} stride 格子点の間隔
+ * @param {number} w 拡大後の幅
+ * @param {number} h 拡大後の高さ
+ * @returns {Uint16Array}
+ */
+function upscale(data, stride, w, h) {
+    if (stride === 1) return data;
+    const cw = Math.ceil(w / stride);
+    const out = new Uint16Array(w * h);
+    for (let y = 0; y < h; y++) {
+        const row = Math.floor(y / stride) * cw;
+        for (let x = 0; x < w; x++) {
+            out[y * w + x] = data[row + Math.floor(x / stride)];
+        }
+    }
+    return out;
+}
+
+/**
+ * 矩形領域を粗い解像度から段階的に描画する
+ * @param {number} x 開始X座標
+ * @param {number} y 開始Y座標
+ * @param {number} w 幅
+ * @param {number} h 高さ
+ * @returns {Promise<void>}
+ */
+async function renderTileProgressive(x, y, w, h) {
+    let done = false;
+    const unlisten = await listen("tile-refined", (event) => {
+        const tile = event.payload;
+        if (done || tile.x !== x || tile.y !== y || tile.w !== w || tile.h !== h) return;
+        updateTexture(upscale(new Uint16Array(tile.data), tile.stride, w, h), w, h, x, y);
+        renderFrame();
+    });
+
+    try {
+        const data = await invoke("render_tile_progressive", { x, y, w, h });
+        done = true;
+        // 中断された場合は空の配列が返る
+        if (data.length === w * h) {
+            updateTexture(new Uint16Array(data), w, h, x, y);
+            renderFrame();
+        }
+    } finally {
+        done = true;
+        unlisten();
+    }
+}
+
 /**
  * 毎フレームの描画更新
  * @returns {void}
@@ -53,6 +53,9 @@ const {
     join,
     pictureDir,
 } = window.__TAURI__.path;
+const {
+    listen,
+} = window.__TAURI__.event;
 // ----- Utilities -----------------------------
 
 /**
@@ -93,10 +96,12 @@ async function setDefault(isUserClidked) {
         {
             return;
         }
+        await invoke("initialize");
+    } else {
+        // 起動時はRust側で初期値の数式をバックグラウンドでコンパイルしているので、完了を待つ
+        await waitFormulaReady();
     }
 
-    await invoke("initialize");
-
     const [defaultFormula, defaultSize, defaultMaxIter] = await Promise.all([
         invoke("get_default_formula"),
         invoke("get_default_size"),
@@ -113,10 +118,36 @@ async function setDefault(isUserClidked) {
     updateMaxIter(defaultMaxIter);
     state.prevMaxIter = defaultMaxIter;
 
-    await Promise.all([updateInfoStr(), invoke("set_size", { size: defaultSize })]);
+    await Promise.all([updateInfoStr(), invoke("set_size", { width: defaultSize, height: defaultSize })]);
     await withSpinner(async () => updateTile(defaultSize, defaultSize));
 }
 
+/** 起動時の数式のコンパイルが完了するまで、"Compiling..."を表示して待つ */
+async function waitFormulaReady() {
+    let resolveReady;
+    const ready = new Promise(resolve => resolveReady = resolve);
+    // is_formula_readyの確認後にイベントを取りこぼさないように、先にlistenする
+    const unlisten = await listen('formula-ready', () => resolveReady());
+
+    try {
+        if (await invoke("is_formula_ready")) {
+            return;
+        }
+
+        const label = elements.spinner.querySelector('span');
+        const prevLabel = label.textContent;
+        label.textContent = 'Compiling...';
+        elements.spinner.style.display = "flex";
+
+        await ready;
+
+        elements.spinner.style.display = "none";
+        label.textContent = prevLabel;
+    } finally {
+        unlisten();
+    }
+}
+
 async function initSyntaxHelp() {
     try {
         const syntax = await invoke("get_available_syntax");
@@ -374,7 +405,14 @@ async function setFexpr() {
             return;
         }
         try {
-            await invoke("set_formula", { formula: f });
+            const status = await invoke("set_formula", { formula: f });
+            // 後から入力された数式に置き換えられたので、描画はそちらに任せる
+            if (status === "SUPERSEDED") {
+                return;
+            }
+            if (status.startsWith("OK: warning:")) {
+                await message(status.slice("OK: warning:".length).trim(), { title: "f(z) may not render well", kind: "warning" });
+            }
         } catch (e) {
             elements.fexpr.value = state.prevFormula;
             await message(e, { title: "Failed to set f(z)", kind: "error" });
@@ -462,7 +500,7 @@ function setSize(value) {
 elements.presetSize.addEventListener('change', async () => {
     const size = Number(elements.presetSize.value);
     setSize(size);
-    await invoke("set_size", { size });
+    await invoke("set_size", { width: size, height: size });
     await withSpinner(async () => updateTile(size, size));
 });
 